@@ -0,0 +1,115 @@
+pub const BANK_SIZE: usize = 0x4000;
+
+/* A location in the cartridge ROM, as seen by the CPU: ROM0 is always mapped
+ * at $0000-$3FFF, every other bank is switched in at $4000-$7FFF. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Address {
+    bank: u16,
+    addr: u16,
+}
+
+impl Address {
+    pub fn new(bank: u16, addr: u16) -> Address {
+        Address { bank, addr }
+    }
+
+    pub fn from_offset(offset: usize) -> Address {
+        let bank = offset / BANK_SIZE;
+        let addr = if bank == 0 {
+            offset
+        } else {
+            BANK_SIZE + offset % BANK_SIZE
+        };
+
+        Address {
+            bank: bank as u16,
+            addr: addr as u16,
+        }
+    }
+
+    pub fn to_offset(&self) -> usize {
+        if self.bank == 0 || self.addr < BANK_SIZE as u16 {
+            self.addr as usize
+        } else {
+            self.bank as usize * BANK_SIZE + (self.addr as usize - BANK_SIZE)
+        }
+    }
+
+    pub fn bank(&self) -> u16 {
+        self.bank
+    }
+
+    pub fn addr(&self) -> u16 {
+        self.addr
+    }
+}
+
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:02X}:{:04X}", self.bank, self.addr)
+    }
+}
+
+/* Parses `[bank:]addr`, both in hexadecimal with an optional `$` or `0x`
+ * prefix. Without a bank, ROM0 addresses are in bank 0 and ROMX addresses in
+ * bank 1. */
+impl std::str::FromStr for Address {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Address, String> {
+        let hex = |s: &str| {
+            let digits = s
+                .trim()
+                .trim_start_matches('$')
+                .trim_start_matches("0x")
+                .trim_start_matches("0X");
+            u16::from_str_radix(digits, 16).map_err(|_| format!("invalid address `{}`", s))
+        };
+
+        let (bank, addr) = match s.split_once(':') {
+            Some((bank, addr)) => (Some(hex(bank)?), hex(addr)?),
+            None => (None, hex(s)?),
+        };
+
+        match (bank, addr) {
+            (_, 0x8000..=0xFFFF) => Err(format!("`{}` is not a ROM address", s)),
+            (Some(bank), 0x0000..=0x3FFF) if bank != 0 => {
+                Err(format!("`{}`: ROM0 addresses are in bank 0", s))
+            }
+            (Some(0), 0x4000..=0x7FFF) => Err(format!("`{}`: bank 0 is mapped at $0000", s)),
+            (Some(bank), _) => Ok(Address::new(bank, addr)),
+            (None, 0x0000..=0x3FFF) => Ok(Address::new(0, addr)),
+            (None, _) => Ok(Address::new(1, addr)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets() {
+        assert_eq!(Address::from_offset(0x0150), Address::new(0, 0x0150));
+        assert_eq!(Address::from_offset(0x4000), Address::new(1, 0x4000));
+        assert_eq!(Address::from_offset(0x9234), Address::new(2, 0x5234));
+        assert_eq!(Address::new(2, 0x5234).to_offset(), 0x9234);
+        assert_eq!(Address::new(3, 0x4000).to_offset(), 0xC000);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("0150".parse(), Ok(Address::new(0, 0x0150)));
+        assert_eq!("$4A00".parse(), Ok(Address::new(1, 0x4A00)));
+        assert_eq!("0x03:0x5000".parse(), Ok(Address::new(3, 0x5000)));
+        assert!("C000".parse::<Address>().is_err());
+        assert!("01:0100".parse::<Address>().is_err());
+        assert!("00:4000".parse::<Address>().is_err());
+        assert!("zz".parse::<Address>().is_err());
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Address::new(0x1F, 0x4ABC).to_string(), "1F:4ABC");
+    }
+}
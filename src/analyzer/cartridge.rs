@@ -1,3 +1,6 @@
+use super::error::AnalyzerError;
+use super::header::{Header, HEADER_END};
+
 #[derive(Debug)]
 pub struct Cartridge {
     bytes: Vec<u8>,
@@ -10,7 +13,30 @@ impl Cartridge {
         })
     }
 
+    pub fn save(&self, path: &std::path::Path) -> Result<(), std::io::Error> {
+        std::fs::write(path, &self.bytes)
+    }
+
     pub fn get_bytes(&self) -> &Vec<u8> {
         &self.bytes
     }
+
+    pub fn header(&self) -> Result<Header, AnalyzerError> {
+        Header::from_bytes(&self.bytes)
+    }
+
+    /* Rewrites the header and global checksums, returns the new values. */
+    pub fn fix_checksums(&mut self) -> Result<(u8, u16), AnalyzerError> {
+        if self.bytes.len() < HEADER_END {
+            return Err(AnalyzerError::InvalidHeader(self.bytes.len()));
+        }
+
+        let header_checksum = Header::compute_header_checksum(&self.bytes);
+        self.bytes[0x14D] = header_checksum;
+
+        let global_checksum = Header::compute_global_checksum(&self.bytes);
+        self.bytes[0x14E..0x150].copy_from_slice(&global_checksum.to_be_bytes());
+
+        Ok((header_checksum, global_checksum))
+    }
 }
@@ -0,0 +1,197 @@
+use std::collections::BTreeMap;
+
+use super::error::AnalyzerError;
+
+/* Text encoding of a game, byte to character(s). */
+#[derive(Debug, Clone, Default)]
+pub struct Charmap {
+    chars: BTreeMap<u8, String>,
+}
+
+impl Charmap {
+    /* Printable ASCII, what assemblers use without a charmap. */
+    pub fn ascii() -> Charmap {
+        let mut charmap = Charmap::default();
+        for byte in 0x20..0x7F {
+            charmap.chars.insert(byte, (byte as char).to_string());
+        }
+
+        charmap
+    }
+
+    /* Reads RGBDS `charmap "A", $80` lines, values can be hexadecimal ($ or
+     * 0x), binary (%) or decimal. Everything after `;` is a comment. Table
+     * files with `80=A` lines are read too. */
+    pub fn parse(text: &str) -> Result<Charmap, AnalyzerError> {
+        let mut charmap = Charmap::default();
+
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() || line.trim().starts_with(';') {
+                continue;
+            }
+
+            let invalid = || AnalyzerError::InvalidCharmap(i + 1, line.trim().to_string());
+            /* The text of a table entry is everything after `=`, spaces
+             * included. */
+            if let Some((byte, chars)) = line.split_once('=') {
+                if byte.len() == 2 {
+                    let value = u8::from_str_radix(byte, 16).map_err(|_| invalid())?;
+                    charmap
+                        .chars
+                        .entry(value)
+                        .or_insert_with(|| chars.to_string());
+                    continue;
+                }
+            }
+
+            let line = line.trim();
+            let rest = line
+                .strip_prefix("charmap")
+                .filter(|rest| rest.starts_with(char::is_whitespace))
+                .ok_or_else(invalid)?
+                .trim_start();
+            let (chars, rest) = Charmap::string(rest).ok_or_else(invalid)?;
+            let value = rest
+                .trim_start()
+                .strip_prefix(',')
+                .ok_or_else(invalid)?
+                .split(';')
+                .next()
+                .unwrap()
+                .trim();
+            let value = if let Some(hex) = value.strip_prefix('$') {
+                u8::from_str_radix(hex, 16)
+            } else if let Some(hex) = value.strip_prefix("0x") {
+                u8::from_str_radix(hex, 16)
+            } else if let Some(bin) = value.strip_prefix('%') {
+                u8::from_str_radix(bin, 2)
+            } else {
+                value.parse()
+            }
+            .map_err(|_| invalid())?;
+
+            /* The first mapping of a byte is the one used to print it. */
+            charmap.chars.entry(value).or_insert(chars);
+        }
+
+        Ok(charmap)
+    }
+
+    /* Splits a leading double-quoted string with its escapes resolved from
+     * the rest of `s`. */
+    fn string(s: &str) -> Option<(String, &str)> {
+        let mut chars = s.strip_prefix('"')?.char_indices();
+        let mut string = String::new();
+
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return Some((string, &s[i + 2..])),
+                '\\' => match chars.next()?.1 {
+                    'n' => string.push('\n'),
+                    't' => string.push('\t'),
+                    '0' => string.push('\0'),
+                    c => string.push(c),
+                },
+                c => string.push(c),
+            }
+        }
+
+        None
+    }
+
+    pub fn get(&self, byte: u8) -> Option<&str> {
+        self.chars.get(&byte).map(String::as_str)
+    }
+
+    pub fn get_chars(&self) -> &BTreeMap<u8, String> {
+        &self.chars
+    }
+
+    /* Byte assemblers encode `chars` to: the lowest one mapped to it, as
+     * only one definition of each string can be kept. */
+    pub fn encode(&self, chars: &str) -> Option<u8> {
+        self.chars
+            .iter()
+            .find(|(_, mapped)| mapped.as_str() == chars)
+            .map(|(&byte, _)| byte)
+    }
+
+    /* Byte ending strings: the one mapped to `<END>`, `@` as in the
+     * Pokémon disassemblies, or `\0`. */
+    pub fn terminator(&self) -> Option<u8> {
+        ["<END>", "@", "\0"].iter().find_map(|end| {
+            self.chars
+                .iter()
+                .find(|(_, chars)| chars == end)
+                .map(|(&byte, _)| byte)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgbds() {
+        let charmap = Charmap::parse(
+            "; Game text\n\
+             charmap \"A\", $80\n\
+             charmap \"B\", 0x81 ; comment\n\
+             charmap \"<PLAYER>\", %10000010\n\
+             charmap \"\\n\", 78\n\
+             charmap \"\\\"\", 20\n\
+             charmap \"Z\", $80\n\
+             charmap \"<END>\", $50\n",
+        )
+        .unwrap();
+
+        assert_eq!(charmap.get(0x80), Some("A"));
+        assert_eq!(charmap.get(0x81), Some("B"));
+        assert_eq!(charmap.get(0x82), Some("<PLAYER>"));
+        assert_eq!(charmap.get(78), Some("\n"));
+        assert_eq!(charmap.get(20), Some("\""));
+        assert_eq!(charmap.get(0x83), None);
+        assert_eq!(charmap.terminator(), Some(0x50));
+    }
+
+    #[test]
+    fn table() {
+        let charmap = Charmap::parse("80=A\n7F= \n50=@\n80=Z\nC0=A\n").unwrap();
+
+        assert_eq!(charmap.get(0x80), Some("A"));
+        assert_eq!(charmap.get(0x7F), Some(" "));
+        assert_eq!(charmap.get(0xC0), Some("A"));
+        assert_eq!(charmap.terminator(), Some(0x50));
+        assert_eq!(charmap.encode("A"), Some(0x80));
+        assert_eq!(charmap.encode("Z"), None);
+    }
+
+    #[test]
+    fn invalid() {
+        for line in &[
+            "charmap A, $80",
+            "charmap \"A\" $80",
+            "charmap \"A\", $100",
+            "charmap \"A, $80",
+            "charmaps \"A\", $80",
+            "G0=A",
+        ] {
+            let text = format!("charmap \"A\", $80\n{}\n", line);
+            assert!(matches!(
+                Charmap::parse(&text),
+                Err(AnalyzerError::InvalidCharmap(2, _))
+            ));
+        }
+    }
+
+    #[test]
+    fn ascii() {
+        let charmap = Charmap::ascii();
+
+        assert_eq!(charmap.get(b'a'), Some("a"));
+        assert_eq!(charmap.get(b' '), Some(" "));
+        assert_eq!(charmap.get(0x7F), None);
+        assert_eq!(charmap.get(0x00), None);
+    }
+}
@@ -0,0 +1,549 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::address::{Address, BANK_SIZE};
+use super::control_flow::{ControlFlowGraph, Function};
+use super::instruction::{Flow, Instruction, Mnemonic, Operand, Register};
+use super::project::Project;
+use super::values::Values;
+
+/* Largest output accepted, decompressors write to RAM. */
+const MAX_DECOMPRESSED_SIZE: usize = 0x10000;
+/* Decompressors are small, larger functions do more than that. */
+const MAX_DECOMPRESSOR_SIZE: usize = 200;
+
+/* Compression formats of the data read by decompressors. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Compression {
+    /* Pairs of a count and the byte to repeat, a zero count ends. */
+    Rle,
+    /* Control bytes: $00 ends, $01-$7F copy that many bytes, $80-$FF
+     * repeat the next byte (control & $7F) + 1 times. */
+    RleLiteral,
+    /* LZSS of Okumura's LZSS.C: the size of the output as a little-endian
+     * word, then groups of 8 items led by a flag byte read from bit 0, 1 for
+     * a literal byte, 0 for a 12-bit position and a 4-bit length minus 3 in
+     * a 4 KiB window of zeros starting at $FEE. */
+    Lzss,
+    /* LZ of the second generation Pokémon games. */
+    PokemonLz,
+    /* `gb_decompress` of GBDK, the format of gbcompress. */
+    Gbdk,
+}
+
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Self::Rle => "rle",
+            Self::RleLiteral => "rle-literal",
+            Self::Lzss => "lzss",
+            Self::PokemonLz => "pokemon-lz",
+            Self::Gbdk => "gbdk",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::str::FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Compression, String> {
+        match s {
+            "rle" => Ok(Self::Rle),
+            "rle-literal" => Ok(Self::RleLiteral),
+            "lzss" => Ok(Self::Lzss),
+            "pokemon-lz" => Ok(Self::PokemonLz),
+            "gbdk" => Ok(Self::Gbdk),
+            _ => Err(format!(
+                "unknown compression `{}`, expected rle, rle-literal, lzss, pokemon-lz or gbdk",
+                s
+            )),
+        }
+    }
+}
+
+impl Compression {
+    /* Decompresses the data at the start of `input`: the output and the
+     * size of the compressed data. */
+    pub fn decompress(self, input: &[u8]) -> Result<(Vec<u8>, usize), String> {
+        let mut reader = Reader { input, pos: 0 };
+        let mut out = Vec::new();
+
+        match self {
+            Self::Rle => loop {
+                let count = reader.byte()?;
+                if count == 0 {
+                    break;
+                }
+                let value = reader.byte()?;
+                out.extend(std::iter::repeat_n(value, count as usize));
+                check_size(&out)?;
+            },
+            Self::RleLiteral => loop {
+                match reader.byte()? {
+                    0x00 => break,
+                    count @ 0x01..=0x7F => out.extend_from_slice(reader.bytes(count as usize)?),
+                    control => {
+                        let value = reader.byte()?;
+                        out.extend(std::iter::repeat_n(value, (control & 0x7F) as usize + 1));
+                    }
+                }
+                check_size(&out)?;
+            },
+            Self::Lzss => {
+                let size = u16::from_le_bytes([reader.byte()?, reader.byte()?]) as usize;
+                let mut window = [0u8; 0x1000];
+                let mut position = 0xFEE;
+                let mut flags = 0u16;
+
+                while out.len() < size {
+                    flags >>= 1;
+                    if flags & 0x100 == 0 {
+                        flags = 0xFF00 | reader.byte()? as u16;
+                    }
+                    if flags & 1 != 0 {
+                        let byte = reader.byte()?;
+                        out.push(byte);
+                        window[position] = byte;
+                        position = (position + 1) & 0xFFF;
+                        continue;
+                    }
+
+                    let (low, high) = (reader.byte()?, reader.byte()?);
+                    let start = low as usize | (high as usize & 0xF0) << 4;
+                    for i in 0..(high & 0x0F) as usize + 3 {
+                        let byte = window[(start + i) & 0xFFF];
+                        out.push(byte);
+                        window[position] = byte;
+                        position = (position + 1) & 0xFFF;
+                    }
+                }
+                out.truncate(size);
+            }
+            Self::PokemonLz => loop {
+                let control = reader.byte()?;
+                if control == 0xFF {
+                    break;
+                }
+                /* Long commands have a 10-bit length and the command in
+                 * bits 4-2. */
+                let (command, length) = if control & 0xE0 == 0xE0 {
+                    let length = ((control as usize & 0x03) << 8 | reader.byte()? as usize) + 1;
+                    ((control >> 2) & 0x07, length)
+                } else {
+                    (control >> 5, (control as usize & 0x1F) + 1)
+                };
+
+                match command {
+                    0 => out.extend_from_slice(reader.bytes(length)?),
+                    1 => {
+                        let value = reader.byte()?;
+                        out.extend(std::iter::repeat_n(value, length));
+                    }
+                    2 => {
+                        let values = [reader.byte()?, reader.byte()?];
+                        out.extend((0..length).map(|i| values[i % 2]));
+                    }
+                    3 => out.extend(std::iter::repeat_n(0, length)),
+                    4..=6 => {
+                        /* Offsets with bit 7 set count back from the end of
+                         * the output, the others are from its start. */
+                        let offset = reader.byte()?;
+                        let start = if offset & 0x80 != 0 {
+                            out.len().checked_sub((offset & 0x7F) as usize + 1)
+                        } else {
+                            Some(u16::from_be_bytes([offset, reader.byte()?]) as usize)
+                        }
+                        .ok_or("LZ offset before the start of the output")?;
+
+                        for i in 0..length {
+                            let source = match command {
+                                6 => start.checked_sub(i),
+                                _ => Some(start + i),
+                            };
+                            let byte = *source
+                                .and_then(|source| out.get(source))
+                                .ok_or("LZ copy outside of the output")?;
+                            out.push(if command == 5 {
+                                byte.reverse_bits()
+                            } else {
+                                byte
+                            });
+                        }
+                    }
+                    _ => return Err(format!("invalid LZ command ${:02X}", control)),
+                }
+                check_size(&out)?;
+            },
+            Self::Gbdk => loop {
+                let control = reader.byte()?;
+                if control == 0 {
+                    break;
+                }
+                let length = (control & 0x3F) as usize + 1;
+
+                match control >> 6 {
+                    0 => {
+                        let value = reader.byte()?;
+                        out.extend(std::iter::repeat_n(value, length));
+                    }
+                    1 => {
+                        let values = [reader.byte()?, reader.byte()?];
+                        for _ in 0..length {
+                            out.extend_from_slice(&values);
+                        }
+                    }
+                    /* Copy from the output, at a negative offset from its
+                     * end. */
+                    2 => {
+                        let offset = u16::from_le_bytes([reader.byte()?, reader.byte()?]);
+                        let start = out
+                            .len()
+                            .checked_sub(0x10000 - offset as usize)
+                            .ok_or("copy before the start of the output")?;
+                        for i in 0..length {
+                            out.push(out[start + i]);
+                        }
+                    }
+                    _ => out.extend_from_slice(reader.bytes(length)?),
+                }
+                check_size(&out)?;
+            },
+        }
+
+        Ok((out, reader.pos))
+    }
+
+    /* Recognizes the decompressor of a format from the constants and
+     * instructions it uses. */
+    pub fn detect(instructions: &[&Instruction]) -> Option<Compression> {
+        if instructions.len() > MAX_DECOMPRESSOR_SIZE {
+            return None;
+        }
+
+        let mut masks = BTreeSet::new();
+        let mut compared = BTreeSet::new();
+        let mut bits = BTreeSet::new();
+        let mut shifts = false;
+        let mut reads = 0;
+        let mut writes = false;
+        for inst in instructions {
+            match (inst.mnemonic(), inst.lhs(), inst.rhs()) {
+                (Mnemonic::AND, Some(Operand::Imm8(mask)), _) => {
+                    masks.insert(*mask);
+                }
+                (Mnemonic::CP, Some(Operand::Imm8(value)), _) => {
+                    compared.insert(*value);
+                }
+                (Mnemonic::BIT, Some(Operand::Bit(bit)), _) => {
+                    bits.insert(*bit);
+                }
+                (Mnemonic::RR, _, _)
+                | (Mnemonic::RRA, _, _)
+                | (Mnemonic::RRC, _, _)
+                | (Mnemonic::RRCA, _, _)
+                | (Mnemonic::SRL, _, _) => shifts = true,
+                (Mnemonic::LDIR, _, _) => reads += 1,
+                (Mnemonic::LDIL, _, _)
+                | (Mnemonic::LD, Some(Operand::DerefReg(_)), Some(Operand::Reg(Register::A))) => {
+                    writes = true
+                }
+                _ => {}
+            }
+        }
+        let masked = |all: &[u8]| all.iter().all(|mask| masks.contains(mask));
+
+        if masked(&[0xE0, 0x1F]) && compared.contains(&0xFF) && compared.contains(&0xE0) {
+            Some(Compression::PokemonLz)
+        } else if masked(&[0x3F]) && bits.contains(&7) && bits.contains(&6) {
+            Some(Compression::Gbdk)
+        } else if masked(&[0x0F, 0xF0]) && shifts {
+            Some(Compression::Lzss)
+        } else if masked(&[0x7F]) && (bits.contains(&7) || masks.contains(&0x80)) {
+            Some(Compression::RleLiteral)
+        } else if masks.is_empty() && reads >= 2 && writes && instructions.len() <= 24 {
+            Some(Compression::Rle)
+        } else {
+            None
+        }
+    }
+}
+
+fn check_size(out: &[u8]) -> Result<(), String> {
+    if out.len() > MAX_DECOMPRESSED_SIZE {
+        Err("output larger than 64 KiB".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .input
+            .get(self.pos..self.pos + count)
+            .ok_or("compressed data runs past the end of the bank")?;
+        self.pos += count;
+
+        Ok(bytes)
+    }
+}
+
+/* Data decompressed by a call to a recognized decompressor, with its source
+ * in HL, or in DE for functions compiled by SDCC. */
+#[derive(Debug, Clone)]
+pub struct CompressedData {
+    call: Address,
+    decompressor: Address,
+    compression: Compression,
+    source: Address,
+    size: usize,
+    destination: Option<u16>,
+    decompressed_size: usize,
+}
+
+impl CompressedData {
+    pub fn find(cfg: &ControlFlowGraph, bytes: &[u8], project: &Project) -> Vec<CompressedData> {
+        let decompressors: BTreeMap<Address, Compression> = cfg
+            .get_functions()
+            .iter()
+            .filter_map(|(&entry, function)| {
+                Some((entry, Compression::detect(&instructions(cfg, function))?))
+            })
+            .collect();
+        if decompressors.is_empty() {
+            return Vec::new();
+        }
+
+        let mut found = Vec::new();
+        for block in cfg.get_blocks().values() {
+            let mut values = Values::default();
+
+            for (pc, inst) in block.get_instructions() {
+                if let Flow::Call(target) = inst.flow(pc.addr()) {
+                    let decompressor = ControlFlowGraph::target(bytes, project, *pc, target)
+                        .filter(|callee| decompressors.contains_key(callee));
+                    if let Some(decompressor) = decompressor {
+                        found.extend(CompressedData::at_call(
+                            bytes,
+                            project,
+                            *pc,
+                            &values,
+                            decompressor,
+                            decompressors[&decompressor],
+                        ));
+                    }
+                }
+                values.step(inst);
+            }
+        }
+        found.sort_by_key(|data| (data.source, data.call));
+
+        found
+    }
+
+    fn at_call(
+        bytes: &[u8],
+        project: &Project,
+        call: Address,
+        values: &Values,
+        decompressor: Address,
+        compression: Compression,
+    ) -> Option<CompressedData> {
+        let (source, destination) = [(Register::HL, Register::DE), (Register::DE, Register::BC)]
+            .iter()
+            .find_map(|&(source, destination)| {
+                let source = values.get(source).filter(|&source| source < 0x8000)?;
+                Some((source, values.get(destination)))
+            })?;
+        let source = match (source, values.bank()) {
+            (0x4000..=0x7FFF, Some(bank)) if bank != 0 => Address::new(bank as u16, source),
+            _ => ControlFlowGraph::target(bytes, project, call, source)?,
+        };
+
+        let start = source.to_offset();
+        let end = std::cmp::min((start / BANK_SIZE + 1) * BANK_SIZE, bytes.len());
+        let (output, size) = compression.decompress(&bytes[start..end]).ok()?;
+
+        Some(CompressedData {
+            call,
+            decompressor,
+            compression,
+            source,
+            size,
+            destination,
+            decompressed_size: output.len(),
+        })
+    }
+
+    /* The call to the decompressor. */
+    pub fn call(&self) -> Address {
+        self.call
+    }
+
+    pub fn decompressor(&self) -> Address {
+        self.decompressor
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    pub fn source(&self) -> Address {
+        self.source
+    }
+
+    /* Size of the compressed data. */
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /* Address of the last byte of compressed data. */
+    pub fn end(&self) -> Address {
+        Address::new(
+            self.source.bank(),
+            self.source.addr() + self.size as u16 - 1,
+        )
+    }
+
+    /* Where the data is decompressed, when the caller gives a constant. */
+    pub fn destination(&self) -> Option<u16> {
+        self.destination
+    }
+
+    pub fn decompressed_size(&self) -> usize {
+        self.decompressed_size
+    }
+
+    pub fn decompress(&self, bytes: &[u8]) -> Vec<u8> {
+        let start = self.source.to_offset();
+        match self
+            .compression
+            .decompress(&bytes[start..start + self.size])
+        {
+            Ok((output, _)) => output,
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+fn instructions<'a>(cfg: &'a ControlFlowGraph, function: &Function) -> Vec<&'a Instruction> {
+    function
+        .get_blocks()
+        .iter()
+        .filter_map(|start| cfg.get_blocks().get(start))
+        .flat_map(|block| block.get_instructions().iter().map(|(_, inst)| inst))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rle() {
+        let input = [3, 0xAA, 1, 0xBB, 0, 0xFF];
+        assert_eq!(
+            Compression::Rle.decompress(&input),
+            Ok((vec![0xAA, 0xAA, 0xAA, 0xBB], 5))
+        );
+        assert!(Compression::Rle.decompress(&[3]).is_err());
+    }
+
+    #[test]
+    fn rle_literal() {
+        let input = [0x02, 1, 2, 0x82, 0x55, 0x00];
+        assert_eq!(
+            Compression::RleLiteral.decompress(&input),
+            Ok((vec![1, 2, 0x55, 0x55, 0x55], 6))
+        );
+        assert!(Compression::RleLiteral.decompress(&[0x03, 1, 2]).is_err());
+    }
+
+    #[test]
+    fn lzss() {
+        /* Three literals, then four bytes from the start of the window,
+         * overlapping what they write. */
+        let input = [0x07, 0x00, 0b0111, b'A', b'B', b'C', 0xEE, 0xF1];
+        assert_eq!(
+            Compression::Lzss.decompress(&input),
+            Ok((b"ABCABCA".to_vec(), 8))
+        );
+    }
+
+    #[test]
+    fn pokemon_lz() {
+        let input = [
+            0x02, 1, 2, 3, /* literal */
+            0x21, 0x07, /* repeat */
+            0x41, 0xAA, 0xBB, /* alternate */
+            0x61, /* zeros */
+            0x82, 0x00, 0x00, /* copy from the start */
+            0xA0, 0x81, /* bit-reversed copy of the byte before last */
+            0xC1, 0x80, /* backwards copy from the last byte */
+            0xE4, 0x20, 0x11, /* long repeat */
+            0xFF,
+        ];
+        let mut expected = vec![1, 2, 3, 7, 7, 0xAA, 0xBB, 0, 0, 1, 2, 3, 0x40, 0x40, 3];
+        expected.extend(std::iter::repeat_n(0x11, 33));
+        assert_eq!(
+            Compression::PokemonLz.decompress(&input),
+            Ok((expected, input.len()))
+        );
+
+        assert!(Compression::PokemonLz
+            .decompress(&[0x80, 0x85, 0xFF])
+            .is_err());
+        assert!(Compression::PokemonLz
+            .decompress(&[0xFC, 0x00, 0xFF])
+            .is_err());
+    }
+
+    #[test]
+    fn gbdk() {
+        let input = [
+            0x02, 0x55, /* repeat */
+            0x41, 1, 2, /* repeat a pair */
+            0x81, 0xFE, 0xFF, /* copy from two bytes back */
+            0xC1, 9, 8, /* literal */
+            0x00,
+        ];
+        assert_eq!(
+            Compression::Gbdk.decompress(&input),
+            Ok((vec![0x55, 0x55, 0x55, 1, 2, 1, 2, 1, 2, 9, 8], input.len()))
+        );
+        assert!(Compression::Gbdk
+            .decompress(&[0x80, 0xFF, 0xFF, 0x00])
+            .is_err());
+    }
+
+    #[test]
+    fn detect() {
+        let code: [&[u8]; 9] = [
+            &[0x2A],       /* ld a, [hl+] */
+            &[0xA7],       /* and a */
+            &[0xC8],       /* ret z */
+            &[0x47],       /* ld b, a */
+            &[0x2A],       /* ld a, [hl+] */
+            &[0x12],       /* .loop ld [de], a */
+            &[0x13],       /* inc de */
+            &[0x05],       /* dec b */
+            &[0x20, 0xFB], /* jr nz, .loop */
+        ];
+        let instructions: Vec<Instruction> = code
+            .iter()
+            .map(|bytes| Instruction::from_slice(bytes).unwrap())
+            .collect();
+        let instructions: Vec<&Instruction> = instructions.iter().collect();
+        assert_eq!(Compression::detect(&instructions), Some(Compression::Rle));
+        assert_eq!(Compression::detect(&instructions[..2]), None);
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+
+use super::address::Address;
+use super::control_flow::ControlFlowGraph;
+use super::instruction::{Flow, Instruction, Mnemonic, Operand, Register};
+use super::project::Project;
+use super::values::Values;
+
+/* Constants known in the registers before each instruction, found by
+ * following the flow of every function from its entry, where nothing is
+ * known. Values reaching a block from several paths are kept when they
+ * agree, calls forget the registers but not SP and the ROM bank. */
+#[derive(Debug, Default)]
+pub struct Constants {
+    states: BTreeMap<Address, Values>,
+    resolved: BTreeMap<Address, Address>,
+    annotations: BTreeMap<Address, (Register, u16)>,
+}
+
+impl Constants {
+    pub fn build(cfg: &ControlFlowGraph, bytes: &[u8], project: &Project) -> Constants {
+        let mut constants = Constants::default();
+
+        for &entry in cfg.get_functions().keys() {
+            constants.function(cfg, entry);
+        }
+        for block in cfg.get_blocks().values() {
+            for (pc, inst) in block.get_instructions() {
+                constants.resolve(bytes, project, *pc, inst);
+            }
+        }
+
+        constants
+    }
+
+    /* Values before the instruction at `pc`. */
+    pub fn values(&self, pc: Address) -> Option<&Values> {
+        self.states.get(&pc)
+    }
+
+    pub fn get(&self, pc: Address, reg: Register) -> Option<u16> {
+        self.states.get(&pc)?.get(reg)
+    }
+
+    /* ROM bank selected when the instruction at `pc` runs. */
+    pub fn bank(&self, pc: Address) -> Option<u8> {
+        self.states.get(&pc)?.bank()
+    }
+
+    /* Targets of `jp hl` with HL known, and of jumps and calls from bank 0
+     * into the switchable bank while the bank selected is known, by
+     * instruction. */
+    pub fn get_resolved(&self) -> &BTreeMap<Address, Address> {
+        &self.resolved
+    }
+
+    /* Register holding the address used by each instruction, with its
+     * value: the pointer it reads or writes through, the target of `jp hl`
+     * or the bank written to the bank controller. */
+    pub fn get_annotations(&self) -> &BTreeMap<Address, (Register, u16)> {
+        &self.annotations
+    }
+
+    /* The values before every instruction of the function at `entry`. Flow
+     * into another function is its call, not part of this one. */
+    fn function(&mut self, cfg: &ControlFlowGraph, entry: Address) {
+        let functions = cfg.get_functions();
+        let mut inputs: BTreeMap<Address, Values> = BTreeMap::new();
+        inputs.insert(entry, Values::default());
+        let mut worklist = vec![entry];
+
+        while let Some(start) = worklist.pop() {
+            let block = match cfg.get_blocks().get(&start) {
+                Some(block) => block,
+                None => continue,
+            };
+            let mut values = inputs[&start].clone();
+            for (pc, inst) in block.get_instructions() {
+                let state = match self.states.get(pc) {
+                    Some(state) => state.join(&values),
+                    None => values.clone(),
+                };
+                self.states.insert(*pc, state);
+                values.step(inst);
+            }
+
+            for edge in block.get_successors() {
+                let target = edge.target();
+                if target != entry && functions.contains_key(&target) {
+                    continue;
+                }
+                let joined = match inputs.get(&target) {
+                    Some(input) => input.join(&values),
+                    None => values.clone(),
+                };
+                if inputs.get(&target) != Some(&joined) {
+                    inputs.insert(target, joined);
+                    worklist.push(target);
+                }
+            }
+        }
+    }
+
+    fn resolve(&mut self, bytes: &[u8], project: &Project, pc: Address, inst: &Instruction) {
+        let values = match self.states.get(&pc) {
+            Some(values) => values,
+            None => return,
+        };
+
+        let pointer = [inst.lhs(), inst.rhs()]
+            .iter()
+            .find_map(|operand| match operand {
+                Some(Operand::DerefReg(reg @ Register::BC))
+                | Some(Operand::DerefReg(reg @ Register::DE))
+                | Some(Operand::DerefReg(reg @ Register::HL)) => Some(*reg),
+                _ => None,
+            })
+            .and_then(|reg| Some((reg, values.get(reg)?)));
+        let bank = match Values::store(inst) {
+            Some(0x2000..=0x3FFF) => values.a().map(|a| (Register::A, a as u16)),
+            _ => None,
+        };
+        if let Some(annotation) = pointer.or(bank) {
+            self.annotations.insert(pc, annotation);
+        }
+
+        let target = match inst.flow(pc.addr()) {
+            Flow::Jump(None) => match pointer {
+                Some((Register::HL, hl)) => Constants::target(bytes, pc, values.bank(), hl),
+                _ => None,
+            },
+            Flow::Jump(Some(target)) | Flow::Branch(target) | Flow::Call(target)
+                if *inst.mnemonic() != Mnemonic::RST
+                    && ControlFlowGraph::target(bytes, project, pc, target).is_none() =>
+            {
+                Constants::target(bytes, pc, values.bank(), target)
+            }
+            _ => None,
+        };
+        if let Some(target) = target {
+            self.resolved.insert(pc, target);
+        }
+    }
+
+    /* Code at `target` when the instruction at `pc` runs with `bank`
+     * selected. */
+    fn target(bytes: &[u8], pc: Address, bank: Option<u8>, target: u16) -> Option<Address> {
+        let target = match target {
+            0x0000..=0x3FFF => Address::new(0, target),
+            0x4000..=0x7FFF if pc.bank() != 0 => Address::new(pc.bank(), target),
+            0x4000..=0x7FFF => match bank? {
+                0 => return None,
+                bank => Address::new(bank as u16, target),
+            },
+            _ => return None,
+        };
+
+        Some(target).filter(|target| target.to_offset() < bytes.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Constants of `code` at $0100, in a ROM of four banks. */
+    fn build(code: &[u8]) -> Constants {
+        let mut bytes = vec![0x00; 0x10000];
+        bytes[0x0100..0x0100 + code.len()].copy_from_slice(code);
+        let project = Project::new(&bytes);
+        let cfg = ControlFlowGraph::build(&bytes, &[Address::new(0, 0x0100)], &project);
+
+        Constants::build(&cfg, &bytes, &project)
+    }
+
+    #[test]
+    fn jump_hl() {
+        let constants = build(&[
+            0x21, 0x50, 0x01, /* ld hl, $0150 */
+            0xE9, /* jp hl */
+        ]);
+        let jump = Address::new(0, 0x0103);
+        assert_eq!(constants.get(jump, Register::HL), Some(0x0150));
+        assert_eq!(constants.get_resolved()[&jump], Address::new(0, 0x0150));
+        assert_eq!(constants.get_annotations()[&jump], (Register::HL, 0x0150));
+    }
+
+    #[test]
+    fn bank_switch() {
+        let constants = build(&[
+            0x3E, 0x02, /* ld a, 2 */
+            0xEA, 0x00, 0x20, /* ld [$2000], a */
+            0xCD, 0x00, 0x40, /* call $4000 */
+            0x18, 0xFE, /* jr @ */
+        ]);
+        let store = Address::new(0, 0x0102);
+        let call = Address::new(0, 0x0105);
+        assert_eq!(constants.get_annotations()[&store], (Register::A, 2));
+        assert_eq!(constants.bank(call), Some(2));
+        assert_eq!(constants.get_resolved()[&call], Address::new(2, 0x4000));
+        /* Calls forget the registers but not the bank. */
+        let after = Address::new(0, 0x0108);
+        assert_eq!(constants.bank(after), Some(2));
+        assert_eq!(constants.get(after, Register::A), None);
+    }
+
+    #[test]
+    fn paths_meeting() {
+        let constants = build(&[
+            0x06, 0x01, /* ld b, 1 */
+            0x21, 0x00, 0xC0, /* ld hl, $C000 */
+            0x28, 0x02, /* jr z, .skip */
+            0x06, 0x02, /* ld b, 2 */
+            0x70, /* .skip: ld [hl], b */
+            0x18, 0xFE, /* jr @ */
+        ]);
+        let store = Address::new(0, 0x0109);
+        assert_eq!(constants.get(store, Register::B), None);
+        assert_eq!(constants.get_annotations()[&store], (Register::HL, 0xC000));
+        assert_eq!(constants.values(Address::new(0, 0x0200)), None);
+    }
+}
@@ -0,0 +1,1560 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::address::{Address, BANK_SIZE};
+use super::constants::Constants;
+use super::disassembler::Disassembler;
+use super::instruction::{Flow, Instruction, Mnemonic, Operand, Register};
+use super::project::{DataType, InlineArgs, Project, Trampoline, TypedRange};
+use super::warning::Warning;
+
+/* Entry points every cartridge has: the header entry and interrupt vectors. */
+pub const DEFAULT_ENTRY_POINTS: [u16; 6] = [0x0100, 0x0040, 0x0048, 0x0050, 0x0058, 0x0060];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    Fallthrough,
+    Jump,
+    Branch, /* Conditional jump taken */
+    Table,  /* Entry of a jump table */
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Edge {
+    kind: EdgeKind,
+    target: Address,
+}
+
+impl Edge {
+    pub fn kind(&self) -> EdgeKind {
+        self.kind
+    }
+
+    pub fn target(&self) -> Address {
+        self.target
+    }
+}
+
+#[derive(Debug)]
+pub struct BasicBlock {
+    start: Address,
+    instructions: Vec<(Address, Instruction)>,
+    successors: Vec<Edge>,
+}
+
+impl BasicBlock {
+    pub fn start(&self) -> Address {
+        self.start
+    }
+
+    /* Address right after the last instruction of the block. */
+    pub fn end(&self) -> Address {
+        match self.instructions.last() {
+            Some((addr, inst)) => Address::new(addr.bank(), addr.addr() + inst.size() as u16),
+            None => self.start,
+        }
+    }
+
+    pub fn get_instructions(&self) -> &Vec<(Address, Instruction)> {
+        &self.instructions
+    }
+
+    pub fn get_successors(&self) -> &Vec<Edge> {
+        &self.successors
+    }
+}
+
+#[derive(Debug)]
+pub struct Function {
+    entry: Address,
+    blocks: BTreeSet<Address>,
+    calls: BTreeSet<Address>,
+}
+
+impl Function {
+    pub fn entry(&self) -> Address {
+        self.entry
+    }
+
+    pub fn get_blocks(&self) -> &BTreeSet<Address> {
+        &self.blocks
+    }
+
+    pub fn get_calls(&self) -> &BTreeSet<Address> {
+        &self.calls
+    }
+}
+
+/* Table of code pointers read by a dispatcher: an RST routine reading the
+ * words inline after the `rst`, or a `jp hl` to an entry of a table loaded in
+ * HL. */
+#[derive(Debug, Clone)]
+pub struct JumpTable {
+    dispatch: Address,
+    start: Address,
+    end: Address,
+    /* Address of each word read and where it points. */
+    targets: Vec<(Address, Address)>,
+}
+
+impl JumpTable {
+    /* The `rst` or `jp hl` instruction. */
+    pub fn dispatch(&self) -> Address {
+        self.dispatch
+    }
+
+    pub fn start(&self) -> Address {
+        self.start
+    }
+
+    /* Address of the last byte of the table. */
+    pub fn end(&self) -> Address {
+        self.end
+    }
+
+    pub fn get_targets(&self) -> &Vec<(Address, Address)> {
+        &self.targets
+    }
+}
+
+/* Table of pointers read two bytes at a time through HL after indexing it,
+ * to data, or to functions when the pointer read is then called. */
+#[derive(Debug, Clone)]
+pub struct PointerTable {
+    read: Address,
+    start: Address,
+    end: Address,
+    /* Address of each word read and where it points. */
+    targets: Vec<(Address, Address)>,
+    code: bool,
+}
+
+impl PointerTable {
+    /* The instruction reading the high byte of the pointer. */
+    pub fn read(&self) -> Address {
+        self.read
+    }
+
+    pub fn start(&self) -> Address {
+        self.start
+    }
+
+    /* Address of the last byte of the table. */
+    pub fn end(&self) -> Address {
+        self.end
+    }
+
+    pub fn get_targets(&self) -> &Vec<(Address, Address)> {
+        &self.targets
+    }
+
+    /* Whether the targets are functions. */
+    pub fn code(&self) -> bool {
+        self.code
+    }
+}
+
+/* Bytes placed after a call for the callee to read, flow resumes after them. */
+#[derive(Debug, Clone)]
+pub struct CallArguments {
+    call: Address,
+    start: Address,
+    size: usize,
+    kind: InlineArgs,
+}
+
+impl CallArguments {
+    pub fn call(&self) -> Address {
+        self.call
+    }
+
+    pub fn start(&self) -> Address {
+        self.start
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /* Address right after the arguments, where flow resumes. */
+    pub fn end(&self) -> Address {
+        Address::new(self.start.bank(), self.start.addr() + self.size as u16)
+    }
+
+    pub fn kind(&self) -> &InlineArgs {
+        &self.kind
+    }
+}
+
+#[derive(Debug)]
+pub struct ControlFlowGraph {
+    blocks: BTreeMap<Address, BasicBlock>,
+    functions: BTreeMap<Address, Function>,
+    jump_tables: BTreeMap<Address, JumpTable>,
+    pointer_tables: BTreeMap<Address, PointerTable>,
+    arguments: BTreeMap<Address, CallArguments>,
+    far_calls: BTreeMap<Address, Address>,
+    resolved: BTreeMap<Address, Address>,
+    constants: Constants,
+    warnings: Vec<Warning>,
+}
+
+impl ControlFlowGraph {
+    /* Follows code from `entries`, every entry and call target becomes a
+     * function. Bank hints of `project` resolve targets in switchable banks
+     * and flow stops at ranges it types as data. The code is followed again
+     * from the targets the constants in registers resolve, until there are
+     * no new ones. */
+    pub fn build(bytes: &[u8], entries: &[Address], project: &Project) -> ControlFlowGraph {
+        let mut resolved = BTreeMap::new();
+
+        for _ in 0..MAX_RESOLVE_ROUNDS {
+            let mut cfg = ControlFlowGraph::trace(bytes, entries, project, &resolved);
+            let constants = Constants::build(&cfg, bytes, project);
+            let found: Vec<(Address, Address)> = constants
+                .get_resolved()
+                .iter()
+                .filter(|(pc, _)| !resolved.contains_key(pc))
+                .map(|(&pc, &target)| (pc, target))
+                .collect();
+            if found.is_empty() {
+                cfg.constants = constants;
+                return cfg;
+            }
+            resolved.extend(found);
+        }
+
+        let mut cfg = ControlFlowGraph::trace(bytes, entries, project, &resolved);
+        cfg.constants = Constants::build(&cfg, bytes, project);
+        cfg
+    }
+
+    fn trace(
+        bytes: &[u8],
+        entries: &[Address],
+        project: &Project,
+        resolved: &BTreeMap<Address, Address>,
+    ) -> ControlFlowGraph {
+        let mut builder = Builder {
+            bytes,
+            project,
+            resolved,
+            table_vectors: Builder::table_vectors(bytes, project),
+            code: BTreeMap::new(),
+            leaders: entries.iter().copied().collect(),
+            function_entries: entries.iter().copied().collect(),
+            jump_tables: BTreeMap::new(),
+            pointer_reads: Vec::new(),
+            pointer_tables: BTreeMap::new(),
+            arguments: BTreeMap::new(),
+            far_calls: BTreeMap::new(),
+            inline_args: BTreeMap::new(),
+            trampolines: BTreeMap::new(),
+            data: BTreeSet::new(),
+            warnings: Vec::new(),
+            worklist: entries.to_vec(),
+        };
+        /* Pointer tables are sized once the code around them is known, the
+         * functions they point to are then followed in turn. */
+        loop {
+            while let Some(start) = builder.worklist.pop() {
+                builder.trace(start);
+            }
+            if builder.pointer_reads.is_empty() {
+                break;
+            }
+            for (read, start) in std::mem::take(&mut builder.pointer_reads) {
+                builder.add_pointer_table(read, start);
+            }
+        }
+
+        let Builder {
+            code,
+            leaders,
+            function_entries,
+            jump_tables,
+            pointer_tables,
+            arguments,
+            far_calls,
+            warnings,
+            ..
+        } = builder;
+        let blocks = ControlFlowGraph::split_blocks(
+            bytes,
+            project,
+            code,
+            &leaders,
+            &jump_tables,
+            &arguments,
+            resolved,
+        );
+        let functions = function_entries
+            .iter()
+            .filter(|entry| blocks.contains_key(entry))
+            .map(|&entry| {
+                (
+                    entry,
+                    ControlFlowGraph::collect_function(
+                        bytes, project, &blocks, &far_calls, resolved, entry,
+                    ),
+                )
+            })
+            .collect();
+
+        ControlFlowGraph {
+            blocks,
+            functions,
+            jump_tables,
+            pointer_tables,
+            arguments,
+            far_calls,
+            resolved: resolved.clone(),
+            constants: Constants::default(),
+            warnings,
+        }
+    }
+
+    pub fn get_blocks(&self) -> &BTreeMap<Address, BasicBlock> {
+        &self.blocks
+    }
+
+    pub fn get_functions(&self) -> &BTreeMap<Address, Function> {
+        &self.functions
+    }
+
+    /* Jump tables keyed by the address of their dispatch instruction. */
+    pub fn get_jump_tables(&self) -> &BTreeMap<Address, JumpTable> {
+        &self.jump_tables
+    }
+
+    /* Pointer tables keyed by the address of the instruction reading them. */
+    pub fn get_pointer_tables(&self) -> &BTreeMap<Address, PointerTable> {
+        &self.pointer_tables
+    }
+
+    /* Final targets of calls through bank trampolines, keyed by the address
+     * of the call. */
+    pub fn get_far_calls(&self) -> &BTreeMap<Address, Address> {
+        &self.far_calls
+    }
+
+    /* Targets of `jp hl` and of jumps and calls into the switchable bank
+     * found from the constants in registers, by instruction. */
+    pub fn get_resolved(&self) -> &BTreeMap<Address, Address> {
+        &self.resolved
+    }
+
+    /* Constants in registers before each instruction. */
+    pub fn get_constants(&self) -> &Constants {
+        &self.constants
+    }
+
+    /* Inline arguments keyed by the address of their call. */
+    pub fn get_arguments(&self) -> &BTreeMap<Address, CallArguments> {
+        &self.arguments
+    }
+
+    pub fn get_warnings(&self) -> &Vec<Warning> {
+        &self.warnings
+    }
+
+    /* Maps a CPU address reached from code at `from` to a ROM location. Jumps
+     * into $4000-$7FFF from ROM0 can only be resolved when there is a single
+     * switchable bank. */
+    pub fn resolve(bytes: &[u8], from: Address, target: u16) -> Option<Address> {
+        let address = match target {
+            0x0000..=0x3FFF => Address::new(0, target),
+            0x4000..=0x7FFF if from.bank() != 0 => Address::new(from.bank(), target),
+            0x4000..=0x7FFF if bytes.len() <= 2 * BANK_SIZE => Address::new(1, target),
+            _ => return None,
+        };
+
+        if address.to_offset() < bytes.len() {
+            Some(address)
+        } else {
+            None
+        }
+    }
+
+    /* Target of the instruction at `from`, in the bank hinted by the project
+     * if there is one. */
+    pub fn target(bytes: &[u8], project: &Project, from: Address, target: u16) -> Option<Address> {
+        match (target, project.bank_hint(from)) {
+            (0x4000..=0x7FFF, Some(bank)) => {
+                Some(Address::new(bank, target)).filter(|address| address.to_offset() < bytes.len())
+            }
+            _ => ControlFlowGraph::resolve(bytes, from, target),
+        }
+    }
+
+    fn split_blocks(
+        bytes: &[u8],
+        project: &Project,
+        code: BTreeMap<Address, Instruction>,
+        leaders: &BTreeSet<Address>,
+        jump_tables: &BTreeMap<Address, JumpTable>,
+        arguments: &BTreeMap<Address, CallArguments>,
+        resolved: &BTreeMap<Address, Address>,
+    ) -> BTreeMap<Address, BasicBlock> {
+        let mut blocks: BTreeMap<Address, BasicBlock> = BTreeMap::new();
+        let mut current: Option<BasicBlock> = None;
+
+        for (pc, inst) in code {
+            /* A new block starts at leaders and after any gap in the code. */
+            let contiguous = match &current {
+                Some(block) => block.end() == pc && !leaders.contains(&pc),
+                None => false,
+            };
+            if !contiguous {
+                if let Some(block) = current.take() {
+                    blocks.insert(block.start, block);
+                }
+                current = Some(BasicBlock {
+                    start: pc,
+                    instructions: Vec::new(),
+                    successors: Vec::new(),
+                });
+            }
+
+            let block = current.as_mut().unwrap();
+            let flow = inst.flow(pc.addr());
+            block.instructions.push((pc, inst));
+
+            if !matches!(flow, Flow::Next | Flow::Call(_))
+                || jump_tables.contains_key(&pc)
+                || arguments.contains_key(&pc)
+            {
+                let block = current.take().unwrap();
+                blocks.insert(block.start, block);
+            }
+        }
+        if let Some(block) = current.take() {
+            blocks.insert(block.start, block);
+        }
+
+        let starts: BTreeSet<Address> = blocks.keys().copied().collect();
+        for block in blocks.values_mut() {
+            let (pc, inst) = block.instructions.last().unwrap();
+            let end = block.end();
+            let mut successors = Vec::new();
+
+            let mut edge = |kind: EdgeKind, target: Option<Address>| {
+                if let Some(target) = target.filter(|target| starts.contains(target)) {
+                    successors.push(Edge { kind, target });
+                }
+            };
+            let next = match arguments.get(pc) {
+                Some(arguments) => Some(arguments.end()),
+                None => {
+                    ControlFlowGraph::resolve(bytes, *pc, end.addr()).filter(|&next| next == end)
+                }
+            };
+
+            if let Some(table) = jump_tables.get(pc) {
+                let mut targets: Vec<Address> =
+                    table.targets.iter().map(|&(_, target)| target).collect();
+                targets.sort_unstable();
+                targets.dedup();
+                for target in targets {
+                    edge(EdgeKind::Table, Some(target));
+                }
+                block.successors = successors;
+                continue;
+            }
+
+            let target = |target: u16| {
+                resolved
+                    .get(pc)
+                    .copied()
+                    .or_else(|| ControlFlowGraph::target(bytes, project, *pc, target))
+            };
+            match inst.flow(pc.addr()) {
+                Flow::Next | Flow::Call(_) | Flow::ConditionalReturn => {
+                    edge(EdgeKind::Fallthrough, next)
+                }
+                Flow::Jump(Some(addr)) => edge(EdgeKind::Jump, target(addr)),
+                Flow::Branch(addr) => {
+                    edge(EdgeKind::Branch, target(addr));
+                    edge(EdgeKind::Fallthrough, next);
+                }
+                Flow::Jump(None) => edge(EdgeKind::Jump, resolved.get(pc).copied()),
+                Flow::Return => {}
+            }
+
+            block.successors = successors;
+        }
+
+        blocks
+    }
+
+    fn collect_function(
+        bytes: &[u8],
+        project: &Project,
+        blocks: &BTreeMap<Address, BasicBlock>,
+        far_calls: &BTreeMap<Address, Address>,
+        resolved: &BTreeMap<Address, Address>,
+        entry: Address,
+    ) -> Function {
+        let mut visited: BTreeSet<Address> = BTreeSet::new();
+        let mut calls: BTreeSet<Address> = BTreeSet::new();
+        let mut worklist = vec![entry];
+
+        while let Some(start) = worklist.pop() {
+            if !visited.insert(start) {
+                continue;
+            }
+            let block = &blocks[&start];
+
+            for (pc, inst) in &block.instructions {
+                if let Flow::Call(target) = inst.flow(pc.addr()) {
+                    let target = resolved
+                        .get(pc)
+                        .copied()
+                        .or_else(|| ControlFlowGraph::target(bytes, project, *pc, target));
+                    if let Some(target) = target {
+                        calls.insert(target);
+                    }
+                }
+                if let Some(&target) = far_calls.get(pc) {
+                    calls.insert(target);
+                }
+            }
+            worklist.extend(block.successors.iter().map(|edge| edge.target));
+        }
+
+        Function {
+            entry,
+            blocks: visited,
+            calls,
+        }
+    }
+}
+
+/* Times code is followed again from the targets resolved by constants. */
+const MAX_RESOLVE_ROUNDS: usize = 4;
+
+/* Longest jump table whose size is guessed. */
+const MAX_TABLE_ENTRIES: usize = 128;
+
+/* Longest routine examined to recognize a calling convention. */
+const MAX_SCANNED_INSTRUCTIONS: usize = 48;
+
+/* State of the flow analysis while code is being traced. */
+struct Builder<'a> {
+    bytes: &'a [u8],
+    project: &'a Project,
+    /* Targets found from the constants in registers, by instruction. */
+    resolved: &'a BTreeMap<Address, Address>,
+    /* RST vectors followed by an inline jump table. */
+    table_vectors: BTreeSet<u16>,
+    code: BTreeMap<Address, Instruction>,
+    leaders: BTreeSet<Address>,
+    function_entries: BTreeSet<Address>,
+    jump_tables: BTreeMap<Address, JumpTable>,
+    /* Pointers read from a table, with the table start, not sized yet. */
+    pointer_reads: Vec<(Address, Address)>,
+    pointer_tables: BTreeMap<Address, PointerTable>,
+    arguments: BTreeMap<Address, CallArguments>,
+    /* Calls through trampolines and their final target, by call. */
+    far_calls: BTreeMap<Address, Address>,
+    /* Inline arguments and trampolines of the functions seen so far. */
+    inline_args: BTreeMap<Address, Option<InlineArgs>>,
+    trampolines: BTreeMap<Address, Option<Trampoline>>,
+    /* Bytes of jump tables and arguments, never decoded as code. */
+    data: BTreeSet<Address>,
+    warnings: Vec<Warning>,
+    worklist: Vec<Address>,
+}
+
+impl<'a> Builder<'a> {
+    /* Decodes straight-line code from `start` until flow leaves it. */
+    fn trace(&mut self, start: Address) {
+        let mut pc = start;
+
+        while !self.code.contains_key(&pc) && !self.data.contains(&pc) {
+            if self
+                .project
+                .type_at(pc)
+                .is_some_and(|range| range.entry().is_none())
+            {
+                self.warnings.push(Warning::FlowIntoData { address: pc });
+                break;
+            }
+            let inst = match Disassembler::decode_at(self.bytes, pc.to_offset()) {
+                Ok(inst) => inst,
+                Err(warning) => {
+                    self.warnings.push(warning);
+                    break;
+                }
+            };
+            let mut next = Address::new(pc.bank(), pc.addr().wrapping_add(inst.size() as u16));
+            let flow = inst.flow(pc.addr());
+            let rst = *inst.mnemonic() == Mnemonic::RST;
+            self.code.insert(pc, inst);
+
+            match flow {
+                Flow::Next => {
+                    if let Some(table) = self.pointer_table(pc) {
+                        self.pointer_reads.push((pc, table));
+                    }
+                }
+                Flow::Jump(Some(target)) => {
+                    self.follow(pc, target);
+                    break;
+                }
+                Flow::Jump(None) => {
+                    if let Some(table) = self.hl_table(pc) {
+                        self.add_jump_table(pc, table);
+                    } else if let Some(&target) = self.resolved.get(&pc) {
+                        self.leaders.insert(target);
+                        self.worklist.push(target);
+                    }
+                    break;
+                }
+                Flow::Return => break,
+                Flow::Branch(target) => {
+                    self.follow(pc, target);
+                    self.leaders.insert(next);
+                }
+                Flow::Call(target) => {
+                    let callee = self.follow(pc, target);
+                    if rst && self.table_vectors.contains(&target) {
+                        self.add_jump_table(pc, next);
+                        break;
+                    }
+                    if let Some(callee) = callee {
+                        self.function_entries.insert(callee);
+                        if let Some(arguments) = self.call_arguments(pc, next, callee) {
+                            next = arguments.end();
+                            self.leaders.insert(next);
+                            self.arguments.insert(pc, arguments);
+                        }
+                        if let Some(target) = self.far_call(pc, callee) {
+                            self.leaders.insert(target);
+                            self.function_entries.insert(target);
+                            self.worklist.push(target);
+                            self.far_calls.insert(pc, target);
+                        }
+                    }
+                }
+                Flow::ConditionalReturn => {
+                    self.leaders.insert(next);
+                }
+            }
+
+            if ControlFlowGraph::resolve(self.bytes, pc, next.addr()) != Some(next) {
+                break;
+            }
+            pc = next;
+        }
+    }
+
+    /* Queues the code at `target`, reached from the instruction at `pc`. */
+    fn follow(&mut self, pc: Address, target: u16) -> Option<Address> {
+        let target = match self.resolved.get(&pc) {
+            Some(&target) => target,
+            None => ControlFlowGraph::target(self.bytes, self.project, pc, target)?,
+        };
+        self.leaders.insert(target);
+        self.worklist.push(target);
+
+        Some(target)
+    }
+
+    fn add_jump_table(&mut self, dispatch: Address, start: Address) {
+        let (targets, end) = match self.table_targets(dispatch, start, true) {
+            Some(table) => table,
+            None => return,
+        };
+
+        for addr in start.addr()..=end.addr() {
+            self.data.insert(Address::new(start.bank(), addr));
+        }
+        for &(_, target) in &targets {
+            self.leaders.insert(target);
+            self.worklist.push(target);
+        }
+        self.jump_tables.insert(
+            dispatch,
+            JumpTable {
+                dispatch,
+                start,
+                end,
+                targets,
+            },
+        );
+    }
+
+    /* Pointers read by the code at `read` from the table at `start`, unless
+     * that table is a jump table. They point to functions when the code
+     * reading them calls a `jp hl`. */
+    fn add_pointer_table(&mut self, read: Address, start: Address) {
+        if self.code.contains_key(&start)
+            || self.data.contains(&start)
+            || self.jump_tables.values().any(|table| table.start == start)
+        {
+            return;
+        }
+
+        let code = self.calls_pointer(read);
+        let (targets, end) = match self.table_targets(read, start, code) {
+            Some(table) => table,
+            None => return,
+        };
+
+        for addr in start.addr()..=end.addr() {
+            self.data.insert(Address::new(start.bank(), addr));
+        }
+        if code {
+            for &(_, target) in &targets {
+                self.leaders.insert(target);
+                self.function_entries.insert(target);
+                self.worklist.push(target);
+            }
+        }
+        self.pointer_tables.insert(
+            read,
+            PointerTable {
+                read,
+                start,
+                end,
+                targets,
+                code,
+            },
+        );
+    }
+
+    /* Whether the pointer read at `read` is called: straight-line code after
+     * it calls a function starting with `jp hl`. */
+    fn calls_pointer(&self, read: Address) -> bool {
+        let mut pc = read;
+
+        for _ in 0..8 {
+            let inst = match self.code.get(&pc) {
+                Some(inst) => inst,
+                None => return false,
+            };
+            match inst.flow(pc.addr()) {
+                Flow::Next => {}
+                Flow::Call(target) => {
+                    return ControlFlowGraph::target(self.bytes, self.project, pc, target)
+                        .and_then(|callee| self.code.get(&callee))
+                        .is_some_and(|inst| {
+                            *inst.mnemonic() == Mnemonic::JP
+                                && inst.lhs() == Some(&Operand::DerefReg(Register::HL))
+                        })
+                }
+                _ => return false,
+            }
+            pc = Address::new(pc.bank(), pc.addr().wrapping_add(inst.size() as u16));
+        }
+
+        false
+    }
+
+    /* Entries of the table at `start` and its last byte. A pointer range of
+     * the project gives its exact extent, words in it that point nowhere are
+     * skipped. Otherwise the table ends before known code or data, the first
+     * target after it, or the first word that does not point to an
+     * instruction (to anything but code for data pointers). */
+    fn table_targets(
+        &self,
+        dispatch: Address,
+        start: Address,
+        code: bool,
+    ) -> Option<(Vec<(Address, Address)>, Address)> {
+        let declared = self
+            .project
+            .type_at(start)
+            .filter(|range| range.start() == start && range.data_type() == &DataType::Pointers)
+            .map(TypedRange::end);
+        let bank_end = std::cmp::min(
+            (start.to_offset() / BANK_SIZE + 1) * BANK_SIZE,
+            self.bytes.len(),
+        );
+        let entries = match declared {
+            Some(end) => (end.addr() - start.addr() + 1) as usize / 2,
+            None => MAX_TABLE_ENTRIES,
+        };
+
+        let mut targets = Vec::new();
+        let mut end = bank_end;
+        for i in 0..entries {
+            let entry = Address::new(start.bank(), start.addr().wrapping_add(2 * i as u16));
+            let offset = entry.to_offset();
+            if offset + 2 > end
+                || (declared.is_none()
+                    && (self.code.contains_key(&entry) || self.data.contains(&entry)))
+            {
+                break;
+            }
+
+            let word = u16::from_le_bytes([self.bytes[offset], self.bytes[offset + 1]]);
+            let target = match ControlFlowGraph::target(self.bytes, self.project, dispatch, word) {
+                Some(target) => target,
+                None if declared.is_some() => continue,
+                None => break,
+            };
+            if declared.is_none() {
+                let valid = if code {
+                    Disassembler::decode_at(self.bytes, target.to_offset()).is_ok()
+                } else {
+                    !self.code.contains_key(&target)
+                };
+                /* Nothing points into the table itself. */
+                if !valid || (target >= start && target <= entry) {
+                    break;
+                }
+                if target.bank() == start.bank() && target > entry {
+                    end = std::cmp::min(end, target.to_offset());
+                }
+            }
+            targets.push((entry, target));
+        }
+
+        let &(last, _) = targets.last()?;
+        let end = declared.unwrap_or_else(|| Address::new(last.bank(), last.addr() + 1));
+        Some((targets, end))
+    }
+
+    /* Table indexed by the `jp hl` at `pc`: the address loaded in HL, or
+     * added to it, before the pointer is read through HL. */
+    fn hl_table(&self, pc: Address) -> Option<Address> {
+        let mut read = false;
+        let mut added: Option<Register> = None;
+
+        for inst in self.preceding(pc) {
+            match (inst.mnemonic(), inst.lhs(), inst.rhs()) {
+                (Mnemonic::LD, Some(Operand::Reg(_)), Some(Operand::DerefReg(Register::HL)))
+                | (Mnemonic::LDIR, _, _)
+                | (Mnemonic::LDDR, _, _) => read = true,
+                (Mnemonic::ADD, Some(Operand::Reg(Register::HL)), Some(Operand::Reg(reg)))
+                    if read && *reg != Register::HL =>
+                {
+                    added = Some(*reg)
+                }
+                (Mnemonic::LD, Some(Operand::Reg(reg)), Some(Operand::Imm16(table)))
+                    if read && (*reg == Register::HL || Some(*reg) == added) =>
+                {
+                    return ControlFlowGraph::target(self.bytes, self.project, pc, *table);
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    /* Table indexed by the code leading to `pc` when the instruction at `pc`
+     * reads the high byte of a pointer: `ld hl, Table` or `ld de, Table`,
+     * `add hl, de`, then `ld a, [hl+]` or `ld a, [hl]` / `inc hl` before
+     * `ld h, [hl]`. */
+    fn pointer_table(&self, pc: Address) -> Option<Address> {
+        let reads = |inst: &Instruction| {
+            *inst.mnemonic() == Mnemonic::LD
+                && matches!(inst.lhs(), Some(Operand::Reg(_)))
+                && inst.rhs() == Some(&Operand::DerefReg(Register::HL))
+        };
+        if !reads(self.code.get(&pc)?) && *self.code[&pc].mnemonic() != Mnemonic::LDIR {
+            return None;
+        }
+
+        let mut preceding = self.preceding(pc).into_iter();
+        let low = preceding.next()?;
+        match (low.mnemonic(), low.lhs()) {
+            (Mnemonic::LDIR, _) => {}
+            (Mnemonic::INC, Some(Operand::Reg(Register::HL))) if reads(preceding.next()?) => {}
+            _ => return None,
+        }
+
+        let mut added: Option<Register> = None;
+        for inst in preceding {
+            match (inst.mnemonic(), inst.lhs(), inst.rhs()) {
+                (Mnemonic::ADD, Some(Operand::Reg(Register::HL)), Some(Operand::Reg(reg)))
+                    if added.is_none() && *reg != Register::HL =>
+                {
+                    added = Some(*reg)
+                }
+                (Mnemonic::LD, Some(Operand::Reg(reg)), Some(Operand::Imm16(table)))
+                    if added.is_some() && (*reg == Register::HL || Some(*reg) == added) =>
+                {
+                    return ControlFlowGraph::target(self.bytes, self.project, pc, *table);
+                }
+                /* HL changed between the indexing and the read. */
+                _ if added.is_none() && inst.writes(Register::HL) => return None,
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    /* Straight-line code leading to `pc`, nearest first. */
+    fn preceding(&self, pc: Address) -> Vec<&Instruction> {
+        let mut instructions = Vec::new();
+        let mut expected = pc;
+
+        for (&addr, inst) in self.code.range(..pc).rev().take(16) {
+            if addr.bank() != pc.bank()
+                || addr.addr().wrapping_add(inst.size() as u16) != expected.addr()
+                || matches!(inst.flow(addr.addr()), Flow::Jump(_) | Flow::Return)
+            {
+                break;
+            }
+            expected = addr;
+            instructions.push(inst);
+        }
+
+        instructions
+    }
+
+    /* Constant loaded in `reg` by the code leading to `pc`. */
+    fn register_value(&self, pc: Address, reg: Register) -> Option<u16> {
+        for inst in self.preceding(pc) {
+            if !inst.writes(reg) {
+                continue;
+            }
+
+            return match (inst.mnemonic(), inst.lhs(), inst.rhs()) {
+                (Mnemonic::LD, Some(Operand::Reg(dest)), Some(Operand::Imm8(value)))
+                    if *dest == reg =>
+                {
+                    Some(*value as u16)
+                }
+                (Mnemonic::LD, Some(Operand::Reg(dest)), Some(Operand::Imm16(value))) => {
+                    match (dest, reg) {
+                        (dest, reg) if *dest == reg => Some(*value),
+                        (Register::BC, Register::B)
+                        | (Register::DE, Register::D)
+                        | (Register::HL, Register::H) => Some(value >> 8),
+                        (Register::BC, Register::C)
+                        | (Register::DE, Register::E)
+                        | (Register::HL, Register::L) => Some(value & 0xFF),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+        }
+
+        None
+    }
+
+    /* Target of the call at `pc` to the trampoline `callee`, from the
+     * registers loaded before the call or its inline arguments. */
+    fn far_call(&mut self, pc: Address, callee: Address) -> Option<Address> {
+        let trampoline = match self.project.get_trampolines().get(&callee) {
+            Some(&trampoline) => trampoline,
+            None => match self.trampolines.get(&callee) {
+                Some(&trampoline) => trampoline?,
+                None => {
+                    let trampoline = self.detect_trampoline(callee);
+                    self.trampolines.insert(callee, trampoline);
+                    trampoline?
+                }
+            },
+        };
+
+        let (bank, addr) = match trampoline {
+            Trampoline::Inline => {
+                let arguments = self.arguments.get(&pc).filter(|args| args.size >= 3)?;
+                let offset = arguments.start.to_offset();
+                (
+                    self.bytes[offset] as u16,
+                    u16::from_le_bytes([self.bytes[offset + 1], self.bytes[offset + 2]]),
+                )
+            }
+            Trampoline::Registers(bank, addr) => (
+                self.register_value(pc, bank)?,
+                self.register_value(pc, addr)?,
+            ),
+        };
+
+        let target = match addr {
+            0x0000..=0x3FFF => Address::new(0, addr),
+            0x4000..=0x7FFF if bank != 0 => Address::new(bank, addr),
+            _ => return None,
+        };
+        Some(target).filter(|target| target.to_offset() < self.bytes.len())
+    }
+
+    /* Recognizes functions that write a bank number to the bank controller
+     * and then jump to an address: `Inline` when they read three bytes after
+     * their call, `Registers` when the bank comes from a register at entry
+     * (possibly through a temporary variable) and the address from HL. */
+    fn detect_trampoline(&self, entry: Address) -> Option<Trampoline> {
+        let mut pc = entry;
+        /* Register whose entry value is in A, None when A was computed. */
+        let mut a: Option<Register> = Some(Register::A);
+        let mut saved: BTreeMap<u16, Option<Register>> = BTreeMap::new();
+        let mut bank: Option<Option<Register>> = None;
+        let mut jumps_hl = false;
+
+        for _ in 0..MAX_SCANNED_INSTRUCTIONS {
+            let inst = Disassembler::decode_at(self.bytes, pc.to_offset()).ok()?;
+            let flow = inst.flow(pc.addr());
+            let store = |operand: Option<&Operand>| match operand {
+                Some(Operand::DerefAddr16(addr)) => Some(*addr),
+                Some(Operand::DerefAddr8(addr)) => Some(0xFF00 | *addr as u16),
+                _ => None,
+            };
+
+            match (inst.mnemonic(), inst.lhs(), inst.rhs()) {
+                (Mnemonic::LD, Some(Operand::Reg(Register::A)), Some(Operand::Reg(reg))) => {
+                    a = a.and(Some(*reg))
+                }
+                (Mnemonic::LD, lhs, Some(Operand::Reg(Register::A)))
+                | (Mnemonic::LDHL, lhs, Some(Operand::Reg(Register::A)))
+                    if store(lhs).is_some() =>
+                {
+                    match store(lhs).unwrap() {
+                        0x2000..=0x3FFF if bank.is_none() => bank = Some(a),
+                        addr => {
+                            saved.insert(addr, a);
+                        }
+                    }
+                }
+                (Mnemonic::LD, Some(Operand::Reg(Register::A)), rhs)
+                | (Mnemonic::LDHR, Some(Operand::Reg(Register::A)), rhs)
+                    if store(rhs).is_some() =>
+                {
+                    a = saved.get(&store(rhs).unwrap()).copied().flatten()
+                }
+                (Mnemonic::JP, Some(Operand::DerefReg(Register::HL)), _) => {
+                    jumps_hl = true;
+                    break;
+                }
+                _ if inst.writes(Register::A) => a = None,
+                _ => {}
+            }
+
+            pc = match flow {
+                Flow::Next | Flow::ConditionalReturn | Flow::Branch(_) => {
+                    Address::new(pc.bank(), pc.addr().wrapping_add(inst.size() as u16))
+                }
+                /* Calls to a lone `jp hl` are jumps through HL. */
+                Flow::Call(target) => {
+                    let callee = ControlFlowGraph::target(self.bytes, self.project, pc, target)?;
+                    if let Ok(inst) = Disassembler::decode_at(self.bytes, callee.to_offset()) {
+                        if *inst.mnemonic() == Mnemonic::JP
+                            && inst.lhs() == Some(&Operand::DerefReg(Register::HL))
+                        {
+                            jumps_hl = true;
+                            break;
+                        }
+                    }
+                    Address::new(pc.bank(), pc.addr().wrapping_add(inst.size() as u16))
+                }
+                Flow::Jump(Some(target)) => {
+                    ControlFlowGraph::target(self.bytes, self.project, pc, target)?
+                }
+                Flow::Jump(None) | Flow::Return => break,
+            };
+        }
+
+        let bank = bank?;
+        if self.detect_inline_args(entry) == Some(InlineArgs::Bytes(3)) {
+            Some(Trampoline::Inline)
+        } else {
+            match bank {
+                Some(bank) if jumps_hl && bank != Register::HL => {
+                    Some(Trampoline::Registers(bank, Register::HL))
+                }
+                _ => None,
+            }
+        }
+    }
+
+    /* Arguments after the call at `pc` to `callee`, when it takes some. */
+    fn call_arguments(
+        &mut self,
+        pc: Address,
+        start: Address,
+        callee: Address,
+    ) -> Option<CallArguments> {
+        let declared = match self.project.get_trampolines().get(&callee) {
+            Some(Trampoline::Inline) => Some(InlineArgs::Bytes(3)),
+            _ => self.project.get_inline_args().get(&callee).cloned(),
+        };
+        let kind = match declared {
+            Some(kind) => kind,
+            None => match self.inline_args.get(&callee) {
+                Some(kind) => kind.clone()?,
+                None => {
+                    let kind = self.detect_inline_args(callee);
+                    self.inline_args.insert(callee, kind.clone());
+                    kind?
+                }
+            },
+        };
+
+        let bank_end = std::cmp::min(
+            (start.to_offset() / BANK_SIZE + 1) * BANK_SIZE,
+            self.bytes.len(),
+        );
+        let available = bank_end.checked_sub(start.to_offset())?;
+        let size = match kind {
+            InlineArgs::Bytes(size) => size,
+            InlineArgs::String(terminator) => {
+                self.bytes[start.to_offset()..bank_end]
+                    .iter()
+                    .position(|&byte| byte == terminator)?
+                    + 1
+            }
+        };
+        if size > available {
+            return None;
+        }
+
+        for i in 0..size as u16 {
+            self.data
+                .insert(Address::new(start.bank(), start.addr() + i));
+        }
+
+        Some(CallArguments {
+            call: pc,
+            start,
+            size,
+            kind,
+        })
+    }
+
+    /* Recognizes functions that pop their return address into HL, read
+     * through it and return past what they read: the count of bytes when the
+     * code is straight, a string ending with the value compared in a loop
+     * otherwise. */
+    fn detect_inline_args(&self, entry: Address) -> Option<InlineArgs> {
+        let mut pc = entry;
+        let mut popped = false;
+        let mut count: i32 = 0;
+        let mut looped = false;
+        let mut terminator: Option<u8> = None;
+
+        for i in 0..MAX_SCANNED_INSTRUCTIONS {
+            let inst = Disassembler::decode_at(self.bytes, pc.to_offset()).ok()?;
+            let flow = inst.flow(pc.addr());
+
+            if !popped {
+                /* The pop comes first, after a few unrelated instructions. */
+                match (inst.mnemonic(), inst.lhs()) {
+                    (Mnemonic::POP, Some(Operand::Reg(Register::HL))) => popped = true,
+                    (Mnemonic::PUSH, _) | (Mnemonic::POP, _) => return None,
+                    _ if i >= 4 || !matches!(flow, Flow::Next | Flow::Jump(Some(_))) => {
+                        return None
+                    }
+                    _ => {}
+                }
+            } else {
+                match (inst.mnemonic(), inst.lhs(), inst.rhs()) {
+                    (Mnemonic::LDIR, _, _) | (Mnemonic::LDIL, _, _) => count += 1,
+                    (Mnemonic::LDDR, _, _) | (Mnemonic::LDDL, _, _) => count -= 1,
+                    (Mnemonic::INC, Some(Operand::Reg(Register::HL)), _) => count += 1,
+                    (Mnemonic::DEC, Some(Operand::Reg(Register::HL)), _) => count -= 1,
+                    /* The return address is back on the stack, the rest of
+                     * the function may reuse HL. */
+                    (Mnemonic::PUSH, Some(Operand::Reg(Register::HL)), _) => break,
+                    (Mnemonic::PUSH, _, _) => {}
+                    /* HL no longer holds the return address. */
+                    (Mnemonic::POP, _, _)
+                    | (_, Some(Operand::Reg(Register::HL)), _)
+                    | (_, Some(Operand::Reg(Register::H)), _)
+                    | (_, Some(Operand::Reg(Register::L)), _) => return None,
+                    (Mnemonic::CP, Some(Operand::Imm8(value)), _) => terminator = Some(*value),
+                    (Mnemonic::AND, Some(Operand::Reg(Register::A)), _)
+                    | (Mnemonic::OR, Some(Operand::Reg(Register::A)), _) => {
+                        terminator = terminator.or(Some(0))
+                    }
+                    (Mnemonic::JP, Some(Operand::DerefReg(Register::HL)), _) => break,
+                    _ => {}
+                }
+            }
+
+            pc = match flow {
+                Flow::Next | Flow::Call(_) | Flow::ConditionalReturn => {
+                    Address::new(pc.bank(), pc.addr().wrapping_add(inst.size() as u16))
+                }
+                Flow::Branch(target) => {
+                    looped |= target <= pc.addr();
+                    Address::new(pc.bank(), pc.addr().wrapping_add(inst.size() as u16))
+                }
+                Flow::Jump(Some(target)) => {
+                    ControlFlowGraph::target(self.bytes, self.project, pc, target)?
+                }
+                Flow::Jump(None) | Flow::Return => return None,
+            };
+            if i + 1 == MAX_SCANNED_INSTRUCTIONS {
+                return None;
+            }
+        }
+
+        match (looped, count) {
+            (true, _) => Some(InlineArgs::String(terminator.unwrap_or(0))),
+            (false, count) if count > 0 => Some(InlineArgs::Bytes(count as usize)),
+            _ => None,
+        }
+    }
+
+    /* The vectors listed in the project, or else the RST routines that pop
+     * their return address into HL, add an index to it and jump through it. */
+    fn table_vectors(bytes: &[u8], project: &Project) -> BTreeSet<u16> {
+        if !project.get_table_rsts().is_empty() {
+            return project.get_table_rsts().iter().map(|&v| v as u16).collect();
+        }
+
+        (0x00..=0x38)
+            .step_by(8)
+            .filter(|&vector| Builder::reads_inline_table(bytes, vector))
+            .collect()
+    }
+
+    fn reads_inline_table(bytes: &[u8], vector: u16) -> bool {
+        let mut pc = vector;
+        let mut popped = false;
+        let mut indexed = false;
+
+        for _ in 0..24 {
+            /* ROMs this small have no room for the routine. */
+            if pc as usize >= bytes.len() || pc as usize >= BANK_SIZE {
+                return false;
+            }
+            let inst = match Disassembler::decode_at(bytes, pc as usize) {
+                Ok(inst) => inst,
+                Err(_) => return false,
+            };
+
+            match (inst.mnemonic(), inst.lhs()) {
+                (Mnemonic::POP, Some(Operand::Reg(Register::HL))) => popped = true,
+                (Mnemonic::ADD, Some(Operand::Reg(Register::HL))) => indexed = popped,
+                (Mnemonic::JP, Some(Operand::DerefReg(Register::HL))) => return indexed,
+                _ => {}
+            }
+            pc = match inst.flow(pc) {
+                Flow::Next | Flow::Call(_) => pc.wrapping_add(inst.size() as u16),
+                Flow::Jump(Some(target)) if target < BANK_SIZE as u16 => target,
+                _ => return false,
+            };
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flow_into_data() {
+        let mut bytes = vec![0x00; 0x8000];
+        bytes[0x0200..0x0203].copy_from_slice(&[0xC3, 0x00, 0x03]); /* jp $0300 */
+        bytes[0x0300..0x0302].copy_from_slice(&[0x18, 0xFE]); /* jr @ */
+
+        let mut project = Project::new(&bytes);
+        let range = |start: u16, end: u16, data_type| {
+            TypedRange::new(Address::new(0, start), Address::new(0, end), data_type)
+        };
+        project
+            .add_type(range(0x0104, 0x01FF, DataType::Bytes))
+            .unwrap();
+        project
+            .add_type(range(0x0200, 0x02FF, DataType::Code(None)))
+            .unwrap();
+        project
+            .add_type(range(0x0300, 0x03FF, DataType::Code(None)))
+            .unwrap();
+
+        let entries = [Address::new(0, 0x0100), Address::new(0, 0x0200)];
+        let cfg = ControlFlowGraph::build(&bytes, &entries, &project);
+        match cfg.get_warnings()[..] {
+            [Warning::FlowIntoData { address }] => assert_eq!(address, Address::new(0, 0x0104)),
+            ref warnings => panic!("unexpected warnings {:?}", warnings),
+        }
+        /* Code ranges are followed. */
+        assert!(cfg.get_blocks().contains_key(&Address::new(0, 0x0300)));
+    }
+
+    #[test]
+    fn rst_dispatcher() {
+        let mut bytes = vec![0x00; 0x8000];
+        let dispatcher = [
+            0x87, /* add a */
+            0xE1, /* pop hl */
+            0x5F, /* ld e, a */
+            0x16, 0x00, /* ld d, 0 */
+            0x19, /* add hl, de */
+            0x2A, /* ld a, [hl+] */
+            0x66, /* ld h, [hl] */
+            0x6F, /* ld l, a */
+            0xE9, /* jp hl */
+        ];
+        bytes[0x0028..0x0028 + dispatcher.len()].copy_from_slice(&dispatcher);
+        let code = [
+            0xEF, /* rst $28 */
+            0x50, 0x01, /* dw $0150 */
+            0x60, 0x01, /* dw $0160 */
+            0x00, 0xC0, /* dw $C000, ends the table */
+        ];
+        bytes[0x0100..0x0100 + code.len()].copy_from_slice(&code);
+        bytes[0x0150..0x0152].copy_from_slice(&[0x18, 0xFE]);
+        bytes[0x0160..0x0162].copy_from_slice(&[0x18, 0xFE]);
+
+        let entry = Address::new(0, 0x0100);
+        let cfg = ControlFlowGraph::build(&bytes, &[entry], &Project::new(&bytes));
+        let table = &cfg.get_jump_tables()[&entry];
+        assert_eq!(table.start(), Address::new(0, 0x0101));
+        assert_eq!(table.end(), Address::new(0, 0x0104));
+        assert_eq!(
+            table.get_targets(),
+            &[
+                (Address::new(0, 0x0101), Address::new(0, 0x0150)),
+                (Address::new(0, 0x0103), Address::new(0, 0x0160)),
+            ]
+        );
+        assert!(cfg.get_blocks().contains_key(&Address::new(0, 0x0160)));
+        /* The table is not decoded as code. */
+        assert!(!cfg.get_blocks().contains_key(&Address::new(0, 0x0101)));
+    }
+
+    #[test]
+    fn hl_table() {
+        let mut bytes = vec![0x00; 0x8000];
+        let code = [
+            0x5F, /* ld e, a */
+            0x16, 0x00, /* ld d, 0 */
+            0x21, 0x00, 0x02, /* ld hl, Table */
+            0x19, /* add hl, de */
+            0x19, /* add hl, de */
+            0x2A, /* ld a, [hl+] */
+            0x66, /* ld h, [hl] */
+            0x6F, /* ld l, a */
+            0xE9, /* jp hl */
+        ];
+        bytes[0x0100..0x0100 + code.len()].copy_from_slice(&code);
+        bytes[0x0200..0x0206].copy_from_slice(&[0x50, 0x01, 0x60, 0x01, 0x00, 0xC0]);
+        bytes[0x0150..0x0152].copy_from_slice(&[0x18, 0xFE]);
+        bytes[0x0160..0x0162].copy_from_slice(&[0x18, 0xFE]);
+
+        let cfg =
+            ControlFlowGraph::build(&bytes, &[Address::new(0, 0x0100)], &Project::new(&bytes));
+        let table = &cfg.get_jump_tables()[&Address::new(0, 0x010B)];
+        assert_eq!(table.start(), Address::new(0, 0x0200));
+        assert_eq!(table.get_targets().len(), 2);
+        assert!(cfg.get_blocks().contains_key(&Address::new(0, 0x0150)));
+    }
+
+    #[test]
+    fn declared_table_with_holes() {
+        let mut bytes = vec![0x00; 0x8000];
+        let code = [
+            0xEF, /* rst $28 */
+            0x50, 0x01, /* dw $0150 */
+            0x00, 0xC0, /* dw $C000, not code */
+            0x60, 0x01, /* dw $0160 */
+        ];
+        bytes[0x0100..0x0100 + code.len()].copy_from_slice(&code);
+        bytes[0x0150..0x0152].copy_from_slice(&[0x18, 0xFE]);
+        bytes[0x0160..0x0162].copy_from_slice(&[0x18, 0xFE]);
+
+        let mut project = Project::new(&bytes);
+        project.add_table_rst(0x28);
+        let range = TypedRange::new(
+            Address::new(0, 0x0101),
+            Address::new(0, 0x0106),
+            DataType::Pointers,
+        );
+        project.add_type(range).unwrap();
+
+        let entry = Address::new(0, 0x0100);
+        let cfg = ControlFlowGraph::build(&bytes, &[entry], &project);
+        let table = &cfg.get_jump_tables()[&entry];
+        assert_eq!(table.start(), Address::new(0, 0x0101));
+        assert_eq!(table.end(), Address::new(0, 0x0106));
+        assert_eq!(
+            table.get_targets(),
+            &[
+                (Address::new(0, 0x0101), Address::new(0, 0x0150)),
+                (Address::new(0, 0x0105), Address::new(0, 0x0160)),
+            ]
+        );
+        assert!(cfg.get_blocks().contains_key(&Address::new(0, 0x0160)));
+    }
+
+    #[test]
+    fn guessed_table_end() {
+        let mut bytes = vec![0x00; 0x8000];
+        let code = [
+            0xEF, /* rst $28 */
+            0x50, 0x01, /* dw $0150 */
+            0x60, 0x01, /* dw $0160 */
+            0x00, 0xC0, /* dw $C000, ends the table */
+        ];
+        bytes[0x0100..0x0100 + code.len()].copy_from_slice(&code);
+        bytes[0x0150..0x0152].copy_from_slice(&[0x18, 0xFE]);
+        bytes[0x0160..0x0162].copy_from_slice(&[0x18, 0xFE]);
+
+        let mut project = Project::new(&bytes);
+        project.add_table_rst(0x28);
+
+        let entry = Address::new(0, 0x0100);
+        let cfg = ControlFlowGraph::build(&bytes, &[entry], &project);
+        let table = &cfg.get_jump_tables()[&entry];
+        assert_eq!(table.end(), Address::new(0, 0x0104));
+        assert_eq!(table.get_targets().len(), 2);
+    }
+
+    #[test]
+    fn tiny_rom() {
+        /* Shorter than the RST vectors. */
+        let bytes = [0xE1, 0x19, 0xC3];
+        let project = Project::new(&bytes);
+        let cfg = ControlFlowGraph::build(&bytes, &[Address::new(0, 0x0000)], &project);
+        assert!(cfg.get_jump_tables().is_empty());
+        assert_eq!(cfg.get_blocks().len(), 1);
+    }
+
+    #[test]
+    fn inline_arguments() {
+        let mut bytes = vec![0x00; 0x8000];
+        let code = [
+            0xCD, 0x00, 0x02, /* call ReadTwo */
+            0x12, 0x34, /* db $12, $34 */
+            0xCD, 0x10, 0x02, /* call ReadString */
+            0x48, 0x49, 0x00, /* db "HI", 0 */
+            0x18, 0xFE, /* jr @ */
+        ];
+        bytes[0x0100..0x0100 + code.len()].copy_from_slice(&code);
+        let read_two = [
+            0xE1, /* pop hl */
+            0x2A, /* ld a, [hl+] */
+            0x2A, /* ld a, [hl+] */
+            0xE9, /* jp hl */
+        ];
+        bytes[0x0200..0x0200 + read_two.len()].copy_from_slice(&read_two);
+        let read_string = [
+            0xE1, /* pop hl */
+            0x2A, /* .loop ld a, [hl+] */
+            0xA7, /* and a */
+            0x20, 0xFC, /* jr nz, .loop */
+            0xE9, /* jp hl */
+        ];
+        bytes[0x0210..0x0210 + read_string.len()].copy_from_slice(&read_string);
+
+        let cfg =
+            ControlFlowGraph::build(&bytes, &[Address::new(0, 0x0100)], &Project::new(&bytes));
+        let arguments: Vec<(Address, usize, &InlineArgs)> = cfg
+            .get_arguments()
+            .values()
+            .map(|arguments| (arguments.start(), arguments.size(), arguments.kind()))
+            .collect();
+        assert_eq!(
+            arguments,
+            [
+                (Address::new(0, 0x0103), 2, &InlineArgs::Bytes(2)),
+                (Address::new(0, 0x0108), 3, &InlineArgs::String(0)),
+            ]
+        );
+        assert!(cfg.get_blocks().contains_key(&Address::new(0, 0x0105)));
+        assert!(cfg.get_blocks().contains_key(&Address::new(0, 0x010B)));
+        assert!(cfg
+            .get_blocks()
+            .values()
+            .flat_map(BasicBlock::get_instructions)
+            .all(|(pc, _)| !matches!(pc.addr(), 0x0103..=0x0104 | 0x0108..=0x010A)));
+    }
+
+    #[test]
+    fn far_calls() {
+        let mut bytes = vec![0x00; 0x10000];
+        let code = [
+            0x3E, 0x02, /* ld a, BANK(Far) */
+            0x21, 0x00, 0x41, /* ld hl, Far */
+            0xCD, 0x00, 0x03, /* call Bankswitch */
+            0xCD, 0x10, 0x03, /* call FarCall */
+            0x03, 0x00, 0x42, /* db BANK(Other) dw Other */
+            0x18, 0xFE, /* jr @ */
+        ];
+        bytes[0x0100..0x0100 + code.len()].copy_from_slice(&code);
+        let bankswitch = [
+            0xEA, 0x00, 0x20, /* ld [$2000], a */
+            0xE9, /* jp hl */
+        ];
+        bytes[0x0300..0x0300 + bankswitch.len()].copy_from_slice(&bankswitch);
+        bytes[0x0310] = 0xC9;
+        bytes[0x8100] = 0xC9; /* 02:4100 */
+        bytes[0xC200] = 0xC9; /* 03:4200 */
+
+        let mut project = Project::new(&bytes);
+        project.set_trampoline(Address::new(0, 0x0310), Trampoline::Inline);
+
+        let cfg = ControlFlowGraph::build(&bytes, &[Address::new(0, 0x0100)], &project);
+        let far_calls: Vec<(Address, Address)> = cfg
+            .get_far_calls()
+            .iter()
+            .map(|(&call, &target)| (call, target))
+            .collect();
+        assert_eq!(
+            far_calls,
+            [
+                (Address::new(0, 0x0105), Address::new(2, 0x4100)),
+                (Address::new(0, 0x0108), Address::new(3, 0x4200)),
+            ]
+        );
+        assert!(cfg.get_functions().contains_key(&Address::new(2, 0x4100)));
+        assert!(cfg.get_functions().contains_key(&Address::new(3, 0x4200)));
+        assert!(cfg.get_blocks().contains_key(&Address::new(0, 0x010E)));
+    }
+
+    #[test]
+    fn pointer_tables() {
+        let mut bytes = vec![0x00; 0x8000];
+        let read = [
+            0x21, 0x00, 0x02, /* ld hl, Data */
+            0x19, /* add hl, de */
+            0x2A, /* ld a, [hl+] */
+            0x66, /* ld h, [hl] */
+            0x6F, /* ld l, a */
+            0xC9, /* ret */
+        ];
+        bytes[0x0100..0x0100 + read.len()].copy_from_slice(&read);
+        let call = [
+            0x21, 0x40, 0x02, /* ld hl, Functions */
+            0x19, /* add hl, de */
+            0x2A, /* ld a, [hl+] */
+            0x66, /* ld h, [hl] */
+            0x6F, /* ld l, a */
+            0xCD, 0x50, 0x01, /* call JumpHL */
+            0xC9, /* ret */
+        ];
+        bytes[0x0120..0x0120 + call.len()].copy_from_slice(&call);
+        bytes[0x0150] = 0xE9; /* jp hl */
+        let tables = [
+            (0x0200, [0x10, 0x02, 0x18, 0x02, 0x00, 0xC0]),
+            (0x0240, [0x60, 0x02, 0x70, 0x02, 0x00, 0xC0]),
+        ];
+        for (start, words) in &tables {
+            bytes[*start..*start + words.len()].copy_from_slice(words);
+        }
+        bytes[0x0260] = 0xC9;
+        bytes[0x0270] = 0xC9;
+
+        let entries = [Address::new(0, 0x0100), Address::new(0, 0x0120)];
+        let cfg = ControlFlowGraph::build(&bytes, &entries, &Project::new(&bytes));
+
+        let data = &cfg.get_pointer_tables()[&Address::new(0, 0x0105)];
+        assert!(!data.code());
+        assert_eq!(data.start(), Address::new(0, 0x0200));
+        assert_eq!(data.end(), Address::new(0, 0x0203));
+        assert_eq!(
+            data.get_targets(),
+            &[
+                (Address::new(0, 0x0200), Address::new(0, 0x0210)),
+                (Address::new(0, 0x0202), Address::new(0, 0x0218)),
+            ]
+        );
+
+        let functions = &cfg.get_pointer_tables()[&Address::new(0, 0x0125)];
+        assert!(functions.code());
+        assert_eq!(functions.end(), Address::new(0, 0x0243));
+        assert!(cfg.get_functions().contains_key(&Address::new(0, 0x0260)));
+        assert!(cfg.get_functions().contains_key(&Address::new(0, 0x0270)));
+    }
+}
@@ -0,0 +1,1998 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::address::Address;
+use super::control_flow::{BasicBlock, ControlFlowGraph, EdgeKind};
+use super::hardware;
+use super::instruction::{Flow, Mnemonic, Operand, Register};
+use super::ir::{BinOp, Expr, Flag, Statement};
+use super::memory::Region;
+use super::project::Project;
+use super::symbols::SymbolTable;
+
+/* Rounds of the interprocedural analysis of parameters and clobbered
+ * registers, recursion aside they settle in a few. */
+const MAX_SUMMARY_ROUNDS: usize = 16;
+
+const FLAGS: [Flag; 4] = [Flag::Z, Flag::N, Flag::H, Flag::C];
+
+/* What a statement reads or writes: 8-bit registers and SP, flags, and
+ * memory as a whole. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Location {
+    Reg(Register),
+    Flag(Flag),
+    Memory,
+}
+
+/* A called routine, resolved to its bank when it can be. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Callee {
+    target: u16,
+    address: Option<Address>,
+}
+
+/* Effects of the instructions of a block, calls and conditional returns
+ * kept apart from the statements. */
+#[derive(Debug, Clone)]
+enum Step {
+    /* The statements of one instruction, run together. */
+    Effects(Vec<Statement>),
+    Call(Option<Expr>, Callee),
+    Return(Expr),
+    TailCall(Expr, Callee),
+    Push(Register),
+    Pop(Register),
+}
+
+/* How control leaves a block. */
+#[derive(Debug, Clone)]
+enum Exit {
+    Next(Address),
+    /* Condition, taken and not taken targets. */
+    Branch(Expr, Address, Address),
+    Return,
+    /* Jump to another function. */
+    TailCall(Callee),
+    Computed(Expr),
+    Table(Vec<Address>),
+    /* Flow analysis found no way out. */
+    Stop,
+}
+
+#[derive(Debug, Clone)]
+struct Body {
+    blocks: BTreeMap<Address, (Vec<Step>, Exit)>,
+    /* Registers pushed and popped again, which the function preserves. */
+    saved: BTreeSet<Register>,
+}
+
+/* Registers a function reads before writing them, the registers it may
+ * change, and the flags its callers read after calling it. */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Summary {
+    params: BTreeSet<Register>,
+    clobbers: BTreeSet<Register>,
+    flags: BTreeSet<Flag>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LoopKind {
+    Forever,
+    While(Expr),
+    DoWhile(Expr),
+}
+
+/* Structured pseudocode. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Label(Address),
+    Statement(Statement),
+    /* Statements that depend on each other's old values. */
+    Parallel(Vec<Statement>),
+    Call(Callee),
+    TailCall(Callee),
+    Push(Register),
+    Pop(Register),
+    Return,
+    If(Expr, Vec<Node>, Vec<Node>),
+    Loop(LoopKind, Vec<Node>),
+    Break,
+    Continue,
+    Goto(Address),
+    GotoExpr(Expr),
+    Switch(Vec<Address>),
+}
+
+/* A loop being structured, with the block after it. */
+struct LoopContext {
+    header: Address,
+    follow: Option<Address>,
+    body: BTreeSet<Address>,
+}
+
+/* Pseudocode of a function. */
+#[derive(Debug, Clone)]
+pub struct Pseudocode {
+    entry: Address,
+    params: Vec<Register>,
+    lines: Vec<String>,
+}
+
+impl Pseudocode {
+    pub fn entry(&self) -> Address {
+        self.entry
+    }
+
+    /* Registers the function reads before writing them, pairs whole. */
+    pub fn get_params(&self) -> &Vec<Register> {
+        &self.params
+    }
+
+    pub fn get_lines(&self) -> &Vec<String> {
+        &self.lines
+    }
+}
+
+impl std::fmt::Display for Pseudocode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{}", line)?;
+        }
+
+        Ok(())
+    }
+}
+
+/* Recovers `if`/`else` and loops from the control flow of each function and
+ * prints the lifted instructions as C-like pseudocode, with the flags nobody
+ * reads removed and the ones tested folded into the conditions. */
+pub struct Decompiler<'a> {
+    symbols: &'a SymbolTable,
+    hardware_names: bool,
+    bodies: BTreeMap<Address, Body>,
+    summaries: BTreeMap<Address, Summary>,
+}
+
+impl<'a> Decompiler<'a> {
+    pub fn new(
+        cfg: &ControlFlowGraph,
+        bytes: &[u8],
+        project: &Project,
+        symbols: &'a SymbolTable,
+    ) -> Decompiler<'a> {
+        let bodies = cfg
+            .get_functions()
+            .keys()
+            .map(|&entry| (entry, Decompiler::body(cfg, bytes, project, entry)))
+            .collect();
+        let mut decompiler = Decompiler {
+            symbols,
+            hardware_names: true,
+            bodies,
+            summaries: BTreeMap::new(),
+        };
+        decompiler.summarize();
+
+        decompiler
+    }
+
+    /* Print I/O registers by name, on by default. */
+    pub fn set_hardware_names(&mut self, enabled: bool) {
+        self.hardware_names = enabled;
+    }
+
+    /* Registers the function at `entry` reads before writing them. */
+    pub fn params(&self, entry: Address) -> Vec<Register> {
+        match self.summaries.get(&entry) {
+            Some(summary) => Decompiler::pairs(&summary.params),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn decompile(&self, entry: Address) -> Option<Pseudocode> {
+        let mut body = self.bodies.get(&entry)?.clone();
+        let returned = self.summaries[&entry].flags.clone();
+
+        for (steps, exit) in body.blocks.values_mut() {
+            Decompiler::propagate(steps, exit);
+        }
+        Decompiler::remove_dead_flags(&mut body, &returned);
+
+        let mut exits = BTreeMap::new();
+        let mut nodes = BTreeMap::new();
+        for (&start, (steps, exit)) in &body.blocks {
+            exits.insert(start, exit.clone());
+            nodes.insert(start, Decompiler::nodes(steps));
+        }
+        let mut structurer = Structurer::new(entry, exits, nodes);
+        let mut tree = structurer.region(entry, None, None);
+        /* Blocks only reached through gotos and jump tables. */
+        while let Some(&start) = structurer
+            .nodes
+            .keys()
+            .find(|start| !structurer.emitted.contains(start))
+        {
+            tree.extend(structurer.region(start, None, None));
+        }
+        let mut gotos = BTreeSet::new();
+        Decompiler::gotos(&tree, &mut gotos);
+        let mut tree = Decompiler::cleanup(tree, &gotos);
+        if tree.last() == Some(&Node::Return) {
+            tree.pop();
+        }
+
+        let mut labels = BTreeSet::new();
+        Decompiler::gotos(&tree, &mut labels);
+
+        let params = self.params(entry);
+        let names: Vec<&str> = params.iter().map(|&reg| register_name(reg)).collect();
+        let mut lines = vec![format!(
+            "void {}({}) {{",
+            self.function_name(entry),
+            names.join(", ")
+        )];
+        self.render(&tree, entry.bank(), 1, &labels, &mut lines);
+        lines.push("}".to_string());
+
+        Some(Pseudocode {
+            entry,
+            params,
+            lines,
+        })
+    }
+
+    /* Steps and exits of the blocks reachable from `entry` without entering
+     * another function. */
+    fn body(cfg: &ControlFlowGraph, bytes: &[u8], project: &Project, entry: Address) -> Body {
+        let mut blocks = BTreeMap::new();
+        let mut pushed = BTreeSet::new();
+        let mut popped = BTreeSet::new();
+        let mut worklist = vec![entry];
+
+        while let Some(start) = worklist.pop() {
+            if blocks.contains_key(&start) {
+                continue;
+            }
+            let block = match cfg.get_blocks().get(&start) {
+                Some(block) => block,
+                None => continue,
+            };
+            for (_, inst) in block.get_instructions() {
+                if let Some(Operand::Reg(reg)) = inst.lhs() {
+                    match inst.mnemonic() {
+                        Mnemonic::PUSH => pushed.insert(*reg),
+                        Mnemonic::POP => popped.insert(*reg),
+                        _ => false,
+                    };
+                }
+            }
+
+            let (steps, exit) = Decompiler::lift_block(cfg, bytes, project, block, entry);
+            match &exit {
+                Exit::Next(target) => worklist.push(*target),
+                Exit::Branch(_, taken, fall) => worklist.extend([*taken, *fall]),
+                Exit::Table(targets) => worklist.extend(targets),
+                _ => {}
+            }
+            blocks.insert(start, (steps, exit));
+        }
+
+        let saved = pushed
+            .intersection(&popped)
+            .flat_map(|&reg| byte_registers(reg))
+            .collect();
+        Body { blocks, saved }
+    }
+
+    fn lift_block(
+        cfg: &ControlFlowGraph,
+        bytes: &[u8],
+        project: &Project,
+        block: &BasicBlock,
+        entry: Address,
+    ) -> (Vec<Step>, Exit) {
+        let callee = |pc: Address, target: u16| {
+            let address = cfg
+                .get_far_calls()
+                .get(&pc)
+                .or_else(|| cfg.get_resolved().get(&pc))
+                .copied()
+                .or_else(|| ControlFlowGraph::target(bytes, project, pc, target));
+            Callee {
+                target: address.map_or(target, |address| address.addr()),
+                address,
+            }
+        };
+        let condition = |statements: &[Statement]| {
+            statements.iter().find_map(|statement| match statement {
+                Statement::If(cond, _) => Some(simplify(cond)),
+                _ => None,
+            })
+        };
+        /* Jumping to the start of another function is calling it. */
+        let edge = |target: Address| {
+            if target != entry && cfg.get_functions().contains_key(&target) {
+                Exit::TailCall(Callee {
+                    target: target.addr(),
+                    address: Some(target),
+                })
+            } else {
+                Exit::Next(target)
+            }
+        };
+        let successor = |kind: EdgeKind| {
+            block
+                .get_successors()
+                .iter()
+                .find(|edge| edge.kind() == kind)
+                .map(|edge| edge.target())
+        };
+
+        let mut steps = Vec::new();
+        let mut last = Flow::Next;
+        let mut jump = None;
+        for (pc, inst) in block.get_instructions() {
+            let statements: Vec<Statement> = Statement::lift(inst, pc.addr())
+                .iter()
+                .map(simplify_statement)
+                .collect();
+            last = inst.flow(pc.addr());
+            match (last, inst.mnemonic(), inst.lhs()) {
+                (Flow::Next, Mnemonic::PUSH, Some(Operand::Reg(reg))) => {
+                    steps.push(Step::Push(*reg))
+                }
+                (Flow::Next, Mnemonic::POP, Some(Operand::Reg(reg))) => steps.push(Step::Pop(*reg)),
+                (Flow::Next, _, _) => {
+                    let statements: Vec<Statement> = statements
+                        .into_iter()
+                        .filter(|statement| {
+                            !matches!(statement, Statement::Assign(reg, Expr::Reg(value)) if reg == value)
+                        })
+                        .collect();
+                    if !statements.is_empty() {
+                        steps.push(Step::Effects(statements));
+                    }
+                }
+                (Flow::Call(target), _, _) => {
+                    steps.push(Step::Call(condition(&statements), callee(*pc, target)))
+                }
+                (Flow::Return, _, _) => {
+                    if *inst.mnemonic() == Mnemonic::RETI {
+                        steps.push(Step::Effects(vec![Statement::Interrupts(true)]));
+                    }
+                }
+                (Flow::ConditionalReturn, _, _) => {
+                    if let Some(cond) = condition(&statements) {
+                        steps.push(Step::Return(cond));
+                    }
+                }
+                (Flow::Jump(_), _, _) => {
+                    jump = statements
+                        .into_iter()
+                        .find_map(|statement| match statement {
+                            Statement::Jump(target) => Some(target),
+                            _ => None,
+                        })
+                }
+                (Flow::Branch(target), _, _) => {
+                    let cond = condition(&statements).unwrap_or(Expr::Const(1));
+                    jump = Some(cond);
+                    if successor(EdgeKind::Branch).is_none() {
+                        let pc = block.get_instructions().last().map(|(pc, _)| *pc);
+                        if let Some(pc) = pc {
+                            steps.push(Step::TailCall(
+                                jump.take().unwrap_or(Expr::Const(1)),
+                                callee(pc, target),
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+
+        let table: Vec<Address> = block
+            .get_successors()
+            .iter()
+            .filter(|edge| edge.kind() == EdgeKind::Table)
+            .map(|edge| edge.target())
+            .collect();
+        if !table.is_empty() {
+            /* The RST reading the table is not called. */
+            if let Some(Step::Call(None, _)) = steps.last() {
+                steps.pop();
+            }
+            return (steps, Exit::Table(table));
+        }
+
+        let fallthrough = successor(EdgeKind::Fallthrough);
+        let exit = match last {
+            Flow::Return => Exit::Return,
+            Flow::Jump(_) => match (successor(EdgeKind::Jump), jump) {
+                (Some(target), _) => edge(target),
+                (None, Some(Expr::Const(target))) => {
+                    let pc = block.get_instructions().last().map(|(pc, _)| *pc);
+                    match pc {
+                        Some(pc) => Exit::TailCall(callee(pc, target)),
+                        None => Exit::Stop,
+                    }
+                }
+                (None, Some(target)) => Exit::Computed(target),
+                (None, None) => Exit::Stop,
+            },
+            Flow::Branch(_) => match (successor(EdgeKind::Branch), fallthrough, jump) {
+                (Some(taken), Some(fall), Some(cond)) => match edge(taken) {
+                    Exit::TailCall(callee) => {
+                        steps.push(Step::TailCall(cond, callee));
+                        edge(fall)
+                    }
+                    _ => Exit::Branch(cond, taken, fall),
+                },
+                (_, Some(fall), _) => edge(fall),
+                _ => Exit::Stop,
+            },
+            _ => fallthrough.map_or(Exit::Stop, edge),
+        };
+
+        (steps, exit)
+    }
+
+    /* Parameters and clobbered registers of every function, then the flags
+     * read after calling them. */
+    fn summarize(&mut self) {
+        self.summaries = self
+            .bodies
+            .keys()
+            .map(|&entry| (entry, Summary::default()))
+            .collect();
+
+        for _ in 0..MAX_SUMMARY_ROUNDS {
+            let mut changed = false;
+            for (&entry, body) in &self.bodies {
+                let clobbers = self.clobbers(body);
+                let params = self.live_registers(body, entry);
+                let summary = self.summaries.get_mut(&entry).unwrap();
+                if summary.clobbers != clobbers || summary.params != params {
+                    summary.clobbers = clobbers;
+                    summary.params = params;
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        let mut returned: Vec<(Address, Flag)> = Vec::new();
+        for body in self.bodies.values() {
+            for (steps, exit) in body.blocks.values() {
+                for (i, step) in steps.iter().enumerate() {
+                    if let Step::Call(
+                        _,
+                        Callee {
+                            address: Some(callee),
+                            ..
+                        },
+                    ) = step
+                    {
+                        for flag in Decompiler::flags_read_after(&steps[i + 1..], exit) {
+                            returned.push((*callee, flag));
+                        }
+                    }
+                }
+            }
+        }
+        for (callee, flag) in returned {
+            if let Some(summary) = self.summaries.get_mut(&callee) {
+                summary.flags.insert(flag);
+            }
+        }
+    }
+
+    fn summary(&self, callee: &Callee) -> Option<&Summary> {
+        callee
+            .address
+            .and_then(|address| self.summaries.get(&address))
+    }
+
+    /* Registers written by the function or the ones it calls, unless it
+     * saves them on the stack. */
+    fn clobbers(&self, body: &Body) -> BTreeSet<Register> {
+        let mut clobbers = BTreeSet::new();
+        let callee_clobbers = |callee: &Callee, clobbers: &mut BTreeSet<Register>| {
+            if let Some(summary) = self.summary(callee) {
+                clobbers.extend(summary.clobbers.iter().copied());
+            }
+        };
+
+        for (steps, exit) in body.blocks.values() {
+            for step in steps {
+                match step {
+                    Step::Effects(statements) => {
+                        for statement in statements {
+                            for location in writes(statement) {
+                                if let Location::Reg(reg) = location {
+                                    clobbers.insert(reg);
+                                }
+                            }
+                        }
+                    }
+                    Step::Call(_, callee) | Step::TailCall(_, callee) => {
+                        callee_clobbers(callee, &mut clobbers)
+                    }
+                    Step::Pop(reg) => clobbers.extend(byte_registers(*reg)),
+                    Step::Return(_) | Step::Push(_) => {}
+                }
+            }
+            if let Exit::TailCall(callee) = exit {
+                callee_clobbers(callee, &mut clobbers);
+            }
+        }
+        clobbers.remove(&Register::SP);
+
+        clobbers.difference(&body.saved).copied().collect()
+    }
+
+    /* 8-bit registers live at the entry of the function. */
+    fn live_registers(&self, body: &Body, entry: Address) -> BTreeSet<Register> {
+        let mut live_in: BTreeMap<Address, BTreeSet<Register>> = BTreeMap::new();
+
+        loop {
+            let mut changed = false;
+            for (&start, (steps, exit)) in body.blocks.iter().rev() {
+                let get = |target: &Address| live_in.get(target).cloned().unwrap_or_default();
+                let mut live = match exit {
+                    Exit::Next(target) => get(target),
+                    Exit::Branch(_, taken, fall) => &get(taken) | &get(fall),
+                    Exit::Table(targets) => targets.iter().flat_map(get).collect(),
+                    Exit::TailCall(callee) => self
+                        .summary(callee)
+                        .map(|summary| summary.params.clone())
+                        .unwrap_or_default(),
+                    Exit::Computed(target) => registers(&expr_reads(target)),
+                    Exit::Return | Exit::Stop => BTreeSet::new(),
+                };
+
+                for step in steps.iter().rev() {
+                    match step {
+                        Step::Effects(statements) => {
+                            for statement in statements {
+                                live = &live - &registers(&writes(statement));
+                            }
+                            for statement in statements {
+                                live.extend(registers(&reads(statement)));
+                            }
+                        }
+                        Step::Call(cond, callee) => {
+                            if let Some(summary) = self.summary(callee) {
+                                if cond.is_none() {
+                                    live = &live - &summary.clobbers;
+                                }
+                                live.extend(summary.params.iter().copied());
+                            }
+                        }
+                        Step::TailCall(_, callee) => {
+                            if let Some(summary) = self.summary(callee) {
+                                live.extend(summary.params.iter().copied());
+                            }
+                        }
+                        /* Saving a register is not reading it. */
+                        Step::Push(reg) => {
+                            let bytes: BTreeSet<Register> =
+                                byte_registers(*reg).into_iter().collect();
+                            if bytes.is_disjoint(&body.saved) {
+                                live.extend(bytes);
+                            }
+                        }
+                        Step::Pop(reg) => {
+                            for byte in byte_registers(*reg) {
+                                live.remove(&byte);
+                            }
+                        }
+                        Step::Return(_) => {}
+                    }
+                }
+
+                if live_in.get(&start) != Some(&live) {
+                    live_in.insert(start, live);
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        let mut params = live_in.remove(&entry).unwrap_or_default();
+        params.remove(&Register::SP);
+        params
+    }
+
+    /* Flags read by `steps` and `exit` before being set. */
+    fn flags_read_after(steps: &[Step], exit: &Exit) -> BTreeSet<Flag> {
+        let mut read = BTreeSet::new();
+        let mut set = BTreeSet::new();
+        let mut note = |locations: BTreeSet<Location>, set: &BTreeSet<Flag>| {
+            read.extend(flags(&locations).difference(set).copied());
+        };
+
+        for step in steps {
+            match step {
+                Step::Effects(statements) => {
+                    for statement in statements {
+                        note(reads(statement), &set);
+                    }
+                    for statement in statements {
+                        set.extend(flags(&writes(statement)));
+                    }
+                }
+                Step::Call(cond, _) => {
+                    if let Some(cond) = cond {
+                        note(expr_reads(cond), &set);
+                    }
+                    return read;
+                }
+                Step::Return(cond) | Step::TailCall(cond, _) => note(expr_reads(cond), &set),
+                Step::Pop(Register::AF) => set.extend(FLAGS),
+                Step::Push(_) | Step::Pop(_) => {}
+            }
+        }
+        if let Exit::Branch(cond, _, _) = exit {
+            note(expr_reads(cond), &set);
+        }
+
+        read
+    }
+
+    /* Folds the flags set in a block into the statements and conditions of
+     * the block reading them, while what they were computed from is
+     * unchanged. */
+    fn propagate(steps: &mut [Step], exit: &mut Exit) {
+        let mut defs: BTreeMap<Flag, Expr> = BTreeMap::new();
+
+        for step in steps.iter_mut() {
+            match step {
+                Step::Effects(statements) => {
+                    for statement in statements.iter_mut() {
+                        *statement = substitute_statement(statement, &defs);
+                    }
+
+                    let mut written = BTreeSet::new();
+                    for statement in statements.iter() {
+                        written.extend(writes(statement));
+                    }
+                    for statement in statements.iter() {
+                        if let Statement::SetFlag(flag, value) = statement {
+                            defs.insert(*flag, value.clone());
+                        }
+                    }
+                    let assigned: Vec<(Register, Expr)> = statements
+                        .iter()
+                        .filter_map(|statement| match statement {
+                            Statement::Assign(reg, value) => Some((*reg, value.clone())),
+                            _ => None,
+                        })
+                        .collect();
+                    defs = defs
+                        .into_iter()
+                        .filter_map(|(flag, def)| {
+                            let def = Decompiler::follow_assignments(def, &assigned, &written)?;
+                            Some((flag, def))
+                        })
+                        .collect();
+                }
+                Step::Call(cond, _) => {
+                    if let Some(cond) = cond {
+                        *cond = simplify(&substitute(cond, &defs));
+                    }
+                    defs.clear();
+                }
+                Step::Return(cond) | Step::TailCall(cond, _) => {
+                    *cond = simplify(&substitute(cond, &defs));
+                }
+                Step::Push(_) => defs.retain(|_, def| !expr_reads(def).contains(&Location::Memory)),
+                Step::Pop(_) => defs.clear(),
+            }
+        }
+
+        match exit {
+            Exit::Branch(cond, _, _) | Exit::Computed(cond) => {
+                *cond = simplify(&substitute(cond, &defs))
+            }
+            _ => {}
+        }
+    }
+
+    /* The definition of a flag after an instruction writing `written`, None
+     * when it reads something changed. The result of an assignment in it is
+     * read from the register assigned instead: `dec b` sets Z when b is 0. */
+    fn follow_assignments(
+        def: Expr,
+        assigned: &[(Register, Expr)],
+        written: &BTreeSet<Location>,
+    ) -> Option<Expr> {
+        let mut def = def;
+        let mut test = def.clone();
+        for (reg, value) in assigned {
+            let mask = match byte_registers(*reg).len() {
+                1 => 0xFF,
+                _ => 0xFFFF,
+            };
+            let masked = Expr::Binary(
+                BinOp::And,
+                Box::new(value.clone()),
+                Box::new(Expr::Const(mask)),
+            );
+            let result = Expr::Reg(*reg);
+            test = replace(&test, &masked, &Expr::Const(0));
+            def = replace(&def, &masked, &result);
+            if mask == 0xFF && is_byte(value) {
+                test = replace(&test, value, &Expr::Const(0));
+                def = replace(&def, value, &result);
+            }
+            /* `((b - 1) & $FF) == 0` was simplified to `b == 1`. */
+            if let Expr::Binary(BinOp::Sub, lhs, rhs) = value {
+                if mask == 0xFF {
+                    let equal = Expr::Binary(BinOp::Eq, lhs.clone(), rhs.clone());
+                    let zero = Expr::Binary(
+                        BinOp::Eq,
+                        Box::new(result.clone()),
+                        Box::new(Expr::Const(0)),
+                    );
+                    test = replace(&test, &equal, &Expr::Const(0));
+                    def = replace(&def, &equal, &zero);
+                }
+            }
+        }
+
+        if expr_reads(&test).is_disjoint(written) {
+            Some(simplify(&def))
+        } else {
+            None
+        }
+    }
+
+    /* Drops the flags set and read by nobody. */
+    fn remove_dead_flags(body: &mut Body, returned: &BTreeSet<Flag>) {
+        let mut live_in: BTreeMap<Address, BTreeSet<Flag>> = BTreeMap::new();
+
+        let live_out = |exit: &Exit, live_in: &BTreeMap<Address, BTreeSet<Flag>>| {
+            let get = |target: &Address| live_in.get(target).cloned().unwrap_or_default();
+            match exit {
+                Exit::Next(target) => get(target),
+                Exit::Branch(cond, taken, fall) => {
+                    let mut live = &get(taken) | &get(fall);
+                    live.extend(flags(&expr_reads(cond)));
+                    live
+                }
+                Exit::Table(targets) => targets.iter().flat_map(get).collect(),
+                Exit::Return => returned.clone(),
+                Exit::Computed(target) => flags(&expr_reads(target)),
+                Exit::TailCall(_) | Exit::Stop => BTreeSet::new(),
+            }
+        };
+        /* Live flags before `steps`, removing dead flags from them when
+         * `remove`. */
+        let transfer = |steps: &mut Vec<Step>, mut live: BTreeSet<Flag>, remove: bool| {
+            for step in steps.iter_mut().rev() {
+                match step {
+                    Step::Effects(statements) => {
+                        let kept: Vec<Statement> = statements
+                            .iter()
+                            .filter(|statement| match statement {
+                                Statement::SetFlag(flag, _) => live.contains(flag),
+                                _ => true,
+                            })
+                            .cloned()
+                            .collect();
+                        for statement in &kept {
+                            live = &live - &flags(&writes(statement));
+                        }
+                        for statement in &kept {
+                            live.extend(flags(&reads(statement)));
+                        }
+                        if remove {
+                            *statements = kept;
+                        }
+                    }
+                    Step::Call(cond, _) => {
+                        if cond.is_none() {
+                            live.clear();
+                        }
+                        if let Some(cond) = cond {
+                            live.extend(flags(&expr_reads(cond)));
+                        }
+                    }
+                    Step::Return(cond) => {
+                        live.extend(returned.iter().copied());
+                        live.extend(flags(&expr_reads(cond)));
+                    }
+                    Step::TailCall(cond, _) => live.extend(flags(&expr_reads(cond))),
+                    Step::Push(Register::AF) => live.extend(FLAGS),
+                    Step::Pop(Register::AF) => live.clear(),
+                    Step::Push(_) | Step::Pop(_) => {}
+                }
+            }
+            if remove {
+                steps.retain(
+                    |step| !matches!(step, Step::Effects(statements) if statements.is_empty()),
+                );
+            }
+            live
+        };
+
+        loop {
+            let mut changed = false;
+            for (&start, (steps, exit)) in body.blocks.iter_mut().rev() {
+                let live = transfer(&mut steps.clone(), live_out(exit, &live_in), false);
+                if live_in.get(&start) != Some(&live) {
+                    live_in.insert(start, live);
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        for (steps, exit) in body.blocks.values_mut() {
+            transfer(steps, live_out(exit, &live_in), true);
+        }
+    }
+
+    fn nodes(steps: &[Step]) -> Vec<Node> {
+        let mut nodes = Vec::new();
+        for step in steps {
+            match step {
+                Step::Effects(statements) => nodes.extend(sequence(statements.clone())),
+                Step::Call(None, callee) => nodes.push(Node::Call(*callee)),
+                Step::Call(Some(cond), callee) => nodes.push(Node::If(
+                    cond.clone(),
+                    vec![Node::Call(*callee)],
+                    Vec::new(),
+                )),
+                Step::Return(cond) => {
+                    nodes.push(Node::If(cond.clone(), vec![Node::Return], Vec::new()))
+                }
+                Step::TailCall(cond, callee) => nodes.push(Node::If(
+                    cond.clone(),
+                    vec![Node::TailCall(*callee)],
+                    Vec::new(),
+                )),
+                Step::Push(reg) => nodes.push(Node::Push(*reg)),
+                Step::Pop(reg) => nodes.push(Node::Pop(*reg)),
+            }
+        }
+
+        nodes
+    }
+
+    /* Drops what follows a jump until the next label, and turns loops
+     * tested at one end into `while` and `do`/`while`. */
+    fn cleanup(nodes: Vec<Node>, gotos: &BTreeSet<Address>) -> Vec<Node> {
+        let mut kept = Vec::new();
+        let mut dead = false;
+        for node in nodes {
+            let nodes = match node {
+                Node::If(cond, then, other) => {
+                    let then = Decompiler::cleanup(then, gotos);
+                    let other = Decompiler::cleanup(other, gotos);
+                    if cond == Expr::Const(0) && !has_label(&then, gotos) {
+                        other
+                    } else if matches!(cond, Expr::Const(_)) && !has_label(&other, gotos) {
+                        then
+                    } else if then.is_empty() && !other.is_empty() {
+                        vec![Node::If(simplify(&Expr::Not(Box::new(cond))), other, then)]
+                    } else if then.last().is_some_and(ends) {
+                        /* No `else` after a jump. */
+                        let mut nodes = vec![Node::If(cond, then, Vec::new())];
+                        nodes.extend(other);
+                        nodes
+                    } else {
+                        vec![Node::If(cond, then, other)]
+                    }
+                }
+                Node::Loop(kind, body) => {
+                    vec![Decompiler::make_loop(
+                        kind,
+                        Decompiler::cleanup(body, gotos),
+                    )]
+                }
+                node => vec![node],
+            };
+            for node in nodes {
+                if let Node::Label(address) = node {
+                    if gotos.contains(&address) {
+                        dead = false;
+                    }
+                }
+                if dead {
+                    continue;
+                }
+                dead = ends(&node);
+                kept.push(node);
+            }
+        }
+
+        kept
+    }
+
+    fn make_loop(kind: LoopKind, mut body: Vec<Node>) -> Node {
+        if kind != LoopKind::Forever {
+            return Node::Loop(kind, body);
+        }
+        if body.last() == Some(&Node::Continue) {
+            body.pop();
+        }
+        /* `if (c) continue; break;` */
+        if body.len() >= 2 && body.last() == Some(&Node::Break) {
+            let i = body.len() - 2;
+            if let Node::If(cond, then, other) = &body[i] {
+                if then == &[Node::Continue] && other.is_empty() && !continues(&body[..i]) {
+                    let cond = cond.clone();
+                    body.truncate(i);
+                    return Node::Loop(LoopKind::DoWhile(cond), body);
+                }
+            }
+        }
+
+        /* A `continue` would skip the test at the end. */
+        if !continues(&body)
+            || matches!(body.last(), Some(Node::If(_, then, _)) if then == &[Node::Continue])
+        {
+            let test = match body.last() {
+                Some(Node::If(cond, then, other)) if other.is_empty() => match then.as_slice() {
+                    [Node::Continue] => Some(cond.clone()),
+                    [Node::Break] => Some(simplify(&Expr::Not(Box::new(cond.clone())))),
+                    _ => None,
+                },
+                _ => None,
+            };
+            if let Some(cond) = test {
+                body.pop();
+                if !continues(&body) {
+                    return Node::Loop(LoopKind::DoWhile(cond), body);
+                }
+                body.push(Node::If(cond, vec![Node::Continue], Vec::new()));
+            }
+        }
+
+        let first = body.iter().position(|node| !matches!(node, Node::Label(_)));
+        if let Some(i) = first {
+            if let Node::If(cond, then, other) = &body[i] {
+                if then == &[Node::Break] && other.is_empty() {
+                    let cond = simplify(&Expr::Not(Box::new(cond.clone())));
+                    body.remove(i);
+                    return Node::Loop(LoopKind::While(cond), body);
+                }
+            }
+        }
+
+        Node::Loop(LoopKind::Forever, body)
+    }
+
+    fn gotos(nodes: &[Node], gotos: &mut BTreeSet<Address>) {
+        for node in nodes {
+            match node {
+                Node::Goto(target) => {
+                    gotos.insert(*target);
+                }
+                Node::Switch(targets) => gotos.extend(targets),
+                Node::If(_, then, other) => {
+                    Decompiler::gotos(then, gotos);
+                    Decompiler::gotos(other, gotos);
+                }
+                Node::Loop(_, body) => Decompiler::gotos(body, gotos),
+                _ => {}
+            }
+        }
+    }
+
+    /* Registers with pairs whose halves are both in `registers`. */
+    fn pairs(registers: &BTreeSet<Register>) -> Vec<Register> {
+        let mut pairs = Vec::new();
+        for (pair, high, low) in [
+            (Register::BC, Register::B, Register::C),
+            (Register::DE, Register::D, Register::E),
+            (Register::HL, Register::H, Register::L),
+        ] {
+            match (registers.contains(&high), registers.contains(&low)) {
+                (true, true) => pairs.push(pair),
+                (true, false) => pairs.push(high),
+                (false, true) => pairs.push(low),
+                _ => {}
+            }
+        }
+        if registers.contains(&Register::A) {
+            pairs.insert(0, Register::A);
+        }
+
+        pairs
+    }
+
+    fn function_name(&self, address: Address) -> String {
+        match self.symbols.get(address) {
+            Some(symbol) => symbol.name().to_string(),
+            None => format!("Function_{:03X}_{:04X}", address.bank(), address.addr()),
+        }
+    }
+
+    fn label(&self, address: Address) -> String {
+        match self.symbols.get(address) {
+            Some(symbol) => symbol.name().replace('.', "_"),
+            None => format!("label_{:03X}_{:04X}", address.bank(), address.addr()),
+        }
+    }
+
+    fn call(&self, callee: &Callee, bank: u16) -> String {
+        let name = match callee.address {
+            Some(address) => match self.symbols.get(address) {
+                Some(symbol) => symbol.name().to_string(),
+                None => self.function_name(address),
+            },
+            None => match self.symbols.lookup(bank, callee.target) {
+                Some(symbol) => symbol.name().to_string(),
+                None => format!("Function_{:04X}", callee.target),
+            },
+        };
+        let params = match callee.address {
+            Some(address) => self.params(address),
+            None => Vec::new(),
+        };
+        let params: Vec<&str> = params.iter().map(|&reg| register_name(reg)).collect();
+
+        format!("{}({})", name, params.join(", "))
+    }
+
+    /* Name of the variable at `addr`: its symbol, I/O register, or one made
+     * from its address in WRAM, HRAM and SRAM. */
+    fn variable(&self, addr: u16, bank: u16) -> String {
+        if let Some(symbol) = self.symbols.lookup(bank, addr) {
+            return symbol.name().to_string();
+        }
+        if self.hardware_names && addr >= 0xFF00 {
+            if let Some(name) = hardware::io_register_name(addr) {
+                return match name.contains(' ') {
+                    true => format!("[{}]", name),
+                    false => name,
+                };
+            }
+        }
+
+        match Region::of(addr) {
+            Region::WRAM0 | Region::WRAMX => format!("w{:04X}", addr),
+            Region::HRAM => format!("h{:04X}", addr),
+            Region::SRAM => format!("s{:04X}", addr),
+            _ => format!("[${:04X}]", addr),
+        }
+    }
+
+    fn expr(&self, expr: &Expr, bank: u16) -> String {
+        let nested = |expr: &Expr| match expr {
+            Expr::Binary(_, _, _) | Expr::Select(_, _, _) => format!("({})", self.expr(expr, bank)),
+            Expr::Not(value)
+                if matches!(
+                    **value,
+                    Expr::Binary(BinOp::Eq, _, _) | Expr::Binary(BinOp::Lt, _, _)
+                ) =>
+            {
+                format!("({})", self.expr(expr, bank))
+            }
+            _ => self.expr(expr, bank),
+        };
+
+        match expr {
+            Expr::Load(addr) => match **addr {
+                Expr::Const(addr) => self.variable(addr, bank),
+                ref addr => format!("[{}]", self.expr(addr, bank)),
+            },
+            Expr::Binary(op, lhs, rhs) => format!("{} {} {}", nested(lhs), op, nested(rhs)),
+            Expr::Not(value) => match &**value {
+                Expr::Binary(BinOp::Eq, lhs, rhs) => format!("{} != {}", nested(lhs), nested(rhs)),
+                Expr::Binary(BinOp::Lt, lhs, rhs) => format!("{} >= {}", nested(lhs), nested(rhs)),
+                value => format!("!{}", nested(value)),
+            },
+            Expr::Overflow(bits, value) => format!("overflow{}({})", bits, self.expr(value, bank)),
+            Expr::Select(cond, then, other) => {
+                format!("{} ? {} : {}", nested(cond), nested(then), nested(other))
+            }
+            expr => expr.to_string(),
+        }
+    }
+
+    fn statement(&self, statement: &Statement, bank: u16) -> (String, String) {
+        match statement {
+            /* Addresses of named variables. */
+            Statement::Assign(reg, Expr::Const(addr))
+                if byte_registers(*reg).len() == 2
+                    && *addr >= 0x8000
+                    && self.symbols.lookup(bank, *addr).is_some() =>
+            {
+                (
+                    register_name(*reg).to_string(),
+                    format!("&{}", self.variable(*addr, bank)),
+                )
+            }
+            Statement::Assign(reg, value) => {
+                (register_name(*reg).to_string(), self.expr(value, bank))
+            }
+            Statement::SetFlag(flag, value) => (flag.to_string(), self.expr(value, bank)),
+            Statement::Store(addr, value) => {
+                let target = self.expr(&Expr::Load(Box::new(addr.clone())), bank);
+                (target, self.expr(value, bank))
+            }
+            Statement::Interrupts(true) => (String::new(), "enable_interrupts()".to_string()),
+            Statement::Interrupts(false) => (String::new(), "disable_interrupts()".to_string()),
+            Statement::Halt => (String::new(), "halt()".to_string()),
+            Statement::Stop => (String::new(), "stop()".to_string()),
+            statement => (String::new(), statement.to_string()),
+        }
+    }
+
+    fn render(
+        &self,
+        nodes: &[Node],
+        bank: u16,
+        depth: usize,
+        labels: &BTreeSet<Address>,
+        lines: &mut Vec<String>,
+    ) {
+        let indent = "    ".repeat(depth);
+        for node in nodes {
+            match node {
+                Node::Label(address) => {
+                    if labels.contains(address) {
+                        lines.push(format!("{}:", self.label(*address)));
+                    }
+                }
+                Node::Statement(statement) => {
+                    let line = match self.statement(statement, bank) {
+                        (target, value) if target.is_empty() => format!("{};", value),
+                        (target, value) => format!("{} = {};", target, value),
+                    };
+                    lines.push(format!("{}{}", indent, line));
+                }
+                Node::Parallel(statements) => {
+                    let (targets, values): (Vec<String>, Vec<String>) = statements
+                        .iter()
+                        .map(|statement| self.statement(statement, bank))
+                        .unzip();
+                    lines.push(format!(
+                        "{}{} = {};",
+                        indent,
+                        targets.join(", "),
+                        values.join(", ")
+                    ));
+                }
+                Node::Call(callee) => lines.push(format!("{}{};", indent, self.call(callee, bank))),
+                Node::Push(reg) => lines.push(format!("{}push({});", indent, register_name(*reg))),
+                Node::Pop(reg) => lines.push(format!("{}{} = pop();", indent, register_name(*reg))),
+                Node::TailCall(callee) => {
+                    lines.push(format!("{}{}; return;", indent, self.call(callee, bank)))
+                }
+                Node::Return => lines.push(format!("{}return;", indent)),
+                Node::If(cond, then, other) => {
+                    lines.push(format!("{}if ({}) {{", indent, self.expr(cond, bank)));
+                    self.render(then, bank, depth + 1, labels, lines);
+                    let mut other = other;
+                    /* `else if` chains. */
+                    loop {
+                        let visible: Vec<&Node> = other
+                            .iter()
+                            .filter(|node| match node {
+                                Node::Label(address) => labels.contains(address),
+                                _ => true,
+                            })
+                            .collect();
+                        match visible.as_slice() {
+                            [] => break,
+                            [Node::If(cond, then, next)] => {
+                                lines.push(format!(
+                                    "{}}} else if ({}) {{",
+                                    indent,
+                                    self.expr(cond, bank)
+                                ));
+                                self.render(then, bank, depth + 1, labels, lines);
+                                other = next;
+                            }
+                            _ => {
+                                lines.push(format!("{}}} else {{", indent));
+                                self.render(other, bank, depth + 1, labels, lines);
+                                break;
+                            }
+                        }
+                    }
+                    lines.push(format!("{}}}", indent));
+                }
+                Node::Loop(kind, body) => {
+                    match kind {
+                        LoopKind::Forever => lines.push(format!("{}while (true) {{", indent)),
+                        LoopKind::While(cond) => {
+                            lines.push(format!("{}while ({}) {{", indent, self.expr(cond, bank)))
+                        }
+                        LoopKind::DoWhile(_) => lines.push(format!("{}do {{", indent)),
+                    }
+                    self.render(body, bank, depth + 1, labels, lines);
+                    match kind {
+                        LoopKind::DoWhile(cond) => {
+                            lines.push(format!("{}}} while ({});", indent, self.expr(cond, bank)))
+                        }
+                        _ => lines.push(format!("{}}}", indent)),
+                    }
+                }
+                Node::Break => lines.push(format!("{}break;", indent)),
+                Node::Continue => lines.push(format!("{}continue;", indent)),
+                Node::Goto(target) => {
+                    lines.push(format!("{}goto {};", indent, self.label(*target)))
+                }
+                Node::GotoExpr(target) => {
+                    lines.push(format!("{}goto *{};", indent, self.expr(target, bank)))
+                }
+                Node::Switch(targets) => {
+                    lines.push(format!("{}switch (table) {{", indent));
+                    for (i, target) in targets.iter().enumerate() {
+                        lines.push(format!(
+                            "{}case {}: goto {};",
+                            indent,
+                            i,
+                            self.label(*target)
+                        ));
+                    }
+                    lines.push(format!("{}}}", indent));
+                }
+            }
+        }
+    }
+}
+
+/* Emits the blocks of a function as nested `if` and loops, and `goto` where
+ * the flow is not structured. */
+struct Structurer {
+    exits: BTreeMap<Address, Exit>,
+    nodes: BTreeMap<Address, Vec<Node>>,
+    ipdom: BTreeMap<Address, Address>,
+    loops: BTreeMap<Address, BTreeSet<Address>>,
+    emitted: BTreeSet<Address>,
+}
+
+impl Structurer {
+    fn new(
+        entry: Address,
+        exits: BTreeMap<Address, Exit>,
+        nodes: BTreeMap<Address, Vec<Node>>,
+    ) -> Structurer {
+        let successors: BTreeMap<Address, Vec<Address>> = exits
+            .iter()
+            .map(|(&start, exit)| {
+                let targets = match exit {
+                    Exit::Next(target) => vec![*target],
+                    Exit::Branch(_, taken, fall) => vec![*taken, *fall],
+                    Exit::Table(targets) => targets.clone(),
+                    _ => Vec::new(),
+                };
+                (start, targets)
+            })
+            .collect();
+        let mut predecessors: BTreeMap<Address, Vec<Address>> = BTreeMap::new();
+        for (&start, targets) in &successors {
+            for &target in targets {
+                predecessors.entry(target).or_default().push(start);
+            }
+        }
+
+        let dominators = Structurer::dominators(&[entry], &predecessors, &successors);
+        let mut loops: BTreeMap<Address, BTreeSet<Address>> = BTreeMap::new();
+        for (&from, targets) in &successors {
+            for &header in targets {
+                if !dominators
+                    .get(&from)
+                    .is_some_and(|doms| doms.contains(&header))
+                {
+                    continue;
+                }
+                /* The natural loop of the back edge. */
+                let body = loops.entry(header).or_insert_with(|| [header].into());
+                let mut worklist = vec![from];
+                while let Some(block) = worklist.pop() {
+                    if body.insert(block) {
+                        worklist.extend(predecessors.get(&block).into_iter().flatten());
+                    }
+                }
+            }
+        }
+
+        /* Post-dominators, over the blocks from which the function ends. The
+         * last block of a loop never left ends it too. */
+        let mut exits_of: Vec<Address> = successors
+            .iter()
+            .filter(|(_, targets)| targets.is_empty())
+            .map(|(&start, _)| start)
+            .collect();
+        loop {
+            let mut reaching = BTreeSet::new();
+            let mut worklist = exits_of.clone();
+            while let Some(block) = worklist.pop() {
+                if reaching.insert(block) {
+                    worklist.extend(predecessors.get(&block).into_iter().flatten());
+                }
+            }
+            match successors
+                .keys()
+                .rev()
+                .find(|block| !reaching.contains(block))
+            {
+                Some(&block) => exits_of.push(block),
+                None => break,
+            }
+        }
+        let post_dominators = Structurer::dominators(&exits_of, &successors, &predecessors);
+        let immediate = |sets: &BTreeMap<Address, BTreeSet<Address>>, block: Address| {
+            sets.get(&block)?
+                .iter()
+                .filter(|&&other| other != block)
+                .max_by_key(|other| sets.get(other).map_or(0, |set| set.len()))
+                .copied()
+        };
+        let ipdom = exits
+            .keys()
+            .filter_map(|&block| Some((block, immediate(&post_dominators, block)?)))
+            .collect();
+
+        Structurer {
+            exits,
+            nodes,
+            ipdom,
+            loops,
+            emitted: BTreeSet::new(),
+        }
+    }
+
+    /* Dominators of the blocks reached from `roots` through `forward`
+     * edges, whose reverse are `backward`. */
+    fn dominators(
+        roots: &[Address],
+        backward: &BTreeMap<Address, Vec<Address>>,
+        forward: &BTreeMap<Address, Vec<Address>>,
+    ) -> BTreeMap<Address, BTreeSet<Address>> {
+        let mut reached = BTreeSet::new();
+        let mut worklist = roots.to_vec();
+        while let Some(block) = worklist.pop() {
+            if reached.insert(block) {
+                worklist.extend(forward.get(&block).into_iter().flatten());
+            }
+        }
+
+        let mut sets: BTreeMap<Address, BTreeSet<Address>> = reached
+            .iter()
+            .map(|&block| {
+                if roots.contains(&block) {
+                    (block, [block].into())
+                } else {
+                    (block, reached.clone())
+                }
+            })
+            .collect();
+        loop {
+            let mut changed = false;
+            for &block in &reached {
+                if roots.contains(&block) {
+                    continue;
+                }
+                let mut set: Option<BTreeSet<Address>> = None;
+                for other in backward.get(&block).into_iter().flatten() {
+                    if let Some(other) = sets.get(other) {
+                        set = Some(match set {
+                            Some(set) => &set & other,
+                            None => other.clone(),
+                        });
+                    }
+                }
+                let mut set = set.unwrap_or_default();
+                set.insert(block);
+                if sets[&block] != set {
+                    sets.insert(block, set);
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        sets
+    }
+
+    /* Where flow goes to `target`: None once it is left for `stop`, a loop
+     * or a label, with the `break`, `continue` or `goto` added to `out`. */
+    fn jump(
+        &mut self,
+        target: Address,
+        stop: Option<Address>,
+        context: Option<&LoopContext>,
+        out: &mut Vec<Node>,
+    ) -> Option<Address> {
+        if Some(target) == stop {
+            return None;
+        }
+        if let Some(context) = context {
+            if target == context.header {
+                out.push(Node::Continue);
+                return None;
+            }
+            if Some(target) == context.follow {
+                out.push(Node::Break);
+                return None;
+            }
+            if !context.body.contains(&target) {
+                out.push(Node::Goto(target));
+                return None;
+            }
+        }
+        if self.emitted.contains(&target) || !self.exits.contains_key(&target) {
+            out.push(Node::Goto(target));
+            return None;
+        }
+
+        Some(target)
+    }
+
+    /* The nodes from `start` until `stop`. */
+    fn region(
+        &mut self,
+        start: Address,
+        stop: Option<Address>,
+        context: Option<&LoopContext>,
+    ) -> Vec<Node> {
+        let mut out = Vec::new();
+        let mut current = Some(start);
+
+        while let Some(block) = current.take() {
+            if self.emitted.contains(&block) {
+                out.push(Node::Goto(block));
+                break;
+            }
+
+            if self.loops.contains_key(&block) && context.is_none_or(|c| c.header != block) {
+                let body = self.loops[&block].clone();
+                let follow = self.follow(block, &body);
+                let inner = LoopContext {
+                    header: block,
+                    follow,
+                    body,
+                };
+                let nodes = self.region(block, None, Some(&inner));
+                out.push(Node::Loop(LoopKind::Forever, nodes));
+                if let Some(follow) = follow {
+                    current = self.jump(follow, stop, context, &mut out);
+                }
+                continue;
+            }
+
+            self.emitted.insert(block);
+            out.push(Node::Label(block));
+            out.extend(self.nodes.get(&block).cloned().unwrap_or_default());
+            match self.exits[&block].clone() {
+                Exit::Next(target) => current = self.jump(target, stop, context, &mut out),
+                Exit::Branch(cond, taken, fall) => {
+                    let merge = self
+                        .ipdom
+                        .get(&block)
+                        .copied()
+                        .filter(|merge| !self.emitted.contains(merge));
+                    let then = self.arm(taken, merge, context);
+                    let other = self.arm(fall, merge, context);
+                    out.push(Node::If(cond, then, other));
+                    if let Some(merge) = merge {
+                        current = self.jump(merge, stop, context, &mut out);
+                    }
+                }
+                Exit::Return => out.push(Node::Return),
+                Exit::TailCall(callee) => out.push(Node::TailCall(callee)),
+                Exit::Computed(target) => out.push(Node::GotoExpr(target)),
+                Exit::Table(targets) => out.push(Node::Switch(targets)),
+                Exit::Stop => {}
+            }
+        }
+
+        out
+    }
+
+    fn arm(
+        &mut self,
+        target: Address,
+        merge: Option<Address>,
+        context: Option<&LoopContext>,
+    ) -> Vec<Node> {
+        let mut out = Vec::new();
+        if let Some(next) = self.jump(target, merge, context, &mut out) {
+            out.extend(self.region(next, merge, context));
+        }
+
+        out
+    }
+
+    /* The block a loop exits to: the one after it in every path if it has
+     * one, else the first one. */
+    fn follow(&self, header: Address, body: &BTreeSet<Address>) -> Option<Address> {
+        let exits: BTreeSet<Address> = body
+            .iter()
+            .flat_map(|block| match &self.exits[block] {
+                Exit::Next(target) => vec![*target],
+                Exit::Branch(_, taken, fall) => vec![*taken, *fall],
+                Exit::Table(targets) => targets.clone(),
+                _ => Vec::new(),
+            })
+            .filter(|target| !body.contains(target))
+            .collect();
+
+        match self.ipdom.get(&header) {
+            Some(after) if exits.contains(after) => Some(*after),
+            _ => exits.iter().next().copied(),
+        }
+    }
+}
+
+/* Whether control never goes past the node. */
+fn ends(node: &Node) -> bool {
+    match node {
+        Node::Return
+        | Node::TailCall(_)
+        | Node::Goto(_)
+        | Node::GotoExpr(_)
+        | Node::Switch(_)
+        | Node::Break
+        | Node::Continue => true,
+        Node::If(_, then, other) => then.last().is_some_and(ends) && other.last().is_some_and(ends),
+        Node::Loop(LoopKind::Forever, body) => !breaks(body),
+        _ => false,
+    }
+}
+
+/* Whether a label of `nodes` is jumped to. */
+fn has_label(nodes: &[Node], gotos: &BTreeSet<Address>) -> bool {
+    nodes.iter().any(|node| match node {
+        Node::Label(address) => gotos.contains(address),
+        Node::If(_, then, other) => has_label(then, gotos) || has_label(other, gotos),
+        Node::Loop(_, body) => has_label(body, gotos),
+        _ => false,
+    })
+}
+
+/* Whether a `break` in `nodes` leaves the loop they are in. */
+fn breaks(nodes: &[Node]) -> bool {
+    nodes.iter().any(|node| match node {
+        Node::Break => true,
+        Node::If(_, then, other) => breaks(then) || breaks(other),
+        _ => false,
+    })
+}
+
+/* Whether a `continue` in `nodes` goes to the loop they are in. */
+fn continues(nodes: &[Node]) -> bool {
+    nodes.iter().any(|node| match node {
+        Node::Continue => true,
+        Node::If(_, then, other) => continues(then) || continues(other),
+        _ => false,
+    })
+}
+
+/* Orders the statements of an instruction so that none reads what another
+ * one wrote before it, those that cannot be are assigned together. */
+fn sequence(mut statements: Vec<Statement>) -> Vec<Node> {
+    let mut nodes = Vec::new();
+
+    while !statements.is_empty() {
+        let free = (0..statements.len()).find(|&i| {
+            let written = writes(&statements[i]);
+            statements
+                .iter()
+                .enumerate()
+                .all(|(j, other)| i == j || reads(other).is_disjoint(&written))
+        });
+        match free {
+            Some(i) => nodes.push(Node::Statement(statements.remove(i))),
+            None => {
+                nodes.push(Node::Parallel(statements));
+                break;
+            }
+        }
+    }
+
+    nodes
+}
+
+fn register_name(reg: Register) -> &'static str {
+    match reg {
+        Register::AF => "af",
+        Register::A => "a",
+        Register::F => "f",
+        Register::BC => "bc",
+        Register::B => "b",
+        Register::C => "c",
+        Register::DE => "de",
+        Register::D => "d",
+        Register::E => "e",
+        Register::HL => "hl",
+        Register::H => "h",
+        Register::L => "l",
+        Register::SP => "sp",
+    }
+}
+
+/* The 8-bit registers of `reg`, or SP. */
+fn byte_registers(reg: Register) -> Vec<Register> {
+    match reg {
+        Register::AF => vec![Register::A],
+        Register::F => Vec::new(),
+        Register::BC => vec![Register::B, Register::C],
+        Register::DE => vec![Register::D, Register::E],
+        Register::HL => vec![Register::H, Register::L],
+        reg => vec![reg],
+    }
+}
+
+fn expr_reads(expr: &Expr) -> BTreeSet<Location> {
+    let mut locations = BTreeSet::new();
+    match expr {
+        Expr::Reg(reg) => locations.extend(byte_registers(*reg).into_iter().map(Location::Reg)),
+        Expr::Flag(flag) => {
+            locations.insert(Location::Flag(*flag));
+        }
+        Expr::Load(_) => {
+            locations.insert(Location::Memory);
+        }
+        _ => {}
+    }
+    for child in expr.children() {
+        locations.extend(expr_reads(child));
+    }
+
+    locations
+}
+
+fn reads(statement: &Statement) -> BTreeSet<Location> {
+    match statement {
+        Statement::Assign(_, value)
+        | Statement::SetFlag(_, value)
+        | Statement::Jump(value)
+        | Statement::Call(value) => expr_reads(value),
+        Statement::Store(addr, value) => &expr_reads(addr) | &expr_reads(value),
+        Statement::If(cond, statements) => {
+            let mut locations = expr_reads(cond);
+            for statement in statements {
+                locations.extend(reads(statement));
+            }
+            locations
+        }
+        Statement::Return => [Location::Reg(Register::SP), Location::Memory].into(),
+        _ => BTreeSet::new(),
+    }
+}
+
+fn writes(statement: &Statement) -> BTreeSet<Location> {
+    match statement {
+        Statement::Assign(reg, _) => byte_registers(*reg)
+            .into_iter()
+            .map(Location::Reg)
+            .collect(),
+        Statement::SetFlag(flag, _) => [Location::Flag(*flag)].into(),
+        Statement::Store(_, _) => [Location::Memory].into(),
+        Statement::If(_, statements) => statements.iter().flat_map(writes).collect(),
+        _ => BTreeSet::new(),
+    }
+}
+
+fn registers(locations: &BTreeSet<Location>) -> BTreeSet<Register> {
+    locations
+        .iter()
+        .filter_map(|location| match location {
+            Location::Reg(reg) => Some(*reg),
+            _ => None,
+        })
+        .collect()
+}
+
+fn flags(locations: &BTreeSet<Location>) -> BTreeSet<Flag> {
+    FLAGS
+        .iter()
+        .copied()
+        .filter(|&flag| locations.contains(&Location::Flag(flag)))
+        .collect()
+}
+
+/* `expr` with every `pattern` in it replaced. */
+fn replace(expr: &Expr, pattern: &Expr, replacement: &Expr) -> Expr {
+    if expr == pattern {
+        return replacement.clone();
+    }
+    let r = |expr: &Expr| Box::new(replace(expr, pattern, replacement));
+
+    match expr {
+        Expr::Load(addr) => Expr::Load(r(addr)),
+        Expr::Binary(op, lhs, rhs) => Expr::Binary(*op, r(lhs), r(rhs)),
+        Expr::Not(value) => Expr::Not(r(value)),
+        Expr::Overflow(bits, value) => Expr::Overflow(*bits, r(value)),
+        Expr::Select(cond, then, other) => Expr::Select(r(cond), r(then), r(other)),
+        expr => expr.clone(),
+    }
+}
+
+/* `expr` with the flags defined in `defs` replaced by their value. */
+fn substitute(expr: &Expr, defs: &BTreeMap<Flag, Expr>) -> Expr {
+    defs.iter().fold(expr.clone(), |expr, (&flag, value)| {
+        replace(&expr, &Expr::Flag(flag), value)
+    })
+}
+
+fn substitute_statement(statement: &Statement, defs: &BTreeMap<Flag, Expr>) -> Statement {
+    let s = |expr: &Expr| simplify(&substitute(expr, defs));
+    match statement {
+        Statement::Assign(reg, value) => Statement::Assign(*reg, s(value)),
+        Statement::SetFlag(flag, value) => Statement::SetFlag(*flag, s(value)),
+        Statement::Store(addr, value) => Statement::Store(s(addr), s(value)),
+        statement => statement.clone(),
+    }
+}
+
+fn simplify_statement(statement: &Statement) -> Statement {
+    match statement {
+        Statement::Assign(reg, value) => Statement::Assign(*reg, simplify(value)),
+        Statement::SetFlag(flag, value) => Statement::SetFlag(*flag, simplify(value)),
+        Statement::Store(addr, value) => Statement::Store(simplify(addr), simplify(value)),
+        Statement::Jump(target) => Statement::Jump(simplify(target)),
+        Statement::Call(target) => Statement::Call(simplify(target)),
+        Statement::If(cond, statements) => Statement::If(
+            simplify(cond),
+            statements.iter().map(simplify_statement).collect(),
+        ),
+        statement => statement.clone(),
+    }
+}
+
+/* Whether the value fits in a byte. */
+fn is_byte(expr: &Expr) -> bool {
+    match expr {
+        Expr::Const(value) => *value <= 0xFF,
+        Expr::Reg(reg) => byte_registers(*reg) == [*reg] && *reg != Register::SP,
+        Expr::Flag(_) | Expr::Load(_) | Expr::Not(_) | Expr::Overflow(_, _) => true,
+        Expr::Binary(BinOp::Eq, _, _) | Expr::Binary(BinOp::Lt, _, _) => true,
+        Expr::Binary(BinOp::And, lhs, rhs) => is_byte(lhs) || is_byte(rhs),
+        Expr::Binary(BinOp::Or, lhs, rhs) | Expr::Binary(BinOp::Xor, lhs, rhs) => {
+            is_byte(lhs) && is_byte(rhs)
+        }
+        Expr::Binary(BinOp::Shr, lhs, _) => is_byte(lhs),
+        Expr::Select(_, then, other) => is_byte(then) && is_byte(other),
+        _ => false,
+    }
+}
+
+/* Whether the value fits in a word. */
+fn is_word(expr: &Expr) -> bool {
+    match expr {
+        Expr::Const(_) | Expr::Reg(_) => true,
+        expr => is_byte(expr),
+    }
+}
+
+/* Whether the value is 0 or 1. */
+fn is_bool(expr: &Expr) -> bool {
+    match expr {
+        Expr::Const(value) => *value <= 1,
+        Expr::Flag(_) | Expr::Not(_) | Expr::Overflow(_, _) => true,
+        Expr::Binary(BinOp::Eq, _, _) | Expr::Binary(BinOp::Lt, _, _) => true,
+        _ => false,
+    }
+}
+
+/* Folds constants and identities, and turns the flags of comparisons back
+ * into comparisons. */
+fn simplify(expr: &Expr) -> Expr {
+    match expr {
+        Expr::Load(addr) => Expr::Load(Box::new(simplify(addr))),
+        Expr::Not(value) => match simplify(value) {
+            Expr::Const(value) => Expr::Const((value == 0) as u16),
+            Expr::Not(value) if is_bool(&value) => *value,
+            value => Expr::Not(Box::new(value)),
+        },
+        Expr::Overflow(bits, value) => match simplify(value) {
+            Expr::Binary(BinOp::Sub, lhs, rhs) if *bits == 8 && is_byte(&lhs) && is_byte(&rhs) => {
+                Expr::Binary(BinOp::Lt, lhs, rhs)
+            }
+            Expr::Const(value) => Expr::Const((*bits < 16 && value >> bits != 0) as u16),
+            value if *bits == 8 && is_byte(&value) => Expr::Const(0),
+            value => Expr::Overflow(*bits, Box::new(value)),
+        },
+        Expr::Select(cond, then, other) => match simplify(cond) {
+            Expr::Const(0) => simplify(other),
+            Expr::Const(_) => simplify(then),
+            cond => Expr::Select(
+                Box::new(cond),
+                Box::new(simplify(then)),
+                Box::new(simplify(other)),
+            ),
+        },
+        Expr::Binary(op, lhs, rhs) => binary(*op, simplify(lhs), simplify(rhs)),
+        expr => expr.clone(),
+    }
+}
+
+fn binary(op: BinOp, lhs: Expr, rhs: Expr) -> Expr {
+    if let (Expr::Const(a), Expr::Const(b)) = (&lhs, &rhs) {
+        let (a, b) = (*a as i64, *b as i64);
+        let value = match op {
+            BinOp::Add => a + b,
+            BinOp::Sub => a - b,
+            BinOp::And => a & b,
+            BinOp::Or => a | b,
+            BinOp::Xor => a ^ b,
+            BinOp::Shl => a << (b & 0x1F),
+            BinOp::Shr => a >> (b & 0x1F),
+            BinOp::Eq => (a == b) as i64,
+            BinOp::Lt => (a < b) as i64,
+        };
+        if (0..=0xFFFF).contains(&value) {
+            return Expr::Const(value as u16);
+        }
+    }
+
+    let zero = Expr::Const(0);
+    match (op, &lhs, &rhs) {
+        (BinOp::Add, _, _)
+        | (BinOp::Sub, _, _)
+        | (BinOp::Or, _, _)
+        | (BinOp::Xor, _, _)
+        | (BinOp::Shl, _, _)
+        | (BinOp::Shr, _, _)
+            if rhs == zero =>
+        {
+            lhs
+        }
+        (BinOp::Add, _, _) | (BinOp::Or, _, _) | (BinOp::Xor, _, _) if lhs == zero => rhs,
+        (BinOp::And, _, _) if lhs == zero || rhs == zero => zero,
+        (BinOp::And, _, Expr::Const(0xFF)) if is_byte(&lhs) => lhs,
+        (BinOp::And, _, Expr::Const(0xFFFF)) if is_word(&lhs) => lhs,
+        (BinOp::And, _, _) | (BinOp::Or, _, _) if lhs == rhs => lhs,
+        (BinOp::Xor, _, _) | (BinOp::Sub, _, _) if lhs == rhs => zero,
+        (BinOp::Eq, _, _) if lhs == rhs => Expr::Const(1),
+        /* `cp`: the low byte of A - n is 0 when they are equal. */
+        (BinOp::Eq, Expr::Binary(BinOp::And, value, mask), Expr::Const(0))
+            if **mask == Expr::Const(0xFF) =>
+        {
+            match &**value {
+                Expr::Binary(BinOp::Sub, a, b) | Expr::Binary(BinOp::Xor, a, b)
+                    if is_byte(a) && is_byte(b) =>
+                {
+                    Expr::Binary(BinOp::Eq, a.clone(), b.clone())
+                }
+                _ => Expr::Binary(op, Box::new(lhs), Box::new(rhs)),
+            }
+        }
+        (BinOp::Eq, Expr::Binary(BinOp::Xor, a, b), Expr::Const(0)) => {
+            Expr::Binary(BinOp::Eq, a.clone(), b.clone())
+        }
+        (BinOp::Eq, _, Expr::Const(0)) if is_bool(&lhs) => Expr::Not(Box::new(lhs)),
+        _ => Expr::Binary(op, Box::new(lhs), Box::new(rhs)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Decompiles `function` at $0150, called from $0100. */
+    fn decompile(function: &[u8], hardware_names: bool) -> Pseudocode {
+        let mut bytes = vec![0x00; 0x8000];
+        bytes[0x0100..0x0105].copy_from_slice(&[0xCD, 0x50, 0x01, 0x18, 0xFE]);
+        bytes[0x0150..0x0150 + function.len()].copy_from_slice(function);
+
+        let project = Project::new(&bytes);
+        let cfg = ControlFlowGraph::build(&bytes, &[Address::new(0, 0x0100)], &project);
+        let symbols = SymbolTable::new();
+        let mut decompiler = Decompiler::new(&cfg, &bytes, &project, &symbols);
+        decompiler.set_hardware_names(hardware_names);
+
+        decompiler.decompile(Address::new(0, 0x0150)).unwrap()
+    }
+
+    fn lines(pseudocode: &Pseudocode) -> Vec<&str> {
+        pseudocode.get_lines().iter().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn counted_loop() {
+        let code = [
+            0x21, 0x00, 0xC0, /* ld hl, $C000 */
+            0x06, 0x10, /* ld b, $10 */
+            0xAF, /* xor a */
+            0x22, /* .loop: ld [hl+], a */
+            0x05, /* dec b */
+            0x20, 0xFC, /* jr nz, .loop */
+            0xC9, /* ret */
+        ];
+        let pseudocode = decompile(&code, true);
+        assert!(pseudocode.get_params().is_empty());
+        assert_eq!(
+            lines(&pseudocode),
+            [
+                "void Function_000_0150() {",
+                "    hl = $C000;",
+                "    b = $10;",
+                "    a = 0;",
+                "    do {",
+                "        [hl] = a;",
+                "        hl = hl + 1;",
+                "        b = b - 1;",
+                "    } while (b != 0);",
+                "}",
+            ]
+        );
+    }
+
+    #[test]
+    fn early_return() {
+        let code = [
+            0x7E, /* ld a, [hl] */
+            0xFE, 0x05, /* cp 5 */
+            0x38, 0x03, /* jr c, .small */
+            0x3E, 0x01, /* ld a, 1 */
+            0xC9, /* ret */
+            0xAF, /* .small: xor a */
+            0xC9, /* ret */
+        ];
+        let pseudocode = decompile(&code, true);
+        assert_eq!(pseudocode.get_params(), &[Register::HL]);
+        assert_eq!(
+            lines(&pseudocode),
+            [
+                "void Function_000_0150(hl) {",
+                "    a = [hl];",
+                "    if (a < 5) {",
+                "        a = 0;",
+                "        return;",
+                "    }",
+                "    a = 1;",
+                "}",
+            ]
+        );
+    }
+
+    #[test]
+    fn hardware_names() {
+        let code = [
+            0xF0, 0x44, /* .wait: ldh a, [$FF44] */
+            0xFE, 0x90, /* cp $90 */
+            0x20, 0xFA, /* jr nz, .wait */
+            0xC9, /* ret */
+        ];
+        assert_eq!(lines(&decompile(&code, true))[2], "        a = rLY;");
+        assert_eq!(lines(&decompile(&code, false))[2], "        a = [$FF44];");
+        assert_eq!(lines(&decompile(&code, true))[3], "    } while (a != $90);");
+    }
+
+    #[test]
+    fn saved_registers() {
+        let code = [
+            0xC5, /* push bc */
+            0x06, 0x03, /* ld b, 3 */
+            0x05, /* dec b */
+            0xC1, /* pop bc */
+            0x3E, 0x01, /* ld a, 1 */
+            0xEA, 0x00, 0xC0, /* ld [$C000], a */
+            0xC9, /* ret */
+        ];
+        let pseudocode = decompile(&code, true);
+        /* Pushing BC does not make it a parameter. */
+        assert!(pseudocode.get_params().is_empty());
+        assert_eq!(
+            lines(&pseudocode),
+            [
+                "void Function_000_0150() {",
+                "    push(bc);",
+                "    b = 3;",
+                "    b = b - 1;",
+                "    bc = pop();",
+                "    a = 1;",
+                "    wC000 = a;",
+                "}",
+            ]
+        );
+    }
+}
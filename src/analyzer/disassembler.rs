@@ -1,20 +1,393 @@
+use std::collections::BTreeMap;
+
+use super::address::{Address, BANK_SIZE};
+use super::control_flow::ControlFlowGraph;
 use super::error::AnalyzerError;
 use super::instruction::Instruction;
+use super::project::{DataType, Project};
+use super::warning::Warning;
+
+#[derive(Debug)]
+pub enum Line {
+    Code {
+        offset: usize,
+        instruction: Instruction,
+    },
+    Data {
+        offset: usize,
+        bytes: Vec<u8>,
+        data_type: DataType,
+    },
+}
+
+#[derive(Debug)]
+pub struct Disassembly {
+    lines: Vec<Line>,
+    warnings: Vec<Warning>,
+}
+
+impl Disassembly {
+    pub fn get_lines(&self) -> &Vec<Line> {
+        &self.lines
+    }
+
+    pub fn get_warnings(&self) -> &Vec<Warning> {
+        &self.warnings
+    }
+}
 
 pub struct Disassembler;
 
 impl Disassembler {
-    pub fn disassemble(bytes: &Vec<u8>) -> Result<Vec<Instruction>, AnalyzerError> {
-        let mut instructions: Vec<Instruction> = Vec::new();
+    pub fn disassemble(bytes: &[u8], project: &Project) -> Result<Disassembly, AnalyzerError> {
+        Disassembler::disassemble_range(bytes, 0, bytes.len(), project)
+    }
+
+    /* Linear sweep over the offsets `start..end`, the last instruction may
+     * extend past `end`. Ranges typed as data in `project` are never decoded
+     * as instructions. */
+    pub fn disassemble_range(
+        bytes: &[u8],
+        start: usize,
+        end: usize,
+        project: &Project,
+    ) -> Result<Disassembly, AnalyzerError> {
+        if start >= end || end > bytes.len() {
+            return Err(AnalyzerError::InvalidRange(
+                Address::from_offset(start),
+                Address::from_offset(end),
+            ));
+        }
+
+        let mut lines: Vec<Line> = Vec::new();
+        let mut warnings: Vec<Warning> = Vec::new();
+
+        let mut i = start;
+        while i < end {
+            let address = Address::from_offset(i);
+            if let Some(range) = project.type_at(address) {
+                if range.entry().is_none() {
+                    let range_end = std::cmp::min(range.end().to_offset() + 1, end);
+                    lines.extend(Disassembler::data(
+                        bytes,
+                        i,
+                        range_end,
+                        range.data_type(),
+                        project,
+                    ));
+                    i = range_end;
+                    continue;
+                }
+            }
+            /* Instructions cannot run into the next data range. */
+            let limit = project
+                .get_types()
+                .range(address..)
+                .find(|(start, range)| start.bank() == address.bank() && range.entry().is_none())
+                .map(|(start, _)| start.to_offset());
 
-        let mut i = 0;
-        while i < bytes.len() {
-            let inst = Instruction::from_slice(&bytes[i..])?;
-            i += inst.size();
+            match Disassembler::decode_at(bytes, i) {
+                Ok(inst) if limit.is_some_and(|limit| i + inst.size() > limit) => {
+                    let limit = limit.unwrap();
+                    lines.push(Line::Data {
+                        offset: i,
+                        bytes: bytes[i..limit].to_vec(),
+                        data_type: DataType::Bytes,
+                    });
+                    i = limit;
+                }
+                Ok(inst) => {
+                    let size = inst.size();
+                    lines.push(Line::Code {
+                        offset: i,
+                        instruction: inst,
+                    });
+                    i += size;
+                }
+                Err(warning) => {
+                    /* Undecodable bytes are kept as data up to where decoding
+                     * can resume. */
+                    let size = match warning {
+                        Warning::TruncatedInstruction { available, .. } => available,
+                        _ => 1,
+                    };
+                    warnings.push(warning);
+                    lines.push(Line::Data {
+                        offset: i,
+                        bytes: bytes[i..i + size].to_vec(),
+                        data_type: DataType::Bytes,
+                    });
+                    i += size;
+                }
+            }
+        }
+
+        Ok(Disassembly { lines, warnings })
+    }
+
+    /* Splits `start..end` into lines of `data_type`: 8 bytes or 4 words, one
+     * pointer, one tile, or one string up to its terminator. Leftover bytes
+     * that do not make a whole item are plain bytes. */
+    fn data(
+        bytes: &[u8],
+        start: usize,
+        end: usize,
+        data_type: &DataType,
+        project: &Project,
+    ) -> Vec<Line> {
+        let terminator = match data_type {
+            DataType::Text(Some(name)) => project
+                .get_charmaps()
+                .get(name)
+                .and_then(|charmap| charmap.terminator()),
+            _ => Some(0x00),
+        };
 
-            instructions.push(inst);
+        let mut lines = Vec::new();
+        let mut i = start;
+        while i < end {
+            let (size, whole) = match data_type {
+                DataType::Code(_) | DataType::Bytes => (8, 1),
+                DataType::Words => (8, 2),
+                DataType::Pointers => (2, 2),
+                DataType::Gfx2bpp => (16, 16),
+                DataType::Text(_) => {
+                    let size = bytes[i..std::cmp::min(i + 64, end)]
+                        .iter()
+                        .position(|&byte| Some(byte) == terminator)
+                        .map_or(64, |position| position + 1);
+                    (size, 1)
+                }
+            };
+            let size = std::cmp::min(size, end - i);
+            let (size, data_type) = if size >= whole {
+                (size - size % whole, data_type.clone())
+            } else {
+                (size, DataType::Bytes)
+            };
+
+            lines.push(Line::Data {
+                offset: i,
+                bytes: bytes[i..i + size].to_vec(),
+                data_type,
+            });
+            i += size;
         }
 
-        Ok(instructions)
+        lines
+    }
+
+    /* Lists the instructions, jump and pointer tables and call arguments
+     * found by flow analysis, in address order. */
+    pub fn from_control_flow(cfg: &ControlFlowGraph, bytes: &[u8]) -> Disassembly {
+        let mut lines: Vec<Line> = cfg
+            .get_blocks()
+            .values()
+            .flat_map(|block| block.get_instructions())
+            .map(|(pc, inst)| Line::Code {
+                offset: pc.to_offset(),
+                instruction: inst.clone(),
+            })
+            .collect();
+
+        let tables: BTreeMap<usize, usize> = cfg
+            .get_jump_tables()
+            .values()
+            .map(|table| (table.start(), table.end()))
+            .chain(
+                cfg.get_pointer_tables()
+                    .values()
+                    .map(|table| (table.start(), table.end())),
+            )
+            .map(|(start, end)| (start.to_offset(), end.to_offset() + 1))
+            .collect();
+        for (start, end) in tables {
+            lines.extend((start..end).step_by(2).map(|offset| Line::Data {
+                offset,
+                bytes: bytes[offset..offset + 2].to_vec(),
+                data_type: DataType::Pointers,
+            }));
+        }
+        for arguments in cfg.get_arguments().values() {
+            let offset = arguments.start().to_offset();
+            lines.push(Line::Data {
+                offset,
+                bytes: bytes[offset..offset + arguments.size()].to_vec(),
+                data_type: DataType::of_arguments(arguments.kind()),
+            });
+        }
+        lines.sort_by_key(|line| match line {
+            Line::Code { offset, .. } | Line::Data { offset, .. } => *offset,
+        });
+
+        Disassembly {
+            lines,
+            warnings: cfg.get_warnings().clone(),
+        }
+    }
+
+    /* Decodes the instruction at `offset`. Instructions never continue into
+     * the next bank: it is not necessarily the one mapped after this one at
+     * runtime. */
+    pub fn decode_at(bytes: &[u8], offset: usize) -> Result<Instruction, Warning> {
+        let end = std::cmp::min((offset / BANK_SIZE + 1) * BANK_SIZE, bytes.len());
+        /* Nothing is left past the end of the file. */
+        let partial = bytes.get(offset..end).unwrap_or(&[]);
+
+        match Instruction::from_slice(partial) {
+            Ok(inst) => Ok(inst),
+            Err(AnalyzerError::InvalidOpcode(opcode)) => Err(Warning::InvalidOpcode {
+                address: Address::from_offset(offset),
+                opcode,
+            }),
+            Err(_) => Err(Warning::TruncatedInstruction {
+                address: Address::from_offset(offset),
+                available: partial.len(),
+                expected: Disassembler::expected_size(partial),
+                bank_boundary: end < bytes.len(),
+            }),
+        }
+    }
+
+    fn expected_size(partial: &[u8]) -> usize {
+        /* Decode again with zeroed operands, only the size is of interest. */
+        let mut padded = [0u8; 3];
+        padded[..partial.len()].copy_from_slice(partial);
+
+        match Instruction::from_slice(&padded) {
+            Ok(inst) => inst.size(),
+            Err(_) => padded.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::project::TypedRange;
+
+    #[test]
+    fn truncated_at_file_end() {
+        let bytes = [0x00, 0x3E, 0x01, 0xC3, 0x00];
+        let disassembly = Disassembler::disassemble(&bytes, &Project::new(&bytes)).unwrap();
+
+        let lines = disassembly.get_lines();
+        assert_eq!(lines.len(), 3);
+        match &lines[2] {
+            Line::Data { offset, bytes, .. } => {
+                assert_eq!(*offset, 3);
+                assert_eq!(bytes, &vec![0xC3, 0x00]);
+            }
+            line => panic!("expected data, got {:?}", line),
+        }
+        match disassembly.get_warnings()[..] {
+            [Warning::TruncatedInstruction {
+                available: 2,
+                expected: 3,
+                bank_boundary: false,
+                ..
+            }] => {}
+            ref warnings => panic!("unexpected warnings {:?}", warnings),
+        }
+    }
+
+    #[test]
+    fn past_file_end() {
+        let bytes = [0x00, 0x00];
+        match Disassembler::decode_at(&bytes, 2) {
+            Err(Warning::TruncatedInstruction {
+                available: 0,
+                expected: 1,
+                bank_boundary: false,
+                ..
+            }) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert!(Disassembler::decode_at(&bytes, 0x39).is_err());
+    }
+
+    #[test]
+    fn truncated_at_bank_end() {
+        let mut bytes = vec![0x00; 2 * BANK_SIZE];
+        bytes[BANK_SIZE - 1] = 0xCD;
+
+        match Disassembler::decode_at(&bytes, BANK_SIZE - 1) {
+            Err(Warning::TruncatedInstruction {
+                available: 1,
+                expected: 3,
+                bank_boundary: true,
+                ..
+            }) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+        assert_eq!(
+            Disassembler::decode_at(&bytes, BANK_SIZE).unwrap().size(),
+            1
+        );
+    }
+
+    #[test]
+    fn invalid_opcode() {
+        match Disassembler::decode_at(&[0xD3], 0) {
+            Err(Warning::InvalidOpcode { opcode: 0xD3, .. }) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    #[test]
+    fn range() {
+        let bytes = [0x00, 0x00, 0x3E, 0x01, 0xC9];
+        let project = Project::new(&bytes);
+
+        /* The last instruction runs past the end of the range. */
+        let disassembly = Disassembler::disassemble_range(&bytes, 1, 3, &project).unwrap();
+        let offsets: Vec<usize> = disassembly
+            .get_lines()
+            .iter()
+            .map(|line| match line {
+                Line::Code { offset, .. } | Line::Data { offset, .. } => *offset,
+            })
+            .collect();
+        assert_eq!(offsets, [1, 2]);
+
+        assert!(Disassembler::disassemble_range(&bytes, 3, 3, &project).is_err());
+        assert!(Disassembler::disassemble_range(&bytes, 0, 6, &project).is_err());
+    }
+
+    #[test]
+    fn data_ranges() {
+        let bytes = [
+            0x3E, 0x01, /* ld a, 1 */
+            0x34, 0x12, 0x78, 0x56, /* words */
+            0xC3, 0x00, /* jp cut short by the next range */
+            0xAA,
+        ];
+        let mut project = Project::new(&bytes);
+        let range = |start: u16, end: u16, data_type| {
+            TypedRange::new(Address::new(0, start), Address::new(0, end), data_type)
+        };
+        project.add_type(range(2, 5, DataType::Words)).unwrap();
+        project.add_type(range(8, 8, DataType::Bytes)).unwrap();
+
+        let disassembly = Disassembler::disassemble(&bytes, &project).unwrap();
+        let lines: Vec<(usize, Option<&DataType>)> = disassembly
+            .get_lines()
+            .iter()
+            .map(|line| match line {
+                Line::Code { offset, .. } => (*offset, None),
+                Line::Data {
+                    offset, data_type, ..
+                } => (*offset, Some(data_type)),
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                (0, None),
+                (2, Some(&DataType::Words)),
+                (6, Some(&DataType::Bytes)),
+                (8, Some(&DataType::Bytes)),
+            ]
+        );
     }
 }
@@ -0,0 +1,272 @@
+use super::address::Address;
+use super::emulator::Registers;
+use super::error::AnalyzerError;
+use super::format::Formatter;
+use super::instruction::Instruction;
+
+/* Instructions printed before the one that diverged. */
+pub const DOCTOR_CONTEXT: usize = 5;
+
+/* One line of a Gameboy Doctor log: the registers before an instruction and
+ * the 4 bytes at PC. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DoctorState {
+    line: usize,
+    registers: Registers,
+    pcmem: [u8; 4],
+}
+
+impl DoctorState {
+    /* `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100
+     * PCMEM:00,C3,13,02`, fields in any order. */
+    fn parse(line: usize, text: &str) -> Result<DoctorState, AnalyzerError> {
+        let invalid = || AnalyzerError::InvalidDoctorLog(line, text.to_string());
+        let field = |name: &str| {
+            text.split_whitespace()
+                .find_map(|token| token.strip_prefix(name))
+                .ok_or_else(invalid)
+        };
+        let byte = |name: &str| u8::from_str_radix(field(name)?, 16).map_err(|_| invalid());
+        let word = |name: &str| u16::from_str_radix(field(name)?, 16).map_err(|_| invalid());
+
+        let mut pcmem = [0; 4];
+        let bytes: Vec<&str> = field("PCMEM:")?.split(',').collect();
+        if bytes.len() != pcmem.len() {
+            return Err(invalid());
+        }
+        for (byte, hex) in pcmem.iter_mut().zip(bytes) {
+            *byte = u8::from_str_radix(hex, 16).map_err(|_| invalid())?;
+        }
+
+        Ok(DoctorState {
+            line,
+            registers: Registers {
+                a: byte("A:")?,
+                f: byte("F:")?,
+                b: byte("B:")?,
+                c: byte("C:")?,
+                d: byte("D:")?,
+                e: byte("E:")?,
+                h: byte("H:")?,
+                l: byte("L:")?,
+                sp: word("SP:")?,
+                pc: word("PC:")?,
+            },
+            pcmem,
+        })
+    }
+
+    /* Line of the state in its log, from 1. */
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn pcmem(&self) -> &[u8; 4] {
+        &self.pcmem
+    }
+
+    /* The instruction at PC, decoded from PCMEM. */
+    pub fn instruction(&self) -> Result<Instruction, AnalyzerError> {
+        Instruction::from_slice(&self.pcmem)
+    }
+
+    /* The instruction at PC as `formatter` prints it, or its first byte
+     * when it is not one. */
+    pub fn disassemble(&self, formatter: &Formatter) -> String {
+        match self.instruction() {
+            Ok(inst) => formatter.instruction(&inst, Address::new(0, self.registers.pc)),
+            Err(_) => formatter.data(&self.pcmem[..1]),
+        }
+    }
+
+    /* Names of the fields differing from `other`. */
+    pub fn differences(&self, other: &DoctorState) -> Vec<&'static str> {
+        let (ours, theirs) = (&self.registers, &other.registers);
+        [
+            ("A", ours.a == theirs.a),
+            ("F", ours.f == theirs.f),
+            ("B", ours.b == theirs.b),
+            ("C", ours.c == theirs.c),
+            ("D", ours.d == theirs.d),
+            ("E", ours.e == theirs.e),
+            ("H", ours.h == theirs.h),
+            ("L", ours.l == theirs.l),
+            ("SP", ours.sp == theirs.sp),
+            ("PC", ours.pc == theirs.pc),
+            ("PCMEM", self.pcmem == other.pcmem),
+        ]
+        .iter()
+        .filter(|(_, same)| !same)
+        .map(|&(name, _)| name)
+        .collect()
+    }
+}
+
+impl std::fmt::Display for DoctorState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let r = &self.registers;
+        write!(
+            f,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} \
+             SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            r.a,
+            r.f,
+            r.b,
+            r.c,
+            r.d,
+            r.e,
+            r.h,
+            r.l,
+            r.sp,
+            r.pc,
+            self.pcmem[0],
+            self.pcmem[1],
+            self.pcmem[2],
+            self.pcmem[3]
+        )
+    }
+}
+
+/* The states of a Gameboy Doctor log, one per instruction executed. */
+#[derive(Debug, Default)]
+pub struct DoctorLog {
+    states: Vec<DoctorState>,
+}
+
+/* First state where two logs disagree. The instruction before it, in both
+ * logs, made the difference. */
+#[derive(Debug)]
+pub struct Divergence {
+    index: usize,
+    expected: Option<DoctorState>,
+    actual: Option<DoctorState>,
+}
+
+impl DoctorLog {
+    /* Blank lines are skipped, any other line must be a state. */
+    pub fn parse(text: &str) -> Result<DoctorLog, AnalyzerError> {
+        let states = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| DoctorState::parse(i + 1, line.trim()))
+            .collect::<Result<Vec<DoctorState>, AnalyzerError>>()?;
+
+        Ok(DoctorLog { states })
+    }
+
+    pub fn get_states(&self) -> &Vec<DoctorState> {
+        &self.states
+    }
+
+    /* First state of `actual` differing from the one of `self`, or where one
+     * log ends before the other. None when they are the same. */
+    pub fn compare(&self, actual: &DoctorLog) -> Option<Divergence> {
+        let index =
+            (0..std::cmp::max(self.states.len(), actual.states.len())).find(|&i| {
+                match (self.states.get(i), actual.states.get(i)) {
+                    (Some(expected), Some(actual)) => !expected.differences(actual).is_empty(),
+                    _ => true,
+                }
+            })?;
+
+        Some(Divergence {
+            index,
+            expected: self.states.get(index).copied(),
+            actual: actual.states.get(index).copied(),
+        })
+    }
+}
+
+impl Divergence {
+    /* Instructions executed before the state that differs. */
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /* The state of each log, None where it had ended. */
+    pub fn expected(&self) -> Option<&DoctorState> {
+        self.expected.as_ref()
+    }
+
+    pub fn actual(&self) -> Option<&DoctorState> {
+        self.actual.as_ref()
+    }
+
+    /* Names of the fields that differ, empty when a log had ended. */
+    pub fn differences(&self) -> Vec<&'static str> {
+        match (&self.expected, &self.actual) {
+            (Some(expected), Some(actual)) => expected.differences(actual),
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::format::Syntax;
+
+    const LOG: &str = "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,50,01\n\
+                       A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0101 PCMEM:C3,50,01,CE\n\
+                       \n\
+                       A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0150 PCMEM:FE,11,28,08\n";
+
+    #[test]
+    fn parse() {
+        let log = DoctorLog::parse(LOG).unwrap();
+        let states = log.get_states();
+        assert_eq!(states.len(), 3);
+        assert_eq!(states[2].line(), 4);
+        assert_eq!(states[2].registers().pc, 0x0150);
+        assert_eq!(states[0].registers(), &Registers::default());
+        assert_eq!(states[1].to_string(), LOG.lines().nth(1).unwrap());
+
+        let mut formatter = Formatter::new(Syntax::Rgbds);
+        formatter.set_hardware_names(false);
+        assert_eq!(states[1].disassemble(&formatter), "jp $0150");
+        assert_eq!(states[2].disassemble(&formatter), "cp $11");
+
+        /* Fields can come in any order. */
+        let state = DoctorState::parse(
+            1,
+            "PCMEM:00,00,00,00 PC:0100 SP:FFFE A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D",
+        )
+        .unwrap();
+        assert_eq!(state.registers(), &Registers::default());
+
+        for line in &[
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PCMEM:00,00,00,00",
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,00,00",
+            "A:G1 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,00,00,00",
+        ] {
+            assert!(matches!(
+                DoctorLog::parse(&format!("{}\n{}", LOG.lines().next().unwrap(), line)),
+                Err(AnalyzerError::InvalidDoctorLog(2, _))
+            ));
+        }
+    }
+
+    #[test]
+    fn compare() {
+        let expected = DoctorLog::parse(LOG).unwrap();
+        assert!(expected.compare(&DoctorLog::parse(LOG).unwrap()).is_none());
+
+        let actual =
+            DoctorLog::parse(&LOG.replace("PC:0150 PCMEM:FE", "PC:0153 PCMEM:FF")).unwrap();
+        let divergence = expected.compare(&actual).unwrap();
+        assert_eq!(divergence.index(), 2);
+        assert_eq!(divergence.differences(), ["PC", "PCMEM"]);
+
+        let shorter = DoctorLog::parse(LOG.lines().next().unwrap()).unwrap();
+        let divergence = expected.compare(&shorter).unwrap();
+        assert_eq!(divergence.index(), 1);
+        assert!(divergence.actual().is_none());
+        assert_eq!(divergence.expected().unwrap().registers().pc, 0x0101);
+        assert!(divergence.differences().is_empty());
+    }
+}
@@ -0,0 +1,838 @@
+use super::address::BANK_SIZE;
+use super::error::AnalyzerError;
+use super::instruction::{Condition, Flow, Instruction, Mnemonic, Operand, Register};
+
+const FLAG_Z: u8 = 0x80;
+const FLAG_N: u8 = 0x40;
+const FLAG_H: u8 = 0x20;
+const FLAG_C: u8 = 0x10;
+
+const IF: u16 = 0xFF0F;
+const IE: u16 = 0xFFFF;
+const KEY1: u16 = 0xFF4D;
+/* Cycles taken to push PC and jump to an interrupt vector. */
+const DISPATCH_CYCLES: usize = 20;
+
+/* Memory seen by the CPU. */
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, value: u8);
+}
+
+/* A cartridge with the ROM bank selected by writes to $2000-$3FFF, as most
+ * MBCs do, and RAM at $8000-$FFFF without any hardware behind it. */
+pub struct Memory<'a> {
+    rom: &'a [u8],
+    bank: usize,
+    ram: Vec<u8>,
+}
+
+impl<'a> Memory<'a> {
+    pub fn new(rom: &'a [u8]) -> Memory<'a> {
+        Memory {
+            rom,
+            bank: 1,
+            ram: vec![0; 0x8000],
+        }
+    }
+
+    /* The ROM bank mapped at $4000-$7FFF. */
+    pub fn bank(&self) -> usize {
+        self.bank
+    }
+
+    /* The bytes of $8000-$FFFF. */
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+}
+
+impl<'a> Bus for Memory<'a> {
+    fn read(&mut self, addr: u16) -> u8 {
+        let offset = match addr {
+            0x0000..=0x3FFF => addr as usize,
+            0x4000..=0x7FFF => self.bank * BANK_SIZE + addr as usize - 0x4000,
+            _ => return self.ram[addr as usize - 0x8000],
+        };
+        self.rom.get(offset).copied().unwrap_or(0xFF)
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x2000..=0x3FFF => {
+                let banks = std::cmp::max(self.rom.len() / BANK_SIZE, 2);
+                self.bank = std::cmp::max(value as usize % banks, 1);
+            }
+            0x0000..=0x7FFF => {}
+            _ => self.ram[addr as usize - 0x8000] = value,
+        }
+    }
+}
+
+/* Registers of the SM83, F holds the flags in its high nibble. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+}
+
+impl Default for Registers {
+    /* The state the DMG boot ROM leaves. */
+    fn default() -> Registers {
+        Registers {
+            a: 0x01,
+            f: 0xB0,
+            b: 0x00,
+            c: 0x13,
+            d: 0x00,
+            e: 0xD8,
+            h: 0x01,
+            l: 0x4D,
+            sp: 0xFFFE,
+            pc: 0x0100,
+        }
+    }
+}
+
+impl Registers {
+    pub fn get(&self, reg: Register) -> u16 {
+        let pair = |high: u8, low: u8| u16::from_be_bytes([high, low]);
+        match reg {
+            Register::AF => pair(self.a, self.f),
+            Register::A => self.a as u16,
+            Register::F => self.f as u16,
+            Register::BC => pair(self.b, self.c),
+            Register::B => self.b as u16,
+            Register::C => self.c as u16,
+            Register::DE => pair(self.d, self.e),
+            Register::D => self.d as u16,
+            Register::E => self.e as u16,
+            Register::HL => pair(self.h, self.l),
+            Register::H => self.h as u16,
+            Register::L => self.l as u16,
+            Register::SP => self.sp,
+        }
+    }
+
+    /* Sets `reg`, 8-bit registers take the low byte of `value`. */
+    pub fn set(&mut self, reg: Register, value: u16) {
+        let [high, low] = value.to_be_bytes();
+        match reg {
+            Register::AF => {
+                self.a = high;
+                self.f = low & 0xF0;
+            }
+            Register::A => self.a = low,
+            Register::F => self.f = low & 0xF0,
+            Register::BC => {
+                self.b = high;
+                self.c = low;
+            }
+            Register::B => self.b = low,
+            Register::C => self.c = low,
+            Register::DE => {
+                self.d = high;
+                self.e = low;
+            }
+            Register::D => self.d = low,
+            Register::E => self.e = low,
+            Register::HL => {
+                self.h = high;
+                self.l = low;
+            }
+            Register::H => self.h = low,
+            Register::L => self.l = low,
+            Register::SP => self.sp = value,
+        }
+    }
+
+    pub fn flag(&self, flag: u8) -> bool {
+        self.f & flag != 0
+    }
+
+    fn set_flags(&mut self, z: bool, n: bool, h: bool, c: bool) {
+        self.f = (z as u8) << 7 | (n as u8) << 6 | (h as u8) << 5 | (c as u8) << 4;
+    }
+
+    fn condition(&self, condition: Condition) -> bool {
+        match condition {
+            Condition::Z => self.flag(FLAG_Z),
+            Condition::NZ => !self.flag(FLAG_Z),
+            Condition::C => self.flag(FLAG_C),
+            Condition::NC => !self.flag(FLAG_C),
+        }
+    }
+}
+
+/* SM83 interpreter running the decoded instructions, with the cycles of the
+ * instruction table. Interrupts are taken from IE and IF on the bus, the
+ * hardware raising them is up to the bus. */
+#[derive(Debug, Clone, Default)]
+pub struct Cpu {
+    registers: Registers,
+    ime: bool,
+    /* `ei` enables interrupts after the next instruction. */
+    ei_delay: bool,
+    halted: bool,
+    /* `halt` with interrupts disabled and one pending does not advance PC
+     * past the next opcode, which is read twice. */
+    halt_bug: bool,
+    stopped: bool,
+    double_speed: bool,
+    cycles: u64,
+}
+
+impl Cpu {
+    pub fn new(registers: Registers) -> Cpu {
+        Cpu {
+            registers,
+            ..Cpu::default()
+        }
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    /* Interrupt master enable. */
+    pub fn ime(&self) -> bool {
+        self.ime
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /* Whether a CGB speed switch happened. */
+    pub fn double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    /* Cycles run since the start. */
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /* Runs one instruction, or dispatches an interrupt, or waits 4 cycles
+     * while halted or stopped. Returns the cycles taken. */
+    pub fn step<B: Bus>(&mut self, bus: &mut B) -> Result<usize, AnalyzerError> {
+        let cycles = self.run(bus)?;
+        self.cycles += cycles as u64;
+
+        Ok(cycles)
+    }
+
+    /* Calls the routine at `addr` and runs until it returns, at most
+     * `max_cycles`. Returns the cycles taken. */
+    pub fn call<B: Bus>(
+        &mut self,
+        bus: &mut B,
+        addr: u16,
+        max_cycles: u64,
+    ) -> Result<u64, AnalyzerError> {
+        let (ret, sp) = (self.registers.pc, self.registers.sp);
+        self.push(bus, ret);
+        self.registers.pc = addr;
+
+        let start = self.cycles;
+        while self.registers.pc != ret || self.registers.sp != sp {
+            if self.cycles - start >= max_cycles {
+                return Err(AnalyzerError::EmulationLimit(self.registers.pc, max_cycles));
+            }
+            self.step(bus)?;
+        }
+
+        Ok(self.cycles - start)
+    }
+
+    fn run<B: Bus>(&mut self, bus: &mut B) -> Result<usize, AnalyzerError> {
+        let pending = bus.read(IE) & bus.read(IF) & 0x1F;
+
+        if self.stopped {
+            /* Only a joypad press leaves STOP. */
+            if bus.read(IF) & 0x10 == 0 {
+                return Ok(4);
+            }
+            self.stopped = false;
+        }
+        if self.halted {
+            if pending == 0 {
+                return Ok(4);
+            }
+            self.halted = false;
+        }
+        if self.ime && pending != 0 {
+            let interrupt = pending.trailing_zeros() as u16;
+            let flags = bus.read(IF);
+            bus.write(IF, flags & !(1 << interrupt));
+            self.ime = false;
+            self.ei_delay = false;
+            let pc = self.registers.pc;
+            self.push(bus, pc);
+            self.registers.pc = 0x0040 + 8 * interrupt;
+            return Ok(DISPATCH_CYCLES);
+        }
+
+        let pc = self.registers.pc;
+        let mut bytes = [0; 3];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = bus.read(pc.wrapping_add(i as u16));
+        }
+        let mut next = 0;
+        if std::mem::take(&mut self.halt_bug) {
+            bytes = [bytes[0], bytes[0], bytes[1]];
+            next = 1;
+        }
+        let inst = Instruction::from_slice(&bytes)?;
+        self.registers.pc = pc.wrapping_add((inst.size() - next) as u16);
+
+        let enable = std::mem::take(&mut self.ei_delay);
+        let cycles = self.execute(bus, &inst, pending)?;
+        if enable && *inst.mnemonic() != Mnemonic::DI {
+            self.ime = true;
+        }
+
+        Ok(cycles)
+    }
+
+    fn execute<B: Bus>(
+        &mut self,
+        bus: &mut B,
+        inst: &Instruction,
+        pending: u8,
+    ) -> Result<usize, AnalyzerError> {
+        let (cycles, taken_cycles) = inst.branch_cycles();
+        let (lhs, rhs) = (inst.lhs(), inst.rhs());
+        let next = self.registers.pc;
+        let r = &mut self.registers;
+        let carry = r.flag(FLAG_C);
+
+        match inst.mnemonic() {
+            Mnemonic::NOP => {}
+            Mnemonic::STOP => {
+                let key1 = bus.read(KEY1);
+                if key1 & 0x01 != 0 {
+                    self.double_speed = !self.double_speed;
+                    /* Bit 7 reads back the current speed, the switch is
+                     * disarmed. */
+                    let speed = if self.double_speed { 0x80 } else { 0 };
+                    bus.write(KEY1, (key1 & 0x7E) | speed);
+                } else {
+                    self.stopped = true;
+                }
+            }
+            Mnemonic::HALT => {
+                if !self.ime && pending != 0 {
+                    self.halt_bug = true;
+                } else {
+                    self.halted = true;
+                }
+            }
+            Mnemonic::DI => {
+                self.ime = false;
+                self.ei_delay = false;
+            }
+            Mnemonic::EI => self.ei_delay = !self.ime,
+            Mnemonic::LD => match (lhs, rhs) {
+                (Some(Operand::DerefAddr16(addr)), Some(Operand::Reg(Register::SP))) => {
+                    let [low, high] = r.sp.to_le_bytes();
+                    bus.write(*addr, low);
+                    bus.write(addr.wrapping_add(1), high);
+                }
+                (Some(Operand::Reg(Register::HL)), Some(Operand::SPRel8(offset))) => {
+                    let value = self.add_sp(*offset);
+                    self.registers.set(Register::HL, value);
+                }
+                (Some(Operand::Reg(reg)), Some(Operand::Imm16(value))) => r.set(*reg, *value),
+                (Some(Operand::Reg(Register::SP)), Some(Operand::Reg(Register::HL))) => {
+                    r.sp = r.get(Register::HL)
+                }
+                (Some(lhs), Some(rhs)) => {
+                    let value = self.read(bus, rhs);
+                    self.write(bus, lhs, value);
+                }
+                _ => {}
+            },
+            Mnemonic::LDHL | Mnemonic::LDHR => {
+                if let (Some(lhs), Some(rhs)) = (lhs, rhs) {
+                    let value = self.read(bus, rhs);
+                    self.write(bus, lhs, value);
+                }
+            }
+            Mnemonic::LDIL | Mnemonic::LDDL | Mnemonic::LDIR | Mnemonic::LDDR => {
+                let hl = r.get(Register::HL);
+                match inst.mnemonic() {
+                    Mnemonic::LDIL | Mnemonic::LDDL => bus.write(hl, r.a),
+                    _ => r.a = bus.read(hl),
+                }
+                let hl = match inst.mnemonic() {
+                    Mnemonic::LDIL | Mnemonic::LDIR => hl.wrapping_add(1),
+                    _ => hl.wrapping_sub(1),
+                };
+                r.set(Register::HL, hl);
+            }
+            Mnemonic::JR
+            | Mnemonic::JP
+            | Mnemonic::JRNZ
+            | Mnemonic::JRZ
+            | Mnemonic::JRNC
+            | Mnemonic::JRC
+            | Mnemonic::JPNZ
+            | Mnemonic::JPZ
+            | Mnemonic::JPNC
+            | Mnemonic::JPC => {
+                let condition = match inst.mnemonic() {
+                    Mnemonic::JRNZ | Mnemonic::JPNZ => Some(Condition::NZ),
+                    Mnemonic::JRZ | Mnemonic::JPZ => Some(Condition::Z),
+                    Mnemonic::JRNC | Mnemonic::JPNC => Some(Condition::NC),
+                    Mnemonic::JRC | Mnemonic::JPC => Some(Condition::C),
+                    _ => None,
+                };
+                if condition.is_some_and(|condition| !r.condition(condition)) {
+                    return Ok(cycles);
+                }
+                r.pc = match inst.flow(next.wrapping_sub(inst.size() as u16)) {
+                    Flow::Jump(Some(target)) | Flow::Branch(target) => target,
+                    _ => r.get(Register::HL),
+                };
+                return Ok(taken_cycles);
+            }
+            Mnemonic::CALL | Mnemonic::RST => {
+                if let Some(Operand::Cond(condition)) = lhs {
+                    if !r.condition(*condition) {
+                        return Ok(cycles);
+                    }
+                }
+                if let Flow::Call(target) = inst.flow(next.wrapping_sub(inst.size() as u16)) {
+                    self.push(bus, next);
+                    self.registers.pc = target;
+                }
+                return Ok(taken_cycles);
+            }
+            Mnemonic::RET | Mnemonic::RETI => {
+                if let Some(Operand::Cond(condition)) = lhs {
+                    if !r.condition(*condition) {
+                        return Ok(cycles);
+                    }
+                }
+                if *inst.mnemonic() == Mnemonic::RETI {
+                    self.ime = true;
+                }
+                self.registers.pc = self.pop(bus);
+                return Ok(taken_cycles);
+            }
+            Mnemonic::PUSH => {
+                if let Some(Operand::Reg(reg)) = lhs {
+                    let value = r.get(*reg);
+                    self.push(bus, value);
+                }
+            }
+            Mnemonic::POP => {
+                if let Some(Operand::Reg(reg)) = lhs {
+                    let value = self.pop(bus);
+                    self.registers.set(*reg, value);
+                }
+            }
+            Mnemonic::ADD => match (lhs, rhs) {
+                (Some(Operand::Reg(Register::HL)), Some(Operand::Reg(reg))) => {
+                    let (hl, value) = (r.get(Register::HL), r.get(*reg));
+                    let (result, c) = hl.overflowing_add(value);
+                    let h = (hl & 0x0FFF) + (value & 0x0FFF) > 0x0FFF;
+                    let z = r.flag(FLAG_Z);
+                    r.set_flags(z, false, h, c);
+                    r.set(Register::HL, result);
+                }
+                (Some(Operand::Reg(Register::SP)), Some(Operand::Rel8(offset))) => {
+                    self.registers.sp = self.add_sp(*offset);
+                }
+                (_, Some(rhs)) => {
+                    let value = self.read(bus, rhs);
+                    self.add(value, false);
+                }
+                _ => {}
+            },
+            Mnemonic::ADC => {
+                if let Some(rhs) = rhs {
+                    let value = self.read(bus, rhs);
+                    self.add(value, carry);
+                }
+            }
+            Mnemonic::SUB | Mnemonic::CP => {
+                if let Some(lhs) = lhs {
+                    let value = self.read(bus, lhs);
+                    let result = self.sub(value, false);
+                    if *inst.mnemonic() == Mnemonic::SUB {
+                        self.registers.a = result;
+                    }
+                }
+            }
+            Mnemonic::SBC => {
+                if let Some(rhs) = rhs {
+                    let value = self.read(bus, rhs);
+                    self.registers.a = self.sub(value, carry);
+                }
+            }
+            Mnemonic::AND | Mnemonic::OR | Mnemonic::XOR => {
+                if let Some(lhs) = lhs {
+                    let value = self.read(bus, lhs);
+                    let r = &mut self.registers;
+                    r.a = match inst.mnemonic() {
+                        Mnemonic::AND => r.a & value,
+                        Mnemonic::OR => r.a | value,
+                        _ => r.a ^ value,
+                    };
+                    let and = *inst.mnemonic() == Mnemonic::AND;
+                    r.set_flags(r.a == 0, false, and, false);
+                }
+            }
+            Mnemonic::INC | Mnemonic::DEC => {
+                let inc = *inst.mnemonic() == Mnemonic::INC;
+                match lhs {
+                    Some(Operand::Reg(reg @ Register::BC))
+                    | Some(Operand::Reg(reg @ Register::DE))
+                    | Some(Operand::Reg(reg @ Register::HL))
+                    | Some(Operand::Reg(reg @ Register::SP)) => {
+                        let value = r.get(*reg);
+                        let value = if inc {
+                            value.wrapping_add(1)
+                        } else {
+                            value.wrapping_sub(1)
+                        };
+                        r.set(*reg, value);
+                    }
+                    Some(operand) => {
+                        let value = self.read(bus, operand);
+                        let (result, h) = if inc {
+                            (value.wrapping_add(1), value & 0x0F == 0x0F)
+                        } else {
+                            (value.wrapping_sub(1), value & 0x0F == 0)
+                        };
+                        self.write(bus, operand, result);
+                        self.registers.set_flags(result == 0, !inc, h, carry);
+                    }
+                    None => {}
+                }
+            }
+            Mnemonic::RLCA | Mnemonic::RRCA | Mnemonic::RLA | Mnemonic::RRA => {
+                let mnemonic = match inst.mnemonic() {
+                    Mnemonic::RLCA => Mnemonic::RLC,
+                    Mnemonic::RRCA => Mnemonic::RRC,
+                    Mnemonic::RLA => Mnemonic::RL,
+                    _ => Mnemonic::RR,
+                };
+                let (result, c) = Cpu::shift(mnemonic, r.a, carry);
+                r.a = result;
+                r.set_flags(false, false, false, c);
+            }
+            Mnemonic::RLC
+            | Mnemonic::RRC
+            | Mnemonic::RL
+            | Mnemonic::RR
+            | Mnemonic::SLA
+            | Mnemonic::SRA
+            | Mnemonic::SWAP
+            | Mnemonic::SRL => {
+                if let Some(lhs) = lhs {
+                    let value = self.read(bus, lhs);
+                    let (result, c) = Cpu::shift(*inst.mnemonic(), value, carry);
+                    self.write(bus, lhs, result);
+                    self.registers.set_flags(result == 0, false, false, c);
+                }
+            }
+            Mnemonic::BIT | Mnemonic::RES | Mnemonic::SET => {
+                if let (Some(Operand::Bit(bit)), Some(target)) = (lhs, rhs) {
+                    let value = self.read(bus, target);
+                    match inst.mnemonic() {
+                        Mnemonic::BIT => {
+                            let z = value & (1 << bit) == 0;
+                            self.registers.set_flags(z, false, true, carry);
+                        }
+                        Mnemonic::RES => self.write(bus, target, value & !(1 << bit)),
+                        _ => self.write(bus, target, value | (1 << bit)),
+                    }
+                }
+            }
+            Mnemonic::DA => {
+                let (n, h) = (r.flag(FLAG_N), r.flag(FLAG_H));
+                let mut adjust = 0;
+                let mut c = carry;
+                if h || (!n && r.a & 0x0F > 0x09) {
+                    adjust |= 0x06;
+                }
+                if carry || (!n && r.a > 0x99) {
+                    adjust |= 0x60;
+                    c = true;
+                }
+                r.a = if n {
+                    r.a.wrapping_sub(adjust)
+                } else {
+                    r.a.wrapping_add(adjust)
+                };
+                r.set_flags(r.a == 0, n, false, c);
+            }
+            Mnemonic::CPL => {
+                r.a = !r.a;
+                r.f |= FLAG_N | FLAG_H;
+            }
+            Mnemonic::SCF => r.f = (r.f & FLAG_Z) | FLAG_C,
+            Mnemonic::CCF => r.f = (r.f & FLAG_Z) | if carry { 0 } else { FLAG_C },
+        }
+
+        Ok(cycles)
+    }
+
+    /* Value of an 8-bit operand, DerefReg(C) and DerefAddr8 are in
+     * $FF00-$FFFF. */
+    fn read<B: Bus>(&self, bus: &mut B, operand: &Operand) -> u8 {
+        match operand {
+            Operand::Reg(reg) => self.registers.get(*reg) as u8,
+            Operand::DerefReg(Register::C) => bus.read(0xFF00 | self.registers.c as u16),
+            Operand::DerefReg(reg) => bus.read(self.registers.get(*reg)),
+            Operand::DerefAddr8(addr) => bus.read(0xFF00 | *addr as u16),
+            Operand::DerefAddr16(addr) => bus.read(*addr),
+            Operand::Imm8(value) => *value,
+            _ => 0xFF,
+        }
+    }
+
+    fn write<B: Bus>(&mut self, bus: &mut B, operand: &Operand, value: u8) {
+        match operand {
+            Operand::Reg(reg) => self.registers.set(*reg, value as u16),
+            Operand::DerefReg(Register::C) => bus.write(0xFF00 | self.registers.c as u16, value),
+            Operand::DerefReg(reg) => bus.write(self.registers.get(*reg), value),
+            Operand::DerefAddr8(addr) => bus.write(0xFF00 | *addr as u16, value),
+            Operand::DerefAddr16(addr) => bus.write(*addr, value),
+            _ => {}
+        }
+    }
+
+    fn push<B: Bus>(&mut self, bus: &mut B, value: u16) {
+        let [high, low] = value.to_be_bytes();
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        bus.write(self.registers.sp, high);
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        bus.write(self.registers.sp, low);
+    }
+
+    fn pop<B: Bus>(&mut self, bus: &mut B) -> u16 {
+        let low = bus.read(self.registers.sp);
+        self.registers.sp = self.registers.sp.wrapping_add(1);
+        let high = bus.read(self.registers.sp);
+        self.registers.sp = self.registers.sp.wrapping_add(1);
+
+        u16::from_be_bytes([high, low])
+    }
+
+    fn add(&mut self, value: u8, carry: bool) {
+        let r = &mut self.registers;
+        let result = r.a as u16 + value as u16 + carry as u16;
+        let h = (r.a & 0x0F) + (value & 0x0F) + carry as u8 > 0x0F;
+        r.a = result as u8;
+        r.set_flags(r.a == 0, false, h, result > 0xFF);
+    }
+
+    /* A minus `value` and the borrow, flags set as by `sub`. */
+    fn sub(&mut self, value: u8, carry: bool) -> u8 {
+        let r = &mut self.registers;
+        let result = r.a.wrapping_sub(value).wrapping_sub(carry as u8);
+        let h = (r.a & 0x0F) < (value & 0x0F) + carry as u8;
+        let c = (r.a as u16) < value as u16 + carry as u16;
+        r.set_flags(result == 0, true, h, c);
+
+        result
+    }
+
+    /* SP plus a signed offset, flags from the addition of the low byte. */
+    fn add_sp(&mut self, offset: u8) -> u16 {
+        let r = &mut self.registers;
+        let h = (r.sp & 0x0F) + (offset as u16 & 0x0F) > 0x0F;
+        let c = (r.sp & 0xFF) + offset as u16 > 0xFF;
+        r.set_flags(false, false, h, c);
+
+        r.sp.wrapping_add(offset as i8 as u16)
+    }
+
+    /* Rotations and shifts of CB instructions: the result and carry. */
+    fn shift(mnemonic: Mnemonic, value: u8, carry: bool) -> (u8, bool) {
+        match mnemonic {
+            Mnemonic::RLC => (value.rotate_left(1), value & 0x80 != 0),
+            Mnemonic::RRC => (value.rotate_right(1), value & 0x01 != 0),
+            Mnemonic::RL => (value << 1 | carry as u8, value & 0x80 != 0),
+            Mnemonic::RR => (value >> 1 | (carry as u8) << 7, value & 0x01 != 0),
+            Mnemonic::SLA => (value << 1, value & 0x80 != 0),
+            Mnemonic::SRA => (value >> 1 | (value & 0x80), value & 0x01 != 0),
+            Mnemonic::SWAP => (value.rotate_left(4), false),
+            _ => (value >> 1, value & 0x01 != 0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Runs `steps` steps of `code` placed at $0100. */
+    fn run(code: &[u8], steps: usize, setup: impl FnOnce(&mut Cpu, &mut Memory)) -> (Cpu, Vec<u8>) {
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0100..0x0100 + code.len()].copy_from_slice(code);
+        let mut memory = Memory::new(&rom);
+        let mut cpu = Cpu::new(Registers::default());
+        setup(&mut cpu, &mut memory);
+        for _ in 0..steps {
+            cpu.step(&mut memory).unwrap();
+        }
+
+        (cpu, memory.ram().to_vec())
+    }
+
+    fn flags(cpu: &Cpu) -> u8 {
+        cpu.registers().f
+    }
+
+    #[test]
+    fn daa() {
+        /* ld a, $15, add a, $27, daa */
+        let (cpu, _) = run(&[0x3E, 0x15, 0xC6, 0x27, 0x27], 3, |_, _| {});
+        assert_eq!(cpu.registers().a, 0x42);
+        assert_eq!(flags(&cpu), 0x00);
+
+        /* ld a, $90, add a, $90, daa */
+        let (cpu, _) = run(&[0x3E, 0x90, 0xC6, 0x90, 0x27], 3, |_, _| {});
+        assert_eq!(cpu.registers().a, 0x80);
+        assert_eq!(flags(&cpu), FLAG_C);
+
+        /* ld a, $42, sub $15, daa */
+        let (cpu, _) = run(&[0x3E, 0x42, 0xD6, 0x15, 0x27], 3, |_, _| {});
+        assert_eq!(cpu.registers().a, 0x27);
+        assert_eq!(flags(&cpu), FLAG_N);
+
+        /* ld a, $10, sub $20, daa */
+        let (cpu, _) = run(&[0x3E, 0x10, 0xD6, 0x20, 0x27], 3, |_, _| {});
+        assert_eq!(cpu.registers().a, 0x90);
+        assert_eq!(flags(&cpu), FLAG_N | FLAG_C);
+
+        /* ld a, $50, add a, $50, daa: a zero result */
+        let (cpu, _) = run(&[0x3E, 0x50, 0xC6, 0x50, 0x27], 3, |_, _| {});
+        assert_eq!(cpu.registers().a, 0x00);
+        assert_eq!(flags(&cpu), FLAG_Z | FLAG_C);
+    }
+
+    #[test]
+    fn stack_pointer_offsets() {
+        /* add sp, 8: carries out of both nibbles of the low byte */
+        let (cpu, _) = run(&[0xE8, 0x08], 1, |cpu, _| cpu.registers_mut().sp = 0xFFF8);
+        assert_eq!(cpu.registers().sp, 0x0000);
+        assert_eq!(flags(&cpu), FLAG_H | FLAG_C);
+
+        /* add sp, -1: flags come from the low byte only */
+        let (cpu, _) = run(&[0xE8, 0xFF], 1, |cpu, _| cpu.registers_mut().sp = 0x0000);
+        assert_eq!(cpu.registers().sp, 0xFFFF);
+        assert_eq!(flags(&cpu), 0x00);
+
+        /* ld hl, sp+2 leaves SP alone */
+        let (cpu, _) = run(&[0xF8, 0x02], 1, |_, _| {});
+        assert_eq!(cpu.registers().get(Register::HL), 0x0000);
+        assert_eq!(cpu.registers().sp, 0xFFFE);
+        assert_eq!(flags(&cpu), FLAG_H | FLAG_C);
+
+        /* ld hl, sp-2 */
+        let (cpu, _) = run(&[0xF8, 0xFE], 1, |cpu, _| cpu.registers_mut().sp = 0xC002);
+        assert_eq!(cpu.registers().get(Register::HL), 0xC000);
+        assert_eq!(flags(&cpu), FLAG_H | FLAG_C);
+    }
+
+    #[test]
+    fn ei_delay() {
+        let pending = |_: &mut Cpu, memory: &mut Memory| {
+            memory.write(IE, 0x01);
+            memory.write(IF, 0x01);
+        };
+
+        /* ei, nop, nop */
+        let (cpu, _) = run(&[0xFB, 0x00, 0x00], 1, pending);
+        assert!(!cpu.ime());
+        let (cpu, _) = run(&[0xFB, 0x00, 0x00], 2, pending);
+        assert!(cpu.ime());
+        assert_eq!(cpu.registers().pc, 0x0102);
+
+        /* The instruction after `ei` runs before the interrupt. */
+        let (cpu, ram) = run(&[0xFB, 0x00, 0x00], 3, pending);
+        assert_eq!(cpu.registers().pc, 0x0040);
+        assert!(!cpu.ime());
+        assert_eq!(ram[(IF - 0x8000) as usize], 0x00);
+        assert_eq!(cpu.cycles(), 4 + 4 + DISPATCH_CYCLES as u64);
+
+        /* ei, di */
+        let (cpu, _) = run(&[0xFB, 0xF3, 0x00], 3, pending);
+        assert!(!cpu.ime());
+        assert_eq!(cpu.registers().pc, 0x0103);
+    }
+
+    #[test]
+    fn halt() {
+        /* halt, inc a: nothing pending, the CPU waits */
+        let (cpu, _) = run(&[0x76, 0x3C], 3, |_, _| {});
+        assert!(cpu.halted());
+        assert_eq!(cpu.registers().pc, 0x0101);
+        assert_eq!(cpu.registers().a, 0x01);
+
+        /* With IME off and an interrupt pending, the byte after `halt` is
+         * read twice. */
+        let (cpu, _) = run(&[0x76, 0x3C], 3, |_, memory| {
+            memory.write(IE, 0x04);
+            memory.write(IF, 0x04);
+        });
+        assert!(!cpu.halted());
+        assert_eq!(cpu.registers().a, 0x03);
+        assert_eq!(cpu.registers().pc, 0x0102);
+    }
+
+    #[test]
+    fn stop() {
+        let key1 = (KEY1 - 0x8000) as usize;
+
+        /* stop with the switch armed */
+        let (cpu, ram) = run(&[0x10, 0x00], 1, |_, memory| memory.write(KEY1, 0x01));
+        assert!(cpu.double_speed());
+        assert!(!cpu.stopped());
+        assert_eq!(ram[key1], 0x80);
+        assert_eq!(cpu.registers().pc, 0x0102);
+
+        /* The switch is disarmed, the second `stop` stops. */
+        let (cpu, ram) = run(&[0x10, 0x00, 0x10, 0x00], 2, |_, memory| {
+            memory.write(KEY1, 0x01)
+        });
+        assert!(cpu.double_speed());
+        assert!(cpu.stopped());
+        assert_eq!(ram[key1], 0x80);
+
+        /* Switching back clears bit 7. */
+        let (cpu, ram) = run(&[0x10, 0x00], 1, |cpu, memory| {
+            cpu.double_speed = true;
+            memory.write(KEY1, 0x81);
+        });
+        assert!(!cpu.double_speed());
+        assert_eq!(ram[key1], 0x00);
+
+        /* Without a switch armed the CPU stops until a joypad press. */
+        let (cpu, _) = run(&[0x10, 0x00, 0x3C], 3, |_, _| {});
+        assert!(cpu.stopped());
+        assert_eq!(cpu.registers().a, 0x01);
+    }
+}
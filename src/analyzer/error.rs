@@ -1,7 +1,26 @@
+use super::address::Address;
+
 pub enum AnalyzerError {
     InvalidOpcode(u8),
     InvalidInstructionSize(usize),
     InvalidCartridge(std::io::Error),
+    InvalidHeader(usize),
+    InvalidAddress(Address),
+    InvalidRange(Address, Address),
+    InvalidSymbolFile(std::io::Error),
+    InvalidSymbol(usize, String),
+    InvalidProjectFile(std::io::Error),
+    InvalidProject(usize, String),
+    /* SHA-1 the project was made for, SHA-1 of the ROM. */
+    ProjectMismatch(String, String),
+    InvalidCharmapFile(std::io::Error),
+    InvalidCharmap(usize, String),
+    InvalidCompressedData(Address, String),
+    InvalidTraceFile(std::io::Error),
+    InvalidTrace(String),
+    InvalidDoctorLog(usize, String),
+    /* PC when the limit of cycles was reached. */
+    EmulationLimit(u16, u64),
 }
 
 impl std::fmt::Display for AnalyzerError {
@@ -13,6 +32,68 @@ impl std::fmt::Display for AnalyzerError {
                 write!(f, "invalid cartridge, got: ")?;
                 e.fmt(f)
             }
+            Self::InvalidHeader(size) => write!(
+                f,
+                "cartridge is too small to contain a header: {} bytes",
+                size
+            ),
+            Self::InvalidAddress(address) => {
+                write!(f, "address {} is outside of the cartridge", address)
+            }
+            Self::InvalidRange(start, end) => write!(
+                f,
+                "invalid range {}-{}, it must be in a single bank and not empty",
+                start, end
+            ),
+            Self::InvalidSymbolFile(ref e) => {
+                write!(f, "cannot read symbol file: ")?;
+                e.fmt(f)
+            }
+            Self::InvalidSymbol(line, ref text) => write!(
+                f,
+                "symbol file line {}: expected `bank:address name`, got `{}`",
+                line, text
+            ),
+            Self::InvalidProjectFile(ref e) => {
+                write!(f, "cannot read project file: ")?;
+                e.fmt(f)
+            }
+            Self::InvalidProject(0, ref msg) => write!(f, "invalid project file: {}", msg),
+            Self::InvalidProject(line, ref msg) => {
+                write!(f, "project file line {}: {}", line, msg)
+            }
+            Self::ProjectMismatch(ref expected, ref actual) => write!(
+                f,
+                "project was made for the ROM with SHA-1 {}, this one is {}",
+                expected, actual
+            ),
+            Self::InvalidCharmapFile(ref e) => {
+                write!(f, "cannot read charmap: ")?;
+                e.fmt(f)
+            }
+            Self::InvalidCharmap(line, ref text) => write!(
+                f,
+                "charmap line {}: expected `charmap \"text\", value` or `XX=text`, got `{}`",
+                line, text
+            ),
+            Self::InvalidCompressedData(address, ref msg) => {
+                write!(f, "cannot decompress the data at {}: {}", address, msg)
+            }
+            Self::InvalidTraceFile(ref e) => {
+                write!(f, "cannot read trace: ")?;
+                e.fmt(f)
+            }
+            Self::InvalidTrace(ref msg) => write!(f, "invalid trace: {}", msg),
+            Self::InvalidDoctorLog(line, ref text) => write!(
+                f,
+                "log line {}: expected `A:01 F:B0 ... PC:0100 PCMEM:00,C3,13,02`, got `{}`",
+                line, text
+            ),
+            Self::EmulationLimit(pc, cycles) => write!(
+                f,
+                "emulation stopped at ${:04X} after {} cycles",
+                pc, cycles
+            ),
         }
     }
 }
@@ -29,6 +110,21 @@ impl std::error::Error for AnalyzerError {
             Self::InvalidOpcode(_) => None,
             Self::InvalidInstructionSize(_) => None,
             Self::InvalidCartridge(ref e) => Some(e),
+            Self::InvalidHeader(_) => None,
+            Self::InvalidAddress(_) => None,
+            Self::InvalidRange(_, _) => None,
+            Self::InvalidSymbolFile(ref e) => Some(e),
+            Self::InvalidSymbol(_, _) => None,
+            Self::InvalidProjectFile(ref e) => Some(e),
+            Self::InvalidProject(_, _) => None,
+            Self::ProjectMismatch(_, _) => None,
+            Self::InvalidCharmapFile(ref e) => Some(e),
+            Self::InvalidCharmap(_, _) => None,
+            Self::InvalidCompressedData(_, _) => None,
+            Self::InvalidTraceFile(ref e) => Some(e),
+            Self::InvalidTrace(_) => None,
+            Self::InvalidDoctorLog(_, _) => None,
+            Self::EmulationLimit(_, _) => None,
         }
     }
 }
@@ -0,0 +1,755 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use super::address::{Address, BANK_SIZE};
+use super::charmap::Charmap;
+use super::control_flow::{ControlFlowGraph, EdgeKind};
+use super::disassembler::{Disassembly, Line};
+use super::hardware;
+use super::instruction::{Condition, Instruction, Mnemonic, Operand, Register};
+use super::project::DataType;
+use super::symbols::SymbolTable;
+
+/* Assembler dialect used to print instructions. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    Rgbds, /* ld a, [hl+] */
+    Wla,   /* ldi a,(hl) */
+}
+
+impl std::str::FromStr for Syntax {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Syntax, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "rgbds" => Ok(Syntax::Rgbds),
+            "wla" | "wla-dx" | "wladx" => Ok(Syntax::Wla),
+            _ => Err(format!("unknown syntax `{}`, expected rgbds or wla", s)),
+        }
+    }
+}
+
+/* `text` as the inside of an assembler string literal. */
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\0' => escaped.push_str("\\0"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+pub struct Formatter {
+    syntax: Syntax,
+    hardware_names: bool,
+    symbols: SymbolTable,
+    comments: BTreeMap<Address, String>,
+    annotations: BTreeMap<Address, (Register, u16)>,
+    charmaps: BTreeMap<String, Charmap>,
+}
+
+impl Formatter {
+    pub fn new(syntax: Syntax) -> Formatter {
+        Formatter {
+            syntax,
+            hardware_names: true,
+            symbols: SymbolTable::new(),
+            comments: BTreeMap::new(),
+            annotations: BTreeMap::new(),
+            charmaps: BTreeMap::new(),
+        }
+    }
+
+    /* Names used for labels and memory operands. */
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = symbols;
+    }
+
+    pub fn get_symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /* Comments printed on their own line before the line at their address. */
+    pub fn set_comments(&mut self, comments: BTreeMap<Address, String>) {
+        self.comments = comments;
+    }
+
+    /* Values of the registers instructions use as addresses, printed after
+     * them as `; hl = wPlayerX`. */
+    pub fn set_annotations(&mut self, annotations: BTreeMap<Address, (Register, u16)>) {
+        self.annotations = annotations;
+    }
+
+    /* Charmaps of text ranges, by the name used in the project. */
+    pub fn set_charmaps(&mut self, charmaps: BTreeMap<String, Charmap>) {
+        self.charmaps = charmaps;
+    }
+
+    /* Print I/O register accesses by name (`ldh [rLCDC], a`), on by default. */
+    pub fn set_hardware_names(&mut self, enabled: bool) {
+        self.hardware_names = enabled;
+    }
+
+    /* Formats `inst` located at `pc`, the address is needed to resolve
+     * relative jumps and the bank of jump targets. */
+    pub fn instruction(&self, inst: &Instruction, pc: Address) -> String {
+        let mnemonic = self.mnemonic(inst);
+        let mut operands: Vec<String> = Vec::new();
+
+        if let Mnemonic::LDIL | Mnemonic::LDDL | Mnemonic::LDIR | Mnemonic::LDDR = inst.mnemonic() {
+            if self.syntax == Syntax::Rgbds {
+                let hl = match inst.mnemonic() {
+                    Mnemonic::LDIL | Mnemonic::LDIR => "[hl+]",
+                    _ => "[hl-]",
+                };
+                return match inst.mnemonic() {
+                    Mnemonic::LDIL | Mnemonic::LDDL => format!("ld {}, a", hl),
+                    _ => format!("ld a, {}", hl),
+                };
+            }
+        }
+
+        let conditional = match inst.mnemonic() {
+            Mnemonic::JRNZ | Mnemonic::JPNZ => Some(Condition::NZ),
+            Mnemonic::JRZ | Mnemonic::JPZ => Some(Condition::Z),
+            Mnemonic::JRNC | Mnemonic::JPNC => Some(Condition::NC),
+            Mnemonic::JRC | Mnemonic::JPC => Some(Condition::C),
+            _ => None,
+        };
+        if let Some(cond) = conditional {
+            operands.push(self.operand(inst, &Operand::Cond(cond), pc));
+        }
+
+        for operand in inst.lhs().iter().chain(inst.rhs().iter()) {
+            operands.push(self.operand(inst, operand, pc));
+        }
+
+        if operands.is_empty() {
+            mnemonic.to_string()
+        } else {
+            let separator = match self.syntax {
+                Syntax::Rgbds => ", ",
+                Syntax::Wla => ",",
+            };
+            format!("{} {}", mnemonic, operands.join(separator))
+        }
+    }
+
+    /* Writes an assembly listing, with a section for every contiguous run of
+     * lines and the address and raw bytes of each line in a comment. */
+    pub fn listing(
+        &self,
+        disassembly: &Disassembly,
+        bytes: &[u8],
+        out: &mut dyn Write,
+    ) -> std::io::Result<()> {
+        let mut next = None;
+        let mut scope: Option<&str> = None;
+
+        if self.hardware_names && self.syntax == Syntax::Rgbds {
+            writeln!(out, "INCLUDE \"hardware.inc\"")?;
+            writeln!(out)?;
+        }
+
+        /* Text is assembled with the charmap it was decoded with, ASCII
+         * text with the default one. */
+        let charmaps = self.charmap_names(disassembly);
+        let mut current = "main";
+        for (name, ident) in &charmaps {
+            writeln!(out, "NEWCHARMAP {}", ident)?;
+            for (&byte, chars) in self.charmaps[*name].get_chars() {
+                if Formatter::quoted(&self.charmaps[*name], byte, chars) {
+                    writeln!(out, "CHARMAP \"{}\", ${:02X}", escape(chars), byte)?;
+                }
+            }
+            writeln!(out)?;
+        }
+        if !charmaps.is_empty() {
+            writeln!(out, "SETCHARMAP main")?;
+            writeln!(out)?;
+        }
+
+        for line in disassembly.get_lines() {
+            let (offset, text, size) = match line {
+                Line::Code {
+                    offset,
+                    instruction,
+                } => {
+                    let pc = Address::from_offset(*offset);
+                    let size = instruction.size();
+                    /* Assemblers pad `stop` with $00, other padding bytes
+                     * only reassemble as data. */
+                    let text = match instruction.mnemonic() {
+                        Mnemonic::STOP if bytes.get(*offset + 1).is_some_and(|&b| b != 0) => {
+                            self.data(&bytes[*offset..*offset + size])
+                        }
+                        _ => self.instruction(instruction, pc),
+                    };
+                    (*offset, text, size)
+                }
+                Line::Data {
+                    offset,
+                    bytes,
+                    data_type,
+                } => {
+                    let pc = Address::from_offset(*offset);
+                    (*offset, self.typed_data(bytes, data_type, pc), bytes.len())
+                }
+            };
+            let address = Address::from_offset(offset);
+
+            if next != Some(offset) || offset % BANK_SIZE == 0 {
+                if next.is_some() {
+                    writeln!(out)?;
+                }
+                writeln!(out, "{}", self.section(address))?;
+                writeln!(out)?;
+            }
+            next = Some(offset + size);
+
+            if let Some(symbol) = self.symbols.get(address) {
+                let name = match symbol.parent() {
+                    Some(parent) if scope == Some(parent) && self.syntax == Syntax::Rgbds => {
+                        symbol.local_name()
+                    }
+                    Some(_) => symbol.name(),
+                    None => {
+                        scope = Some(symbol.name());
+                        symbol.name()
+                    }
+                };
+                writeln!(out, "{}:", name)?;
+            }
+            if let Some(comment) = self.comments.get(&address) {
+                writeln!(out, "    ; {}", comment)?;
+            }
+            if let Line::Data {
+                data_type: DataType::Text(name),
+                ..
+            } = line
+            {
+                let charmap = match name {
+                    Some(name) => charmaps.get(name.as_str()).map_or(current, String::as_str),
+                    None => "main",
+                };
+                if charmap != current {
+                    writeln!(out, "    SETCHARMAP {}", charmap)?;
+                    current = charmap;
+                }
+            }
+
+            let raw: Vec<String> = bytes[offset..offset + size]
+                .iter()
+                .map(|b| format!("{:02X}", b))
+                .collect();
+            let annotation = match self.annotations.get(&address) {
+                Some((Register::A, value)) => format!(" ; a = ${:02X}", value),
+                Some((reg, value)) => {
+                    format!(
+                        " ; {} = {}",
+                        self.register(reg),
+                        self.address(address, *value)
+                    )
+                }
+                None => String::new(),
+            };
+            writeln!(
+                out,
+                "    {:<31} ; {} {}{}",
+                text,
+                address,
+                raw.join(" "),
+                annotation
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /* Writes the control flow graph in Graphviz DOT format. */
+    pub fn graph(&self, cfg: &ControlFlowGraph, out: &mut dyn Write) -> std::io::Result<()> {
+        writeln!(out, "digraph cfg {{")?;
+        writeln!(out, "    node [shape=box, fontname=monospace];")?;
+
+        for (start, block) in cfg.get_blocks() {
+            let mut label = match self.symbols.get(*start) {
+                Some(symbol) => format!("{}: {}\\l", start, symbol.name()),
+                None => format!("{}:\\l", start),
+            };
+            for (pc, inst) in block.get_instructions() {
+                label += &format!("    {}\\l", self.instruction(inst, *pc));
+            }
+            writeln!(out, "    \"{}\" [label=\"{}\"];", start, label)?;
+        }
+
+        for (start, block) in cfg.get_blocks() {
+            for edge in block.get_successors() {
+                let style = match edge.kind() {
+                    EdgeKind::Fallthrough => "color=black",
+                    EdgeKind::Jump => "color=blue",
+                    EdgeKind::Branch => "color=green",
+                    EdgeKind::Table => "color=purple",
+                };
+                writeln!(
+                    out,
+                    "    \"{}\" -> \"{}\" [{}];",
+                    start,
+                    edge.target(),
+                    style
+                )?;
+            }
+        }
+
+        /* Calls through a trampoline, from the block making them. */
+        for (pc, target) in cfg.get_far_calls() {
+            if let Some((start, _)) = cfg.get_blocks().range(..=*pc).next_back() {
+                writeln!(
+                    out,
+                    "    \"{}\" -> \"{}\" [color=red, style=dashed];",
+                    start, target
+                )?;
+            }
+        }
+
+        writeln!(out, "}}")
+    }
+
+    fn section(&self, address: Address) -> String {
+        let (bank, addr) = (address.bank(), address.addr());
+
+        match (self.syntax, bank) {
+            (Syntax::Rgbds, 0) => format!(
+                "SECTION \"ROM Bank $000 @ ${:04X}\", ROM0[${:04X}]",
+                addr, addr
+            ),
+            (Syntax::Rgbds, bank) => format!(
+                "SECTION \"ROM Bank ${:03X} @ ${:04X}\", ROMX[${:04X}], BANK[${:X}]",
+                bank, addr, addr, bank
+            ),
+            (Syntax::Wla, bank) => format!(
+                ".BANK {} SLOT {}\n.ORG ${:04X}",
+                bank,
+                bank.min(1),
+                addr as usize % BANK_SIZE
+            ),
+        }
+    }
+
+    pub fn data(&self, bytes: &[u8]) -> String {
+        let separator = match self.syntax {
+            Syntax::Rgbds => ", ",
+            Syntax::Wla => ",",
+        };
+        let bytes: Vec<String> = bytes.iter().map(|b| format!("${:02X}", b)).collect();
+
+        format!("db {}", bytes.join(separator))
+    }
+
+    /* Formats `bytes` located at `pc` as `data_type`. */
+    pub fn typed_data(&self, bytes: &[u8], data_type: &DataType, pc: Address) -> String {
+        let separator = match self.syntax {
+            Syntax::Rgbds => ", ",
+            Syntax::Wla => ",",
+        };
+        let words = bytes
+            .chunks(2)
+            .map(|word| u16::from_le_bytes([word[0], word[1]]));
+
+        match data_type {
+            DataType::Words => {
+                let words: Vec<String> = words.map(|word| format!("${:04X}", word)).collect();
+                format!("dw {}", words.join(separator))
+            }
+            DataType::Pointers => {
+                let words: Vec<String> = words.map(|word| self.address(pc, word)).collect();
+                format!("dw {}", words.join(separator))
+            }
+            /* One `0123 literal per row of pixels, each pixel is its bit in
+             * the first byte plus twice its bit in the second one. */
+            DataType::Gfx2bpp if self.syntax == Syntax::Rgbds => {
+                let rows: Vec<String> = bytes
+                    .chunks(2)
+                    .map(|row| {
+                        let pixels: String = (0..8)
+                            .rev()
+                            .map(|bit| {
+                                let color = (row[0] >> bit) & 1 | ((row[1] >> bit) & 1) << 1;
+                                (b'0' + color) as char
+                            })
+                            .collect();
+                        format!("`{}", pixels)
+                    })
+                    .collect();
+                format!("dw {}", rows.join(separator))
+            }
+            DataType::Text(None) => self.text(bytes, &Charmap::ascii(), separator),
+            DataType::Text(Some(name)) => match self.charmaps.get(name) {
+                Some(charmap) => self.text(bytes, charmap, separator),
+                None => self.data(bytes),
+            },
+            _ => self.data(bytes),
+        }
+    }
+
+    /* RGBDS names of the charmaps of the text in `disassembly`, from the
+     * name of their file. WLA-DX prints such text as numbers. */
+    fn charmap_names<'a>(&self, disassembly: &'a Disassembly) -> BTreeMap<&'a str, String> {
+        let mut names: BTreeMap<&'a str, String> = BTreeMap::new();
+        if self.syntax != Syntax::Rgbds {
+            return names;
+        }
+
+        for line in disassembly.get_lines() {
+            let name = match line {
+                Line::Data {
+                    data_type: DataType::Text(Some(name)),
+                    ..
+                } if self.charmaps.contains_key(name) => name.as_str(),
+                _ => continue,
+            };
+            if names.contains_key(name) {
+                continue;
+            }
+
+            let stem = std::path::Path::new(name)
+                .file_stem()
+                .map_or(name.into(), |stem| stem.to_string_lossy());
+            let mut ident: String = stem
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            if !ident.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                ident.insert_str(0, "charmap_");
+            }
+            let mut unique = ident.clone();
+            let mut n = 1;
+            while unique == "main" || names.values().any(|other| *other == unique) {
+                n += 1;
+                unique = format!("{}_{}", ident, n);
+            }
+            names.insert(name, unique);
+        }
+
+        names
+    }
+
+    /* Whether `byte` can be printed as the `chars` it decodes to: they must
+     * assemble back to it. */
+    fn quoted(charmap: &Charmap, byte: u8, chars: &str) -> bool {
+        !chars.is_empty() && charmap.encode(chars) == Some(byte)
+    }
+
+    /* Runs of bytes the charmap knows are quoted, the others are numbers.
+     * WLA-DX has no charmaps here, only ASCII is quoted. */
+    fn text(&self, bytes: &[u8], charmap: &Charmap, separator: &str) -> String {
+        let mut items: Vec<String> = Vec::new();
+        let mut string: Option<String> = None;
+
+        for &byte in bytes {
+            let chars = charmap.get(byte).filter(|chars| match self.syntax {
+                Syntax::Rgbds => Formatter::quoted(charmap, byte, chars),
+                Syntax::Wla => (0x20..0x7F).contains(&byte) && chars.as_bytes() == [byte],
+            });
+            match chars {
+                Some(chars) => string
+                    .get_or_insert_with(String::new)
+                    .push_str(&escape(chars)),
+                None => {
+                    if let Some(string) = string.take() {
+                        items.push(format!("\"{}\"", string));
+                    }
+                    items.push(format!("${:02X}", byte));
+                }
+            }
+        }
+        if let Some(string) = string {
+            items.push(format!("\"{}\"", string));
+        }
+
+        format!("db {}", items.join(separator))
+    }
+
+    fn mnemonic(&self, inst: &Instruction) -> &'static str {
+        match inst.mnemonic() {
+            Mnemonic::NOP => "nop",
+            Mnemonic::STOP => "stop",
+            Mnemonic::LD => "ld",
+            Mnemonic::LDHL | Mnemonic::LDHR => match (inst.lhs(), inst.rhs()) {
+                (Some(Operand::DerefReg(Register::C)), _)
+                | (_, Some(Operand::DerefReg(Register::C))) => match self.syntax {
+                    Syntax::Rgbds => "ldh",
+                    Syntax::Wla => "ld",
+                },
+                _ => "ldh",
+            },
+            Mnemonic::LDIL | Mnemonic::LDIR => "ldi",
+            Mnemonic::LDDL | Mnemonic::LDDR => "ldd",
+            Mnemonic::JR | Mnemonic::JRNZ | Mnemonic::JRZ | Mnemonic::JRNC | Mnemonic::JRC => "jr",
+            Mnemonic::JP | Mnemonic::JPNZ | Mnemonic::JPZ | Mnemonic::JPNC | Mnemonic::JPC => "jp",
+            Mnemonic::ADD => "add",
+            Mnemonic::ADC => "adc",
+            Mnemonic::SUB => "sub",
+            Mnemonic::SBC => "sbc",
+            Mnemonic::INC => "inc",
+            Mnemonic::DEC => "dec",
+            Mnemonic::AND => "and",
+            Mnemonic::OR => "or",
+            Mnemonic::XOR => "xor",
+            Mnemonic::PUSH => "push",
+            Mnemonic::POP => "pop",
+            Mnemonic::CALL => "call",
+            Mnemonic::RET => "ret",
+            Mnemonic::RETI => "reti",
+            Mnemonic::RLCA => "rlca",
+            Mnemonic::RRCA => "rrca",
+            Mnemonic::RLA => "rla",
+            Mnemonic::RRA => "rra",
+            Mnemonic::RLC => "rlc",
+            Mnemonic::RRC => "rrc",
+            Mnemonic::RL => "rl",
+            Mnemonic::RR => "rr",
+            Mnemonic::SLA => "sla",
+            Mnemonic::SRA => "sra",
+            Mnemonic::SWAP => "swap",
+            Mnemonic::SRL => "srl",
+            Mnemonic::BIT => "bit",
+            Mnemonic::RES => "res",
+            Mnemonic::SET => "set",
+            Mnemonic::DA => "daa",
+            Mnemonic::CPL => "cpl",
+            Mnemonic::SCF => "scf",
+            Mnemonic::CCF => "ccf",
+            Mnemonic::HALT => "halt",
+            Mnemonic::CP => "cp",
+            Mnemonic::RST => "rst",
+            Mnemonic::DI => "di",
+            Mnemonic::EI => "ei",
+        }
+    }
+
+    fn operand(&self, inst: &Instruction, operand: &Operand, pc: Address) -> String {
+        match operand {
+            Operand::Imm8(imm) => format!("${:02X}", imm),
+            Operand::Imm16(imm) => match self.symbols.lookup(pc.bank(), *imm) {
+                /* Only names given by the user, constants may look like
+                 * addresses. */
+                Some(symbol) if !symbol.auto() => symbol.name().to_string(),
+                _ => format!("${:04X}", imm),
+            },
+            Operand::Addr8(addr) => format!("${:02X}", addr),
+            Operand::DerefAddr8(addr) => self.deref(&self.address(pc, 0xFF00 | *addr as u16)),
+            Operand::Addr16(addr) => self.address(pc, *addr),
+            Operand::DerefAddr16(addr) => self.deref(&self.address(pc, *addr)),
+            Operand::Rel8(offset) => match inst.mnemonic() {
+                /* ADD SP, e8 is the only non-branch user of a relative operand. */
+                Mnemonic::ADD => format!("{}", *offset as i8),
+                _ => {
+                    let target = pc
+                        .addr()
+                        .wrapping_add(inst.size() as u16)
+                        .wrapping_add(*offset as i8 as u16);
+                    self.address(pc, target)
+                }
+            },
+            Operand::SPRel8(offset) => {
+                let offset = *offset as i8;
+                match self.syntax {
+                    Syntax::Rgbds if offset < 0 => format!("sp - {}", -(offset as i16)),
+                    Syntax::Rgbds => format!("sp + {}", offset),
+                    Syntax::Wla => format!("sp+{}", offset),
+                }
+            }
+            Operand::Bit(bit) => format!("{}", bit),
+            Operand::Reg(reg) => self.register(reg).to_string(),
+            Operand::DerefReg(Register::C) if self.syntax == Syntax::Wla => "($FF00+c)".to_string(),
+            Operand::DerefReg(reg) => match (inst.mnemonic(), reg) {
+                /* JP HL jumps to HL, it does not read memory. */
+                (Mnemonic::JP, Register::HL) => "hl".to_string(),
+                _ => self.deref(self.register(reg)),
+            },
+            Operand::Cond(cond) => self.condition(cond).to_string(),
+        }
+    }
+
+    /* An address used by code at `pc`, by name when one is known. */
+    fn address(&self, pc: Address, addr: u16) -> String {
+        if let Some(symbol) = self.symbols.lookup(pc.bank(), addr) {
+            return symbol.name().to_string();
+        }
+
+        match hardware::io_register_name(addr) {
+            Some(name) if self.hardware_names => name,
+            _ => format!("${:04X}", addr),
+        }
+    }
+
+    fn deref(&self, inner: &str) -> String {
+        match self.syntax {
+            Syntax::Rgbds => format!("[{}]", inner),
+            Syntax::Wla => format!("({})", inner),
+        }
+    }
+
+    fn register(&self, reg: &Register) -> &'static str {
+        match reg {
+            Register::AF => "af",
+            Register::A => "a",
+            Register::F => "f",
+            Register::BC => "bc",
+            Register::B => "b",
+            Register::C => "c",
+            Register::DE => "de",
+            Register::D => "d",
+            Register::E => "e",
+            Register::HL => "hl",
+            Register::H => "h",
+            Register::L => "l",
+            Register::SP => "sp",
+        }
+    }
+
+    fn condition(&self, cond: &Condition) -> &'static str {
+        match cond {
+            Condition::Z => "z",
+            Condition::NZ => "nz",
+            Condition::C => "c",
+            Condition::NC => "nc",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::disassembler::Disassembler;
+    use crate::analyzer::project::{Project, TypedRange};
+
+    fn format(syntax: Syntax, bytes: &[u8]) -> String {
+        let inst = Instruction::from_slice(bytes).unwrap();
+        Formatter::new(syntax).instruction(&inst, Address::new(0, 0x0150))
+    }
+
+    #[test]
+    fn instructions() {
+        assert_eq!(format(Syntax::Rgbds, &[0x27]), "daa");
+        assert_eq!(format(Syntax::Rgbds, &[0x2F]), "cpl");
+        assert_eq!(format(Syntax::Rgbds, &[0x10, 0x00]), "stop");
+        assert_eq!(format(Syntax::Rgbds, &[0x2A]), "ld a, [hl+]");
+        assert_eq!(format(Syntax::Rgbds, &[0xE2]), "ldh [c], a");
+        assert_eq!(format(Syntax::Wla, &[0xE2]), "ld ($FF00+c),a");
+        assert_eq!(format(Syntax::Rgbds, &[0xCB, 0x7E]), "bit 7, [hl]");
+        assert_eq!(format(Syntax::Rgbds, &[0x20, 0xFE]), "jr nz, $0150");
+    }
+
+    #[test]
+    fn hardware_names() {
+        assert_eq!(format(Syntax::Rgbds, &[0xE0, 0x40]), "ldh [rLCDC], a");
+        assert_eq!(format(Syntax::Rgbds, &[0xEA, 0xFF, 0xFF]), "ld [rIE], a");
+
+        let inst = Instruction::from_slice(&[0xE0, 0x40]).unwrap();
+        let mut formatter = Formatter::new(Syntax::Rgbds);
+        formatter.set_hardware_names(false);
+        assert_eq!(
+            formatter.instruction(&inst, Address::new(0, 0x0150)),
+            "ldh [$FF40], a"
+        );
+    }
+
+    #[test]
+    fn stop_padding() {
+        let bytes = [0x10, 0x00, 0x10, 0x01];
+        let disassembly = Disassembler::disassemble(&bytes, &Project::new(&bytes)).unwrap();
+        let mut formatter = Formatter::new(Syntax::Rgbds);
+        formatter.set_hardware_names(false);
+
+        let mut out = Vec::new();
+        formatter.listing(&disassembly, &bytes, &mut out).unwrap();
+        let listing = String::from_utf8(out).unwrap();
+        let code: Vec<&str> = listing
+            .lines()
+            .filter(|line| line.starts_with("    "))
+            .map(|line| line.split(';').next().unwrap().trim())
+            .collect();
+        assert_eq!(code, ["stop", "db $10, $01"]);
+    }
+
+    #[test]
+    fn typed_data() {
+        let formatter = Formatter::new(Syntax::Rgbds);
+        let pc = Address::new(1, 0x4000);
+        assert_eq!(
+            formatter.typed_data(&[0x34, 0x12, 0x00, 0x40], &DataType::Words, pc),
+            "dw $1234, $4000"
+        );
+        assert_eq!(
+            formatter.typed_data(&[0x7E, 0x3C], &DataType::Gfx2bpp, pc),
+            "dw `01333310"
+        );
+        assert_eq!(
+            formatter.typed_data(b"HI\"\x00", &DataType::Text(None), pc),
+            "db \"HI\\\"\", $00"
+        );
+        assert_eq!(
+            Formatter::new(Syntax::Wla).typed_data(&[1, 2], &DataType::Bytes, pc),
+            "db $01,$02"
+        );
+    }
+
+    #[test]
+    fn charmaps() {
+        let bytes = [0x80, 0x81, 0xC0, 0x50, b'H', b'I'];
+        let charmap = Charmap::parse("80=A\n81=B\n50=<END>\nC0=A\n").unwrap();
+        let mut project = Project::new(&bytes);
+        project.set_charmap("tables/game.tbl", charmap);
+        let text = DataType::Text(Some("tables/game.tbl".to_string()));
+        let range = TypedRange::new(Address::new(0, 0), Address::new(0, 3), text);
+        project.add_type(range).unwrap();
+        let range = TypedRange::new(Address::new(0, 4), Address::new(0, 5), DataType::Text(None));
+        project.add_type(range).unwrap();
+        let disassembly = Disassembler::disassemble(&bytes, &project).unwrap();
+
+        let listing = |syntax| {
+            let mut formatter = Formatter::new(syntax);
+            formatter.set_hardware_names(false);
+            formatter.set_charmaps(project.get_charmaps().clone());
+            let mut out = Vec::new();
+            formatter.listing(&disassembly, &bytes, &mut out).unwrap();
+            let listing = String::from_utf8(out).unwrap();
+            listing
+                .lines()
+                .filter(|line| !line.is_empty())
+                .filter(|line| !line.starts_with("SECTION") && !line.starts_with('.'))
+                .map(|line| line.split(';').next().unwrap().trim().to_string())
+                .collect::<Vec<String>>()
+        };
+
+        /* $C0 is a second "A", it would assemble to $80. */
+        assert_eq!(
+            listing(Syntax::Rgbds),
+            [
+                "NEWCHARMAP game",
+                "CHARMAP \"<END>\", $50",
+                "CHARMAP \"A\", $80",
+                "CHARMAP \"B\", $81",
+                "SETCHARMAP main",
+                "SETCHARMAP game",
+                "db \"AB\", $C0, \"<END>\"",
+                "SETCHARMAP main",
+                "db \"HI\"",
+            ]
+        );
+        assert_eq!(listing(Syntax::Wla), ["db $80,$81,$C0,$50", "db \"HI\""]);
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(escape("Hi"), "Hi");
+        assert_eq!(escape("\"A\\B\"\n\0"), "\\\"A\\\\B\\\"\\n\\0");
+    }
+}
@@ -0,0 +1,283 @@
+use super::address::Address;
+use super::control_flow::{BasicBlock, ControlFlowGraph};
+use super::instruction::{Flow, Mnemonic, Operand, Register};
+use super::project::Project;
+use super::values::Values;
+
+/* Bytes of a 8x8 tile, two bits per pixel. */
+pub const TILE_SIZE: usize = 16;
+/* Tile data in VRAM, $9800 onwards are tile maps. */
+const TILE_DATA: std::ops::Range<u16> = 0x8000..0x9800;
+
+/* Shades of grey of the DMG, from colour 0 (lightest) to 3. */
+pub const DEFAULT_PALETTE: [[u8; 3]; 4] = [
+    [0xFF, 0xFF, 0xFF],
+    [0xAA, 0xAA, 0xAA],
+    [0x55, 0x55, 0x55],
+    [0x00, 0x00, 0x00],
+];
+
+/* Tiles copied from ROM to VRAM by the code at `pc`: a call with the source
+ * and destination in HL and DE, a copy loop entered with them, or a CGB HDMA
+ * transfer. The size is one tile when the code does not give it in BC. */
+#[derive(Debug, Clone)]
+pub struct TileCopy {
+    pc: Address,
+    source: Address,
+    destination: u16,
+    size: usize,
+}
+
+impl TileCopy {
+    pub fn find(cfg: &ControlFlowGraph, bytes: &[u8], project: &Project) -> Vec<TileCopy> {
+        let mut copies = Vec::new();
+
+        for block in cfg.get_blocks().values() {
+            let mut values = Values::default();
+            let mut hdma: [Option<u8>; 4] = [None; 4];
+
+            for (pc, inst) in block.get_instructions() {
+                let copy = match inst.flow(pc.addr()) {
+                    Flow::Call(_) => Copy::of(&values, *pc),
+                    _ => None,
+                };
+                copies.extend(copy);
+
+                /* HDMA1-4 hold the source and destination, writing the length
+                 * to HDMA5 starts the transfer. */
+                match Values::store(inst) {
+                    Some(reg @ 0xFF51..=0xFF54) => hdma[reg as usize - 0xFF51] = values.a(),
+                    Some(0xFF55) => {
+                        if let ([Some(h1), Some(h2), Some(h3), Some(h4)], Some(length)) =
+                            (hdma, values.a())
+                        {
+                            copies.push(Copy {
+                                pc: *pc,
+                                source: u16::from_be_bytes([h1, h2]) & 0xFFF0,
+                                destination: 0x8000 | (u16::from_be_bytes([h3, h4]) & 0x1FF0),
+                                size: Some(((length & 0x7F) as usize + 1) * TILE_SIZE),
+                                bank: values.bank(),
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+                values.step(inst);
+            }
+
+            /* Blocks entering a loop that copies through HL and DE. */
+            if let Some((pc, _)) = block.get_instructions().last() {
+                let loops = block
+                    .get_successors()
+                    .iter()
+                    .filter_map(|edge| cfg.get_blocks().get(&edge.target()))
+                    .any(TileCopy::copy_loop);
+                if loops {
+                    copies.extend(Copy::of(&values, *pc));
+                }
+            }
+        }
+
+        let mut copies: Vec<TileCopy> = copies
+            .into_iter()
+            .filter_map(|copy| {
+                let source = match (copy.source, copy.bank) {
+                    (0x4000..=0x7FFF, Some(bank)) if bank != 0 => {
+                        Some(Address::new(bank as u16, copy.source))
+                    }
+                    _ => ControlFlowGraph::target(bytes, project, copy.pc, copy.source),
+                }?;
+                /* Whole tiles, within the bank of the source. */
+                let size = copy.size.unwrap_or(TILE_SIZE) / TILE_SIZE * TILE_SIZE;
+                let size = std::cmp::min(size, 0x8000 - copy.source as usize);
+                if size == 0 || source.to_offset() + size > bytes.len() {
+                    return None;
+                }
+
+                Some(TileCopy {
+                    pc: copy.pc,
+                    source,
+                    destination: copy.destination,
+                    size,
+                })
+            })
+            .collect();
+        copies.sort_by_key(|copy| (copy.source, copy.pc));
+
+        copies
+    }
+
+    /* A block branching back to itself that reads through one of HL and DE
+     * and writes through the other. */
+    fn copy_loop(block: &BasicBlock) -> bool {
+        let mut reads = false;
+        let mut writes = false;
+
+        for (_, inst) in block.get_instructions() {
+            match (inst.mnemonic(), inst.lhs(), inst.rhs()) {
+                (Mnemonic::LDIR, _, _)
+                | (Mnemonic::LD, Some(Operand::Reg(Register::A)), Some(Operand::DerefReg(_))) => {
+                    reads = true
+                }
+                (Mnemonic::LDIL, _, _)
+                | (Mnemonic::LD, Some(Operand::DerefReg(_)), Some(Operand::Reg(Register::A))) => {
+                    writes = true
+                }
+                _ => {}
+            }
+        }
+
+        reads
+            && writes
+            && block
+                .get_successors()
+                .iter()
+                .any(|edge| edge.target() == block.start())
+    }
+
+    pub fn pc(&self) -> Address {
+        self.pc
+    }
+
+    pub fn source(&self) -> Address {
+        self.source
+    }
+
+    pub fn destination(&self) -> u16 {
+        self.destination
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /* Address of the last byte copied. */
+    pub fn end(&self) -> Address {
+        Address::new(
+            self.source.bank(),
+            self.source.addr() + self.size as u16 - 1,
+        )
+    }
+}
+
+/* Pixels of `bytes` as 2bpp tiles, `width` tiles per row: the width and
+ * height of the sheet in pixels and one colour index per pixel. */
+pub fn tile_sheet(bytes: &[u8], width: usize) -> (usize, usize, Vec<u8>) {
+    let tiles = bytes.len().div_ceil(TILE_SIZE);
+    let columns = std::cmp::max(std::cmp::min(width, tiles), 1);
+    let rows = tiles.div_ceil(columns);
+    let (width, height) = (columns * 8, rows * 8);
+
+    let mut pixels = vec![0; width * height];
+    for (i, tile) in bytes.chunks(TILE_SIZE).enumerate() {
+        let (x, y) = (i % columns * 8, i / columns * 8);
+        for (row, line) in tile.chunks(2).enumerate() {
+            let (low, high) = (line[0], *line.get(1).unwrap_or(&0));
+            for bit in 0..8 {
+                let colour = (low >> (7 - bit) & 1) | (high >> (7 - bit) & 1) << 1;
+                pixels[(y + row) * width + x + bit] = colour;
+            }
+        }
+    }
+
+    (width, height, pixels)
+}
+
+/* A copy before its source is resolved to a bank. */
+struct Copy {
+    pc: Address,
+    source: u16,
+    destination: u16,
+    size: Option<usize>,
+    bank: Option<u8>,
+}
+
+impl Copy {
+    /* A copy from ROM to tile data with the source and destination in HL and
+     * DE, in either order. */
+    fn of(values: &Values, pc: Address) -> Option<Copy> {
+        let (source, destination) = [(Register::HL, Register::DE), (Register::DE, Register::HL)]
+            .iter()
+            .find_map(|&(source, destination)| {
+                let source = values.get(source).filter(|&source| source < 0x8000)?;
+                let destination = values
+                    .get(destination)
+                    .filter(|destination| TILE_DATA.contains(destination))?;
+                Some((source, destination))
+            })?;
+        let size = values
+            .get(Register::BC)
+            .map(usize::from)
+            .filter(|&size| size > 0 && size <= (TILE_DATA.end - destination) as usize);
+
+        Some(Copy {
+            pc,
+            source,
+            destination,
+            size,
+            bank: values.bank(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sheet() {
+        let mut tiles = vec![0; 3 * TILE_SIZE];
+        /* Low bits in the first byte of a row, high bits in the second. */
+        tiles[0..2].copy_from_slice(&[0xFF, 0x00]);
+        tiles[2..4].copy_from_slice(&[0x00, 0xFF]);
+        tiles[4..6].copy_from_slice(&[0xF0, 0x3C]);
+        tiles[2 * TILE_SIZE..2 * TILE_SIZE + 2].copy_from_slice(&[0x80, 0x80]);
+
+        let (width, height, pixels) = tile_sheet(&tiles, 2);
+        assert_eq!((width, height), (16, 16));
+        assert_eq!(pixels[..8], [1; 8]);
+        assert_eq!(pixels[16..24], [2; 8]);
+        assert_eq!(pixels[32..40], [1, 1, 3, 3, 2, 2, 0, 0]);
+        /* Third tile on the second row. */
+        assert_eq!(pixels[8 * 16..8 * 16 + 2], [3, 0]);
+
+        assert_eq!(tile_sheet(&tiles, 16).0, 24);
+        assert_eq!(tile_sheet(&tiles[..20], 0).1, 16);
+    }
+
+    #[test]
+    fn copies() {
+        let mut bytes = vec![0x00; 0x8000];
+        let code = [
+            0x21, 0x00, 0x20, /* ld hl, Tiles */
+            0x11, 0x00, 0x88, /* ld de, $8800 */
+            0x01, 0x28, 0x00, /* ld bc, 40 */
+            0xCD, 0x00, 0x03, /* call Copy */
+            0x18, 0xFE, /* jr @ */
+        ];
+        bytes[0x0100..0x0100 + code.len()].copy_from_slice(&code);
+        let copy = [
+            0x2A, /* .loop ld a, [hl+] */
+            0x12, /* ld [de], a */
+            0x13, /* inc de */
+            0x0B, /* dec bc */
+            0x78, /* ld a, b */
+            0xB1, /* or c */
+            0x20, 0xF8, /* jr nz, .loop */
+            0xC9, /* ret */
+        ];
+        bytes[0x0300..0x0300 + copy.len()].copy_from_slice(&copy);
+
+        let project = Project::new(&bytes);
+        let cfg = ControlFlowGraph::build(&bytes, &[Address::new(0, 0x0100)], &project);
+        let copies = TileCopy::find(&cfg, &bytes, &project);
+
+        assert_eq!(copies.len(), 1);
+        assert_eq!(copies[0].pc(), Address::new(0, 0x0109));
+        assert_eq!(copies[0].source(), Address::new(0, 0x2000));
+        assert_eq!(copies[0].destination(), 0x8800);
+        /* Whole tiles only. */
+        assert_eq!(copies[0].size(), 32);
+        assert_eq!(copies[0].end(), Address::new(0, 0x201F));
+    }
+}
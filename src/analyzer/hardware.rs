@@ -0,0 +1,545 @@
+/* Hardware registers mapped at $FF00-$FF7F and $FFFF, named after
+ * hardware.inc so that listings can be assembled with it. */
+
+pub struct BitField {
+    name: &'static str,
+    high: u8,
+    low: u8,
+    description: &'static str,
+}
+
+impl BitField {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn description(&self) -> &'static str {
+        self.description
+    }
+
+    /* Bits covered by the field, e.g. $03 for bits 1-0. */
+    pub fn mask(&self) -> u8 {
+        (0xFFu16 >> (7 - self.high + self.low) << self.low) as u8
+    }
+
+    pub fn extract(&self, value: u8) -> u8 {
+        (value & self.mask()) >> self.low
+    }
+}
+
+pub struct IoRegister {
+    address: u16,
+    size: u16,
+    name: &'static str,
+    description: &'static str,
+    cgb: bool,
+    fields: &'static [BitField],
+}
+
+impl IoRegister {
+    pub fn address(&self) -> u16 {
+        self.address
+    }
+
+    /* Number of bytes the register spans, more than one for wave RAM. */
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn description(&self) -> &'static str {
+        self.description
+    }
+
+    /* Only present on the Game Boy Color. */
+    pub fn cgb(&self) -> bool {
+        self.cgb
+    }
+
+    pub fn fields(&self) -> &'static [BitField] {
+        self.fields
+    }
+
+    /* Describes every field of `value` written to the register. */
+    pub fn describe(&self, value: u8) -> Vec<String> {
+        self.fields
+            .iter()
+            .map(|field| format!("{}={}", field.name, field.extract(value)))
+            .collect()
+    }
+}
+
+macro_rules! field {
+    ($name:expr, $high:expr, $low:expr, $description:expr) => {
+        BitField {
+            name: $name,
+            high: $high,
+            low: $low,
+            description: $description,
+        }
+    };
+}
+
+macro_rules! register {
+    ($address:expr, $name:expr, $description:expr) => {
+        register!($address, $name, $description, false, &[])
+    };
+    ($address:expr, $name:expr, $description:expr, $cgb:expr, $fields:expr) => {
+        IoRegister {
+            address: $address,
+            size: 1,
+            name: $name,
+            description: $description,
+            cgb: $cgb,
+            fields: $fields,
+        }
+    };
+}
+
+const INTERRUPTS: &[BitField] = &[
+    field!("JOYPAD", 4, 4, "joypad interrupt"),
+    field!("SERIAL", 3, 3, "serial interrupt"),
+    field!("TIMER", 2, 2, "timer interrupt"),
+    field!("STAT", 1, 1, "LCD STAT interrupt"),
+    field!("VBLANK", 0, 0, "vertical blank interrupt"),
+];
+
+const PALETTE: &[BitField] = &[
+    field!("COLOR3", 7, 6, "shade of color 3"),
+    field!("COLOR2", 5, 4, "shade of color 2"),
+    field!("COLOR1", 3, 2, "shade of color 1"),
+    field!("COLOR0", 1, 0, "shade of color 0"),
+];
+
+const PALETTE_INDEX: &[BitField] = &[
+    field!("AUTOINC", 7, 7, "increment the index after each write"),
+    field!("INDEX", 5, 0, "byte index in palette memory"),
+];
+
+const SWEEP: &[BitField] = &[
+    field!("PACE", 6, 4, "sweep pace"),
+    field!("DIRECTION", 3, 3, "0: increase, 1: decrease"),
+    field!("STEP", 2, 0, "sweep individual step"),
+];
+
+const LENGTH_DUTY: &[BitField] = &[
+    field!("DUTY", 7, 6, "wave duty"),
+    field!("LENGTH", 5, 0, "initial length timer"),
+];
+
+const ENVELOPE: &[BitField] = &[
+    field!("VOLUME", 7, 4, "initial volume"),
+    field!("DIRECTION", 3, 3, "0: decrease, 1: increase"),
+    field!("PACE", 2, 0, "envelope sweep pace"),
+];
+
+const PERIOD_HIGH: &[BitField] = &[
+    field!("TRIGGER", 7, 7, "restart the channel"),
+    field!(
+        "LENGTH_ENABLE",
+        6,
+        6,
+        "stop the channel when the length expires"
+    ),
+    field!("PERIOD", 2, 0, "upper 3 bits of the period"),
+];
+
+pub const IO_REGISTERS: &[IoRegister] = &[
+    register!(
+        0xFF00,
+        "rP1",
+        "joypad",
+        false,
+        &[
+            field!("SELECT_BUTTONS", 5, 5, "0: read the action buttons"),
+            field!("SELECT_DPAD", 4, 4, "0: read the directional pad"),
+            field!("INPUT", 3, 0, "pressed keys, active low"),
+        ]
+    ),
+    register!(0xFF01, "rSB", "serial transfer data"),
+    register!(
+        0xFF02,
+        "rSC",
+        "serial transfer control",
+        false,
+        &[
+            field!("START", 7, 7, "transfer in progress or requested"),
+            field!("SPEED", 1, 1, "CGB only, 1: fast clock"),
+            field!("CLOCK", 0, 0, "1: internal clock"),
+        ]
+    ),
+    register!(0xFF04, "rDIV", "divider register"),
+    register!(0xFF05, "rTIMA", "timer counter"),
+    register!(0xFF06, "rTMA", "timer modulo"),
+    register!(
+        0xFF07,
+        "rTAC",
+        "timer control",
+        false,
+        &[
+            field!("ENABLE", 2, 2, "timer enabled"),
+            field!(
+                "CLOCK",
+                1,
+                0,
+                "0: 4096 Hz, 1: 262144 Hz, 2: 65536 Hz, 3: 16384 Hz"
+            ),
+        ]
+    ),
+    register!(0xFF0F, "rIF", "interrupt flag", false, INTERRUPTS),
+    register!(0xFF10, "rNR10", "channel 1 sweep", false, SWEEP),
+    register!(
+        0xFF11,
+        "rNR11",
+        "channel 1 length timer and duty cycle",
+        false,
+        LENGTH_DUTY
+    ),
+    register!(
+        0xFF12,
+        "rNR12",
+        "channel 1 volume and envelope",
+        false,
+        ENVELOPE
+    ),
+    register!(0xFF13, "rNR13", "channel 1 period low"),
+    register!(
+        0xFF14,
+        "rNR14",
+        "channel 1 period high and control",
+        false,
+        PERIOD_HIGH
+    ),
+    register!(
+        0xFF16,
+        "rNR21",
+        "channel 2 length timer and duty cycle",
+        false,
+        LENGTH_DUTY
+    ),
+    register!(
+        0xFF17,
+        "rNR22",
+        "channel 2 volume and envelope",
+        false,
+        ENVELOPE
+    ),
+    register!(0xFF18, "rNR23", "channel 2 period low"),
+    register!(
+        0xFF19,
+        "rNR24",
+        "channel 2 period high and control",
+        false,
+        PERIOD_HIGH
+    ),
+    register!(
+        0xFF1A,
+        "rNR30",
+        "channel 3 DAC enable",
+        false,
+        &[field!("DAC", 7, 7, "DAC enabled")]
+    ),
+    register!(0xFF1B, "rNR31", "channel 3 length timer"),
+    register!(
+        0xFF1C,
+        "rNR32",
+        "channel 3 output level",
+        false,
+        &[field!("LEVEL", 6, 5, "0: mute, 1: 100%, 2: 50%, 3: 25%")]
+    ),
+    register!(0xFF1D, "rNR33", "channel 3 period low"),
+    register!(
+        0xFF1E,
+        "rNR34",
+        "channel 3 period high and control",
+        false,
+        PERIOD_HIGH
+    ),
+    register!(
+        0xFF20,
+        "rNR41",
+        "channel 4 length timer",
+        false,
+        &[field!("LENGTH", 5, 0, "initial length timer")]
+    ),
+    register!(
+        0xFF21,
+        "rNR42",
+        "channel 4 volume and envelope",
+        false,
+        ENVELOPE
+    ),
+    register!(
+        0xFF22,
+        "rNR43",
+        "channel 4 frequency and randomness",
+        false,
+        &[
+            field!("SHIFT", 7, 4, "clock shift"),
+            field!("WIDTH", 3, 3, "1: 7-bit LFSR"),
+            field!("DIVIDER", 2, 0, "clock divider"),
+        ]
+    ),
+    register!(
+        0xFF23,
+        "rNR44",
+        "channel 4 control",
+        false,
+        &[
+            field!("TRIGGER", 7, 7, "restart the channel"),
+            field!(
+                "LENGTH_ENABLE",
+                6,
+                6,
+                "stop the channel when the length expires"
+            ),
+        ]
+    ),
+    register!(
+        0xFF24,
+        "rNR50",
+        "master volume and VIN panning",
+        false,
+        &[
+            field!("VIN_LEFT", 7, 7, "mix VIN into the left output"),
+            field!("LEFT", 6, 4, "left output volume"),
+            field!("VIN_RIGHT", 3, 3, "mix VIN into the right output"),
+            field!("RIGHT", 2, 0, "right output volume"),
+        ]
+    ),
+    register!(
+        0xFF25,
+        "rNR51",
+        "sound panning",
+        false,
+        &[
+            field!("LEFT", 7, 4, "channels 4-1 sent to the left output"),
+            field!("RIGHT", 3, 0, "channels 4-1 sent to the right output"),
+        ]
+    ),
+    register!(
+        0xFF26,
+        "rNR52",
+        "sound on/off",
+        false,
+        &[
+            field!("ENABLE", 7, 7, "audio enabled"),
+            field!("CHANNELS", 3, 0, "channels 4-1 active, read only"),
+        ]
+    ),
+    IoRegister {
+        address: 0xFF30,
+        size: 16,
+        name: "_AUD3WAVERAM",
+        description: "channel 3 wave pattern",
+        cgb: false,
+        fields: &[],
+    },
+    register!(
+        0xFF40,
+        "rLCDC",
+        "LCD control",
+        false,
+        &[
+            field!("ON", 7, 7, "LCD and PPU enabled"),
+            field!("WIN_MAP", 6, 6, "window tile map, 0: $9800, 1: $9C00"),
+            field!("WIN_ENABLE", 5, 5, "window enabled"),
+            field!("TILE_DATA", 4, 4, "BG and window tiles, 0: $8800, 1: $8000"),
+            field!("BG_MAP", 3, 3, "BG tile map, 0: $9800, 1: $9C00"),
+            field!("OBJ_SIZE", 2, 2, "0: 8x8, 1: 8x16"),
+            field!("OBJ_ENABLE", 1, 1, "objects enabled"),
+            field!("BG_ENABLE", 0, 0, "BG and window enabled, priority on CGB"),
+        ]
+    ),
+    register!(
+        0xFF41,
+        "rSTAT",
+        "LCD status",
+        false,
+        &[
+            field!("LYC_INT", 6, 6, "interrupt on LY=LYC"),
+            field!("MODE2_INT", 5, 5, "interrupt on OAM scan"),
+            field!("MODE1_INT", 4, 4, "interrupt on VBlank"),
+            field!("MODE0_INT", 3, 3, "interrupt on HBlank"),
+            field!("LYC_EQUAL", 2, 2, "LY=LYC, read only"),
+            field!("MODE", 1, 0, "PPU mode, read only"),
+        ]
+    ),
+    register!(0xFF42, "rSCY", "background viewport Y"),
+    register!(0xFF43, "rSCX", "background viewport X"),
+    register!(0xFF44, "rLY", "LCD Y coordinate"),
+    register!(0xFF45, "rLYC", "LY compare"),
+    register!(0xFF46, "rDMA", "OAM DMA source address and start"),
+    register!(0xFF47, "rBGP", "BG palette data", false, PALETTE),
+    register!(0xFF48, "rOBP0", "object palette 0 data", false, PALETTE),
+    register!(0xFF49, "rOBP1", "object palette 1 data", false, PALETTE),
+    register!(0xFF4A, "rWY", "window Y position"),
+    register!(0xFF4B, "rWX", "window X position plus 7"),
+    register!(
+        0xFF4C,
+        "rKEY0",
+        "CGB compatibility mode, boot ROM only",
+        true,
+        &[]
+    ),
+    register!(
+        0xFF4D,
+        "rKEY1",
+        "prepare speed switch",
+        true,
+        &[
+            field!("SPEED", 7, 7, "current speed, 1: double speed"),
+            field!("SWITCH", 0, 0, "switch speed on the next STOP"),
+        ]
+    ),
+    register!(
+        0xFF4F,
+        "rVBK",
+        "VRAM bank",
+        true,
+        &[field!("BANK", 0, 0, "VRAM bank mapped at $8000")]
+    ),
+    register!(0xFF50, "rBANK", "boot ROM disable"),
+    register!(0xFF51, "rHDMA1", "VRAM DMA source high", true, &[]),
+    register!(0xFF52, "rHDMA2", "VRAM DMA source low", true, &[]),
+    register!(0xFF53, "rHDMA3", "VRAM DMA destination high", true, &[]),
+    register!(0xFF54, "rHDMA4", "VRAM DMA destination low", true, &[]),
+    register!(
+        0xFF55,
+        "rHDMA5",
+        "VRAM DMA length, mode and start",
+        true,
+        &[
+            field!("MODE", 7, 7, "0: general purpose, 1: HBlank"),
+            field!("LENGTH", 6, 0, "transfer length / $10 - 1"),
+        ]
+    ),
+    register!(
+        0xFF56,
+        "rRP",
+        "infrared communications port",
+        true,
+        &[
+            field!("READ_ENABLE", 7, 6, "3: reading enabled"),
+            field!("RECEIVING", 1, 1, "0: receiving IR signal, read only"),
+            field!("EMITTING", 0, 0, "LED on"),
+        ]
+    ),
+    register!(
+        0xFF68,
+        "rBCPS",
+        "background palette index",
+        true,
+        PALETTE_INDEX
+    ),
+    register!(0xFF69, "rBCPD", "background palette data", true, &[]),
+    register!(0xFF6A, "rOCPS", "object palette index", true, PALETTE_INDEX),
+    register!(0xFF6B, "rOCPD", "object palette data", true, &[]),
+    register!(
+        0xFF6C,
+        "rOPRI",
+        "object priority mode",
+        true,
+        &[field!(
+            "PRIORITY",
+            0,
+            0,
+            "0: by OAM index, 1: by X coordinate"
+        )]
+    ),
+    register!(
+        0xFF70,
+        "rSVBK",
+        "WRAM bank",
+        true,
+        &[field!(
+            "BANK",
+            2,
+            0,
+            "WRAM bank mapped at $D000, 0 selects 1"
+        )]
+    ),
+    register!(
+        0xFF76,
+        "rPCM12",
+        "audio digital outputs 1 and 2",
+        true,
+        &[
+            field!("CH2", 7, 4, "channel 2 output"),
+            field!("CH1", 3, 0, "channel 1 output"),
+        ]
+    ),
+    register!(
+        0xFF77,
+        "rPCM34",
+        "audio digital outputs 3 and 4",
+        true,
+        &[
+            field!("CH4", 7, 4, "channel 4 output"),
+            field!("CH3", 3, 0, "channel 3 output"),
+        ]
+    ),
+    register!(0xFFFF, "rIE", "interrupt enable", false, INTERRUPTS),
+];
+
+/* The register covering `addr`, if any. */
+pub fn io_register(addr: u16) -> Option<&'static IoRegister> {
+    IO_REGISTERS
+        .iter()
+        .find(|reg| addr >= reg.address && addr - reg.address < reg.size)
+}
+
+/* Name to use for `addr`: the register name, plus an offset inside wave RAM. */
+pub fn io_register_name(addr: u16) -> Option<String> {
+    io_register(addr).map(|reg| match addr - reg.address {
+        0 => reg.name.to_string(),
+        offset => format!("{} + {}", reg.name, offset),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert_eq!(io_register_name(0xFF40).as_deref(), Some("rLCDC"));
+        assert_eq!(io_register_name(0xFFFF).as_deref(), Some("rIE"));
+        assert_eq!(io_register_name(0xFF30).as_deref(), Some("_AUD3WAVERAM"));
+        assert_eq!(
+            io_register_name(0xFF3F).as_deref(),
+            Some("_AUD3WAVERAM + 15")
+        );
+        assert_eq!(io_register_name(0xFF03), None);
+        assert_eq!(io_register_name(0xFF80), None);
+    }
+
+    #[test]
+    fn registers_sorted() {
+        for pair in IO_REGISTERS.windows(2) {
+            assert!(
+                pair[0].address + pair[0].size <= pair[1].address,
+                "{} overlaps {}",
+                pair[0].name,
+                pair[1].name
+            );
+        }
+    }
+
+    #[test]
+    fn fields() {
+        let bgp = io_register(0xFF47).unwrap();
+        assert_eq!(
+            bgp.describe(0xE4),
+            ["COLOR3=3", "COLOR2=2", "COLOR1=1", "COLOR0=0"]
+        );
+
+        let index = &PALETTE_INDEX[1];
+        assert_eq!(index.mask(), 0x3F);
+        assert_eq!(index.extract(0x85), 0x05);
+        assert_eq!(PALETTE_INDEX[0].mask(), 0x80);
+    }
+}
@@ -0,0 +1,302 @@
+use super::error::AnalyzerError;
+
+pub const HEADER_END: usize = 0x0150;
+
+const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mbc {
+    None,
+    MBC1,
+    MBC2,
+    MBC3,
+    MBC5,
+    MBC6,
+    MBC7,
+    MMM01,
+    PocketCamera,
+    TAMA5,
+    HuC1,
+    HuC3,
+    Unknown,
+}
+
+impl Mbc {
+    /* Whether writing to the ROM address `addr` reaches a register of the
+     * memory bank controller. */
+    pub fn is_register(&self, addr: u16) -> bool {
+        match self {
+            Mbc::None => false,
+            Mbc::MBC2 => addr < 0x4000,
+            Mbc::MBC5 => addr < 0x6000,
+            _ => addr < 0x8000,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Header {
+    logo_valid: bool,
+    title: String,
+    cgb_flag: u8,
+    new_licensee: [u8; 2],
+    sgb_flag: u8,
+    cartridge_type: u8,
+    rom_size: u8,
+    ram_size: u8,
+    destination: u8,
+    old_licensee: u8,
+    version: u8,
+    header_checksum: u8,
+    global_checksum: u16,
+}
+
+impl Header {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Header, AnalyzerError> {
+        if bytes.len() < HEADER_END {
+            return Err(AnalyzerError::InvalidHeader(bytes.len()));
+        }
+
+        /* On CGB titles the last bytes of the title are reused for the
+         * manufacturer code and the CGB flag. */
+        let cgb_flag = bytes[0x143];
+        let title_end = if cgb_flag & 0x80 != 0 { 0x143 } else { 0x144 };
+        let title = bytes[0x134..title_end]
+            .iter()
+            .take_while(|&&b| b != 0)
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+
+        Ok(Header {
+            logo_valid: bytes[0x104..0x134] == NINTENDO_LOGO,
+            title,
+            cgb_flag,
+            new_licensee: [bytes[0x144], bytes[0x145]],
+            sgb_flag: bytes[0x146],
+            cartridge_type: bytes[0x147],
+            rom_size: bytes[0x148],
+            ram_size: bytes[0x149],
+            destination: bytes[0x14A],
+            old_licensee: bytes[0x14B],
+            version: bytes[0x14C],
+            header_checksum: bytes[0x14D],
+            global_checksum: u16::from_be_bytes([bytes[0x14E], bytes[0x14F]]),
+        })
+    }
+
+    pub fn compute_header_checksum(bytes: &[u8]) -> u8 {
+        bytes[0x134..0x14D]
+            .iter()
+            .fold(0u8, |acc, &b| acc.wrapping_sub(b).wrapping_sub(1))
+    }
+
+    pub fn compute_global_checksum(bytes: &[u8]) -> u16 {
+        bytes
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != 0x14E && i != 0x14F)
+            .fold(0u16, |acc, (_, &b)| acc.wrapping_add(b as u16))
+    }
+
+    pub fn logo_valid(&self) -> bool {
+        self.logo_valid
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn cgb_flag(&self) -> u8 {
+        self.cgb_flag
+    }
+
+    pub fn supports_cgb(&self) -> bool {
+        self.cgb_flag & 0x80 != 0
+    }
+
+    pub fn cgb_only(&self) -> bool {
+        self.cgb_flag == 0xC0
+    }
+
+    pub fn supports_sgb(&self) -> bool {
+        self.sgb_flag == 0x03 && self.old_licensee == 0x33
+    }
+
+    pub fn licensee(&self) -> String {
+        if self.old_licensee == 0x33 {
+            self.new_licensee.iter().map(|&b| b as char).collect()
+        } else {
+            format!("{:02X}", self.old_licensee)
+        }
+    }
+
+    pub fn cartridge_type(&self) -> u8 {
+        self.cartridge_type
+    }
+
+    pub fn cartridge_type_name(&self) -> &'static str {
+        match self.cartridge_type {
+            0x00 => "ROM ONLY",
+            0x01 => "MBC1",
+            0x02 => "MBC1+RAM",
+            0x03 => "MBC1+RAM+BATTERY",
+            0x05 => "MBC2",
+            0x06 => "MBC2+BATTERY",
+            0x08 => "ROM+RAM",
+            0x09 => "ROM+RAM+BATTERY",
+            0x0B => "MMM01",
+            0x0C => "MMM01+RAM",
+            0x0D => "MMM01+RAM+BATTERY",
+            0x0F => "MBC3+TIMER+BATTERY",
+            0x10 => "MBC3+TIMER+RAM+BATTERY",
+            0x11 => "MBC3",
+            0x12 => "MBC3+RAM",
+            0x13 => "MBC3+RAM+BATTERY",
+            0x19 => "MBC5",
+            0x1A => "MBC5+RAM",
+            0x1B => "MBC5+RAM+BATTERY",
+            0x1C => "MBC5+RUMBLE",
+            0x1D => "MBC5+RUMBLE+RAM",
+            0x1E => "MBC5+RUMBLE+RAM+BATTERY",
+            0x20 => "MBC6",
+            0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+            0xFC => "POCKET CAMERA",
+            0xFD => "BANDAI TAMA5",
+            0xFE => "HuC3",
+            0xFF => "HuC1+RAM+BATTERY",
+            _ => "UNKNOWN",
+        }
+    }
+
+    pub fn mbc(&self) -> Mbc {
+        match self.cartridge_type {
+            0x00 | 0x08 | 0x09 => Mbc::None,
+            0x01..=0x03 => Mbc::MBC1,
+            0x05 | 0x06 => Mbc::MBC2,
+            0x0B..=0x0D => Mbc::MMM01,
+            0x0F..=0x13 => Mbc::MBC3,
+            0x19..=0x1E => Mbc::MBC5,
+            0x20 => Mbc::MBC6,
+            0x22 => Mbc::MBC7,
+            0xFC => Mbc::PocketCamera,
+            0xFD => Mbc::TAMA5,
+            0xFE => Mbc::HuC3,
+            0xFF => Mbc::HuC1,
+            _ => Mbc::Unknown,
+        }
+    }
+
+    pub fn rom_banks(&self) -> usize {
+        match self.rom_size {
+            0x00..=0x08 => 2 << self.rom_size,
+            0x52 => 72,
+            0x53 => 80,
+            0x54 => 96,
+            _ => 0,
+        }
+    }
+
+    pub fn ram_size(&self) -> usize {
+        match self.ram_size {
+            0x01 => 2 * 1024,
+            0x02 => 8 * 1024,
+            0x03 => 32 * 1024,
+            0x04 => 128 * 1024,
+            0x05 => 64 * 1024,
+            _ => 0,
+        }
+    }
+
+    pub fn japanese(&self) -> bool {
+        self.destination == 0x00
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn header_checksum(&self) -> u8 {
+        self.header_checksum
+    }
+
+    pub fn global_checksum(&self) -> u16 {
+        self.global_checksum
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom() -> Vec<u8> {
+        let mut bytes = vec![0x00; 0x8000];
+        bytes[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+        bytes[0x134..0x138].copy_from_slice(b"TEST");
+        bytes[0x147] = 0x03;
+        bytes[0x148] = 0x01;
+        bytes[0x149] = 0x02;
+        bytes
+    }
+
+    #[test]
+    fn fields() {
+        let header = Header::from_bytes(&rom()).unwrap();
+
+        assert!(header.logo_valid());
+        assert_eq!(header.title(), "TEST");
+        assert_eq!(header.cartridge_type_name(), "MBC1+RAM+BATTERY");
+        assert_eq!(header.mbc(), Mbc::MBC1);
+        assert_eq!(header.rom_banks(), 4);
+        assert_eq!(header.ram_size(), 8 * 1024);
+        assert!(!header.supports_cgb());
+        assert!(Header::from_bytes(&[0x00; 0x14F]).is_err());
+    }
+
+    #[test]
+    fn cgb_title() {
+        let mut bytes = rom();
+        bytes[0x134..0x144].copy_from_slice(b"ABCDEFGHIJKLMNO\xC0");
+
+        let header = Header::from_bytes(&bytes).unwrap();
+        assert_eq!(header.title(), "ABCDEFGHIJKLMNO");
+        assert!(header.cgb_only());
+    }
+
+    #[test]
+    fn checksums() {
+        /* 25 bytes from the title to the version: 0 - 25 * 1 - the bytes. */
+        let mut bytes = vec![0x00; HEADER_END];
+        assert_eq!(Header::compute_header_checksum(&bytes), 0xE7);
+        bytes[0x134] = 0x10;
+        assert_eq!(Header::compute_header_checksum(&bytes), 0xD7);
+
+        /* The global checksum skips its own two bytes. */
+        bytes[0x14E] = 0xFF;
+        bytes[0x14F] = 0xFF;
+        bytes[0x000] = 0xFF;
+        assert_eq!(Header::compute_global_checksum(&bytes), 0x010F);
+        let bytes = vec![0xFF; 0x200];
+        assert_eq!(
+            Header::compute_global_checksum(&bytes),
+            (0x1FE * 0xFF) as u16
+        );
+    }
+
+    #[test]
+    fn bank_registers() {
+        assert!(!Mbc::None.is_register(0x2000));
+        assert!(Mbc::MBC1.is_register(0x6000));
+        assert!(!Mbc::MBC2.is_register(0x4000));
+        assert!(!Mbc::MBC5.is_register(0x6000));
+    }
+}
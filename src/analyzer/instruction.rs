@@ -1,7 +1,7 @@
 use super::error::AnalyzerError;
 
-#[derive(Debug)]
-enum Mnemonic {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mnemonic {
     NOP,
     STOP,
     LD, /* Load */
@@ -35,10 +35,21 @@ enum Mnemonic {
     CALL,
     RET,
     RETI,
+    RLCA,
+    RRCA,
+    RLA,
+    RRA,
     RLC,
     RRC,
     RL,
     RR,
+    SLA,
+    SRA,
+    SWAP,
+    SRL,
+    BIT,
+    RES,
+    SET,
     DA,
     CPL,
     SCF,
@@ -46,13 +57,12 @@ enum Mnemonic {
     HALT,
     CP,
     RST,
-    CB,
     DI,
     EI,
 }
 
-#[derive(Debug)]
-enum Register {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Register {
     AF,
     A,
     F,
@@ -72,16 +82,32 @@ enum Register {
     SP,
 }
 
-#[derive(Debug)]
-enum Condition {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
     Z,
     NZ,
     C,
     NC,
 }
 
-#[derive(Debug)]
-enum Operand {
+impl Register {
+    /* Whether the two registers share bits: a pair and its halves. */
+    pub fn overlaps(self, other: Register) -> bool {
+        let pair = |reg: Register| match reg {
+            Register::AF | Register::A | Register::F => Some(Register::AF),
+            Register::BC | Register::B | Register::C => Some(Register::BC),
+            Register::DE | Register::D | Register::E => Some(Register::DE),
+            Register::HL | Register::H | Register::L => Some(Register::HL),
+            Register::SP => None,
+        };
+        let is_pair = |reg: Register| pair(reg) == Some(reg);
+
+        self == other || (pair(self) == pair(other) && (is_pair(self) || is_pair(other)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
     Imm8(u8),
     Imm16(u16),
     Addr8(u8),
@@ -89,11 +115,25 @@ enum Operand {
     Addr16(u16),
     DerefAddr16(u16),
     Rel8(u8),
+    SPRel8(u8), /* SP + signed offset */
+    Bit(u8),
     Reg(Register),
     DerefReg(Register),
     Cond(Condition),
 }
 
+/* How an instruction affects the program counter. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Next,
+    Jump(Option<u16>), /* None when the target is computed (JP HL) */
+    Branch(u16),       /* Conditional jump, falls through otherwise */
+    Call(u16),
+    Return,
+    ConditionalReturn,
+}
+
+#[derive(Clone)]
 pub struct Instruction {
     size: usize,
     cycles: usize,
@@ -104,7 +144,7 @@ pub struct Instruction {
 
 impl Instruction {
     pub fn from_slice(bytes: &[u8]) -> Result<Instruction, AnalyzerError> {
-        if bytes.len() < 1 {
+        if bytes.is_empty() {
             return Err(AnalyzerError::InvalidInstructionSize(0));
         }
 
@@ -115,6 +155,128 @@ impl Instruction {
         self.size
     }
 
+    pub fn cycles(&self) -> usize {
+        self.cycles
+    }
+
+    /* Cycles when the condition does not hold and when it does, conditional
+     * instructions are recorded with 0 cycles. Others take `cycles` either
+     * way. */
+    pub fn branch_cycles(&self) -> (usize, usize) {
+        if self.cycles != 0 {
+            return (self.cycles, self.cycles);
+        }
+
+        match self.mnemonic {
+            Mnemonic::JRNZ | Mnemonic::JRZ | Mnemonic::JRNC | Mnemonic::JRC => (8, 12),
+            Mnemonic::JPNZ | Mnemonic::JPZ | Mnemonic::JPNC | Mnemonic::JPC => (12, 16),
+            Mnemonic::CALL => (12, 24),
+            _ => (8, 20),
+        }
+    }
+
+    pub fn mnemonic(&self) -> &Mnemonic {
+        &self.mnemonic
+    }
+
+    pub fn lhs(&self) -> Option<&Operand> {
+        self.lhs.as_ref()
+    }
+
+    pub fn rhs(&self) -> Option<&Operand> {
+        self.rhs.as_ref()
+    }
+
+    /* Registers the instruction may change, flags and SP aside. Calls may
+     * change any of them. */
+    pub fn written(&self) -> Vec<Register> {
+        let reg = |operand: Option<&Operand>| match operand {
+            Some(Operand::Reg(reg)) => vec![*reg],
+            _ => Vec::new(),
+        };
+
+        match self.mnemonic {
+            Mnemonic::LD
+            | Mnemonic::LDHR
+            | Mnemonic::ADD
+            | Mnemonic::ADC
+            | Mnemonic::SBC
+            | Mnemonic::INC
+            | Mnemonic::DEC
+            | Mnemonic::POP
+            | Mnemonic::RLC
+            | Mnemonic::RRC
+            | Mnemonic::RL
+            | Mnemonic::RR
+            | Mnemonic::SLA
+            | Mnemonic::SRA
+            | Mnemonic::SWAP
+            | Mnemonic::SRL => reg(self.lhs()),
+            Mnemonic::RES | Mnemonic::SET => reg(self.rhs()),
+            Mnemonic::LDIL | Mnemonic::LDDL => vec![Register::HL],
+            Mnemonic::LDIR | Mnemonic::LDDR => vec![Register::A, Register::HL],
+            Mnemonic::SUB
+            | Mnemonic::AND
+            | Mnemonic::OR
+            | Mnemonic::XOR
+            | Mnemonic::CPL
+            | Mnemonic::DA
+            | Mnemonic::RLCA
+            | Mnemonic::RRCA
+            | Mnemonic::RLA
+            | Mnemonic::RRA => vec![Register::A],
+            Mnemonic::CALL | Mnemonic::RST => {
+                vec![Register::AF, Register::BC, Register::DE, Register::HL]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /* Whether the instruction may change `reg` or a part of it. */
+    pub fn writes(&self, reg: Register) -> bool {
+        self.written().iter().any(|written| written.overlaps(reg))
+    }
+
+    /* Control flow of the instruction when it is located at `addr`. */
+    pub fn flow(&self, addr: u16) -> Flow {
+        let next = addr.wrapping_add(self.size as u16);
+        let target = |operand: Option<&Operand>| match operand {
+            Some(Operand::Rel8(offset)) => Some(next.wrapping_add(*offset as i8 as u16)),
+            Some(Operand::Addr16(target)) => Some(*target),
+            Some(Operand::Imm8(vector)) => Some(*vector as u16),
+            _ => None,
+        };
+
+        match self.mnemonic {
+            Mnemonic::JR | Mnemonic::JP => Flow::Jump(target(self.lhs())),
+            Mnemonic::JRNZ
+            | Mnemonic::JRZ
+            | Mnemonic::JRNC
+            | Mnemonic::JRC
+            | Mnemonic::JPNZ
+            | Mnemonic::JPZ
+            | Mnemonic::JPNC
+            | Mnemonic::JPC => match target(self.lhs()) {
+                Some(target) => Flow::Branch(target),
+                None => Flow::Next,
+            },
+            Mnemonic::CALL | Mnemonic::RST => {
+                /* Conditional calls hold their target in the rhs. */
+                match target(self.rhs()).or_else(|| target(self.lhs())) {
+                    Some(target) => Flow::Call(target),
+                    None => Flow::Next,
+                }
+            }
+            Mnemonic::RET if self.lhs.is_some() => Flow::ConditionalReturn,
+            Mnemonic::RET | Mnemonic::RETI => Flow::Return,
+            _ => Flow::Next,
+        }
+    }
+
+    /* Sizes, cycles and operands follow the opcode table of the Pan Docs,
+     * which the tests below check for every opcode. Conditional jumps, calls
+     * and returns are recorded with 0 cycles, their cost depends on the
+     * branch taken. */
     fn decode(bytes: &[u8]) -> Result<Instruction, AnalyzerError> {
         let opcode = bytes[0];
 
@@ -149,7 +311,7 @@ impl Instruction {
             },
             0x04 => Instruction {
                 size: 1,
-                cycles: 8,
+                cycles: 4,
                 mnemonic: Mnemonic::INC,
                 lhs: Some(Operand::Reg(Register::B)),
                 rhs: None,
@@ -171,8 +333,8 @@ impl Instruction {
             0x07 => Instruction {
                 size: 1,
                 cycles: 4,
-                mnemonic: Mnemonic::RLC,
-                lhs: Some(Operand::Reg(Register::A)),
+                mnemonic: Mnemonic::RLCA,
+                lhs: None,
                 rhs: None,
             },
             0x08 => Instruction {
@@ -227,15 +389,15 @@ impl Instruction {
             0x0F => Instruction {
                 size: 1,
                 cycles: 4,
-                mnemonic: Mnemonic::RRC,
-                lhs: Some(Operand::Reg(Register::A)),
+                mnemonic: Mnemonic::RRCA,
+                lhs: None,
                 rhs: None,
             },
             0x10 => Instruction {
-                size: 2,
+                size: 2, /* Assemblers emit the padding byte after it. */
                 cycles: 4,
                 mnemonic: Mnemonic::STOP,
-                lhs: Some(Operand::Imm8(0)),
+                lhs: None,
                 rhs: None,
             },
             0x11 => Instruction {
@@ -283,8 +445,8 @@ impl Instruction {
             0x17 => Instruction {
                 size: 1,
                 cycles: 4,
-                mnemonic: Mnemonic::RL,
-                lhs: Some(Operand::Reg(Register::A)),
+                mnemonic: Mnemonic::RLA,
+                lhs: None,
                 rhs: None,
             },
             0x18 => Instruction {
@@ -319,19 +481,19 @@ impl Instruction {
                 size: 1,
                 cycles: 4,
                 mnemonic: Mnemonic::INC,
-                lhs: Some(Operand::Reg(Register::C)),
+                lhs: Some(Operand::Reg(Register::E)),
                 rhs: None,
             },
             0x1D => Instruction {
                 size: 1,
                 cycles: 4,
                 mnemonic: Mnemonic::DEC,
-                lhs: Some(Operand::Reg(Register::C)),
+                lhs: Some(Operand::Reg(Register::E)),
                 rhs: None,
             },
             0x1E => Instruction {
-                size: 1,
-                cycles: 4,
+                size: 2,
+                cycles: 8,
                 mnemonic: Mnemonic::LD,
                 lhs: Some(Operand::Reg(Register::E)),
                 rhs: Some(Operand::Imm8(Instruction::read_imm8(bytes)?)),
@@ -339,8 +501,8 @@ impl Instruction {
             0x1F => Instruction {
                 size: 1,
                 cycles: 4,
-                mnemonic: Mnemonic::RR,
-                lhs: Some(Operand::Reg(Register::A)),
+                mnemonic: Mnemonic::RRA,
+                lhs: None,
                 rhs: None,
             },
             0x20 => Instruction {
@@ -368,7 +530,7 @@ impl Instruction {
                 size: 1,
                 cycles: 8,
                 mnemonic: Mnemonic::INC,
-                lhs: Some(Operand::Reg(Register::DE)),
+                lhs: Some(Operand::Reg(Register::HL)),
                 rhs: None,
             },
             0x24 => Instruction {
@@ -387,7 +549,7 @@ impl Instruction {
             },
             0x26 => Instruction {
                 size: 2,
-                cycles: 6,
+                cycles: 8,
                 mnemonic: Mnemonic::LD,
                 lhs: Some(Operand::Reg(Register::H)),
                 rhs: Some(Operand::Imm8(Instruction::read_imm8(bytes)?)),
@@ -396,7 +558,7 @@ impl Instruction {
                 size: 1,
                 cycles: 4,
                 mnemonic: Mnemonic::DA,
-                lhs: Some(Operand::Reg(Register::A)),
+                lhs: None,
                 rhs: None,
             },
             0x28 => Instruction {
@@ -417,8 +579,8 @@ impl Instruction {
                 size: 1,
                 cycles: 8,
                 mnemonic: Mnemonic::LDIR,
-                lhs: Some(Operand::DerefReg(Register::A)),
-                rhs: Some(Operand::Reg(Register::HL)),
+                lhs: Some(Operand::Reg(Register::A)),
+                rhs: Some(Operand::DerefReg(Register::HL)),
             },
             0x2B => Instruction {
                 size: 1,
@@ -452,7 +614,7 @@ impl Instruction {
                 size: 1,
                 cycles: 4,
                 mnemonic: Mnemonic::CPL,
-                lhs: Some(Operand::Reg(Register::A)),
+                lhs: None,
                 rhs: None,
             },
             0x30 => Instruction {
@@ -515,7 +677,7 @@ impl Instruction {
                 size: 2,
                 cycles: 0, /* Cycle depends on branch taken (12/8 true/false). */
                 mnemonic: Mnemonic::JRC,
-                lhs: Some(Operand::Imm8(Instruction::read_imm8(bytes)?)),
+                lhs: Some(Operand::Rel8(Instruction::read_imm8(bytes)?)),
                 rhs: None,
             },
             0x39 => Instruction {
@@ -611,7 +773,7 @@ impl Instruction {
             },
             0x46 => Instruction {
                 size: 1,
-                cycles: 4,
+                cycles: 8,
                 mnemonic: Mnemonic::LD,
                 lhs: Some(Operand::Reg(Register::B)),
                 rhs: Some(Operand::DerefReg(Register::HL)),
@@ -667,7 +829,7 @@ impl Instruction {
             },
             0x4E => Instruction {
                 size: 1,
-                cycles: 4,
+                cycles: 8,
                 mnemonic: Mnemonic::LD,
                 lhs: Some(Operand::Reg(Register::C)),
                 rhs: Some(Operand::DerefReg(Register::HL)),
@@ -723,7 +885,7 @@ impl Instruction {
             },
             0x56 => Instruction {
                 size: 1,
-                cycles: 4,
+                cycles: 8,
                 mnemonic: Mnemonic::LD,
                 lhs: Some(Operand::Reg(Register::D)),
                 rhs: Some(Operand::DerefReg(Register::HL)),
@@ -742,13 +904,6 @@ impl Instruction {
                 lhs: Some(Operand::Reg(Register::E)),
                 rhs: Some(Operand::Reg(Register::B)),
             },
-            0x58 => Instruction {
-                size: 1,
-                cycles: 4,
-                mnemonic: Mnemonic::LD,
-                lhs: Some(Operand::Reg(Register::E)),
-                rhs: Some(Operand::Reg(Register::C)),
-            },
             0x59 => Instruction {
                 size: 1,
                 cycles: 4,
@@ -777,11 +932,18 @@ impl Instruction {
                 lhs: Some(Operand::Reg(Register::E)),
                 rhs: Some(Operand::Reg(Register::H)),
             },
-            0x5E => Instruction {
+            0x5D => Instruction {
                 size: 1,
                 cycles: 4,
                 mnemonic: Mnemonic::LD,
                 lhs: Some(Operand::Reg(Register::E)),
+                rhs: Some(Operand::Reg(Register::L)),
+            },
+            0x5E => Instruction {
+                size: 1,
+                cycles: 8,
+                mnemonic: Mnemonic::LD,
+                lhs: Some(Operand::Reg(Register::E)),
                 rhs: Some(Operand::DerefReg(Register::HL)),
             },
             0x5F => Instruction {
@@ -835,7 +997,7 @@ impl Instruction {
             },
             0x66 => Instruction {
                 size: 1,
-                cycles: 4,
+                cycles: 8,
                 mnemonic: Mnemonic::LD,
                 lhs: Some(Operand::Reg(Register::H)),
                 rhs: Some(Operand::DerefReg(Register::HL)),
@@ -891,7 +1053,7 @@ impl Instruction {
             },
             0x6E => Instruction {
                 size: 1,
-                cycles: 4,
+                cycles: 8,
                 mnemonic: Mnemonic::LD,
                 lhs: Some(Operand::Reg(Register::L)),
                 rhs: Some(Operand::DerefReg(Register::HL)),
@@ -905,42 +1067,42 @@ impl Instruction {
             },
             0x70 => Instruction {
                 size: 1,
-                cycles: 4,
+                cycles: 8,
                 mnemonic: Mnemonic::LD,
                 lhs: Some(Operand::DerefReg(Register::HL)),
                 rhs: Some(Operand::Reg(Register::B)),
             },
             0x71 => Instruction {
                 size: 1,
-                cycles: 4,
+                cycles: 8,
                 mnemonic: Mnemonic::LD,
                 lhs: Some(Operand::DerefReg(Register::HL)),
                 rhs: Some(Operand::Reg(Register::C)),
             },
             0x72 => Instruction {
                 size: 1,
-                cycles: 4,
+                cycles: 8,
                 mnemonic: Mnemonic::LD,
                 lhs: Some(Operand::DerefReg(Register::HL)),
                 rhs: Some(Operand::Reg(Register::D)),
             },
             0x73 => Instruction {
                 size: 1,
-                cycles: 4,
+                cycles: 8,
                 mnemonic: Mnemonic::LD,
                 lhs: Some(Operand::DerefReg(Register::HL)),
                 rhs: Some(Operand::Reg(Register::E)),
             },
             0x74 => Instruction {
                 size: 1,
-                cycles: 4,
+                cycles: 8,
                 mnemonic: Mnemonic::LD,
                 lhs: Some(Operand::DerefReg(Register::HL)),
                 rhs: Some(Operand::Reg(Register::H)),
             },
             0x75 => Instruction {
                 size: 1,
-                cycles: 4,
+                cycles: 8,
                 mnemonic: Mnemonic::LD,
                 lhs: Some(Operand::DerefReg(Register::HL)),
                 rhs: Some(Operand::Reg(Register::L)),
@@ -954,7 +1116,7 @@ impl Instruction {
             },
             0x77 => Instruction {
                 size: 1,
-                cycles: 4,
+                cycles: 8,
                 mnemonic: Mnemonic::LD,
                 lhs: Some(Operand::DerefReg(Register::HL)),
                 rhs: Some(Operand::Reg(Register::A)),
@@ -992,7 +1154,7 @@ impl Instruction {
                 cycles: 4,
                 mnemonic: Mnemonic::LD,
                 lhs: Some(Operand::Reg(Register::A)),
-                rhs: Some(Operand::Reg(Register::E)),
+                rhs: Some(Operand::Reg(Register::H)),
             },
             0x7D => Instruction {
                 size: 1,
@@ -1003,7 +1165,7 @@ impl Instruction {
             },
             0x7E => Instruction {
                 size: 1,
-                cycles: 4,
+                cycles: 8,
                 mnemonic: Mnemonic::LD,
                 lhs: Some(Operand::Reg(Register::A)),
                 rhs: Some(Operand::DerefReg(Register::HL)),
@@ -1059,7 +1221,7 @@ impl Instruction {
             },
             0x86 => Instruction {
                 size: 1,
-                cycles: 4,
+                cycles: 8,
                 mnemonic: Mnemonic::ADD,
                 lhs: Some(Operand::Reg(Register::A)),
                 rhs: Some(Operand::DerefReg(Register::HL)),
@@ -1093,36 +1255,29 @@ impl Instruction {
                 rhs: Some(Operand::Reg(Register::D)),
             },
             0x8B => Instruction {
-                size: 1,
-                cycles: 4,
-                mnemonic: Mnemonic::ADC,
-                lhs: Some(Operand::Reg(Register::A)),
-                rhs: Some(Operand::Reg(Register::D)),
-            },
-            0x8C => Instruction {
                 size: 1,
                 cycles: 4,
                 mnemonic: Mnemonic::ADC,
                 lhs: Some(Operand::Reg(Register::A)),
                 rhs: Some(Operand::Reg(Register::E)),
             },
-            0x8D => Instruction {
+            0x8C => Instruction {
                 size: 1,
                 cycles: 4,
                 mnemonic: Mnemonic::ADC,
                 lhs: Some(Operand::Reg(Register::A)),
                 rhs: Some(Operand::Reg(Register::H)),
             },
-            0x8C => Instruction {
+            0x8D => Instruction {
                 size: 1,
                 cycles: 4,
                 mnemonic: Mnemonic::ADC,
                 lhs: Some(Operand::Reg(Register::A)),
                 rhs: Some(Operand::Reg(Register::L)),
             },
-            0x8D => Instruction {
+            0x8E => Instruction {
                 size: 1,
-                cycles: 4,
+                cycles: 8,
                 mnemonic: Mnemonic::ADC,
                 lhs: Some(Operand::Reg(Register::A)),
                 rhs: Some(Operand::DerefReg(Register::HL)),
@@ -1178,7 +1333,7 @@ impl Instruction {
             },
             0x96 => Instruction {
                 size: 1,
-                cycles: 4,
+                cycles: 8,
                 mnemonic: Mnemonic::SUB,
                 lhs: Some(Operand::DerefReg(Register::HL)),
                 rhs: None,
@@ -1234,7 +1389,7 @@ impl Instruction {
             },
             0x9E => Instruction {
                 size: 1,
-                cycles: 4,
+                cycles: 8,
                 mnemonic: Mnemonic::SBC,
                 lhs: Some(Operand::Reg(Register::A)),
                 rhs: Some(Operand::DerefReg(Register::HL)),
@@ -1290,7 +1445,7 @@ impl Instruction {
             },
             0xA6 => Instruction {
                 size: 1,
-                cycles: 4,
+                cycles: 8,
                 mnemonic: Mnemonic::AND,
                 lhs: Some(Operand::DerefReg(Register::HL)),
                 rhs: None,
@@ -1344,16 +1499,9 @@ impl Instruction {
                 lhs: Some(Operand::Reg(Register::L)),
                 rhs: None,
             },
-            0xAD => Instruction {
-                size: 1,
-                cycles: 4,
-                mnemonic: Mnemonic::XOR,
-                lhs: Some(Operand::Reg(Register::L)),
-                rhs: None,
-            },
             0xAE => Instruction {
                 size: 1,
-                cycles: 4,
+                cycles: 8,
                 mnemonic: Mnemonic::XOR,
                 lhs: Some(Operand::DerefReg(Register::HL)),
                 rhs: None,
@@ -1409,7 +1557,7 @@ impl Instruction {
             },
             0xB6 => Instruction {
                 size: 1,
-                cycles: 4,
+                cycles: 8,
                 mnemonic: Mnemonic::OR,
                 lhs: Some(Operand::DerefReg(Register::HL)),
                 rhs: None,
@@ -1465,7 +1613,7 @@ impl Instruction {
             },
             0xBE => Instruction {
                 size: 1,
-                cycles: 4,
+                cycles: 8,
                 mnemonic: Mnemonic::CP,
                 lhs: Some(Operand::DerefReg(Register::HL)),
                 rhs: None,
@@ -1554,13 +1702,7 @@ impl Instruction {
                 lhs: Some(Operand::Addr16(Instruction::read_imm16(bytes)?)),
                 rhs: None,
             },
-            0xCB => Instruction {
-                size: 1,
-                cycles: 4,
-                mnemonic: Mnemonic::CB,
-                lhs: None,
-                rhs: None,
-            },
+            0xCB => Instruction::decode_cb(bytes)?,
             0xCC => Instruction {
                 size: 3,
                 cycles: 0, /* Cycle depends on branch taken (24/12 true/false). */
@@ -1607,7 +1749,7 @@ impl Instruction {
                 size: 3,
                 cycles: 0, /* Cycle depends on branch taken (16/12 true/false). */
                 mnemonic: Mnemonic::JPNC,
-                lhs: Some(Operand::Imm16(Instruction::read_imm16(bytes)?)),
+                lhs: Some(Operand::Addr16(Instruction::read_imm16(bytes)?)),
                 rhs: None,
             },
             0xD4 => Instruction {
@@ -1642,7 +1784,7 @@ impl Instruction {
                 size: 1,
                 cycles: 0, /* Cycle depends on branch taken (20/8 true/false). */
                 mnemonic: Mnemonic::RET,
-                lhs: Some(Operand::Reg(Register::C)),
+                lhs: Some(Operand::Cond(Condition::C)),
                 rhs: None,
             },
             0xD9 => Instruction {
@@ -1695,7 +1837,7 @@ impl Instruction {
                rhs: None,
            },
            0xE2 => Instruction {
-               size: 2,
+               size: 1,
                cycles: 8,
                mnemonic: Mnemonic::LDHL,
                lhs: Some(Operand::DerefReg(Register::C)),
@@ -1743,13 +1885,6 @@ impl Instruction {
                lhs: Some(Operand::DerefAddr16(Instruction::read_imm16(bytes)?)),
                rhs: Some(Operand::Reg(Register::A)),
            },
-           0xEC => Instruction {
-               size: 3,
-               cycles: 0, /* Cycle depends on branch taken (24/12 true/false). */
-               mnemonic: Mnemonic::CALL,
-               lhs: Some(Operand::Cond(Condition::C)),
-               rhs: Some(Operand::Addr16(Instruction::read_imm16(bytes)?)),
-           },
            0xEE => Instruction {
                size: 2,
                cycles: 8,
@@ -1761,7 +1896,7 @@ impl Instruction {
                size: 1,
                cycles: 16,
                mnemonic: Mnemonic::RST,
-               lhs: Some(Operand::Imm8(0x18)),
+               lhs: Some(Operand::Imm8(0x28)),
                rhs: None,
            },
            0xF0 => Instruction {
@@ -1779,7 +1914,7 @@ impl Instruction {
                rhs: None,
            },
            0xF2 => Instruction {
-               size: 2,
+               size: 1,
                cycles: 8,
                mnemonic: Mnemonic::LDHR,
                lhs: Some(Operand::Reg(Register::A)),
@@ -1816,9 +1951,9 @@ impl Instruction {
            0xF8 => Instruction {
                size: 2,
                cycles: 12,
-               mnemonic: Mnemonic::LDHL,
+               mnemonic: Mnemonic::LD,
                lhs: Some(Operand::Reg(Register::HL)),
-               rhs: Some(Operand::Imm8(Instruction::read_imm8(bytes)?)),
+               rhs: Some(Operand::SPRel8(Instruction::read_imm8(bytes)?)),
            },
            0xF9 => Instruction {
                size: 1,
@@ -1861,6 +1996,57 @@ impl Instruction {
         Ok(inst)
     }
 
+    fn decode_cb(bytes: &[u8]) -> Result<Instruction, AnalyzerError> {
+        /* The second byte selects the operation, bit index and target. */
+        let opcode = Instruction::read_imm8(bytes)?;
+
+        let target = match opcode & 0x07 {
+            0x00 => Operand::Reg(Register::B),
+            0x01 => Operand::Reg(Register::C),
+            0x02 => Operand::Reg(Register::D),
+            0x03 => Operand::Reg(Register::E),
+            0x04 => Operand::Reg(Register::H),
+            0x05 => Operand::Reg(Register::L),
+            0x06 => Operand::DerefReg(Register::HL),
+            _ => Operand::Reg(Register::A),
+        };
+        let deref = opcode & 0x07 == 0x06;
+        let bit = (opcode >> 3) & 0x07;
+
+        let (mnemonic, lhs, rhs) = match opcode >> 6 {
+            0x00 => {
+                let mnemonic = match bit {
+                    0x00 => Mnemonic::RLC,
+                    0x01 => Mnemonic::RRC,
+                    0x02 => Mnemonic::RL,
+                    0x03 => Mnemonic::RR,
+                    0x04 => Mnemonic::SLA,
+                    0x05 => Mnemonic::SRA,
+                    0x06 => Mnemonic::SWAP,
+                    _ => Mnemonic::SRL,
+                };
+                (mnemonic, target, None)
+            }
+            0x01 => (Mnemonic::BIT, Operand::Bit(bit), Some(target)),
+            0x02 => (Mnemonic::RES, Operand::Bit(bit), Some(target)),
+            _ => (Mnemonic::SET, Operand::Bit(bit), Some(target)),
+        };
+
+        let cycles = match (&mnemonic, deref) {
+            (_, false) => 8,
+            (Mnemonic::BIT, true) => 12,
+            (_, true) => 16,
+        };
+
+        Ok(Instruction {
+            size: 2,
+            cycles,
+            mnemonic,
+            lhs: Some(lhs),
+            rhs,
+        })
+    }
+
     fn read_imm8(bytes: &[u8]) -> Result<u8, AnalyzerError> {
         /* bytes[0] is the opcodes, operands are after */
         if bytes.len() < 2 {
@@ -1895,3 +2081,143 @@ impl std::fmt::Debug for Instruction {
         res
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Size of each base opcode, 0 for the invalid ones. */
+    #[rustfmt::skip]
+    const SIZES: [usize; 256] = [
+        1, 3, 1, 1, 1, 1, 2, 1, 3, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 3, 3, 3, 1, 2, 1, 1, 1, 3, 2, 3, 3, 2, 1,
+        1, 1, 3, 0, 3, 1, 2, 1, 1, 1, 3, 0, 3, 0, 2, 1,
+        2, 1, 1, 0, 0, 1, 2, 1, 2, 1, 3, 0, 0, 0, 2, 1,
+        2, 1, 1, 1, 0, 1, 2, 1, 2, 1, 3, 1, 0, 0, 2, 1,
+    ];
+
+    /* Cycles of each base opcode, 0 for the conditional ones and for $CB,
+     * whose cycles are those of the second byte. */
+    #[rustfmt::skip]
+    const CYCLES: [usize; 256] = [
+        4, 12,  8,  8,  4,  4,  8,  4, 20,  8,  8,  8,  4,  4,  8,  4,
+        4, 12,  8,  8,  4,  4,  8,  4, 12,  8,  8,  8,  4,  4,  8,  4,
+        0, 12,  8,  8,  4,  4,  8,  4,  0,  8,  8,  8,  4,  4,  8,  4,
+        0, 12,  8,  8, 12, 12, 12,  4,  0,  8,  8,  8,  4,  4,  8,  4,
+        4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+        4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+        4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+        8,  8,  8,  8,  8,  8,  4,  8,  4,  4,  4,  4,  4,  4,  8,  4,
+        4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+        4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+        4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+        4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+        0, 12,  0, 16,  0, 16,  8, 16,  0, 16,  0,  0,  0, 24,  8, 16,
+        0, 12,  0,  0,  0, 16,  8, 16,  0, 16,  0,  0,  0,  0,  8, 16,
+       12, 12,  8,  0,  0, 16,  8, 16, 16,  4, 16,  0,  0,  0,  8, 16,
+       12, 12,  8,  4,  0, 16,  8, 16, 12,  8, 16,  4,  0,  0,  8, 16,
+    ];
+
+    #[test]
+    fn base_opcodes() {
+        for opcode in 0..=0xFF_u8 {
+            if opcode == 0xCB {
+                continue;
+            }
+            let decoded = Instruction::from_slice(&[opcode, 0x00, 0x00]);
+            match SIZES[opcode as usize] {
+                0 => assert!(decoded.is_err(), "${:02X} is invalid", opcode),
+                size => {
+                    let inst = decoded.unwrap();
+                    assert_eq!(inst.size(), size, "size of ${:02X}", opcode);
+                    assert_eq!(
+                        inst.cycles(),
+                        CYCLES[opcode as usize],
+                        "cycles of ${:02X}",
+                        opcode
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cb_opcodes() {
+        for opcode in 0..=0xFF_u8 {
+            let inst = Instruction::from_slice(&[0xCB, opcode]).unwrap();
+            let cycles = match (opcode & 0x07, opcode >> 6) {
+                (0x06, 0x01) => 12,
+                (0x06, _) => 16,
+                _ => 8,
+            };
+            assert_eq!(inst.size(), 2, "size of $CB ${:02X}", opcode);
+            assert_eq!(inst.cycles(), cycles, "cycles of $CB ${:02X}", opcode);
+        }
+    }
+
+    #[test]
+    fn branch_cycles() {
+        let cycles = |bytes: &[u8]| Instruction::from_slice(bytes).unwrap().branch_cycles();
+
+        assert_eq!(cycles(&[0x20, 0x00]), (8, 12));
+        assert_eq!(cycles(&[0xC2, 0x00, 0x00]), (12, 16));
+        assert_eq!(cycles(&[0xC4, 0x00, 0x00]), (12, 24));
+        assert_eq!(cycles(&[0xC0]), (8, 20));
+        assert_eq!(cycles(&[0xC9]), (16, 16));
+    }
+
+    #[test]
+    fn operands() {
+        let inst = Instruction::from_slice(&[0xCB, 0x7E]).unwrap();
+        assert!(matches!(inst.mnemonic(), Mnemonic::BIT));
+        assert!(matches!(inst.lhs(), Some(Operand::Bit(7))));
+        assert!(matches!(inst.rhs(), Some(Operand::DerefReg(Register::HL))));
+
+        let inst = Instruction::from_slice(&[0xF8, 0xFE]).unwrap();
+        assert!(matches!(inst.rhs(), Some(Operand::SPRel8(0xFE))));
+
+        let inst = Instruction::from_slice(&[0xEF]).unwrap();
+        assert!(matches!(inst.lhs(), Some(Operand::Imm8(0x28))));
+
+        let inst = Instruction::from_slice(&[0x17]).unwrap();
+        assert!(matches!(inst.mnemonic(), Mnemonic::RLA));
+        assert!(inst.lhs().is_none());
+    }
+
+    #[test]
+    fn truncated() {
+        assert!(Instruction::from_slice(&[]).is_err());
+        assert!(Instruction::from_slice(&[0x3E]).is_err());
+        assert!(Instruction::from_slice(&[0xC3, 0x00]).is_err());
+        assert!(Instruction::from_slice(&[0xCB]).is_err());
+    }
+
+    #[test]
+    fn written_registers() {
+        assert!(Register::HL.overlaps(Register::L));
+        assert!(Register::A.overlaps(Register::AF));
+        assert!(!Register::H.overlaps(Register::L));
+
+        let inst = Instruction::from_slice(&[0x2A]).unwrap(); /* ld a, [hl+] */
+        assert!(inst.writes(Register::A));
+        assert!(inst.writes(Register::HL));
+        assert!(!inst.writes(Register::DE));
+
+        let inst = Instruction::from_slice(&[0xEA, 0x00, 0x20]).unwrap(); /* ld [$2000], a */
+        assert!(inst.written().is_empty());
+
+        let inst = Instruction::from_slice(&[0xCD, 0x00, 0x03]).unwrap();
+        assert!(inst.writes(Register::E));
+    }
+}
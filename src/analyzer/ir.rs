@@ -0,0 +1,941 @@
+use super::instruction::{Condition, Flow, Instruction, Mnemonic, Operand, Register};
+
+/* Flags of the F register. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Flag {
+    Z,
+    N,
+    H,
+    C,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+    Eq, /* 1 when equal, else 0 */
+    Lt, /* 1 when lower, else 0 */
+}
+
+/* A value computed by an instruction. Values are integers of unbounded
+ * width, negative ones in two's complement: they are truncated to the width
+ * of where they are written, so that carries can be told from them. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Const(u16),
+    /* 8- or 16-bit register, never F or AF: flags are read one by one. */
+    Reg(Register),
+    /* 1 when set, else 0. */
+    Flag(Flag),
+    /* Byte at the address, truncated to 16 bits. */
+    Load(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    /* 1 when the value is 0, else 0. */
+    Not(Box<Expr>),
+    /* 1 when the value does not fit in that many bits, else 0: the carry
+     * or borrow of an addition or subtraction. */
+    Overflow(u8, Box<Expr>),
+    /* The second value when the first is not 0, else the third. */
+    Select(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+/* An effect of an instruction. The statements lifted from an instruction
+ * all read the state before it, like the CPU does: `push` stores relative
+ * to the SP it decrements. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Statement {
+    /* Truncated to the width of the register. */
+    Assign(Register, Expr),
+    /* Set when the value is not 0. */
+    SetFlag(Flag, Expr),
+    /* Byte at the first address, the value truncated to 8 bits. */
+    Store(Expr, Expr),
+    Jump(Expr),
+    /* The return address is stored by the statements before. */
+    Call(Expr),
+    /* Jumps to the word at SP, which is moved by an assignment. */
+    Return,
+    If(Expr, Vec<Statement>),
+    /* `ei` and `di`, `reti` also returns. */
+    Interrupts(bool),
+    Halt,
+    Stop,
+}
+
+impl Expr {
+    fn reg(reg: Register) -> Expr {
+        Expr::Reg(reg)
+    }
+
+    fn load(addr: Expr) -> Expr {
+        Expr::Load(Box::new(addr))
+    }
+
+    fn binary(op: BinOp, lhs: Expr, rhs: Expr) -> Expr {
+        Expr::Binary(op, Box::new(lhs), Box::new(rhs))
+    }
+
+    fn add(lhs: Expr, rhs: Expr) -> Expr {
+        Expr::binary(BinOp::Add, lhs, rhs)
+    }
+
+    fn sub(lhs: Expr, rhs: Expr) -> Expr {
+        Expr::binary(BinOp::Sub, lhs, rhs)
+    }
+
+    fn and(lhs: Expr, mask: u16) -> Expr {
+        Expr::binary(BinOp::And, lhs, Expr::Const(mask))
+    }
+
+    fn or(lhs: Expr, rhs: Expr) -> Expr {
+        Expr::binary(BinOp::Or, lhs, rhs)
+    }
+
+    fn shl(lhs: Expr, bits: u16) -> Expr {
+        Expr::binary(BinOp::Shl, lhs, Expr::Const(bits))
+    }
+
+    fn shr(lhs: Expr, bits: u16) -> Expr {
+        Expr::binary(BinOp::Shr, lhs, Expr::Const(bits))
+    }
+
+    fn not(value: Expr) -> Expr {
+        Expr::Not(Box::new(value))
+    }
+
+    fn overflow(bits: u8, value: Expr) -> Expr {
+        Expr::Overflow(bits, Box::new(value))
+    }
+
+    /* 1 when the low byte of the value is 0. */
+    fn zero(value: Expr) -> Expr {
+        Expr::binary(BinOp::Eq, Expr::and(value, 0xFF), Expr::Const(0))
+    }
+
+    fn condition(condition: Condition) -> Expr {
+        match condition {
+            Condition::Z => Expr::Flag(Flag::Z),
+            Condition::NZ => Expr::not(Expr::Flag(Flag::Z)),
+            Condition::C => Expr::Flag(Flag::C),
+            Condition::NC => Expr::not(Expr::Flag(Flag::C)),
+        }
+    }
+
+    /* Sub-expressions, for analyses walking the tree. */
+    pub fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Const(_) | Expr::Reg(_) | Expr::Flag(_) => Vec::new(),
+            Expr::Load(addr) => vec![addr],
+            Expr::Binary(_, lhs, rhs) => vec![lhs, rhs],
+            Expr::Not(value) | Expr::Overflow(_, value) => vec![value],
+            Expr::Select(cond, then, other) => vec![cond, then, other],
+        }
+    }
+}
+
+/* 8-bit operands read or written by instructions. */
+fn read(operand: &Operand) -> Expr {
+    match operand {
+        Operand::Reg(reg) => Expr::reg(*reg),
+        Operand::DerefReg(Register::C) => {
+            Expr::load(Expr::add(Expr::Const(0xFF00), Expr::reg(Register::C)))
+        }
+        Operand::DerefReg(reg) => Expr::load(Expr::reg(*reg)),
+        Operand::DerefAddr8(addr) => Expr::load(Expr::Const(0xFF00 | *addr as u16)),
+        Operand::DerefAddr16(addr) => Expr::load(Expr::Const(*addr)),
+        Operand::Imm8(value) => Expr::Const(*value as u16),
+        Operand::Imm16(value) | Operand::Addr16(value) => Expr::Const(*value),
+        Operand::Addr8(value) | Operand::Bit(value) => Expr::Const(*value as u16),
+        Operand::Rel8(offset) | Operand::SPRel8(offset) => Expr::Const(*offset as i8 as u16),
+        Operand::Cond(condition) => Expr::condition(*condition),
+    }
+}
+
+fn write(operand: &Operand, value: Expr) -> Statement {
+    match (operand, read(operand)) {
+        (Operand::Reg(reg), _) => Statement::Assign(*reg, value),
+        (_, Expr::Load(addr)) => Statement::Store(*addr, value),
+        /* Immediates are never written. */
+        (_, addr) => Statement::Store(addr, value),
+    }
+}
+
+impl Statement {
+    /* Effects of `inst` located at `addr`. The address gives the targets of
+     * relative jumps and the return address of calls. */
+    pub fn lift(inst: &Instruction, addr: u16) -> Vec<Statement> {
+        let (lhs, rhs) = (inst.lhs(), inst.rhs());
+        let next = addr.wrapping_add(inst.size() as u16);
+        let sp = || Expr::reg(Register::SP);
+        let flags = |z: Expr, n: u16, h: Expr, c: Expr| {
+            vec![
+                Statement::SetFlag(Flag::Z, z),
+                Statement::SetFlag(Flag::N, Expr::Const(n)),
+                Statement::SetFlag(Flag::H, h),
+                Statement::SetFlag(Flag::C, c),
+            ]
+        };
+        let conditional = |statements: Vec<Statement>| match lhs {
+            Some(Operand::Cond(condition)) => {
+                vec![Statement::If(Expr::condition(*condition), statements)]
+            }
+            _ => statements,
+        };
+
+        match inst.mnemonic() {
+            Mnemonic::NOP => Vec::new(),
+            Mnemonic::STOP => vec![Statement::Stop],
+            Mnemonic::HALT => vec![Statement::Halt],
+            Mnemonic::DI => vec![Statement::Interrupts(false)],
+            Mnemonic::EI => vec![Statement::Interrupts(true)],
+            Mnemonic::LD | Mnemonic::LDHL | Mnemonic::LDHR => match (lhs, rhs) {
+                (Some(Operand::DerefAddr16(target)), Some(Operand::Reg(Register::SP))) => vec![
+                    Statement::Store(Expr::Const(*target), sp()),
+                    Statement::Store(Expr::Const(target.wrapping_add(1)), Expr::shr(sp(), 8)),
+                ],
+                (Some(Operand::Reg(Register::HL)), Some(Operand::SPRel8(offset))) => {
+                    let mut statements = Statement::add_sp(*offset);
+                    statements.push(Statement::Assign(
+                        Register::HL,
+                        Statement::sp_offset(*offset),
+                    ));
+                    statements
+                }
+                (Some(lhs), Some(rhs)) => vec![write(lhs, read(rhs))],
+                _ => Vec::new(),
+            },
+            Mnemonic::LDIL | Mnemonic::LDDL | Mnemonic::LDIR | Mnemonic::LDDR => {
+                let hl = Expr::reg(Register::HL);
+                let load = match inst.mnemonic() {
+                    Mnemonic::LDIL | Mnemonic::LDDL => {
+                        Statement::Store(hl.clone(), Expr::reg(Register::A))
+                    }
+                    _ => Statement::Assign(Register::A, Expr::load(hl.clone())),
+                };
+                let hl = match inst.mnemonic() {
+                    Mnemonic::LDIL | Mnemonic::LDIR => Expr::add(hl, Expr::Const(1)),
+                    _ => Expr::sub(hl, Expr::Const(1)),
+                };
+                vec![load, Statement::Assign(Register::HL, hl)]
+            }
+            Mnemonic::JR
+            | Mnemonic::JP
+            | Mnemonic::JRNZ
+            | Mnemonic::JRZ
+            | Mnemonic::JRNC
+            | Mnemonic::JRC
+            | Mnemonic::JPNZ
+            | Mnemonic::JPZ
+            | Mnemonic::JPNC
+            | Mnemonic::JPC => {
+                let condition = match inst.mnemonic() {
+                    Mnemonic::JRNZ | Mnemonic::JPNZ => Some(Condition::NZ),
+                    Mnemonic::JRZ | Mnemonic::JPZ => Some(Condition::Z),
+                    Mnemonic::JRNC | Mnemonic::JPNC => Some(Condition::NC),
+                    Mnemonic::JRC | Mnemonic::JPC => Some(Condition::C),
+                    _ => None,
+                };
+                let jump = match inst.flow(addr) {
+                    Flow::Jump(Some(target)) | Flow::Branch(target) => {
+                        Statement::Jump(Expr::Const(target))
+                    }
+                    _ => Statement::Jump(Expr::reg(Register::HL)),
+                };
+                match condition {
+                    Some(condition) => vec![Statement::If(Expr::condition(condition), vec![jump])],
+                    None => vec![jump],
+                }
+            }
+            Mnemonic::CALL | Mnemonic::RST => match inst.flow(addr) {
+                Flow::Call(target) => {
+                    let [high, low] = next.to_be_bytes();
+                    let mut statements =
+                        Statement::push(Expr::Const(high as u16), Expr::Const(low as u16));
+                    statements.push(Statement::Call(Expr::Const(target)));
+                    conditional(statements)
+                }
+                _ => Vec::new(),
+            },
+            Mnemonic::RET | Mnemonic::RETI => {
+                let mut statements = vec![
+                    Statement::Assign(Register::SP, Expr::add(sp(), Expr::Const(2))),
+                    Statement::Return,
+                ];
+                if *inst.mnemonic() == Mnemonic::RETI {
+                    statements.insert(0, Statement::Interrupts(true));
+                }
+                conditional(statements)
+            }
+            Mnemonic::PUSH => match lhs {
+                Some(Operand::Reg(Register::AF)) => {
+                    let flags = [(Flag::Z, 7), (Flag::N, 6), (Flag::H, 5), (Flag::C, 4)]
+                        .iter()
+                        .map(|&(flag, bit)| Expr::shl(Expr::Flag(flag), bit))
+                        .reduce(Expr::or)
+                        .unwrap_or(Expr::Const(0));
+                    Statement::push(Expr::reg(Register::A), flags)
+                }
+                Some(Operand::Reg(reg)) => match Statement::halves(*reg) {
+                    Some((high, low)) => Statement::push(Expr::reg(high), Expr::reg(low)),
+                    None => Vec::new(),
+                },
+                _ => Vec::new(),
+            },
+            Mnemonic::POP => {
+                let low = Expr::load(sp());
+                let high = Expr::load(Expr::add(sp(), Expr::Const(1)));
+                let mut statements = match lhs {
+                    Some(Operand::Reg(Register::AF)) => {
+                        let mut statements = vec![Statement::Assign(Register::A, high)];
+                        for (flag, mask) in [
+                            (Flag::Z, 0x80),
+                            (Flag::N, 0x40),
+                            (Flag::H, 0x20),
+                            (Flag::C, 0x10),
+                        ] {
+                            statements.push(Statement::SetFlag(flag, Expr::and(low.clone(), mask)));
+                        }
+                        statements
+                    }
+                    Some(Operand::Reg(reg)) => match Statement::halves(*reg) {
+                        Some((h, l)) => vec![Statement::Assign(h, high), Statement::Assign(l, low)],
+                        None => Vec::new(),
+                    },
+                    _ => Vec::new(),
+                };
+                statements.push(Statement::Assign(
+                    Register::SP,
+                    Expr::add(sp(), Expr::Const(2)),
+                ));
+                statements
+            }
+            Mnemonic::ADD => match (lhs, rhs) {
+                (Some(Operand::Reg(Register::HL)), Some(Operand::Reg(reg))) => {
+                    let (hl, value) = (Expr::reg(Register::HL), Expr::reg(*reg));
+                    let sum = Expr::add(hl.clone(), value.clone());
+                    let half = Expr::add(Expr::and(hl, 0x0FFF), Expr::and(value, 0x0FFF));
+                    vec![
+                        Statement::SetFlag(Flag::N, Expr::Const(0)),
+                        Statement::SetFlag(Flag::H, Expr::overflow(12, half)),
+                        Statement::SetFlag(Flag::C, Expr::overflow(16, sum.clone())),
+                        Statement::Assign(Register::HL, sum),
+                    ]
+                }
+                (Some(Operand::Reg(Register::SP)), Some(Operand::Rel8(offset))) => {
+                    let mut statements = Statement::add_sp(*offset);
+                    statements.push(Statement::Assign(
+                        Register::SP,
+                        Statement::sp_offset(*offset),
+                    ));
+                    statements
+                }
+                (_, Some(rhs)) => Statement::add(read(rhs), false),
+                _ => Vec::new(),
+            },
+            Mnemonic::ADC => rhs.map_or_else(Vec::new, |rhs| Statement::add(read(rhs), true)),
+            Mnemonic::SUB | Mnemonic::CP => lhs.map_or_else(Vec::new, |lhs| {
+                Statement::sub(read(lhs), false, *inst.mnemonic() == Mnemonic::SUB)
+            }),
+            Mnemonic::SBC => rhs.map_or_else(Vec::new, |rhs| Statement::sub(read(rhs), true, true)),
+            Mnemonic::AND | Mnemonic::OR | Mnemonic::XOR => match lhs {
+                Some(lhs) => {
+                    let op = match inst.mnemonic() {
+                        Mnemonic::AND => BinOp::And,
+                        Mnemonic::OR => BinOp::Or,
+                        _ => BinOp::Xor,
+                    };
+                    let result = Expr::binary(op, Expr::reg(Register::A), read(lhs));
+                    let h = (op == BinOp::And) as u16;
+                    let zero = Expr::binary(BinOp::Eq, result.clone(), Expr::Const(0));
+                    let mut statements = flags(zero, 0, Expr::Const(h), Expr::Const(0));
+                    statements.push(Statement::Assign(Register::A, result));
+                    statements
+                }
+                None => Vec::new(),
+            },
+            Mnemonic::INC | Mnemonic::DEC => {
+                let inc = *inst.mnemonic() == Mnemonic::INC;
+                let step = |value: Expr| {
+                    if inc {
+                        Expr::add(value, Expr::Const(1))
+                    } else {
+                        Expr::sub(value, Expr::Const(1))
+                    }
+                };
+                match lhs {
+                    Some(Operand::Reg(reg @ Register::BC))
+                    | Some(Operand::Reg(reg @ Register::DE))
+                    | Some(Operand::Reg(reg @ Register::HL))
+                    | Some(Operand::Reg(reg @ Register::SP)) => {
+                        vec![Statement::Assign(*reg, step(Expr::reg(*reg)))]
+                    }
+                    Some(operand) => {
+                        let value = read(operand);
+                        let half = step(Expr::and(value.clone(), 0x0F));
+                        let result = step(value);
+                        vec![
+                            Statement::SetFlag(Flag::Z, Expr::zero(result.clone())),
+                            Statement::SetFlag(Flag::N, Expr::Const(!inc as u16)),
+                            Statement::SetFlag(Flag::H, Expr::overflow(4, half)),
+                            write(operand, result),
+                        ]
+                    }
+                    None => Vec::new(),
+                }
+            }
+            Mnemonic::RLCA | Mnemonic::RRCA | Mnemonic::RLA | Mnemonic::RRA => {
+                let mnemonic = match inst.mnemonic() {
+                    Mnemonic::RLCA => Mnemonic::RLC,
+                    Mnemonic::RRCA => Mnemonic::RRC,
+                    Mnemonic::RLA => Mnemonic::RL,
+                    _ => Mnemonic::RR,
+                };
+                let (result, carry) = Statement::shift(mnemonic, Expr::reg(Register::A));
+                let mut statements = flags(Expr::Const(0), 0, Expr::Const(0), carry);
+                statements.push(Statement::Assign(Register::A, result));
+                statements
+            }
+            Mnemonic::RLC
+            | Mnemonic::RRC
+            | Mnemonic::RL
+            | Mnemonic::RR
+            | Mnemonic::SLA
+            | Mnemonic::SRA
+            | Mnemonic::SWAP
+            | Mnemonic::SRL => match lhs {
+                Some(lhs) => {
+                    let (result, carry) = Statement::shift(*inst.mnemonic(), read(lhs));
+                    let mut statements =
+                        flags(Expr::zero(result.clone()), 0, Expr::Const(0), carry);
+                    statements.push(write(lhs, result));
+                    statements
+                }
+                None => Vec::new(),
+            },
+            Mnemonic::BIT | Mnemonic::RES | Mnemonic::SET => match (lhs, rhs) {
+                (Some(Operand::Bit(bit)), Some(target)) => {
+                    let mask = 1 << bit;
+                    match inst.mnemonic() {
+                        Mnemonic::BIT => vec![
+                            Statement::SetFlag(
+                                Flag::Z,
+                                Expr::binary(
+                                    BinOp::Eq,
+                                    Expr::and(read(target), mask),
+                                    Expr::Const(0),
+                                ),
+                            ),
+                            Statement::SetFlag(Flag::N, Expr::Const(0)),
+                            Statement::SetFlag(Flag::H, Expr::Const(1)),
+                        ],
+                        Mnemonic::RES => vec![write(target, Expr::and(read(target), !mask & 0xFF))],
+                        _ => vec![write(target, Expr::or(read(target), Expr::Const(mask)))],
+                    }
+                }
+                _ => Vec::new(),
+            },
+            Mnemonic::DA => {
+                let a = || Expr::reg(Register::A);
+                let n = || Expr::Flag(Flag::N);
+                let adds = |value: Expr, limit: u16| {
+                    Expr::binary(
+                        BinOp::And,
+                        Expr::not(n()),
+                        Expr::binary(BinOp::Lt, Expr::Const(limit), value),
+                    )
+                };
+                let low = Expr::or(Expr::Flag(Flag::H), adds(Expr::and(a(), 0x0F), 0x09));
+                let high = Expr::or(Expr::Flag(Flag::C), adds(a(), 0x99));
+                let adjust = Expr::or(
+                    Expr::Select(
+                        Box::new(low),
+                        Box::new(Expr::Const(0x06)),
+                        Box::new(Expr::Const(0)),
+                    ),
+                    Expr::Select(
+                        Box::new(high.clone()),
+                        Box::new(Expr::Const(0x60)),
+                        Box::new(Expr::Const(0)),
+                    ),
+                );
+                let result = Expr::Select(
+                    Box::new(n()),
+                    Box::new(Expr::sub(a(), adjust.clone())),
+                    Box::new(Expr::add(a(), adjust)),
+                );
+                vec![
+                    Statement::SetFlag(Flag::Z, Expr::zero(result.clone())),
+                    Statement::SetFlag(Flag::H, Expr::Const(0)),
+                    Statement::SetFlag(Flag::C, high),
+                    Statement::Assign(Register::A, result),
+                ]
+            }
+            Mnemonic::CPL => vec![
+                Statement::SetFlag(Flag::N, Expr::Const(1)),
+                Statement::SetFlag(Flag::H, Expr::Const(1)),
+                Statement::Assign(
+                    Register::A,
+                    Expr::binary(BinOp::Xor, Expr::reg(Register::A), Expr::Const(0xFF)),
+                ),
+            ],
+            Mnemonic::SCF | Mnemonic::CCF => {
+                let carry = match inst.mnemonic() {
+                    Mnemonic::SCF => Expr::Const(1),
+                    _ => Expr::not(Expr::Flag(Flag::C)),
+                };
+                vec![
+                    Statement::SetFlag(Flag::N, Expr::Const(0)),
+                    Statement::SetFlag(Flag::H, Expr::Const(0)),
+                    Statement::SetFlag(Flag::C, carry),
+                ]
+            }
+        }
+    }
+
+    /* High and low registers of a pair pushed or popped. */
+    fn halves(reg: Register) -> Option<(Register, Register)> {
+        match reg {
+            Register::BC => Some((Register::B, Register::C)),
+            Register::DE => Some((Register::D, Register::E)),
+            Register::HL => Some((Register::H, Register::L)),
+            _ => None,
+        }
+    }
+
+    /* Stores `high` and `low` at SP - 1 and SP - 2 and moves SP there. */
+    fn push(high: Expr, low: Expr) -> Vec<Statement> {
+        let sp = || Expr::reg(Register::SP);
+        vec![
+            Statement::Store(Expr::sub(sp(), Expr::Const(1)), high),
+            Statement::Store(Expr::sub(sp(), Expr::Const(2)), low),
+            Statement::Assign(Register::SP, Expr::sub(sp(), Expr::Const(2))),
+        ]
+    }
+
+    /* SP plus a signed offset. */
+    fn sp_offset(offset: u8) -> Expr {
+        let sp = Expr::reg(Register::SP);
+        match offset as i8 {
+            offset if offset < 0 => Expr::sub(sp, Expr::Const(offset.unsigned_abs() as u16)),
+            offset => Expr::add(sp, Expr::Const(offset as u16)),
+        }
+    }
+
+    /* Flags of `add sp, e8` and `ld hl, sp+e8`, from the low byte. */
+    fn add_sp(offset: u8) -> Vec<Statement> {
+        let sp = || Expr::reg(Register::SP);
+        let half = Expr::add(Expr::and(sp(), 0x0F), Expr::Const(offset as u16 & 0x0F));
+        let low = Expr::add(Expr::and(sp(), 0xFF), Expr::Const(offset as u16));
+        vec![
+            Statement::SetFlag(Flag::Z, Expr::Const(0)),
+            Statement::SetFlag(Flag::N, Expr::Const(0)),
+            Statement::SetFlag(Flag::H, Expr::overflow(4, half)),
+            Statement::SetFlag(Flag::C, Expr::overflow(8, low)),
+        ]
+    }
+
+    /* A plus `value`, and the carry with `adc`. */
+    fn add(value: Expr, carry: bool) -> Vec<Statement> {
+        let a = Expr::reg(Register::A);
+        let mut sum = Expr::add(a.clone(), value.clone());
+        let mut half = Expr::add(Expr::and(a, 0x0F), Expr::and(value, 0x0F));
+        if carry {
+            sum = Expr::add(sum, Expr::Flag(Flag::C));
+            half = Expr::add(half, Expr::Flag(Flag::C));
+        }
+
+        vec![
+            Statement::SetFlag(Flag::Z, Expr::zero(sum.clone())),
+            Statement::SetFlag(Flag::N, Expr::Const(0)),
+            Statement::SetFlag(Flag::H, Expr::overflow(4, half)),
+            Statement::SetFlag(Flag::C, Expr::overflow(8, sum.clone())),
+            Statement::Assign(Register::A, sum),
+        ]
+    }
+
+    /* A minus `value`, and the borrow with `sbc`. `cp` only sets the
+     * flags. */
+    fn sub(value: Expr, carry: bool, assign: bool) -> Vec<Statement> {
+        let a = Expr::reg(Register::A);
+        let mut difference = Expr::sub(a.clone(), value.clone());
+        let mut half = Expr::sub(Expr::and(a, 0x0F), Expr::and(value, 0x0F));
+        if carry {
+            difference = Expr::sub(difference, Expr::Flag(Flag::C));
+            half = Expr::sub(half, Expr::Flag(Flag::C));
+        }
+
+        let mut statements = vec![
+            Statement::SetFlag(Flag::Z, Expr::zero(difference.clone())),
+            Statement::SetFlag(Flag::N, Expr::Const(1)),
+            Statement::SetFlag(Flag::H, Expr::overflow(4, half)),
+            Statement::SetFlag(Flag::C, Expr::overflow(8, difference.clone())),
+        ];
+        if assign {
+            statements.push(Statement::Assign(Register::A, difference));
+        }
+        statements
+    }
+
+    /* Rotations and shifts: the result and the carry. */
+    fn shift(mnemonic: Mnemonic, value: Expr) -> (Expr, Expr) {
+        let v = || value.clone();
+        let carry = || Expr::Flag(Flag::C);
+        match mnemonic {
+            Mnemonic::RLC => (
+                Expr::or(Expr::shl(v(), 1), Expr::shr(v(), 7)),
+                Expr::shr(v(), 7),
+            ),
+            Mnemonic::RRC => (
+                Expr::or(Expr::shr(v(), 1), Expr::shl(Expr::and(v(), 0x01), 7)),
+                Expr::and(v(), 0x01),
+            ),
+            Mnemonic::RL => (Expr::or(Expr::shl(v(), 1), carry()), Expr::shr(v(), 7)),
+            Mnemonic::RR => (
+                Expr::or(Expr::shr(v(), 1), Expr::shl(carry(), 7)),
+                Expr::and(v(), 0x01),
+            ),
+            Mnemonic::SLA => (Expr::shl(v(), 1), Expr::shr(v(), 7)),
+            Mnemonic::SRA => (
+                Expr::or(Expr::shr(v(), 1), Expr::and(v(), 0x80)),
+                Expr::and(v(), 0x01),
+            ),
+            Mnemonic::SWAP => (
+                Expr::or(Expr::shl(Expr::and(v(), 0x0F), 4), Expr::shr(v(), 4)),
+                Expr::Const(0),
+            ),
+            _ => (Expr::shr(v(), 1), Expr::and(v(), 0x01)),
+        }
+    }
+
+    /* Whether the statement, or one under it, moves PC. */
+    pub fn is_control(&self) -> bool {
+        match self {
+            Statement::Jump(_) | Statement::Call(_) | Statement::Return => true,
+            Statement::If(_, statements) => statements.iter().any(Statement::is_control),
+            _ => false,
+        }
+    }
+}
+
+fn register_name(reg: Register) -> &'static str {
+    match reg {
+        Register::AF => "af",
+        Register::A => "a",
+        Register::F => "f",
+        Register::BC => "bc",
+        Register::B => "b",
+        Register::C => "c",
+        Register::DE => "de",
+        Register::D => "d",
+        Register::E => "e",
+        Register::HL => "hl",
+        Register::H => "h",
+        Register::L => "l",
+        Register::SP => "sp",
+    }
+}
+
+impl std::fmt::Display for Flag {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Flag::Z => "zero",
+            Flag::N => "subtract",
+            Flag::H => "half_carry",
+            Flag::C => "carry",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::fmt::Display for BinOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let op = match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::And => "&",
+            BinOp::Or => "|",
+            BinOp::Xor => "^",
+            BinOp::Shl => "<<",
+            BinOp::Shr => ">>",
+            BinOp::Eq => "==",
+            BinOp::Lt => "<",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+/* Nested operations are parenthesized, the outermost is not. */
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let nested = |expr: &Expr| match expr {
+            Expr::Binary(_, _, _) | Expr::Select(_, _, _) => format!("({})", expr),
+            _ => expr.to_string(),
+        };
+        match self {
+            Expr::Const(value) if *value < 10 => write!(f, "{}", value),
+            Expr::Const(value) if *value <= 0xFF => write!(f, "${:02X}", value),
+            Expr::Const(value) => write!(f, "${:04X}", value),
+            Expr::Reg(reg) => write!(f, "{}", register_name(*reg)),
+            Expr::Flag(flag) => write!(f, "{}", flag),
+            Expr::Load(addr) => write!(f, "[{}]", addr),
+            Expr::Binary(op, lhs, rhs) => write!(f, "{} {} {}", nested(lhs), op, nested(rhs)),
+            Expr::Not(value) => write!(f, "!{}", nested(value)),
+            Expr::Overflow(bits, value) => write!(f, "overflow{}({})", bits, value),
+            Expr::Select(cond, then, other) => {
+                write!(f, "{} ? {} : {}", nested(cond), nested(then), nested(other))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Statement {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Statement::Assign(reg, value) => write!(f, "{} = {}", register_name(*reg), value),
+            Statement::SetFlag(flag, value) => write!(f, "{} = {}", flag, value),
+            Statement::Store(addr, value) => write!(f, "[{}] = {}", addr, value),
+            Statement::Jump(target) => write!(f, "goto {}", target),
+            Statement::Call(target) => write!(f, "call {}", target),
+            Statement::Return => write!(f, "return"),
+            Statement::If(cond, statements) => {
+                let statements: Vec<String> = statements.iter().map(|s| s.to_string()).collect();
+                write!(f, "if ({}) {{ {} }}", cond, statements.join("; "))
+            }
+            Statement::Interrupts(true) => write!(f, "enable_interrupts()"),
+            Statement::Interrupts(false) => write!(f, "disable_interrupts()"),
+            Statement::Halt => write!(f, "halt()"),
+            Statement::Stop => write!(f, "stop()"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::emulator::{Bus, Cpu, Memory, Registers};
+
+    const PC: u16 = 0x0200;
+
+    /* Machine state the statements are run on: registers, flags apart, and
+     * the whole address space. */
+    #[derive(Clone)]
+    struct State {
+        registers: Registers,
+        flags: [bool; 4],
+        pc: u16,
+        memory: Vec<u8>,
+    }
+
+    impl State {
+        fn eval(&self, expr: &Expr) -> i64 {
+            match expr {
+                Expr::Const(value) => *value as i64,
+                Expr::Reg(reg) => self.registers.get(*reg) as i64,
+                Expr::Flag(flag) => self.flags[*flag as usize] as i64,
+                Expr::Load(addr) => self.memory[(self.eval(addr) & 0xFFFF) as usize] as i64,
+                Expr::Binary(op, lhs, rhs) => {
+                    let (lhs, rhs) = (self.eval(lhs), self.eval(rhs));
+                    match op {
+                        BinOp::Add => lhs + rhs,
+                        BinOp::Sub => lhs - rhs,
+                        BinOp::And => lhs & rhs,
+                        BinOp::Or => lhs | rhs,
+                        BinOp::Xor => lhs ^ rhs,
+                        BinOp::Shl => lhs << rhs,
+                        BinOp::Shr => lhs >> rhs,
+                        BinOp::Eq => (lhs == rhs) as i64,
+                        BinOp::Lt => (lhs < rhs) as i64,
+                    }
+                }
+                Expr::Not(value) => (self.eval(value) == 0) as i64,
+                Expr::Overflow(bits, value) => {
+                    let value = self.eval(value);
+                    (value < 0 || value >= 1 << bits) as i64
+                }
+                Expr::Select(cond, then, other) => match self.eval(cond) {
+                    0 => self.eval(other),
+                    _ => self.eval(then),
+                },
+            }
+        }
+
+        /* Runs `statements` on a copy, every one reading the state before
+         * them. */
+        fn run(&self, statements: &[Statement], next: u16) -> State {
+            let mut after = self.clone();
+            after.pc = next;
+            self.apply(statements, &mut after);
+            after
+        }
+
+        fn apply(&self, statements: &[Statement], after: &mut State) {
+            for statement in statements {
+                match statement {
+                    Statement::Assign(reg, value) => {
+                        after.registers.set(*reg, self.eval(value) as u16)
+                    }
+                    Statement::SetFlag(flag, value) => {
+                        after.flags[*flag as usize] = self.eval(value) != 0
+                    }
+                    Statement::Store(addr, value) => {
+                        let addr = (self.eval(addr) & 0xFFFF) as usize;
+                        /* ROM is not written, as with the emulator. */
+                        if addr >= 0x8000 {
+                            after.memory[addr] = self.eval(value) as u8;
+                        }
+                    }
+                    Statement::Jump(target) | Statement::Call(target) => {
+                        after.pc = self.eval(target) as u16
+                    }
+                    Statement::Return => {
+                        let sp = self.registers.sp as usize;
+                        after.pc = u16::from_le_bytes([self.memory[sp], self.memory[sp + 1]]);
+                    }
+                    Statement::If(cond, statements) => {
+                        if self.eval(cond) != 0 {
+                            self.apply(statements, after);
+                        }
+                    }
+                    Statement::Interrupts(_) | Statement::Halt | Statement::Stop => {}
+                }
+            }
+        }
+    }
+
+    /* Lifts the instruction made of `bytes` and checks the statements do
+     * what the emulator does, from states covering every flag. */
+    fn check(bytes: &[u8]) {
+        let inst = match Instruction::from_slice(bytes) {
+            Ok(inst) => inst,
+            Err(_) => return,
+        };
+        let statements = Statement::lift(&inst, PC);
+
+        for (i, &(a, f)) in [
+            (0x00, 0x00),
+            (0x0F, 0x10),
+            (0x99, 0x50),
+            (0x9A, 0x20),
+            (0xFF, 0xF0),
+            (0x80, 0xA0),
+            (0x45, 0x30),
+            (0x01, 0xC0),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let mut rom = vec![0x00; 0x8000];
+            rom[PC as usize..PC as usize + bytes.len()].copy_from_slice(bytes);
+            let registers = Registers {
+                a,
+                f,
+                b: 0xC0,
+                c: 0x0F + i as u8 * 0x11,
+                d: 0xC1,
+                e: 0xF8 - i as u8,
+                h: 0xC2,
+                l: 0x80 + i as u8 * 0x0D,
+                sp: 0xD000 + i as u16,
+                pc: PC,
+            };
+
+            let mut memory = Memory::new(&rom);
+            for addr in 0x8000..=0xFFFFu16 {
+                memory.write(addr, (addr as u8).wrapping_mul(7) ^ i as u8);
+            }
+            /* No interrupt pending. */
+            memory.write(0xFFFF, 0x00);
+
+            let mut state = State {
+                registers,
+                flags: [f & 0x80 != 0, f & 0x40 != 0, f & 0x20 != 0, f & 0x10 != 0],
+                pc: PC,
+                memory: rom.clone(),
+            };
+            state.memory.extend_from_slice(memory.ram());
+            let next = PC + inst.size() as u16;
+            let lifted = state.run(&statements, next);
+
+            let mut cpu = Cpu::new(registers);
+            cpu.step(&mut memory).unwrap();
+            let expected = cpu.registers();
+
+            let flags = lifted
+                .flags
+                .iter()
+                .enumerate()
+                .fold(0, |f, (bit, &set)| f | (set as u8) << (7 - bit));
+            let context = format!("{:02X?} with {:02X?}", bytes, registers);
+            assert_eq!(
+                Registers {
+                    f: flags,
+                    pc: lifted.pc,
+                    ..lifted.registers
+                },
+                *expected,
+                "{}",
+                context
+            );
+            assert!(lifted.memory[0x8000..] == *memory.ram(), "{}", context);
+        }
+    }
+
+    #[test]
+    fn base_opcodes() {
+        for opcode in 0x00..=0xFF {
+            /* STOP resets DIV and speed switches, which are no statements. */
+            if opcode != 0xCB && opcode != 0x10 {
+                check(&[opcode, 0x34, 0xC1]);
+            }
+        }
+        /* Backward relative jumps and offsets from SP. */
+        for opcode in [0x18, 0x20, 0x28, 0x30, 0x38, 0xE8, 0xF8] {
+            check(&[opcode, 0xF0, 0x00]);
+        }
+    }
+
+    #[test]
+    fn cb_opcodes() {
+        for opcode in 0x00..=0xFF {
+            check(&[0xCB, opcode]);
+        }
+    }
+
+    #[test]
+    fn display() {
+        let lift = |bytes: &[u8]| -> Vec<String> {
+            let inst = Instruction::from_slice(bytes).unwrap();
+            Statement::lift(&inst, 0x0150)
+                .iter()
+                .map(Statement::to_string)
+                .collect()
+        };
+
+        assert_eq!(lift(&[0x2A]), ["a = [hl]", "hl = hl + 1"]);
+        assert_eq!(lift(&[0xE0, 0x40]), ["[$FF40] = a"]);
+        assert_eq!(lift(&[0x20, 0xFE]), ["if (!zero) { goto $0150 }"]);
+        assert_eq!(
+            lift(&[0xCD, 0x00, 0x40]),
+            [
+                "[sp - 1] = 1",
+                "[sp - 2] = $53",
+                "sp = sp - 2",
+                "call $4000"
+            ]
+        );
+        assert_eq!(
+            lift(&[0xFE, 0x10]),
+            [
+                "zero = ((a - $10) & $FF) == 0",
+                "subtract = 1",
+                "half_carry = overflow4((a & $0F) - ($10 & $0F))",
+                "carry = overflow8(a - $10)"
+            ]
+        );
+    }
+}
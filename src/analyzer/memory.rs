@@ -0,0 +1,380 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::address::Address;
+use super::control_flow::ControlFlowGraph;
+use super::header::Mbc;
+use super::instruction::{Instruction, Mnemonic, Operand, Register};
+use super::values::Values;
+use super::warning::Warning;
+
+/* Areas of the Game Boy memory map. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Region {
+    ROM0,
+    ROMX,
+    VRAM,
+    SRAM,
+    WRAM0,
+    WRAMX,
+    Echo,
+    OAM,
+    Unusable,
+    IO,
+    HRAM,
+    IE,
+}
+
+impl Region {
+    pub fn of(addr: u16) -> Region {
+        match addr {
+            0x0000..=0x3FFF => Region::ROM0,
+            0x4000..=0x7FFF => Region::ROMX,
+            0x8000..=0x9FFF => Region::VRAM,
+            0xA000..=0xBFFF => Region::SRAM,
+            0xC000..=0xCFFF => Region::WRAM0,
+            0xD000..=0xDFFF => Region::WRAMX,
+            0xE000..=0xFDFF => Region::Echo,
+            0xFE00..=0xFE9F => Region::OAM,
+            0xFEA0..=0xFEFF => Region::Unusable,
+            0xFF00..=0xFF7F => Region::IO,
+            0xFF80..=0xFFFE => Region::HRAM,
+            0xFFFF => Region::IE,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Region::ROM0 => "ROM0",
+            Region::ROMX => "ROMX",
+            Region::VRAM => "VRAM",
+            Region::SRAM => "SRAM",
+            Region::WRAM0 => "WRAM0",
+            Region::WRAMX => "WRAMX",
+            Region::Echo => "ECHO",
+            Region::OAM => "OAM",
+            Region::Unusable => "UNUSABLE",
+            Region::IO => "IO",
+            Region::HRAM => "HRAM",
+            Region::IE => "IE",
+        }
+    }
+
+    /* Regions holding program variables. */
+    pub fn is_variable(&self) -> bool {
+        matches!(self, Region::WRAM0 | Region::WRAMX | Region::HRAM)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl AccessKind {
+    pub fn merge(self, other: AccessKind) -> AccessKind {
+        if self == other {
+            self
+        } else {
+            AccessKind::ReadWrite
+        }
+    }
+}
+
+/* A memory access at a statically known address. */
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryAccess {
+    pc: Address,
+    target: u16,
+    region: Region,
+    kind: AccessKind,
+}
+
+impl MemoryAccess {
+    /* Accesses of `inst` whose target is encoded in the instruction. */
+    pub fn of(inst: &Instruction, pc: Address) -> Vec<MemoryAccess> {
+        let mut accesses = Vec::new();
+        let target = |operand: Option<&Operand>| match operand {
+            Some(Operand::DerefAddr8(addr)) => Some(0xFF00 | *addr as u16),
+            Some(Operand::DerefAddr16(addr)) => Some(*addr),
+            _ => None,
+        };
+
+        if let Some(target) = target(inst.lhs()) {
+            let kind = match inst.mnemonic() {
+                Mnemonic::LD | Mnemonic::LDHL => AccessKind::Write,
+                _ => AccessKind::ReadWrite,
+            };
+            accesses.push(MemoryAccess::new(pc, target, kind));
+        }
+        if let Some(target) = target(inst.rhs()) {
+            accesses.push(MemoryAccess::new(pc, target, AccessKind::Read));
+        }
+
+        accesses
+    }
+
+    /* Accesses of `inst` through a register whose value is known. */
+    pub fn through_registers(
+        inst: &Instruction,
+        pc: Address,
+        values: &Values,
+    ) -> Vec<MemoryAccess> {
+        let mut accesses = Vec::new();
+        let target = |operand: Option<&Operand>| match operand {
+            Some(Operand::DerefReg(Register::C)) => values.get(Register::C).map(|c| 0xFF00 | c),
+            Some(Operand::DerefReg(reg)) if *inst.mnemonic() != Mnemonic::JP => values.get(*reg),
+            _ => None,
+        };
+
+        if let Some(target) = target(inst.lhs()) {
+            /* Arithmetic and comparisons only read their operand. */
+            let kind = match inst.mnemonic() {
+                Mnemonic::LD | Mnemonic::LDHL | Mnemonic::LDIL | Mnemonic::LDDL => {
+                    AccessKind::Write
+                }
+                Mnemonic::INC
+                | Mnemonic::DEC
+                | Mnemonic::RL
+                | Mnemonic::RR
+                | Mnemonic::RLC
+                | Mnemonic::RRC
+                | Mnemonic::SLA
+                | Mnemonic::SRA
+                | Mnemonic::SRL
+                | Mnemonic::SWAP => AccessKind::ReadWrite,
+                _ => AccessKind::Read,
+            };
+            accesses.push(MemoryAccess::new(pc, target, kind));
+        }
+        if let Some(target) = target(inst.rhs()) {
+            let kind = match inst.mnemonic() {
+                Mnemonic::RES | Mnemonic::SET => AccessKind::ReadWrite,
+                _ => AccessKind::Read,
+            };
+            accesses.push(MemoryAccess::new(pc, target, kind));
+        }
+
+        accesses
+    }
+
+    pub fn new(pc: Address, target: u16, kind: AccessKind) -> MemoryAccess {
+        MemoryAccess {
+            pc,
+            target,
+            region: Region::of(target),
+            kind,
+        }
+    }
+
+    pub fn pc(&self) -> Address {
+        self.pc
+    }
+
+    pub fn target(&self) -> u16 {
+        self.target
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    pub fn kind(&self) -> AccessKind {
+        self.kind
+    }
+}
+
+/* Static memory accesses of every function, with the suspicious ones
+ * reported as warnings. */
+#[derive(Debug)]
+pub struct MemoryUsage {
+    accesses: BTreeMap<Address, Vec<MemoryAccess>>,
+    warnings: Vec<Warning>,
+}
+
+impl MemoryUsage {
+    pub fn analyze(cfg: &ControlFlowGraph, mbc: Mbc) -> MemoryUsage {
+        let mut accesses = BTreeMap::new();
+        let mut warnings = Vec::new();
+
+        for (&entry, function) in cfg.get_functions() {
+            let mut function_accesses = Vec::new();
+
+            for start in function.get_blocks() {
+                for (pc, inst) in cfg.get_blocks()[start].get_instructions() {
+                    function_accesses.extend(MemoryAccess::of(inst, *pc));
+                    if let Some(values) = cfg.get_constants().values(*pc) {
+                        function_accesses
+                            .extend(MemoryAccess::through_registers(inst, *pc, values));
+                    }
+                }
+            }
+            accesses.insert(entry, function_accesses);
+        }
+
+        /* Blocks can be shared by several functions, report them once. */
+        let mut seen: BTreeSet<(Address, u16)> = BTreeSet::new();
+        for access in accesses.values().flatten() {
+            if !seen.insert((access.pc, access.target)) {
+                continue;
+            }
+            if let Some(warning) = MemoryUsage::check(access, mbc) {
+                warnings.push(warning);
+            }
+        }
+
+        MemoryUsage { accesses, warnings }
+    }
+
+    pub fn get_accesses(&self) -> &BTreeMap<Address, Vec<MemoryAccess>> {
+        &self.accesses
+    }
+
+    pub fn get_warnings(&self) -> &Vec<Warning> {
+        &self.warnings
+    }
+
+    /* WRAM and HRAM addresses touched by the function at `entry`. */
+    pub fn variables(&self, entry: Address) -> BTreeMap<u16, AccessKind> {
+        let mut variables: BTreeMap<u16, AccessKind> = BTreeMap::new();
+
+        for access in self.accesses.get(&entry).into_iter().flatten() {
+            if access.region.is_variable() {
+                let kind = match variables.get(&access.target) {
+                    Some(kind) => kind.merge(access.kind),
+                    None => access.kind,
+                };
+                variables.insert(access.target, kind);
+            }
+        }
+
+        variables
+    }
+
+    fn check(access: &MemoryAccess, mbc: Mbc) -> Option<Warning> {
+        let writes = access.kind != AccessKind::Read;
+
+        match access.region {
+            Region::ROM0 | Region::ROMX if writes && !mbc.is_register(access.target) => {
+                Some(Warning::RomWrite {
+                    address: access.pc,
+                    target: access.target,
+                })
+            }
+            Region::Unusable if access.kind != AccessKind::Write => Some(Warning::UnusableRead {
+                address: access.pc,
+                target: access.target,
+            }),
+            Region::Echo => Some(Warning::EchoRamAccess {
+                address: access.pc,
+                target: access.target,
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::project::Project;
+
+    #[test]
+    fn regions() {
+        assert_eq!(Region::of(0x3FFF), Region::ROM0);
+        assert_eq!(Region::of(0x4000), Region::ROMX);
+        assert_eq!(Region::of(0xD000), Region::WRAMX);
+        assert_eq!(Region::of(0xE123), Region::Echo);
+        assert_eq!(Region::of(0xFEA0), Region::Unusable);
+        assert_eq!(Region::of(0xFF7F), Region::IO);
+        assert_eq!(Region::of(0xFFFE), Region::HRAM);
+        assert_eq!(Region::of(0xFFFF), Region::IE);
+        assert!(Region::HRAM.is_variable());
+        assert!(!Region::IO.is_variable());
+    }
+
+    #[test]
+    fn accesses() {
+        let access = |bytes: &[u8]| {
+            let inst = Instruction::from_slice(bytes).unwrap();
+            MemoryAccess::of(&inst, Address::new(0, 0x0150))
+                .iter()
+                .map(|access| (access.target(), access.kind()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(access(&[0xFA, 0x00, 0xC0]), [(0xC000, AccessKind::Read)]);
+        assert_eq!(access(&[0xEA, 0x00, 0xC0]), [(0xC000, AccessKind::Write)]);
+        assert_eq!(access(&[0xE0, 0x80]), [(0xFF80, AccessKind::Write)]);
+        assert_eq!(access(&[0xF0, 0x44]), [(0xFF44, AccessKind::Read)]);
+        assert_eq!(access(&[0x08, 0x00, 0xC1]), [(0xC100, AccessKind::Write)]);
+        assert_eq!(access(&[0x7E]), []);
+    }
+
+    #[test]
+    fn through_registers() {
+        let mut bytes = vec![0x00; 0x8000];
+        let code = [
+            0x21, 0x00, 0x02, /* ld hl, $0200 */
+            0xBE, /* cp [hl] */
+            0xA6, /* and [hl] */
+            0xCB, 0x46, /* bit 0, [hl] */
+            0x21, 0x00, 0xC0, /* ld hl, $C000 */
+            0x96, /* sub [hl] */
+            0x23, /* inc hl */
+            0xCB, 0x36, /* swap [hl] */
+            0x18, 0xFE, /* jr @ */
+        ];
+        bytes[0x0100..0x0100 + code.len()].copy_from_slice(&code);
+        let entry = Address::new(0, 0x0100);
+        let cfg = ControlFlowGraph::build(&bytes, &[entry], &Project::new(&bytes));
+
+        let usage = MemoryUsage::analyze(&cfg, Mbc::None);
+        let variables: Vec<(u16, AccessKind)> = usage.variables(entry).into_iter().collect();
+        assert_eq!(
+            variables,
+            [(0xC000, AccessKind::Read), (0xC001, AccessKind::ReadWrite)]
+        );
+        assert!(usage.get_warnings().is_empty());
+    }
+
+    #[test]
+    fn usage() {
+        let mut bytes = vec![0x00; 0x8000];
+        let code = [
+            0xFA, 0x00, 0xC0, /* ld a, [$C000] */
+            0xEA, 0x00, 0x20, /* ld [$2000], a */
+            0xEA, 0x00, 0xE0, /* ld [$E000], a */
+            0xE0, 0x80, /* ldh [$FF80], a */
+            0x21, 0x10, 0xC0, /* ld hl, $C010 */
+            0x77, /* ld [hl], a */
+            0x18, 0xFE, /* jr @ */
+        ];
+        bytes[0x0100..0x0100 + code.len()].copy_from_slice(&code);
+        let entry = Address::new(0, 0x0100);
+        let cfg = ControlFlowGraph::build(&bytes, &[entry], &Project::new(&bytes));
+
+        let usage = MemoryUsage::analyze(&cfg, Mbc::None);
+        let variables: Vec<(u16, AccessKind)> = usage.variables(entry).into_iter().collect();
+        assert_eq!(
+            variables,
+            [
+                (0xC000, AccessKind::Read),
+                (0xC010, AccessKind::Write),
+                (0xFF80, AccessKind::Write),
+            ]
+        );
+        let warnings: Vec<String> = usage
+            .get_warnings()
+            .iter()
+            .map(|warning| warning.to_string())
+            .collect();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("$2000"));
+        assert!(warnings[1].contains("echo RAM"));
+
+        let usage = MemoryUsage::analyze(&cfg, Mbc::MBC1);
+        assert_eq!(usage.get_warnings().len(), 1);
+    }
+}
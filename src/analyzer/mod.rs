@@ -1,30 +1,536 @@
+mod address;
 mod cartridge;
+mod charmap;
+mod compression;
+mod constants;
+mod control_flow;
+mod decompiler;
 mod disassembler;
+mod doctor;
+mod emulator;
 mod error;
+mod format;
+mod graphics;
+mod hardware;
+mod header;
 mod instruction;
+mod ir;
+mod memory;
+mod png;
+mod project;
+mod sha1;
+mod stack;
+mod strings;
+mod symbols;
+mod timing;
+mod trace;
+mod values;
+mod warning;
+mod xrefs;
 
+pub use address::{Address, BANK_SIZE};
 use cartridge::Cartridge;
+pub use charmap::Charmap;
+pub use compression::{CompressedData, Compression};
+pub use constants::Constants;
+pub use control_flow::{
+    BasicBlock, CallArguments, ControlFlowGraph, Edge, EdgeKind, Function, JumpTable, PointerTable,
+};
+pub use decompiler::{Decompiler, Pseudocode};
 use disassembler::Disassembler;
-use error::AnalyzerError;
-use instruction::Instruction;
+pub use disassembler::{Disassembly, Line};
+pub use doctor::{Divergence, DoctorLog, DoctorState, DOCTOR_CONTEXT};
+pub use emulator::{Bus, Cpu, Memory, Registers};
+pub use error::AnalyzerError;
+pub use format::{escape, Formatter, Syntax};
+pub use graphics::{tile_sheet, TileCopy, DEFAULT_PALETTE, TILE_SIZE};
+pub use hardware::{io_register, io_register_name, BitField, IoRegister, IO_REGISTERS};
+pub use header::{Header, Mbc};
+pub use instruction::{Condition, Flow, Instruction, Mnemonic, Operand, Register};
+pub use ir::{BinOp, Expr, Flag, Statement};
+pub use memory::{AccessKind, MemoryAccess, MemoryUsage, Region};
+pub use project::{DataType, InlineArgs, Project, Trampoline, TypedRange};
+pub use stack::{Stack, StackUsage, INITIAL_SP};
+pub use strings::{Text, MIN_STRING_LENGTH};
+pub use symbols::{Symbol, SymbolTable};
+pub use timing::{Cycles, Loop, Timing, INTERRUPT_CYCLES, VBLANK_CYCLES};
+pub use trace::Trace;
+pub use warning::Warning;
+pub use xrefs::{Xref, XrefKind, Xrefs};
+
+/* Longest data typed at the target of a pointer table. */
+const MAX_POINTEE_SIZE: u16 = 0x100;
 
 #[derive(Debug)]
 pub struct Analyzer<'a> {
     path: &'a std::path::Path,
     cartridge: cartridge::Cartridge,
+    symbols: SymbolTable,
+    project: Project,
 }
 
 impl<'a> Analyzer<'a> {
-    pub fn from_path(path: &std::path::Path) -> Result<Analyzer, AnalyzerError> {
+    pub fn from_path(path: &std::path::Path) -> Result<Analyzer<'_>, AnalyzerError> {
+        let cartridge = Cartridge::from_path(path)?;
+        let project = Project::new(cartridge.get_bytes());
+
         Ok(Analyzer {
             path,
-            cartridge: Cartridge::from_path(path)?,
+            cartridge,
+            symbols: SymbolTable::new(),
+            project,
         })
     }
 
-    pub fn disassemble(&self) -> Result<Vec<Instruction>, AnalyzerError> {
-        let disassembly = Disassembler::disassemble(self.cartridge.get_bytes())?;
+    pub fn get_path(&self) -> &std::path::Path {
+        self.path
+    }
+
+    pub fn get_bytes(&self) -> &Vec<u8> {
+        self.cartridge.get_bytes()
+    }
+
+    /* Loads names from a .sym file, on top of the ones already known. */
+    pub fn load_symbols(&mut self, path: &std::path::Path) -> Result<(), AnalyzerError> {
+        let text = std::fs::read_to_string(path).map_err(AnalyzerError::InvalidSymbolFile)?;
+
+        self.symbols.parse(&text)
+    }
+
+    pub fn get_symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /* Loads the annotations of a project file made for this ROM, merged into
+     * the ones already loaded. Returns the conflicts between them. */
+    pub fn load_project(&mut self, path: &std::path::Path) -> Result<Vec<Warning>, AnalyzerError> {
+        let text = std::fs::read_to_string(path).map_err(AnalyzerError::InvalidProjectFile)?;
+        let mut project = Project::parse(&text)?;
+
+        /* Charmaps are named by their path relative to the project file. */
+        let dir = path.parent().unwrap_or_else(|| std::path::Path::new(""));
+        let names: Vec<String> = project
+            .charmap_names()
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        for name in names {
+            let charmap = Analyzer::read_charmap(&dir.join(&name))?;
+            project.set_charmap(&name, charmap);
+        }
+
+        self.project.merge(&project)
+    }
+
+    /* Marks the code executed in an emulator trace or code/data log as code,
+     * which also makes it an entry of flow analysis, and the bytes it read
+     * as data. Ranges of the project win over the trace. */
+    pub fn load_trace(&mut self, path: &std::path::Path) -> Result<Vec<Warning>, AnalyzerError> {
+        let file = std::fs::read(path).map_err(AnalyzerError::InvalidTraceFile)?;
+        let bytes = self.cartridge.get_bytes();
+        let trace = Trace::parse(&file, bytes)?;
+
+        let mut warnings = trace.get_warnings().clone();
+        for range in trace
+            .code_ranges(bytes)
+            .into_iter()
+            .chain(trace.data_ranges())
+        {
+            if let Err(range) = self.project.add_type(range) {
+                let ours = self
+                    .project
+                    .get_types()
+                    .values()
+                    .find(|ours| ours.start() <= range.end() && range.start() <= ours.end());
+                if let Some(ours) = ours.filter(|ours| ours.data_type() != range.data_type()) {
+                    warnings.push(Warning::AnnotationConflict {
+                        address: range.start(),
+                        what: "type",
+                        ours: ours.data_type().to_string(),
+                        theirs: range.data_type().to_string(),
+                    });
+                }
+            }
+        }
+
+        Ok(warnings)
+    }
+
+    /* Loads the charmap used by text ranges typed with `name`. */
+    pub fn load_charmap(
+        &mut self,
+        name: &str,
+        path: &std::path::Path,
+    ) -> Result<(), AnalyzerError> {
+        let charmap = Analyzer::read_charmap(path)?;
+        self.project.set_charmap(name, charmap);
+
+        Ok(())
+    }
+
+    fn read_charmap(path: &std::path::Path) -> Result<Charmap, AnalyzerError> {
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            AnalyzerError::InvalidCharmapFile(std::io::Error::new(
+                e.kind(),
+                format!("{}: {}", path.display(), e),
+            ))
+        })?;
+
+        Charmap::parse(&text)
+    }
+
+    pub fn save_project(&self, path: &std::path::Path) -> Result<(), std::io::Error> {
+        let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.project.write(&mut out)?;
+
+        std::io::Write::flush(&mut out)
+    }
+
+    pub fn get_project(&self) -> &Project {
+        &self.project
+    }
+
+    pub fn get_project_mut(&mut self) -> &mut Project {
+        &mut self.project
+    }
+
+    /* Loaded symbols with the project labels on top. */
+    fn known_symbols(&self) -> SymbolTable {
+        let mut symbols = self.symbols.clone();
+        for (&address, name) in self.project.get_labels() {
+            symbols.insert(address, name, false);
+        }
+
+        symbols
+    }
+
+    /* Known symbols plus generated labels for the targets in `disassembly`. */
+    pub fn disassembly_symbols(&self, disassembly: &Disassembly) -> SymbolTable {
+        let mut symbols = self.known_symbols();
+        symbols.add_disassembly_labels(disassembly);
+
+        symbols
+    }
+
+    /* Known symbols plus generated labels for the functions and blocks of
+     * `cfg`. */
+    pub fn control_flow_symbols(&self, cfg: &ControlFlowGraph) -> SymbolTable {
+        let mut symbols = self.known_symbols();
+        symbols.add_control_flow_labels(cfg);
+
+        symbols
+    }
+
+    pub fn header(&self) -> Result<Header, AnalyzerError> {
+        self.cartridge.header()
+    }
+
+    /* Linear sweep of the whole rom, around the data found by `cfg`. */
+    pub fn disassemble(&self, cfg: &ControlFlowGraph) -> Result<Disassembly, AnalyzerError> {
+        let disassembly =
+            Disassembler::disassemble(self.cartridge.get_bytes(), &self.sweep_project(cfg))?;
 
         Ok(disassembly)
     }
+
+    /* Linear sweep from `start` to `end` included, both in the same bank. */
+    pub fn disassemble_range(
+        &self,
+        start: Address,
+        end: Address,
+        cfg: &ControlFlowGraph,
+    ) -> Result<Disassembly, AnalyzerError> {
+        let bytes = self.cartridge.get_bytes();
+        self.check_address(start)?;
+        self.check_address(end)?;
+        if start.bank() != end.bank() || start > end {
+            return Err(AnalyzerError::InvalidRange(start, end));
+        }
+
+        Disassembler::disassemble_range(
+            bytes,
+            start.to_offset(),
+            end.to_offset() + 1,
+            &self.sweep_project(cfg),
+        )
+    }
+
+    pub fn disassemble_bank(
+        &self,
+        bank: u16,
+        cfg: &ControlFlowGraph,
+    ) -> Result<Disassembly, AnalyzerError> {
+        let bytes = self.cartridge.get_bytes();
+        let start = bank as usize * BANK_SIZE;
+        if start >= bytes.len() {
+            return Err(AnalyzerError::InvalidAddress(Address::from_offset(start)));
+        }
+
+        let end = std::cmp::min(start + BANK_SIZE, bytes.len());
+        Disassembler::disassemble_range(bytes, start, end, &self.sweep_project(cfg))
+    }
+
+    /* The project plus the jump tables and call arguments found by `cfg`,
+     * so that a linear sweep does not decode them as instructions. */
+    fn sweep_project(&self, cfg: &ControlFlowGraph) -> Project {
+        let mut project = self.project.clone();
+        /* Ranges of the user win over the guessed ones. */
+        for table in cfg.get_jump_tables().values() {
+            let _ = project.add_type(TypedRange::new(
+                table.start(),
+                table.end(),
+                DataType::Pointers,
+            ));
+        }
+        /* Larger copies first, a partial copy of the same tiles then fails. */
+        let mut copies = self.tile_copies(cfg);
+        copies.sort_by_key(|copy| std::cmp::Reverse(copy.size()));
+        for copy in copies {
+            let _ = project.add_type(TypedRange::new(
+                copy.source(),
+                copy.end(),
+                DataType::Gfx2bpp,
+            ));
+        }
+        for data in self.compressed_data(cfg) {
+            let _ = project.add_type(TypedRange::new(data.source(), data.end(), DataType::Bytes));
+        }
+        /* Data between two targets of a pointer table is one entry, the last
+         * one ends before the next code. */
+        for table in cfg.get_pointer_tables().values() {
+            let _ = project.add_type(TypedRange::new(
+                table.start(),
+                table.end(),
+                DataType::Pointers,
+            ));
+            if table.code() {
+                continue;
+            }
+
+            let mut targets: Vec<Address> = table
+                .get_targets()
+                .iter()
+                .map(|&(_, target)| target)
+                .collect();
+            targets.sort_unstable();
+            targets.dedup();
+            for (i, &start) in targets.iter().enumerate() {
+                let code = cfg
+                    .get_blocks()
+                    .range(start..)
+                    .next()
+                    .map(|(&code, _)| code);
+                let next = match targets.get(i + 1).copied().or(code) {
+                    Some(next) if next.bank() == start.bank() => next,
+                    _ => continue,
+                };
+                if next.addr() - start.addr() <= MAX_POINTEE_SIZE
+                    && code.is_none_or(|code| code >= next)
+                {
+                    let end = Address::new(start.bank(), next.addr() - 1);
+                    let _ = project.add_type(TypedRange::new(start, end, DataType::Bytes));
+                }
+            }
+        }
+        for arguments in cfg.get_arguments().values() {
+            let end = Address::new(arguments.start().bank(), arguments.end().addr() - 1);
+            let _ = project.add_type(TypedRange::new(
+                arguments.start(),
+                end,
+                DataType::of_arguments(arguments.kind()),
+            ));
+        }
+
+        project
+    }
+
+    /* Disassembles only the code reachable from `entry`. */
+    pub fn follow(&self, entry: Address) -> Result<Disassembly, AnalyzerError> {
+        let cfg = self.control_flow_from(&[entry])?;
+
+        Ok(Disassembler::from_control_flow(
+            &cfg,
+            self.cartridge.get_bytes(),
+        ))
+    }
+
+    /* Control flow reachable from the header entry point, the interrupt
+     * vectors and the entries of ranges the project says are code. */
+    pub fn control_flow(&self) -> ControlFlowGraph {
+        let entries: Vec<Address> = control_flow::DEFAULT_ENTRY_POINTS
+            .iter()
+            .map(|&addr| Address::new(0, addr))
+            .chain(self.project.entry_points())
+            .filter(|addr| addr.to_offset() < self.cartridge.get_bytes().len())
+            .collect();
+
+        ControlFlowGraph::build(self.cartridge.get_bytes(), &entries, &self.project)
+    }
+
+    pub fn control_flow_from(
+        &self,
+        entries: &[Address],
+    ) -> Result<ControlFlowGraph, AnalyzerError> {
+        for &entry in entries {
+            self.check_address(entry)?;
+        }
+
+        Ok(ControlFlowGraph::build(
+            self.cartridge.get_bytes(),
+            entries,
+            &self.project,
+        ))
+    }
+
+    fn check_address(&self, address: Address) -> Result<(), AnalyzerError> {
+        if address.to_offset() < self.cartridge.get_bytes().len() {
+            Ok(())
+        } else {
+            Err(AnalyzerError::InvalidAddress(address))
+        }
+    }
+
+    /* Memory accesses of every function found by `cfg`. */
+    pub fn memory_usage(&self, cfg: &ControlFlowGraph) -> MemoryUsage {
+        let mbc = match self.header() {
+            Ok(header) => header.mbc(),
+            Err(_) => Mbc::None,
+        };
+
+        MemoryUsage::analyze(cfg, mbc)
+    }
+
+    /* Strings of `charmap` ending with `terminator` in the bytes that are
+     * neither code found by `cfg` nor typed as something else than bytes or
+     * text by the project. */
+    pub fn strings(&self, cfg: &ControlFlowGraph, charmap: &Charmap, terminator: u8) -> Vec<Text> {
+        let mut excluded = std::collections::BTreeMap::new();
+        let mut exclude = |start: Address, size: usize| {
+            excluded.insert(start.to_offset(), start.to_offset() + size);
+        };
+
+        for block in cfg.get_blocks().values() {
+            exclude(
+                block.start(),
+                (block.end().addr() - block.start().addr()) as usize,
+            );
+        }
+        for table in cfg.get_jump_tables().values() {
+            exclude(
+                table.start(),
+                (table.end().addr() - table.start().addr()) as usize + 1,
+            );
+        }
+        for table in cfg.get_pointer_tables().values() {
+            exclude(
+                table.start(),
+                (table.end().addr() - table.start().addr()) as usize + 1,
+            );
+        }
+        for arguments in cfg.get_arguments().values() {
+            exclude(arguments.start(), arguments.size());
+        }
+        for range in self.project.get_types().values() {
+            if !matches!(range.data_type(), DataType::Bytes | DataType::Text(_)) {
+                exclude(
+                    range.start(),
+                    (range.end().addr() - range.start().addr()) as usize + 1,
+                );
+            }
+        }
+
+        Text::scan(self.cartridge.get_bytes(), charmap, terminator, &excluded)
+    }
+
+    /* Tiles copied to VRAM by the code found by `cfg`. */
+    pub fn tile_copies(&self, cfg: &ControlFlowGraph) -> Vec<TileCopy> {
+        TileCopy::find(cfg, self.cartridge.get_bytes(), &self.project)
+    }
+
+    /* Encodes `bytes` as a PNG sheet of 2bpp tiles, `width` tiles per row. */
+    pub fn export_tiles(bytes: &[u8], width: usize, palette: &[[u8; 3]; 4]) -> Vec<u8> {
+        let (width, height, pixels) = tile_sheet(bytes, width);
+
+        png::encode(width, height, palette, &pixels)
+    }
+
+    /* Data decompressed by the decompressors found in `cfg`. */
+    pub fn compressed_data(&self, cfg: &ControlFlowGraph) -> Vec<CompressedData> {
+        CompressedData::find(cfg, self.cartridge.get_bytes(), &self.project)
+    }
+
+    /* Decompresses the data at `address`, and gives the size of the
+     * compressed data. */
+    pub fn decompress(
+        &self,
+        address: Address,
+        compression: Compression,
+    ) -> Result<(Vec<u8>, usize), AnalyzerError> {
+        let bytes = self.cartridge.get_bytes();
+        let start = address.to_offset();
+        if start >= bytes.len() {
+            return Err(AnalyzerError::InvalidAddress(address));
+        }
+        let end = std::cmp::min((start / BANK_SIZE + 1) * BANK_SIZE, bytes.len());
+
+        compression
+            .decompress(&bytes[start..end])
+            .map_err(|msg| AnalyzerError::InvalidCompressedData(address, msg))
+    }
+
+    /* Calls the routine at `entry` on an emulated CPU starting with
+     * `registers`, with its bank mapped, until it returns or runs
+     * `max_cycles`. Gives the CPU and the RAM it left. */
+    pub fn run(
+        &self,
+        entry: Address,
+        registers: Registers,
+        max_cycles: u64,
+    ) -> Result<(Cpu, Vec<u8>), AnalyzerError> {
+        if entry.to_offset() >= self.cartridge.get_bytes().len() {
+            return Err(AnalyzerError::InvalidAddress(entry));
+        }
+        let mut memory = Memory::new(self.cartridge.get_bytes());
+        if entry.bank() != 0 {
+            memory.write(0x2000, entry.bank() as u8);
+        }
+
+        let mut cpu = Cpu::new(registers);
+        cpu.call(&mut memory, entry.addr(), max_cycles)?;
+
+        Ok((cpu, memory.ram().to_vec()))
+    }
+
+    /* Cycles of the blocks and functions found by `cfg`. */
+    pub fn timing(&self, cfg: &ControlFlowGraph) -> Timing {
+        Timing::build(cfg, self.cartridge.get_bytes(), &self.project)
+    }
+
+    /* Stack used by the functions found by `cfg`. */
+    pub fn stack(&self, cfg: &ControlFlowGraph) -> Stack {
+        Stack::build(cfg, self.cartridge.get_bytes(), &self.project)
+    }
+
+    /* Cross references of the code found by `cfg` and of the pointer
+     * tables. */
+    pub fn xrefs(&self, cfg: &ControlFlowGraph) -> Xrefs {
+        Xrefs::build(cfg, self.cartridge.get_bytes(), &self.project)
+    }
+
+    /* Pseudocode of the functions found by `cfg`, named after `symbols`. */
+    pub fn decompiler<'b>(
+        &'b self,
+        cfg: &ControlFlowGraph,
+        symbols: &'b SymbolTable,
+    ) -> Decompiler<'b> {
+        Decompiler::new(cfg, self.cartridge.get_bytes(), &self.project, symbols)
+    }
+
+    pub fn fix_checksums(&mut self) -> Result<(u8, u16), AnalyzerError> {
+        self.cartridge.fix_checksums()
+    }
+
+    pub fn save(&self, path: &std::path::Path) -> Result<(), std::io::Error> {
+        self.cartridge.save(path)
+    }
 }
@@ -0,0 +1,148 @@
+/* Minimal PNG encoder for indexed images: the pixel data is stored in
+ * uncompressed deflate blocks, which every decoder reads. */
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+/* Largest stored deflate block. */
+const MAX_BLOCK_SIZE: usize = 0xFFFF;
+
+/* Encodes a `width` x `height` image of palette indices, one byte each, row
+ * by row. */
+pub fn encode(width: usize, height: usize, palette: &[[u8; 3]], pixels: &[u8]) -> Vec<u8> {
+    let mut png = SIGNATURE.to_vec();
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    /* 8 bits per pixel, indexed colour, deflate, no filter, no interlace. */
+    header.extend_from_slice(&[8, 3, 0, 0, 0]);
+    chunk(&mut png, b"IHDR", &header);
+
+    let colours: Vec<u8> = palette.iter().flatten().copied().collect();
+    chunk(&mut png, b"PLTE", &colours);
+
+    /* Every row starts with its filter type, 0 for none. */
+    let mut raw = Vec::with_capacity((width + 1) * height);
+    for row in pixels.chunks(width).take(height) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    chunk(&mut png, b"IEND", &[]);
+
+    png
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/* zlib stream of stored deflate blocks. */
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+
+    let mut blocks = data.chunks(MAX_BLOCK_SIZE).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let size = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&size.to_le_bytes());
+        out.extend_from_slice(&(!size).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+
+    out
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(adler32(b""), 1);
+    }
+
+    #[test]
+    fn stored_blocks() {
+        assert_eq!(
+            zlib_stored(&[]),
+            [0x78, 0x01, 1, 0, 0, 0xFF, 0xFF, 0, 0, 0, 1]
+        );
+        assert_eq!(
+            zlib_stored(&[0xAB]),
+            [0x78, 0x01, 1, 1, 0, 0xFE, 0xFF, 0xAB, 0, 0xAC, 0, 0xAC]
+        );
+
+        let data = vec![0; MAX_BLOCK_SIZE + 1];
+        let zlib = zlib_stored(&data);
+        assert_eq!(zlib.len(), 2 + 2 * 5 + data.len() + 4);
+        assert_eq!(zlib[2..7], [0, 0xFF, 0xFF, 0, 0]);
+        let last = 7 + MAX_BLOCK_SIZE;
+        assert_eq!(zlib[last..last + 5], [1, 1, 0, 0xFE, 0xFF]);
+    }
+
+    #[test]
+    fn image() {
+        let palette = [[0xFF, 0xFF, 0xFF], [0x00, 0x00, 0x00]];
+        let png = encode(2, 2, &palette, &[0, 1, 1, 0]);
+
+        assert_eq!(png[..8], SIGNATURE);
+        assert_eq!(
+            png[8..33],
+            [
+                0, 0, 0, 13, b'I', b'H', b'D', b'R', 0, 0, 0, 2, 0, 0, 0, 2, 8, 3, 0, 0, 0, 0x45,
+                0x68, 0xFD, 0x16
+            ][..]
+        );
+        assert_eq!(png[33..41], [0, 0, 0, 6, b'P', b'L', b'T', b'E']);
+        assert_eq!(png[41..47], [0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00]);
+
+        /* Filter byte then the indices of each row, stored as is. */
+        let idat = 51 + 8;
+        assert_eq!(png[51..idat], [0, 0, 0, 17, b'I', b'D', b'A', b'T']);
+        assert_eq!(png[idat + 7..idat + 13], [0, 0, 1, 0, 1, 0]);
+
+        assert_eq!(
+            png[png.len() - 12..],
+            [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]
+        );
+    }
+}
@@ -0,0 +1,825 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+
+use super::address::Address;
+use super::charmap::Charmap;
+use super::error::AnalyzerError;
+use super::instruction::Register;
+use super::sha1;
+use super::warning::Warning;
+
+/* How the bytes of a range of ROM are to be interpreted. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DataType {
+    Code(Option<Address>), /* Entry point, the start of the range without one */
+    Bytes,
+    Words,
+    Pointers,
+    Text(Option<String>), /* Path of the charmap, ASCII without one */
+    Gfx2bpp,
+}
+
+impl std::fmt::Display for DataType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Code(None) => write!(f, "code"),
+            Self::Code(Some(entry)) => write!(f, "code {}", entry),
+            Self::Bytes => write!(f, "bytes"),
+            Self::Words => write!(f, "words"),
+            Self::Pointers => write!(f, "pointers"),
+            Self::Text(None) => write!(f, "text"),
+            Self::Text(Some(charmap)) => write!(f, "text {}", charmap),
+            Self::Gfx2bpp => write!(f, "gfx2bpp"),
+        }
+    }
+}
+
+impl std::str::FromStr for DataType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DataType, String> {
+        let (kind, argument) = match s.trim().split_once(char::is_whitespace) {
+            Some((kind, argument)) => (kind, Some(argument.trim())),
+            None => (s.trim(), None),
+        };
+
+        match (kind, argument) {
+            ("code", None) => Ok(DataType::Code(None)),
+            ("code", Some(entry)) => Ok(DataType::Code(Some(entry.parse()?))),
+            ("bytes", None) => Ok(DataType::Bytes),
+            ("words", None) => Ok(DataType::Words),
+            ("pointers", None) => Ok(DataType::Pointers),
+            ("text", charmap) => Ok(DataType::Text(charmap.map(str::to_string))),
+            ("gfx2bpp", None) => Ok(DataType::Gfx2bpp),
+            _ => Err(format!(
+                "unknown data type `{}`, expected code [entry], bytes, words, pointers, text [charmap] or gfx2bpp",
+                s
+            )),
+        }
+    }
+}
+
+impl DataType {
+    /* Strings are shown as text, other arguments as bytes. */
+    pub fn of_arguments(args: &InlineArgs) -> DataType {
+        match args {
+            InlineArgs::Bytes(_) => DataType::Bytes,
+            InlineArgs::String(_) => DataType::Text(None),
+        }
+    }
+}
+
+/* What a function reads right after the call instruction that called it. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InlineArgs {
+    Bytes(usize),
+    String(u8), /* Terminator, included */
+}
+
+impl std::fmt::Display for InlineArgs {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Bytes(size) => write!(f, "{}", size),
+            Self::String(terminator) => write!(f, "string {:02X}", terminator),
+        }
+    }
+}
+
+/* `N` bytes, or `string [terminator]` in hexadecimal, $00 by default. */
+impl std::str::FromStr for InlineArgs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<InlineArgs, String> {
+        let invalid = || {
+            format!(
+                "invalid inline arguments `{}`, expected a size or string [terminator]",
+                s
+            )
+        };
+        let mut fields = s.split_whitespace();
+
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some("string"), None, None) => Ok(InlineArgs::String(0)),
+            (Some("string"), Some(terminator), None) => {
+                u8::from_str_radix(terminator.trim_start_matches('$'), 16)
+                    .map(InlineArgs::String)
+                    .map_err(|_| invalid())
+            }
+            (Some(size), None, None) => match size.parse() {
+                Ok(size) if size > 0 => Ok(InlineArgs::Bytes(size)),
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/* How a function calls code in another bank for its caller. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trampoline {
+    Registers(Register, Register), /* Bank, address */
+    Inline,                        /* db bank, dw address after the call */
+}
+
+impl Trampoline {
+    fn register(s: &str) -> Option<Register> {
+        match s.to_ascii_lowercase().as_str() {
+            "a" => Some(Register::A),
+            "b" => Some(Register::B),
+            "c" => Some(Register::C),
+            "d" => Some(Register::D),
+            "e" => Some(Register::E),
+            "h" => Some(Register::H),
+            "l" => Some(Register::L),
+            "bc" => Some(Register::BC),
+            "de" => Some(Register::DE),
+            "hl" => Some(Register::HL),
+            _ => None,
+        }
+    }
+
+    fn register_name(reg: Register) -> &'static str {
+        match reg {
+            Register::A => "a",
+            Register::B => "b",
+            Register::C => "c",
+            Register::D => "d",
+            Register::E => "e",
+            Register::H => "h",
+            Register::L => "l",
+            Register::BC => "bc",
+            Register::DE => "de",
+            Register::HL => "hl",
+            _ => "?",
+        }
+    }
+}
+
+impl std::fmt::Display for Trampoline {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Registers(bank, address) => write!(
+                f,
+                "{} {}",
+                Trampoline::register_name(*bank),
+                Trampoline::register_name(*address)
+            ),
+            Self::Inline => write!(f, "inline"),
+        }
+    }
+}
+
+/* `inline`, or the registers holding the bank and the address: `a hl`. */
+impl std::str::FromStr for Trampoline {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Trampoline, String> {
+        let mut fields = s.split_whitespace();
+
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some("inline"), None, None) => Ok(Trampoline::Inline),
+            (Some(bank), Some(address), None) => {
+                match (Trampoline::register(bank), Trampoline::register(address)) {
+                    (Some(bank @ Register::A), Some(address))
+                    | (Some(bank @ Register::B), Some(address))
+                    | (Some(bank @ Register::C), Some(address))
+                    | (Some(bank @ Register::D), Some(address))
+                    | (Some(bank @ Register::E), Some(address))
+                        if matches!(address, Register::BC | Register::DE | Register::HL) =>
+                    {
+                        Ok(Trampoline::Registers(bank, address))
+                    }
+                    _ => Err(format!(
+                        "invalid trampoline registers `{}`, expected a bank register and a pair",
+                        s
+                    )),
+                }
+            }
+            _ => Err(format!(
+                "invalid trampoline `{}`, expected inline or <bank register> <address pair>",
+                s
+            )),
+        }
+    }
+}
+
+/* Range of ROM from `start` to `end` included, in a single bank. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypedRange {
+    start: Address,
+    end: Address,
+    data_type: DataType,
+}
+
+impl TypedRange {
+    pub fn new(start: Address, end: Address, data_type: DataType) -> TypedRange {
+        TypedRange {
+            start,
+            end,
+            data_type,
+        }
+    }
+
+    pub fn start(&self) -> Address {
+        self.start
+    }
+
+    pub fn end(&self) -> Address {
+        self.end
+    }
+
+    pub fn data_type(&self) -> &DataType {
+        &self.data_type
+    }
+
+    /* Where flow analysis starts for code ranges. */
+    pub fn entry(&self) -> Option<Address> {
+        match self.data_type {
+            DataType::Code(entry) => Some(entry.unwrap_or(self.start)),
+            _ => None,
+        }
+    }
+
+    pub fn contains(&self, address: Address) -> bool {
+        self.start <= address && address <= self.end
+    }
+
+    fn overlaps(&self, other: &TypedRange) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+}
+
+/* What is known about a ROM beyond what the analysis finds by itself: names,
+ * comments, data types and banks. Saved as a line based text file so that it
+ * can be kept under version control next to the disassembly. */
+#[derive(Debug, Clone)]
+pub struct Project {
+    sha1: String,
+    labels: BTreeMap<Address, String>,
+    comments: BTreeMap<Address, String>,
+    types: BTreeMap<Address, TypedRange>,
+    /* Bank mapped at $4000-$7FFF when the instruction at the address runs. */
+    bank_hints: BTreeMap<Address, u16>,
+    /* RST vectors whose routine reads a jump table inline after the `rst`. */
+    table_rsts: BTreeSet<u8>,
+    /* Arguments read after their call by the function at the address. */
+    inline_args: BTreeMap<Address, InlineArgs>,
+    trampolines: BTreeMap<Address, Trampoline>,
+    /* Charmaps of text ranges by the name used in the project file, loaded
+     * separately. */
+    charmaps: BTreeMap<String, Charmap>,
+}
+
+impl Project {
+    /* Empty project for the ROM made of `bytes`. */
+    pub fn new(bytes: &[u8]) -> Project {
+        Project {
+            sha1: sha1::to_hex(&sha1::sha1(bytes)),
+            labels: BTreeMap::new(),
+            comments: BTreeMap::new(),
+            types: BTreeMap::new(),
+            bank_hints: BTreeMap::new(),
+            table_rsts: BTreeSet::new(),
+            inline_args: BTreeMap::new(),
+            trampolines: BTreeMap::new(),
+            charmaps: BTreeMap::new(),
+        }
+    }
+
+    /* Reads a project file as written by `write`. Blank lines and lines
+     * starting with `;` are ignored. */
+    pub fn parse(text: &str) -> Result<Project, AnalyzerError> {
+        let mut project = Project {
+            sha1: String::new(),
+            labels: BTreeMap::new(),
+            comments: BTreeMap::new(),
+            types: BTreeMap::new(),
+            bank_hints: BTreeMap::new(),
+            table_rsts: BTreeSet::new(),
+            inline_args: BTreeMap::new(),
+            trampolines: BTreeMap::new(),
+            charmaps: BTreeMap::new(),
+        };
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+
+            let invalid = |msg: String| AnalyzerError::InvalidProject(i + 1, msg);
+            let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let (first, rest) = rest
+                .trim()
+                .split_once(char::is_whitespace)
+                .unwrap_or((rest.trim(), ""));
+            let rest = rest.trim();
+
+            match keyword {
+                "rom" => {
+                    if first.len() != 40 || !first.bytes().all(|b| b.is_ascii_hexdigit()) {
+                        return Err(invalid(format!("invalid SHA-1 `{}`", first)));
+                    }
+                    project.sha1 = first.to_ascii_lowercase();
+                }
+                "label" => {
+                    let address = Project::location(first).map_err(invalid)?;
+                    if rest.is_empty() || rest.contains(char::is_whitespace) {
+                        return Err(invalid(format!("invalid label name `{}`", rest)));
+                    }
+                    project.labels.insert(address, rest.to_string());
+                }
+                "comment" => {
+                    let address = first.parse().map_err(invalid)?;
+                    project.comments.insert(address, rest.to_string());
+                }
+                "type" => {
+                    let (start, end) = first.split_once('-').ok_or_else(|| {
+                        invalid(format!("invalid range `{}`, expected start-end", first))
+                    })?;
+                    let start: Address = start.parse().map_err(invalid)?;
+                    let end: Address = if end.contains(':') {
+                        end.parse().map_err(invalid)?
+                    } else {
+                        format!("{:X}:{}", start.bank(), end)
+                            .parse()
+                            .map_err(invalid)?
+                    };
+                    if start.bank() != end.bank() || start > end {
+                        return Err(invalid(format!(
+                            "invalid range {}-{}, it must be in a single bank and not empty",
+                            start, end
+                        )));
+                    }
+                    let range = TypedRange::new(start, end, rest.parse().map_err(invalid)?);
+                    project.add_type(range).map_err(|range| {
+                        invalid(format!(
+                            "range {}-{} overlaps another one",
+                            range.start, range.end
+                        ))
+                    })?;
+                }
+                "bank" => {
+                    let address = first.parse().map_err(invalid)?;
+                    let bank = u16::from_str_radix(rest.trim_start_matches('$'), 16)
+                        .map_err(|_| invalid(format!("invalid bank `{}`", rest)))?;
+                    project.bank_hints.insert(address, bank);
+                }
+                "inline" => {
+                    let address = first.parse().map_err(invalid)?;
+                    project
+                        .inline_args
+                        .insert(address, rest.parse().map_err(invalid)?);
+                }
+                "farcall" => {
+                    let address = first.parse().map_err(invalid)?;
+                    project
+                        .trampolines
+                        .insert(address, rest.parse().map_err(invalid)?);
+                }
+                "table-rst" => {
+                    let vector = Project::vector(first).map_err(invalid)?;
+                    project.table_rsts.insert(vector);
+                }
+                _ => return Err(invalid(format!("unknown entry `{}`", keyword))),
+            }
+        }
+
+        if project.sha1.is_empty() {
+            return Err(AnalyzerError::InvalidProject(
+                0,
+                "missing rom line".to_string(),
+            ));
+        }
+
+        Ok(project)
+    }
+
+    /* RST vector in hexadecimal, `28` or `$28`. */
+    pub fn vector(s: &str) -> Result<u8, String> {
+        let digits = s.trim_start_matches('$').trim_start_matches("0x");
+        match u8::from_str_radix(digits, 16) {
+            Ok(vector) if vector <= 0x38 && vector & 0x07 == 0 => Ok(vector),
+            _ => Err(format!("invalid RST vector `{}`", s)),
+        }
+    }
+
+    /* `bank:addr` anywhere in the address space, for labels of RAM. */
+    fn location(s: &str) -> Result<Address, String> {
+        let invalid = || format!("invalid address `{}`, expected bank:address", s);
+        let (bank, addr) = s.split_once(':').ok_or_else(invalid)?;
+        let bank = u16::from_str_radix(bank, 16).map_err(|_| invalid())?;
+        let addr = u16::from_str_radix(addr, 16).map_err(|_| invalid())?;
+
+        Ok(Address::new(bank, addr))
+    }
+
+    /* Writes the project with entries sorted by address, so that files of
+     * two people annotating the same ROM diff cleanly. */
+    pub fn write(&self, out: &mut dyn Write) -> std::io::Result<()> {
+        writeln!(out, "; analboy project")?;
+        writeln!(out, "rom {}", self.sha1)?;
+
+        for (address, name) in &self.labels {
+            writeln!(out, "label {} {}", address, name)?;
+        }
+        for (address, comment) in &self.comments {
+            writeln!(out, "comment {} {}", address, comment)?;
+        }
+        for range in self.types.values() {
+            writeln!(
+                out,
+                "type {}-{} {}",
+                range.start, range.end, range.data_type
+            )?;
+        }
+        for (address, bank) in &self.bank_hints {
+            writeln!(out, "bank {} {:02X}", address, bank)?;
+        }
+        for vector in &self.table_rsts {
+            writeln!(out, "table-rst {:02X}", vector)?;
+        }
+        for (address, args) in &self.inline_args {
+            writeln!(out, "inline {} {}", address, args)?;
+        }
+        for (address, trampoline) in &self.trampolines {
+            writeln!(out, "farcall {} {}", address, trampoline)?;
+        }
+
+        Ok(())
+    }
+
+    /* SHA-1 of the ROM the project was made for, in lowercase hexadecimal. */
+    pub fn sha1(&self) -> &str {
+        &self.sha1
+    }
+
+    pub fn get_labels(&self) -> &BTreeMap<Address, String> {
+        &self.labels
+    }
+
+    pub fn get_comments(&self) -> &BTreeMap<Address, String> {
+        &self.comments
+    }
+
+    /* Typed ranges keyed by their start. */
+    pub fn get_types(&self) -> &BTreeMap<Address, TypedRange> {
+        &self.types
+    }
+
+    pub fn get_bank_hints(&self) -> &BTreeMap<Address, u16> {
+        &self.bank_hints
+    }
+
+    /* RST vectors followed by jump tables, detected when empty. */
+    pub fn get_table_rsts(&self) -> &BTreeSet<u8> {
+        &self.table_rsts
+    }
+
+    pub fn add_table_rst(&mut self, vector: u8) {
+        self.table_rsts.insert(vector);
+    }
+
+    /* Functions declared to read arguments after their call, by entry. */
+    pub fn get_inline_args(&self) -> &BTreeMap<Address, InlineArgs> {
+        &self.inline_args
+    }
+
+    pub fn set_inline_args(&mut self, function: Address, args: InlineArgs) {
+        self.inline_args.insert(function, args);
+    }
+
+    /* Functions declared to call into another bank, by entry. */
+    pub fn get_trampolines(&self) -> &BTreeMap<Address, Trampoline> {
+        &self.trampolines
+    }
+
+    pub fn set_trampoline(&mut self, function: Address, trampoline: Trampoline) {
+        self.trampolines.insert(function, trampoline);
+    }
+
+    pub fn get_charmaps(&self) -> &BTreeMap<String, Charmap> {
+        &self.charmaps
+    }
+
+    /* Charmap names used by text ranges. */
+    pub fn charmap_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .types
+            .values()
+            .filter_map(|range| match &range.data_type {
+                DataType::Text(Some(name)) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+
+        names
+    }
+
+    pub fn set_charmap(&mut self, name: &str, charmap: Charmap) {
+        self.charmaps.insert(name.to_string(), charmap);
+    }
+
+    /* Entry points of the ranges forced to be code. */
+    pub fn entry_points(&self) -> Vec<Address> {
+        self.types.values().filter_map(TypedRange::entry).collect()
+    }
+
+    pub fn set_label(&mut self, address: Address, name: &str) {
+        self.labels.insert(address, name.to_string());
+    }
+
+    pub fn set_comment(&mut self, address: Address, comment: &str) {
+        self.comments.insert(address, comment.to_string());
+    }
+
+    pub fn set_bank_hint(&mut self, address: Address, bank: u16) {
+        self.bank_hints.insert(address, bank);
+    }
+
+    /* Ranges cannot overlap, the range is given back when it would. */
+    pub fn add_type(&mut self, range: TypedRange) -> Result<(), TypedRange> {
+        if self.types.values().any(|other| other.overlaps(&range)) {
+            return Err(range);
+        }
+
+        self.types.insert(range.start, range);
+        Ok(())
+    }
+
+    /* Removes the range starting at `start`. */
+    pub fn remove_type(&mut self, start: Address) -> Option<TypedRange> {
+        self.types.remove(&start)
+    }
+
+    /* Range containing `address`, if any. */
+    pub fn type_at(&self, address: Address) -> Option<&TypedRange> {
+        self.types
+            .range(..=address)
+            .next_back()
+            .map(|(_, range)| range)
+            .filter(|range| range.contains(address))
+    }
+
+    /* Bank switched in when the instruction at `address` runs. */
+    pub fn bank_hint(&self, address: Address) -> Option<u16> {
+        self.bank_hints.get(&address).copied()
+    }
+
+    /* Adds the annotations of `other`, made for the same ROM. Our entries
+     * win over theirs, every disagreement is reported. Different comments on
+     * the same address are both kept. */
+    pub fn merge(&mut self, other: &Project) -> Result<Vec<Warning>, AnalyzerError> {
+        if other.sha1 != self.sha1 {
+            return Err(AnalyzerError::ProjectMismatch(
+                other.sha1.clone(),
+                self.sha1.clone(),
+            ));
+        }
+
+        let mut conflicts = Vec::new();
+        let mut conflict = |address: Address, what: &'static str, ours: String, theirs: String| {
+            conflicts.push(Warning::AnnotationConflict {
+                address,
+                what,
+                ours,
+                theirs,
+            })
+        };
+
+        for (&address, name) in &other.labels {
+            match self.labels.get(&address) {
+                Some(ours) if ours != name => {
+                    conflict(address, "label", ours.clone(), name.clone())
+                }
+                Some(_) => {}
+                None => {
+                    self.labels.insert(address, name.clone());
+                }
+            }
+        }
+
+        for (&address, comment) in &other.comments {
+            let ours = self.comments.entry(address).or_default();
+            if ours.is_empty() {
+                *ours = comment.clone();
+            } else if !ours.contains(comment.as_str()) {
+                *ours = format!("{} / {}", ours, comment);
+            }
+        }
+
+        for range in other.types.values() {
+            if self.types.get(&range.start) == Some(range) {
+                continue;
+            }
+            if let Err(range) = self.add_type(range.clone()) {
+                let ours = self
+                    .types
+                    .values()
+                    .find(|ours| ours.overlaps(&range))
+                    .unwrap();
+                conflict(
+                    range.start,
+                    "type",
+                    format!("{}-{} {}", ours.start, ours.end, ours.data_type),
+                    format!("{}-{} {}", range.start, range.end, range.data_type),
+                );
+            }
+        }
+
+        for (&address, &bank) in &other.bank_hints {
+            match self.bank_hints.get(&address) {
+                Some(&ours) if ours != bank => conflict(
+                    address,
+                    "bank",
+                    format!("{:02X}", ours),
+                    format!("{:02X}", bank),
+                ),
+                Some(_) => {}
+                None => {
+                    self.bank_hints.insert(address, bank);
+                }
+            }
+        }
+
+        self.table_rsts.extend(other.table_rsts.iter().copied());
+
+        for (&address, args) in &other.inline_args {
+            match self.inline_args.get(&address) {
+                Some(ours) if ours != args => conflict(
+                    address,
+                    "inline arguments",
+                    ours.to_string(),
+                    args.to_string(),
+                ),
+                Some(_) => {}
+                None => {
+                    self.inline_args.insert(address, args.clone());
+                }
+            }
+        }
+
+        for (&address, &trampoline) in &other.trampolines {
+            match self.trampolines.get(&address) {
+                Some(&ours) if ours != trampoline => conflict(
+                    address,
+                    "trampoline",
+                    ours.to_string(),
+                    trampoline.to_string(),
+                ),
+                Some(_) => {}
+                None => {
+                    self.trampolines.insert(address, trampoline);
+                }
+            }
+        }
+
+        for (name, charmap) in &other.charmaps {
+            if !self.charmaps.contains_key(name) {
+                self.charmaps.insert(name.clone(), charmap.clone());
+            }
+        }
+
+        Ok(conflicts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA1: &str = "da39a3ee5e6b4b0d3255bfef95601890afd80709";
+
+    fn project(entries: &str) -> Project {
+        Project::parse(&format!("rom {}\n{}", SHA1, entries)).unwrap()
+    }
+
+    fn text(project: &Project) -> String {
+        let mut out = Vec::new();
+        project.write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let written = "; analboy project\n\
+                       rom da39a3ee5e6b4b0d3255bfef95601890afd80709\n\
+                       label 00:0150 Main\n\
+                       label 00:C000 wCounter\n\
+                       comment 00:0150 Entry point\n\
+                       type 01:4000-01:40FF pointers\n\
+                       type 01:5000-01:5FFF text main.tbl\n\
+                       bank 00:0200 03\n\
+                       table-rst 28\n\
+                       inline 00:0300 string FF\n\
+                       farcall 00:0008 a hl\n";
+
+        let parsed = Project::parse(written).unwrap();
+        assert_eq!(text(&parsed), written);
+        assert_eq!(parsed.sha1(), SHA1);
+        assert_eq!(parsed.bank_hint(Address::new(0, 0x0200)), Some(3));
+        assert_eq!(
+            parsed.type_at(Address::new(1, 0x5123)).unwrap().data_type(),
+            &DataType::Text(Some("main.tbl".to_string()))
+        );
+        assert!(parsed.type_at(Address::new(1, 0x4100)).is_none());
+    }
+
+    #[test]
+    fn shorthands() {
+        let parsed = project(
+            "; comment\n\
+             \n\
+             type 02:4000-4FFF code 02:4010\n\
+             table-rst $30\n\
+             inline 00:0300 2\n\
+             bank 00:0200 $1F\n",
+        );
+
+        let range = parsed.type_at(Address::new(2, 0x4FFF)).unwrap();
+        assert_eq!(range.end(), Address::new(2, 0x4FFF));
+        assert_eq!(range.entry(), Some(Address::new(2, 0x4010)));
+        assert_eq!(parsed.entry_points(), [Address::new(2, 0x4010)]);
+        assert!(parsed.get_table_rsts().contains(&0x30));
+        assert_eq!(
+            parsed.get_inline_args().get(&Address::new(0, 0x0300)),
+            Some(&InlineArgs::Bytes(2))
+        );
+        assert_eq!(parsed.bank_hint(Address::new(0, 0x0200)), Some(0x1F));
+    }
+
+    #[test]
+    fn invalid() {
+        let line = |entry: &str| match Project::parse(&format!("rom {}\n{}\n", SHA1, entry)) {
+            Err(AnalyzerError::InvalidProject(line, _)) => line,
+            _ => panic!("`{}` was accepted", entry),
+        };
+
+        assert_eq!(line("label 00:0150"), 2);
+        assert_eq!(line("label 00:0150 two words"), 2);
+        assert_eq!(line("type 01:4000-02:4000 bytes"), 2);
+        assert_eq!(line("type 01:5000-4000 bytes"), 2);
+        assert_eq!(line("type 01:4000-40FF floats"), 2);
+        assert_eq!(line("type 01:4000-40FF bytes\ntype 01:40F0-41FF words"), 3);
+        assert_eq!(line("table-rst 09"), 2);
+        assert_eq!(line("inline 00:0300 0"), 2);
+        assert_eq!(line("farcall 00:0008 hl a"), 2);
+        assert_eq!(line("symbol 00:0150 Main"), 2);
+        assert!(matches!(
+            Project::parse("label 00:0150 Main\n"),
+            Err(AnalyzerError::InvalidProject(0, _))
+        ));
+    }
+
+    #[test]
+    fn merge() {
+        let mut ours = project(
+            "label 00:0150 Main\n\
+             comment 00:0150 Ours\n\
+             type 01:4000-40FF bytes\n\
+             bank 00:0200 03\n",
+        );
+        let theirs = project(
+            "label 00:0150 Start\n\
+             label 00:0160 Loop\n\
+             comment 00:0150 Theirs\n\
+             type 01:4080-417F words\n\
+             type 01:5000-50FF words\n\
+             bank 00:0200 04\n\
+             table-rst 28\n",
+        );
+
+        let conflicts = ours.merge(&theirs).unwrap();
+        let conflicts: Vec<&str> = conflicts
+            .iter()
+            .map(|conflict| match conflict {
+                Warning::AnnotationConflict { what, .. } => *what,
+                _ => panic!("unexpected warning {}", conflict),
+            })
+            .collect();
+        assert_eq!(conflicts, ["label", "type", "bank"]);
+
+        assert_eq!(ours.get_labels()[&Address::new(0, 0x0150)], "Main");
+        assert_eq!(ours.get_labels()[&Address::new(0, 0x0160)], "Loop");
+        assert_eq!(
+            ours.get_comments()[&Address::new(0, 0x0150)],
+            "Ours / Theirs"
+        );
+        assert_eq!(ours.get_types().len(), 2);
+        assert_eq!(ours.bank_hint(Address::new(0, 0x0200)), Some(3));
+        assert!(ours.get_table_rsts().contains(&0x28));
+
+        /* Merging again changes nothing. */
+        let merged = text(&ours);
+        ours.merge(&theirs).unwrap();
+        assert_eq!(text(&ours), merged);
+
+        let other = Project::new(&[0x00]);
+        assert!(matches!(
+            ours.merge(&other),
+            Err(AnalyzerError::ProjectMismatch(_, _))
+        ));
+    }
+}
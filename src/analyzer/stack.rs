@@ -0,0 +1,581 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::address::Address;
+use super::control_flow::ControlFlowGraph;
+use super::instruction::{Flow, Instruction, Mnemonic, Operand, Register};
+use super::project::Project;
+use super::warning::Warning;
+
+/* SP left by the boot ROM when it jumps to the entry point. */
+pub const INITIAL_SP: u16 = 0xFFFE;
+const ENTRY_POINT: u16 = 0x0100;
+const INTERRUPT_VECTORS: [u16; 5] = [0x0040, 0x0048, 0x0050, 0x0058, 0x0060];
+
+/* Bytes of stack a function uses below the SP it is entered with: pushed by
+ * itself, and with the return addresses and the stack of its callees. They
+ * are a lower bound when a callee is unknown or recursive, or SP is loaded
+ * from an unknown value. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackUsage {
+    own: usize,
+    total: usize,
+    bounded: bool,
+    returns: bool,
+    /* Lowest address pushed to after the function loads SP itself. */
+    lowest: Option<u16>,
+    /* Highest value loaded into SP by the function or its callees. */
+    top: Option<u16>,
+    /* Callee on the deepest path. */
+    deepest: Option<Address>,
+}
+
+impl StackUsage {
+    fn unknown() -> StackUsage {
+        StackUsage {
+            own: 0,
+            total: 0,
+            bounded: false,
+            returns: true,
+            lowest: None,
+            top: None,
+            deepest: None,
+        }
+    }
+
+    pub fn own(&self) -> usize {
+        self.own
+    }
+
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    pub fn bounded(&self) -> bool {
+        self.bounded
+    }
+
+    pub fn returns(&self) -> bool {
+        self.returns
+    }
+
+    pub fn lowest(&self) -> Option<u16> {
+        self.lowest
+    }
+
+    pub fn top(&self) -> Option<u16> {
+        self.top
+    }
+}
+
+/* Stack used by the functions found by flow analysis, and the range of
+ * memory it takes from the entry point with an interrupt on top. */
+#[derive(Debug)]
+pub struct Stack {
+    functions: BTreeMap<Address, StackUsage>,
+    warnings: Vec<Warning>,
+}
+
+impl Stack {
+    pub fn build(cfg: &ControlFlowGraph, bytes: &[u8], project: &Project) -> Stack {
+        let mut analysis = Analysis {
+            cfg,
+            bytes,
+            project,
+            functions: BTreeMap::new(),
+            active: BTreeSet::new(),
+            warnings: BTreeMap::new(),
+        };
+        for &entry in cfg.get_functions().keys() {
+            analysis.function(entry);
+        }
+
+        Stack {
+            functions: analysis.functions,
+            warnings: analysis.warnings.into_values().collect(),
+        }
+    }
+
+    pub fn get_functions(&self) -> &BTreeMap<Address, StackUsage> {
+        &self.functions
+    }
+
+    pub fn get_warnings(&self) -> &Vec<Warning> {
+        &self.warnings
+    }
+
+    /* Functions from `entry` down the deepest chain of calls. */
+    pub fn path(&self, entry: Address) -> Vec<Address> {
+        let mut path = vec![entry];
+        let mut current = entry;
+
+        while let Some(next) = self.functions.get(&current).and_then(|usage| usage.deepest) {
+            if path.contains(&next) {
+                break;
+            }
+            path.push(next);
+            current = next;
+        }
+
+        path
+    }
+
+    /* Highest value loaded into SP from the entry point, or the one left by
+     * the boot ROM. */
+    pub fn top(&self) -> u16 {
+        self.functions
+            .get(&Address::new(0, ENTRY_POINT))
+            .and_then(|boot| boot.top)
+            .unwrap_or(INITIAL_SP)
+    }
+
+    /* Interrupt handler using the most stack, the first one of equals. */
+    pub fn interrupt(&self) -> Option<Address> {
+        INTERRUPT_VECTORS
+            .iter()
+            .rev()
+            .map(|&vector| Address::new(0, vector))
+            .filter(|vector| self.functions.contains_key(vector))
+            .max_by_key(|vector| self.functions[vector].total)
+    }
+
+    /* Lowest address pushed to below `top` from the entry point, with the
+     * deepest interrupt handler called at the deepest point. */
+    pub fn lowest(&self) -> Option<u16> {
+        let boot = self.functions.get(&Address::new(0, ENTRY_POINT))?;
+        /* Once SP is loaded, what was pushed before is left behind. */
+        let lowest = match boot.top {
+            Some(top) => boot.lowest.map_or(top, |l| std::cmp::min(l, top)),
+            None => INITIAL_SP.saturating_sub(boot.total as u16),
+        };
+        let interrupt = self
+            .interrupt()
+            .map_or(0, |vector| 2 + self.functions[&vector].total);
+
+        Some(lowest.saturating_sub(interrupt as u16))
+    }
+
+    /* Whether `lowest` is a bound rather than the least the stack reaches. */
+    pub fn bounded(&self) -> bool {
+        let vectors = INTERRUPT_VECTORS
+            .iter()
+            .map(|&vector| Address::new(0, vector));
+        std::iter::once(Address::new(0, ENTRY_POINT))
+            .chain(vectors)
+            .filter_map(|entry| self.functions.get(&entry))
+            .all(|usage| usage.bounded)
+    }
+}
+
+/* SP relative to the one the function is entered with, or loaded by it. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sp {
+    Entry(i32),
+    Absolute(u16),
+    Unknown,
+}
+
+impl Sp {
+    fn offset(self, bytes: i32) -> Sp {
+        match self {
+            Sp::Entry(offset) => Sp::Entry(offset + bytes),
+            Sp::Absolute(sp) => Sp::Absolute(sp.wrapping_add(bytes as u16)),
+            Sp::Unknown => Sp::Unknown,
+        }
+    }
+}
+
+/* Usage of a function being analysed, with the callees on its deepest
+ * paths from its entry and from the SP it loads. */
+struct Frame {
+    usage: StackUsage,
+    deepest_entry: Option<Address>,
+    deepest_absolute: Option<Address>,
+}
+
+struct Analysis<'a> {
+    cfg: &'a ControlFlowGraph,
+    bytes: &'a [u8],
+    project: &'a Project,
+    functions: BTreeMap<Address, StackUsage>,
+    /* Functions being analysed, a call to them is recursion. */
+    active: BTreeSet<Address>,
+    /* Keyed by address so blocks shared by functions warn once. */
+    warnings: BTreeMap<Address, Warning>,
+}
+
+impl<'a> Analysis<'a> {
+    /* Follows SP from the entry through the blocks of the function. Flow into
+     * another function is a tail call to it. */
+    fn function(&mut self, entry: Address) -> StackUsage {
+        if let Some(&usage) = self.functions.get(&entry) {
+            return usage;
+        }
+        let cfg = self.cfg;
+        if !cfg.get_functions().contains_key(&entry) || !self.active.insert(entry) {
+            return StackUsage::unknown();
+        }
+
+        let mut frame = Frame {
+            usage: StackUsage {
+                own: 0,
+                total: 0,
+                bounded: true,
+                returns: false,
+                lowest: None,
+                top: None,
+                deepest: None,
+            },
+            deepest_entry: None,
+            deepest_absolute: None,
+        };
+        let mut inputs: BTreeMap<Address, Sp> = BTreeMap::new();
+        inputs.insert(entry, Sp::Entry(0));
+        let mut worklist = vec![entry];
+
+        while let Some(start) = worklist.pop() {
+            let block = match cfg.get_blocks().get(&start) {
+                Some(block) => block,
+                None => continue,
+            };
+            let mut sp = inputs[&start];
+            for (pc, inst) in block.get_instructions() {
+                sp = self.instruction(&mut frame, sp, *pc, inst);
+            }
+
+            for edge in block.get_successors() {
+                let target = edge.target();
+                if target != entry && cfg.get_functions().contains_key(&target) {
+                    let callee = self.function(target);
+                    self.reach(&mut frame, sp, target, callee, 0);
+                    if callee.returns {
+                        if let Some((pc, _)) = block.get_instructions().last() {
+                            self.leave(&mut frame, sp, *pc);
+                        }
+                    }
+                    continue;
+                }
+                match inputs.get(&target) {
+                    None => {
+                        inputs.insert(target, sp);
+                        worklist.push(target);
+                    }
+                    Some(&other) if other == sp || other == Sp::Unknown => {}
+                    Some(_) => {
+                        self.warnings
+                            .insert(target, Warning::StackMismatch { address: target });
+                        inputs.insert(target, Sp::Unknown);
+                        worklist.push(target);
+                    }
+                }
+            }
+        }
+
+        let mut usage = frame.usage;
+        usage.deepest = frame.deepest_absolute.or(frame.deepest_entry);
+        self.active.remove(&entry);
+        self.functions.insert(entry, usage);
+
+        usage
+    }
+
+    /* SP after the instruction at `pc`, recording the stack its calls use. */
+    fn instruction(&mut self, frame: &mut Frame, sp: Sp, pc: Address, inst: &Instruction) -> Sp {
+        match inst.flow(pc.addr()) {
+            Flow::Call(target) => self.call(frame, sp, pc, target),
+            Flow::Return | Flow::ConditionalReturn => self.leave(frame, sp, pc),
+            _ => {}
+        }
+
+        let sp = match (inst.mnemonic(), inst.lhs(), inst.rhs()) {
+            (Mnemonic::PUSH, _, _) => sp.offset(-2),
+            (Mnemonic::POP, _, _) => {
+                self.popped(sp, 2, pc);
+                sp.offset(2)
+            }
+            (Mnemonic::ADD, Some(Operand::Reg(Register::SP)), Some(Operand::Rel8(e))) => {
+                let bytes = *e as i8 as i32;
+                self.popped(sp, bytes, pc);
+                sp.offset(bytes)
+            }
+            (Mnemonic::INC, Some(Operand::Reg(Register::SP)), _) => sp.offset(1),
+            (Mnemonic::DEC, Some(Operand::Reg(Register::SP)), _) => sp.offset(-1),
+            (Mnemonic::LD, Some(Operand::Reg(Register::SP)), Some(rhs)) => {
+                let value = match rhs {
+                    Operand::Imm16(value) => Some(*value),
+                    Operand::Reg(Register::HL) => self.cfg.get_constants().get(pc, Register::HL),
+                    _ => None,
+                };
+                match value {
+                    Some(value) => {
+                        frame.usage.top = frame.usage.top.max(Some(value));
+                        Sp::Absolute(value)
+                    }
+                    None => {
+                        frame.usage.bounded = false;
+                        Sp::Unknown
+                    }
+                }
+            }
+            _ => sp,
+        };
+
+        match sp {
+            Sp::Entry(offset) if offset < 0 => {
+                frame.usage.own = std::cmp::max(frame.usage.own, -offset as usize);
+                frame.usage.total = std::cmp::max(frame.usage.total, -offset as usize);
+            }
+            /* Loading SP does not write to the stack yet. */
+            Sp::Absolute(value) if *inst.mnemonic() != Mnemonic::LD => {
+                frame.usage.lowest = Some(frame.usage.lowest.map_or(value, |l| l.min(value)));
+            }
+            _ => {}
+        }
+
+        sp
+    }
+
+    fn call(&mut self, frame: &mut Frame, sp: Sp, pc: Address, target: u16) {
+        let cfg = self.cfg;
+        let callee = cfg
+            .get_resolved()
+            .get(&pc)
+            .copied()
+            .or_else(|| ControlFlowGraph::target(self.bytes, self.project, pc, target));
+        let callee = match callee {
+            Some(callee) => callee,
+            None => {
+                frame.usage.bounded = false;
+                return;
+            }
+        };
+        let mut usage = self.function(callee);
+
+        /* The trampoline of a far call runs its target on top of itself. */
+        if let Some(&far) = cfg.get_far_calls().get(&pc) {
+            let target = self.function(far);
+            usage.total += target.total;
+            usage.bounded &= target.bounded;
+            usage.lowest = usage.lowest.or(target.lowest);
+        }
+
+        self.reach(frame, sp, callee, usage, 2);
+    }
+
+    /* Records the stack used by `callee` run at `sp` with `pushed` bytes on
+     * top: the return address of a call, none for a tail call. */
+    fn reach(
+        &mut self,
+        frame: &mut Frame,
+        sp: Sp,
+        callee: Address,
+        usage: StackUsage,
+        pushed: usize,
+    ) {
+        let depth = pushed + usage.total;
+        match sp {
+            Sp::Entry(offset) => {
+                let total = (depth as i32 - offset).max(0) as usize;
+                if total > frame.usage.total {
+                    frame.usage.total = total;
+                    frame.deepest_entry = Some(callee);
+                }
+            }
+            Sp::Absolute(value) => {
+                let lowest = value.saturating_sub(depth as u16);
+                if frame.usage.lowest.is_none_or(|l| lowest < l) {
+                    frame.usage.lowest = Some(lowest);
+                    frame.deepest_absolute = Some(callee);
+                }
+            }
+            Sp::Unknown => {}
+        }
+        frame.usage.top = frame.usage.top.max(usage.top);
+        if let Some(lowest) = usage.lowest {
+            if frame.usage.lowest.is_none_or(|l| lowest < l) {
+                frame.usage.lowest = Some(lowest);
+                frame.deepest_absolute = Some(callee);
+            }
+        }
+        frame.usage.bounded &= usage.bounded;
+    }
+
+    /* Returns to the caller, which needs everything pushed popped. */
+    fn leave(&mut self, frame: &mut Frame, sp: Sp, pc: Address) {
+        frame.usage.returns = true;
+        if let Sp::Entry(offset) = sp {
+            if offset != 0 {
+                self.warnings.insert(
+                    pc,
+                    Warning::UnbalancedStack {
+                        address: pc,
+                        pushed: -offset,
+                    },
+                );
+            }
+        }
+    }
+
+    /* Popping at or above the SP the function is entered with takes its
+     * return address off the stack. */
+    fn popped(&mut self, sp: Sp, bytes: i32, pc: Address) {
+        if let Sp::Entry(offset) = sp {
+            if bytes > 0 && offset + bytes > 0 {
+                self.warnings
+                    .insert(pc, Warning::ReturnAddressPopped { address: pc });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Stack of the code at each address, from the entry point and the
+     * interrupt vectors among them. */
+    fn build(code: &[(u16, &[u8])]) -> Stack {
+        let mut bytes = vec![0x00; 0x8000];
+        for &(addr, code) in code {
+            bytes[addr as usize..addr as usize + code.len()].copy_from_slice(code);
+        }
+        let entries: Vec<Address> = code
+            .iter()
+            .map(|&(addr, _)| Address::new(0, addr))
+            .filter(|entry| entry.addr() == ENTRY_POINT || entry.addr() < 0x0100)
+            .collect();
+        let project = Project::new(&bytes);
+        let cfg = ControlFlowGraph::build(&bytes, &entries, &project);
+
+        Stack::build(&cfg, &bytes, &project)
+    }
+
+    #[test]
+    fn depth() {
+        let stack = build(&[
+            (
+                0x0040,
+                &[
+                    0xF5, /* push af */
+                    0xCD, 0x60, 0x01, /* call $0160 */
+                    0xF1, /* pop af */
+                    0xD9, /* reti */
+                ],
+            ),
+            (
+                0x0100,
+                &[
+                    0x31, 0x00, 0xE0, /* ld sp, $E000 */
+                    0xCD, 0x50, 0x01, /* call $0150 */
+                    0x18, 0xFE, /* jr @ */
+                ],
+            ),
+            (
+                0x0150,
+                &[
+                    0xC5, /* push bc */
+                    0xD5, /* push de */
+                    0xCD, 0x60, 0x01, /* call $0160 */
+                    0xD1, /* pop de */
+                    0xC1, /* pop bc */
+                    0xC9, /* ret */
+                ],
+            ),
+            (
+                0x0160,
+                &[
+                    0xF5, /* push af */
+                    0xF1, /* pop af */
+                    0xC9, /* ret */
+                ],
+            ),
+        ]);
+
+        let usage = |addr| stack.get_functions()[&Address::new(0, addr)];
+        assert_eq!((usage(0x0160).own(), usage(0x0160).total()), (2, 2));
+        assert_eq!((usage(0x0150).own(), usage(0x0150).total()), (4, 8));
+        assert_eq!((usage(0x0040).own(), usage(0x0040).total()), (2, 6));
+        assert!(usage(0x0150).returns());
+        assert!(!usage(0x0100).returns());
+        assert_eq!(usage(0x0100).top(), Some(0xE000));
+        assert_eq!(usage(0x0100).lowest(), Some(0xDFF6));
+
+        assert_eq!(
+            stack.path(Address::new(0, 0x0100)),
+            [
+                Address::new(0, 0x0100),
+                Address::new(0, 0x0150),
+                Address::new(0, 0x0160)
+            ]
+        );
+        assert_eq!(stack.top(), 0xE000);
+        assert_eq!(stack.interrupt(), Some(Address::new(0, 0x0040)));
+        /* The handler and its return address under the deepest call. */
+        assert_eq!(stack.lowest(), Some(0xDFF6 - 8));
+        assert!(stack.bounded());
+        assert!(stack.get_warnings().is_empty());
+    }
+
+    #[test]
+    fn boot_stack() {
+        let stack = build(&[(
+            0x0100,
+            &[
+                0xC5, /* push bc */
+                0xC5, /* push bc */
+                0x18, 0xFE, /* jr @ */
+            ],
+        )]);
+        assert_eq!(stack.top(), INITIAL_SP);
+        assert_eq!(stack.lowest(), Some(INITIAL_SP - 4));
+
+        let stack = build(&[(
+            0x0100,
+            &[
+                0xF9, /* ld sp, hl */
+                0xC5, /* push bc */
+                0x18, 0xFE, /* jr @ */
+            ],
+        )]);
+        assert!(!stack.bounded());
+    }
+
+    #[test]
+    fn warnings() {
+        let stack = build(&[
+            (
+                0x0100,
+                &[
+                    0xCD, 0x50, 0x01, /* call $0150 */
+                    0xCD, 0x60, 0x01, /* call $0160 */
+                    0xCD, 0x70, 0x01, /* call $0170 */
+                    0x18, 0xFE, /* jr @ */
+                ],
+            ),
+            (0x0150, &[0xC5 /* push bc */, 0xC9 /* ret */]),
+            (0x0160, &[0xE1 /* pop hl */, 0xE9 /* jp hl */]),
+            (
+                0x0170,
+                &[
+                    0x28, 0x01, /* jr z, .skip */
+                    0xC5, /* push bc */
+                    0xC9, /* .skip: ret */
+                ],
+            ),
+        ]);
+
+        let warnings = stack.get_warnings();
+        assert_eq!(warnings.len(), 3, "{:?}", warnings);
+        assert!(matches!(
+            warnings[0],
+            Warning::UnbalancedStack { address, pushed: 2 } if address == Address::new(0, 0x0151)
+        ));
+        assert!(matches!(
+            warnings[1],
+            Warning::ReturnAddressPopped { address } if address == Address::new(0, 0x0160)
+        ));
+        assert!(matches!(
+            warnings[2],
+            Warning::StackMismatch { address } if address == Address::new(0, 0x0173)
+        ));
+    }
+}
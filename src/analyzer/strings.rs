@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+
+use super::address::{Address, BANK_SIZE};
+use super::charmap::Charmap;
+
+/* Shortest run of characters taken for a string. */
+pub const MIN_STRING_LENGTH: usize = 4;
+
+/* A string found in data: characters of the charmap followed by the
+ * terminator. */
+#[derive(Debug, Clone)]
+pub struct Text {
+    start: Address,
+    size: usize,
+    text: String,
+}
+
+impl Text {
+    /* Strings in `bytes` outside of the `excluded` ranges, given as start and
+     * end offsets (excluded). Strings do not cross banks. */
+    pub fn scan(
+        bytes: &[u8],
+        charmap: &Charmap,
+        terminator: u8,
+        excluded: &BTreeMap<usize, usize>,
+    ) -> Vec<Text> {
+        let excluded = |offset: usize| {
+            excluded
+                .range(..=offset)
+                .next_back()
+                .is_some_and(|(_, &end)| offset < end)
+        };
+
+        let mut texts = Vec::new();
+        let mut start = 0;
+        let mut text = String::new();
+        for (offset, &byte) in bytes.iter().enumerate() {
+            if offset % BANK_SIZE == 0 {
+                start = offset;
+                text.clear();
+            }
+            if excluded(offset) {
+                start = offset + 1;
+                text.clear();
+                continue;
+            }
+
+            if byte == terminator {
+                if offset - start >= MIN_STRING_LENGTH {
+                    texts.push(Text {
+                        start: Address::from_offset(start),
+                        size: offset + 1 - start,
+                        text: text.clone(),
+                    });
+                }
+            } else if let Some(chars) = charmap.get(byte) {
+                text.push_str(chars);
+                continue;
+            }
+            start = offset + 1;
+            text.clear();
+        }
+
+        texts
+    }
+
+    pub fn start(&self) -> Address {
+        self.start
+    }
+
+    /* Size in bytes, terminator included. */
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /* Address of the terminator. */
+    pub fn end(&self) -> Address {
+        Address::new(self.start.bank(), self.start.addr() + self.size as u16 - 1)
+    }
+
+    /* The characters, without the terminator. */
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(bytes: &[u8], excluded: &BTreeMap<usize, usize>) -> Vec<(usize, usize, String)> {
+        Text::scan(bytes, &Charmap::ascii(), 0x00, excluded)
+            .iter()
+            .map(|text| {
+                (
+                    text.start().to_offset(),
+                    text.size(),
+                    text.text().to_string(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn scan() {
+        let bytes = b"\x01HELLO\x00abc\x00\xFFWORLD!\x00TAIL";
+
+        assert_eq!(
+            texts(bytes, &BTreeMap::new()),
+            [(1, 6, "HELLO".to_string()), (12, 7, "WORLD!".to_string()),]
+        );
+
+        /* Code over the start of the first string. */
+        let excluded = [(0, 3)].iter().copied().collect();
+        assert_eq!(texts(bytes, &excluded), [(12, 7, "WORLD!".to_string())]);
+    }
+
+    #[test]
+    fn bank_boundary() {
+        let mut bytes = vec![0xFF; 2 * BANK_SIZE];
+        bytes[BANK_SIZE - 2..BANK_SIZE + 4].copy_from_slice(b"ABCDE\x00");
+        bytes[BANK_SIZE + 8..BANK_SIZE + 13].copy_from_slice(b"TEXT\x00");
+
+        let texts = texts(&bytes, &BTreeMap::new());
+        assert_eq!(texts, [(BANK_SIZE + 8, 5, "TEXT".to_string())]);
+        assert_eq!(Address::from_offset(texts[0].0), Address::new(1, 0x4008));
+    }
+}
@@ -0,0 +1,432 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+
+use super::address::Address;
+use super::control_flow::{ControlFlowGraph, EdgeKind};
+use super::disassembler::{Disassembly, Line};
+use super::error::AnalyzerError;
+use super::instruction::{Flow, Mnemonic};
+use super::project::DataType;
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    name: String,
+    auto: bool,
+}
+
+impl Symbol {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /* Generated by the analysis rather than given by the user. */
+    pub fn auto(&self) -> bool {
+        self.auto
+    }
+
+    /* `Func` for `Func.loop`, None for global labels. */
+    pub fn parent(&self) -> Option<&str> {
+        self.name.split_once('.').map(|(parent, _)| parent)
+    }
+
+    /* `.loop` for `Func.loop`, the full name for global labels. */
+    pub fn local_name(&self) -> &str {
+        match self.name.find('.') {
+            Some(dot) => &self.name[dot..],
+            None => &self.name,
+        }
+    }
+}
+
+/* Names of ROM and RAM locations, keyed by bank and CPU address. */
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    symbols: BTreeMap<Address, Symbol>,
+    /* Banks with a symbol at each CPU address, to resolve accesses whose bank
+     * is not known. */
+    banks: BTreeMap<u16, Vec<u16>>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable::default()
+    }
+
+    /* Reads `bank:addr name` lines as written by rgblink -n, bgb and no$gmb.
+     * Later names replace earlier ones at the same address. */
+    pub fn parse(&mut self, text: &str) -> Result<(), AnalyzerError> {
+        for (i, line) in text.lines().enumerate() {
+            let line = match line.find(';') {
+                Some(comment) => &line[..comment],
+                None => line,
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let invalid = || AnalyzerError::InvalidSymbol(i + 1, line.to_string());
+            let mut fields = line.split_whitespace();
+            let (bank, addr) = fields
+                .next()
+                .and_then(|location| location.split_once(':'))
+                .ok_or_else(invalid)?;
+            let name = fields.next().ok_or_else(invalid)?;
+            let bank = u16::from_str_radix(bank, 16).map_err(|_| invalid())?;
+            let addr = u16::from_str_radix(addr, 16).map_err(|_| invalid())?;
+
+            self.insert(Address::new(bank, addr), name, false);
+        }
+
+        Ok(())
+    }
+
+    pub fn insert(&mut self, address: Address, name: &str, auto: bool) {
+        let symbol = Symbol {
+            name: name.to_string(),
+            auto,
+        };
+
+        if self.symbols.insert(address, symbol).is_none() {
+            let banks = self.banks.entry(address.addr()).or_default();
+            banks.push(address.bank());
+            banks.sort_unstable();
+        }
+    }
+
+    /* Adds a generated name unless the address already has one. */
+    pub fn insert_auto(&mut self, address: Address, name: &str) {
+        if !self.symbols.contains_key(&address) {
+            self.insert(address, name, true);
+        }
+    }
+
+    pub fn get(&self, address: Address) -> Option<&Symbol> {
+        self.symbols.get(&address)
+    }
+
+    pub fn get_symbols(&self) -> &BTreeMap<Address, Symbol> {
+        &self.symbols
+    }
+
+    /* Symbol of the CPU address `addr` accessed from code in bank `bank`.
+     * When the mapped bank is unknown the name is used only if a single bank
+     * has one. */
+    pub fn lookup(&self, bank: u16, addr: u16) -> Option<&Symbol> {
+        let banked = match addr {
+            0x0000..=0x3FFF | 0xC000..=0xCFFF | 0xE000..=0xFFFF => {
+                return self.get(Address::new(0, addr))
+            }
+            0x4000..=0x7FFF if bank != 0 => return self.get(Address::new(bank, addr)),
+            _ => self.banks.get(&addr)?,
+        };
+
+        match banked.as_slice() {
+            [bank] => self.get(Address::new(*bank, addr)),
+            _ => None,
+        }
+    }
+
+    /* Names the targets of jumps, calls and pointer tables found by a linear
+     * sweep. */
+    pub fn add_disassembly_labels(&mut self, disassembly: &Disassembly) {
+        let starts: BTreeSet<usize> = disassembly
+            .get_lines()
+            .iter()
+            .map(|line| match line {
+                Line::Code { offset, .. } | Line::Data { offset, .. } => *offset,
+            })
+            .collect();
+        let code: BTreeSet<usize> = disassembly
+            .get_lines()
+            .iter()
+            .filter_map(|line| match line {
+                Line::Code { offset, .. } => Some(*offset),
+                Line::Data { .. } => None,
+            })
+            .collect();
+
+        let mut labels: BTreeMap<Address, LabelKind> = BTreeMap::new();
+        for line in disassembly.get_lines() {
+            /* Pointers name what they point to, as code or data. */
+            if let Line::Data {
+                offset,
+                bytes,
+                data_type: DataType::Pointers,
+            } = line
+            {
+                let pc = Address::from_offset(*offset);
+                for word in bytes.chunks(2) {
+                    let target = match u16::from_le_bytes([word[0], word[1]]) {
+                        target @ 0x0000..=0x3FFF => Address::new(0, target),
+                        target @ 0x4000..=0x7FFF if pc.bank() != 0 => {
+                            Address::new(pc.bank(), target)
+                        }
+                        _ => continue,
+                    };
+                    if code.contains(&target.to_offset()) {
+                        let label = labels.entry(target).or_insert(LabelKind::Jump);
+                        *label = std::cmp::max(*label, LabelKind::Jump);
+                    } else if starts.contains(&target.to_offset()) {
+                        labels.entry(target).or_insert(LabelKind::Data);
+                    }
+                }
+            }
+
+            if let Line::Code {
+                offset,
+                instruction,
+            } = line
+            {
+                let pc = Address::from_offset(*offset);
+                let (target, kind) = match instruction.flow(pc.addr()) {
+                    Flow::Call(target) => (target, LabelKind::Call),
+                    Flow::Jump(Some(target)) | Flow::Branch(target) => {
+                        match instruction.mnemonic() {
+                            Mnemonic::JP
+                            | Mnemonic::JPNZ
+                            | Mnemonic::JPZ
+                            | Mnemonic::JPNC
+                            | Mnemonic::JPC => (target, LabelKind::Jump),
+                            _ => (target, LabelKind::Relative),
+                        }
+                    }
+                    _ => continue,
+                };
+                let target = match target {
+                    0x0000..=0x3FFF => Address::new(0, target),
+                    0x4000..=0x7FFF if pc.bank() != 0 => Address::new(pc.bank(), target),
+                    _ => continue,
+                };
+
+                if starts.contains(&target.to_offset()) {
+                    let label = labels.entry(target).or_insert(kind);
+                    *label = std::cmp::max(*label, kind);
+                }
+            }
+        }
+
+        for (address, kind) in labels {
+            self.insert_auto(address, &kind.name(address));
+        }
+    }
+
+    /* Writes every symbol as `bank:addr name`, the format read by rgblink,
+     * bgb, SameBoy and Emulicious. */
+    pub fn write_sym(&self, out: &mut dyn Write) -> std::io::Result<()> {
+        writeln!(out, "; File generated by analboy")?;
+
+        for (address, symbol) in &self.symbols {
+            writeln!(out, "{} {}", address, symbol.name)?;
+        }
+
+        Ok(())
+    }
+
+    /* Names the functions and basic blocks found by flow analysis, as the
+     * listing names them: jr targets are relative labels unless something
+     * else jumps or calls there too. */
+    pub fn add_control_flow_labels(&mut self, cfg: &ControlFlowGraph) {
+        let mut labels: BTreeMap<Address, LabelKind> = BTreeMap::new();
+        for &entry in cfg.get_functions().keys() {
+            labels.insert(entry, LabelKind::Call);
+        }
+
+        for block in cfg.get_blocks().values() {
+            let relative = match block.get_instructions().last() {
+                Some((_, instruction)) => matches!(
+                    instruction.mnemonic(),
+                    Mnemonic::JR | Mnemonic::JRNZ | Mnemonic::JRZ | Mnemonic::JRNC | Mnemonic::JRC
+                ),
+                None => false,
+            };
+            for edge in block.get_successors() {
+                let kind = match edge.kind() {
+                    EdgeKind::Fallthrough => continue,
+                    EdgeKind::Jump | EdgeKind::Branch if relative => LabelKind::Relative,
+                    EdgeKind::Jump | EdgeKind::Branch | EdgeKind::Table => LabelKind::Jump,
+                };
+                let label = labels.entry(edge.target()).or_insert(kind);
+                *label = std::cmp::max(*label, kind);
+            }
+        }
+
+        for (address, kind) in labels {
+            self.insert_auto(address, &kind.name(address));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Data,
+    Relative,
+    Jump,
+    Call,
+}
+
+impl LabelKind {
+    /* Same naming as mgbdis so that listings look familiar. */
+    fn name(&self, address: Address) -> String {
+        if address.bank() == 0 {
+            let vector = match address.addr() {
+                0x0100 => Some("Boot"),
+                0x0040 => Some("VBlankInterrupt"),
+                0x0048 => Some("LCDCInterrupt"),
+                0x0050 => Some("TimerOverflowInterrupt"),
+                0x0058 => Some("SerialTransferCompleteInterrupt"),
+                0x0060 => Some("JoypadTransitionInterrupt"),
+                _ => None,
+            };
+            if let Some(vector) = vector {
+                return vector.to_string();
+            }
+            if address.addr() <= 0x0038 && address.addr() & 0x07 == 0 {
+                return format!("RST_{:02X}", address.addr());
+            }
+        }
+
+        let prefix = match self {
+            LabelKind::Data => "Data",
+            LabelKind::Relative => "jr",
+            LabelKind::Jump => "Jump",
+            LabelKind::Call => "Call",
+        };
+
+        format!("{}_{:03X}_{:04X}", prefix, address.bank(), address.addr())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::disassembler::Disassembler;
+    use crate::analyzer::project::Project;
+
+    #[test]
+    fn parse() {
+        let mut symbols = SymbolTable::new();
+        symbols
+            .parse(
+                "; File generated by rgblink\n\
+                 \n\
+                 00:0150 Main\n\
+                 00:0158 Main.loop ; local label\n\
+                 1f:4000 Banked\n\
+                 00:c000 wCounter\n\
+                 00:0150 Start\n",
+            )
+            .unwrap();
+
+        let names: Vec<(String, &str)> = symbols
+            .get_symbols()
+            .iter()
+            .map(|(address, symbol)| (address.to_string(), symbol.name()))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("00:0150".to_string(), "Start"),
+                ("00:0158".to_string(), "Main.loop"),
+                ("00:C000".to_string(), "wCounter"),
+                ("1F:4000".to_string(), "Banked"),
+            ]
+        );
+
+        let local = symbols.get(Address::new(0, 0x0158)).unwrap();
+        assert_eq!(local.parent(), Some("Main"));
+        assert_eq!(local.local_name(), ".loop");
+        assert!(!local.auto());
+    }
+
+    #[test]
+    fn invalid() {
+        for text in &["00:0150", "0150 Main", "00:01G0 Main", "10000:0150 Main"] {
+            let mut symbols = SymbolTable::new();
+            let error = symbols.parse(&format!("00:0100 Boot\n{}\n", text));
+            assert!(matches!(error, Err(AnalyzerError::InvalidSymbol(2, _))));
+        }
+    }
+
+    #[test]
+    fn write_sym() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert(Address::new(1, 0x4000), "Banked", false);
+        symbols.insert_auto(Address::new(0, 0x0100), "Boot");
+        symbols.insert(Address::new(0, 0xC000), "wCounter", false);
+
+        let mut out = Vec::new();
+        symbols.write_sym(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(
+            text,
+            "; File generated by analboy\n\
+             00:0100 Boot\n\
+             00:C000 wCounter\n\
+             01:4000 Banked\n"
+        );
+
+        let mut parsed = SymbolTable::new();
+        parsed.parse(&text).unwrap();
+        assert_eq!(parsed.get_symbols().len(), 3);
+        assert_eq!(
+            parsed.get(Address::new(1, 0x4000)).unwrap().name(),
+            "Banked"
+        );
+    }
+
+    #[test]
+    fn control_flow_labels() {
+        let mut bytes = vec![0; 0x8000];
+        let code = [
+            0xCD, 0x58, 0x01, /* call $0158 */
+            0x18, 0xFE, /* jr @ */
+            0x00, 0x00, 0x00, /* nop */
+            0x20, 0x03, /* jr nz, $015D */
+            0xC3, 0x5E, 0x01, /* jp $015E */
+            0xC9, /* ret */
+            0x18, 0xFD, /* jr $015D */
+        ];
+        bytes[0x0150..0x0150 + code.len()].copy_from_slice(&code);
+
+        let project = Project::new(&bytes);
+        let cfg = ControlFlowGraph::build(&bytes, &[Address::new(0, 0x0150)], &project);
+        let mut flow = SymbolTable::new();
+        flow.add_control_flow_labels(&cfg);
+        let names: Vec<&str> = flow.get_symbols().values().map(Symbol::name).collect();
+        assert_eq!(
+            names,
+            [
+                "Call_000_0150",
+                "jr_000_0153",
+                "Call_000_0158",
+                "jr_000_015D",
+                "Jump_000_015E"
+            ]
+        );
+
+        /* The .sym file and the listing agree on every name. */
+        let disassembly = Disassembler::disassemble(&bytes, &project).unwrap();
+        let mut listing = SymbolTable::new();
+        listing.add_disassembly_labels(&disassembly);
+        for (&address, symbol) in listing.get_symbols() {
+            assert_eq!(flow.get(address).map(Symbol::name), Some(symbol.name()));
+        }
+    }
+
+    #[test]
+    fn lookup() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert(Address::new(0, 0x0150), "Main", false);
+        symbols.insert(Address::new(1, 0x4000), "One", false);
+        symbols.insert(Address::new(2, 0x4000), "Two", false);
+        symbols.insert(Address::new(3, 0x5000), "Three", false);
+        symbols.insert_auto(Address::new(0, 0x0150), "Call_000_0150");
+
+        assert_eq!(symbols.lookup(5, 0x0150).unwrap().name(), "Main");
+        assert_eq!(symbols.lookup(2, 0x4000).unwrap().name(), "Two");
+        assert!(symbols.lookup(0, 0x4000).is_none());
+        assert_eq!(symbols.lookup(0, 0x5000).unwrap().name(), "Three");
+        assert!(symbols.lookup(1, 0x5000).is_none());
+    }
+}
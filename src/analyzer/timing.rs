@@ -0,0 +1,677 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::address::Address;
+use super::control_flow::{BasicBlock, ControlFlowGraph, Edge, EdgeKind};
+use super::instruction::{Flow, Mnemonic, Operand, Register};
+use super::project::Project;
+use super::values::Values;
+
+/* Cycles of the 10 lines of VBlank, 456 each, at normal speed. */
+pub const VBLANK_CYCLES: usize = 4560;
+/* Cycles taken to push PC and jump to an interrupt vector. */
+pub const INTERRUPT_CYCLES: usize = 20;
+const VBLANK_VECTOR: u16 = 0x0040;
+/* KEY1, written before a `stop` to switch the CGB to double speed. */
+const KEY1: u16 = 0xFF4D;
+
+/* Fewest and most cycles taken by some code. The most are unknown when it
+ * loops without a constant count, waits for an interrupt or jumps to an
+ * unknown address. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cycles {
+    min: usize,
+    max: Option<usize>,
+}
+
+impl Cycles {
+    fn exact(cycles: usize) -> Cycles {
+        Cycles {
+            min: cycles,
+            max: Some(cycles),
+        }
+    }
+
+    fn unbounded(min: usize) -> Cycles {
+        Cycles { min, max: None }
+    }
+
+    pub fn min(&self) -> usize {
+        self.min
+    }
+
+    pub fn max(&self) -> Option<usize> {
+        self.max
+    }
+
+    fn add(self, other: Cycles) -> Cycles {
+        Cycles {
+            min: self.min.saturating_add(other.min),
+            max: self.max.zip(other.max).map(|(a, b)| a.saturating_add(b)),
+        }
+    }
+
+    /* One path or the other. */
+    fn either(self, other: Cycles) -> Cycles {
+        Cycles {
+            min: std::cmp::min(self.min, other.min),
+            max: self.max.zip(other.max).map(|(a, b)| std::cmp::max(a, b)),
+        }
+    }
+
+    fn times(self, min: usize, max: Option<usize>) -> Cycles {
+        Cycles {
+            min: self.min.saturating_mul(min),
+            max: self.max.zip(max).map(|(a, b)| a.saturating_mul(b)),
+        }
+    }
+}
+
+impl std::fmt::Display for Cycles {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{}", max),
+            Some(max) => write!(f, "{}-{}", self.min, max),
+            None => write!(f, "{}-?", self.min),
+        }
+    }
+}
+
+/* A loop of a function: the blocks from its header to the blocks branching
+ * back to it, and how many times it runs when a counter decremented right
+ * before the branch back starts from a constant. */
+#[derive(Debug, Clone)]
+pub struct Loop {
+    header: Address,
+    blocks: BTreeSet<Address>,
+    latches: BTreeSet<Address>,
+    count: Option<usize>,
+    /* Only the branch back can leave the loop, so it runs `count` times. */
+    single_exit: bool,
+}
+
+impl Loop {
+    pub fn header(&self) -> Address {
+        self.header
+    }
+
+    pub fn get_blocks(&self) -> &BTreeSet<Address> {
+        &self.blocks
+    }
+
+    pub fn count(&self) -> Option<usize> {
+        self.count
+    }
+
+    /* Factors applied to the fewest and most cycles of its blocks. */
+    fn factors(&self) -> (usize, Option<usize>) {
+        match self.count {
+            Some(count) if self.single_exit => (count, Some(count)),
+            count => (1, count),
+        }
+    }
+}
+
+/* Cycles taken by the blocks and the functions found by flow analysis. */
+#[derive(Debug)]
+pub struct Timing {
+    blocks: BTreeMap<Address, Cycles>,
+    functions: BTreeMap<Address, Option<Cycles>>,
+    loops: BTreeMap<Address, Loop>,
+    double_speed: bool,
+}
+
+impl Timing {
+    pub fn build(cfg: &ControlFlowGraph, bytes: &[u8], project: &Project) -> Timing {
+        let blocks = cfg
+            .get_blocks()
+            .iter()
+            .map(|(&start, block)| {
+                let cycles = block
+                    .get_instructions()
+                    .iter()
+                    .map(|(_, inst)| {
+                        let (not_taken, taken) = inst.branch_cycles();
+                        Cycles {
+                            min: not_taken,
+                            max: Some(taken),
+                        }
+                    })
+                    .fold(Cycles::exact(0), Cycles::add);
+                (start, cycles)
+            })
+            .collect();
+
+        let mut analysis = Analysis {
+            cfg,
+            bytes,
+            project,
+            functions: BTreeMap::new(),
+            active: BTreeSet::new(),
+            loops: BTreeMap::new(),
+        };
+        for &entry in cfg.get_functions().keys() {
+            analysis.function(entry);
+        }
+
+        /* The speed switch is armed through KEY1 and done by a `stop`. */
+        let instructions = || {
+            cfg.get_blocks()
+                .values()
+                .flat_map(|block| block.get_instructions().iter().map(|(_, inst)| inst))
+        };
+        let double_speed = instructions().any(|inst| Values::store(inst) == Some(KEY1))
+            && instructions().any(|inst| *inst.mnemonic() == Mnemonic::STOP);
+
+        Timing {
+            blocks,
+            functions: analysis.functions,
+            loops: analysis.loops,
+            double_speed,
+        }
+    }
+
+    /* Cycles of the instructions of each block, calls not followed. */
+    pub fn get_blocks(&self) -> &BTreeMap<Address, Cycles> {
+        &self.blocks
+    }
+
+    /* Cycles from the entry of each function to its return, callees
+     * included. `None` for functions that never return. */
+    pub fn get_functions(&self) -> &BTreeMap<Address, Option<Cycles>> {
+        &self.functions
+    }
+
+    /* Loops keyed by their header. */
+    pub fn get_loops(&self) -> &BTreeMap<Address, Loop> {
+        &self.loops
+    }
+
+    /* Whether the code switches a CGB to double speed. */
+    pub fn double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    /* Cycles of the VBlank interrupt, from its dispatch to the `reti`. */
+    pub fn vblank(&self) -> Option<Cycles> {
+        let handler = self.functions.get(&Address::new(0, VBLANK_VECTOR))?;
+        handler.map(|cycles| cycles.add(Cycles::exact(INTERRUPT_CYCLES)))
+    }
+
+    /* Cycles the CPU runs during VBlank, twice as many in double speed. */
+    pub fn vblank_budget(&self) -> usize {
+        if self.double_speed {
+            2 * VBLANK_CYCLES
+        } else {
+            VBLANK_CYCLES
+        }
+    }
+}
+
+/* How a block is left: an edge of the control flow graph, or leaving its
+ * function. */
+#[derive(Clone, Copy)]
+enum Exit {
+    Edge(EdgeKind),
+    Return { taken: bool },
+    Unknown,
+}
+
+struct Analysis<'a> {
+    cfg: &'a ControlFlowGraph,
+    bytes: &'a [u8],
+    project: &'a Project,
+    functions: BTreeMap<Address, Option<Cycles>>,
+    /* Functions being analysed, a call to them is recursion. */
+    active: BTreeSet<Address>,
+    loops: BTreeMap<Address, Loop>,
+}
+
+impl<'a> Analysis<'a> {
+    /* Cycles of the paths from the entry to the exits of the function, with
+     * the blocks of loops counted as many times as the loops run. */
+    fn function(&mut self, entry: Address) -> Option<Cycles> {
+        if let Some(&cycles) = self.functions.get(&entry) {
+            return cycles;
+        }
+        let function = match self.cfg.get_functions().get(&entry) {
+            Some(function) => function,
+            None => return Some(Cycles::unbounded(0)),
+        };
+        if !self.active.insert(entry) {
+            return Some(Cycles::unbounded(0));
+        }
+
+        let blocks = function.get_blocks();
+        let back_edges = self.back_edges(entry, blocks);
+        let loops = self.loops(entry, blocks, &back_edges);
+
+        /* Blocks in topological order once the back edges are removed. */
+        let mut incoming: BTreeMap<Address, usize> = blocks.iter().map(|&b| (b, 0)).collect();
+        for &start in blocks {
+            for edge in self.successors(start) {
+                if !back_edges.contains(&(start, edge.target())) {
+                    *incoming.entry(edge.target()).or_default() += 1;
+                }
+            }
+        }
+        let mut ready = vec![entry];
+        let mut paths: BTreeMap<Address, Cycles> = BTreeMap::new();
+        paths.insert(entry, Cycles::exact(0));
+        let mut result: Option<Cycles> = None;
+
+        while let Some(start) = ready.pop() {
+            let path = paths[&start];
+            let block = &self.cfg.get_blocks()[&start];
+            let (min_factor, max_factor) = loops.iter().filter(|l| l.blocks.contains(&start)).fold(
+                (1, Some(1)),
+                |(min, max), l| {
+                    let (l_min, l_max) = l.factors();
+                    (min * l_min, max.zip(l_max).map(|(a, b)| a * b))
+                },
+            );
+
+            for (exit, target) in self.exits(block) {
+                let mut cycles = self.block(block, exit);
+                /* The branch back of a counted loop is taken one time less
+                 * than the loop runs, then the loop is left. */
+                let latch = loops.iter().find(|l| {
+                    l.latches.contains(&start) && target.is_none_or(|t| !l.blocks.contains(&t))
+                });
+                cycles = match latch.map(Loop::factors) {
+                    Some((l_min, Some(l_max))) => {
+                        let back = self.block(block, Exit::Edge(EdgeKind::Branch));
+                        back.times(l_min - 1, Some(l_max - 1))
+                            .add(cycles)
+                            .times(min_factor / l_min, max_factor.map(|m| m / l_max))
+                    }
+                    _ => cycles.times(min_factor, max_factor),
+                };
+                let cycles = path.add(cycles);
+
+                match target {
+                    Some(target) => {
+                        if back_edges.contains(&(start, target)) {
+                            continue;
+                        }
+                        let cycles = match paths.get(&target) {
+                            Some(&other) => other.either(cycles),
+                            None => cycles,
+                        };
+                        paths.insert(target, cycles);
+                        let count = incoming.get_mut(&target).unwrap();
+                        *count -= 1;
+                        if *count == 0 {
+                            ready.push(target);
+                        }
+                    }
+                    None => {
+                        let cycles = match exit {
+                            Exit::Unknown => Cycles::unbounded(cycles.min),
+                            _ => cycles,
+                        };
+                        result = Some(match result {
+                            Some(other) => other.either(cycles),
+                            None => cycles,
+                        });
+                    }
+                }
+            }
+        }
+
+        for l in loops {
+            self.loops.insert(l.header, l);
+        }
+        self.active.remove(&entry);
+        self.functions.insert(entry, result);
+
+        result
+    }
+
+    fn successors(&self, start: Address) -> &'a [Edge] {
+        match self.cfg.get_blocks().get(&start) {
+            Some(block) => block.get_successors(),
+            None => &[],
+        }
+    }
+
+    /* Ways of leaving `block`, with the block reached. */
+    fn exits(&self, block: &BasicBlock) -> Vec<(Exit, Option<Address>)> {
+        let mut exits: Vec<(Exit, Option<Address>)> = block
+            .get_successors()
+            .iter()
+            .map(|edge| (Exit::Edge(edge.kind()), Some(edge.target())))
+            .collect();
+        let (pc, last) = match block.get_instructions().last() {
+            Some((pc, last)) => (pc, last),
+            None => return exits,
+        };
+
+        match last.flow(pc.addr()) {
+            Flow::Return => exits.push((Exit::Return { taken: false }, None)),
+            Flow::ConditionalReturn => exits.push((Exit::Return { taken: true }, None)),
+            _ if exits.is_empty() => exits.push((Exit::Unknown, None)),
+            _ => {}
+        }
+
+        exits
+    }
+
+    /* Cycles of `block` left through `exit`, callees included. The last
+     * instruction takes its branch for taken branches and returns. */
+    fn block(&mut self, block: &BasicBlock, exit: Exit) -> Cycles {
+        let taken = match exit {
+            Exit::Edge(EdgeKind::Fallthrough) => false,
+            Exit::Return { taken } => taken,
+            _ => true,
+        };
+        let instructions = block.get_instructions();
+        let mut cycles = Cycles::exact(0);
+
+        for (i, (pc, inst)) in instructions.iter().enumerate() {
+            let (not_taken, taken_cycles) = inst.branch_cycles();
+            let flow = inst.flow(pc.addr());
+            let last = i + 1 == instructions.len();
+
+            cycles = cycles.add(match flow {
+                Flow::Branch(_) | Flow::ConditionalReturn if last => {
+                    Cycles::exact(if taken { taken_cycles } else { not_taken })
+                }
+                Flow::Call(target) => {
+                    let mut callee = self.callee(*pc, target);
+                    if let Some(&far) = self.cfg.get_far_calls().get(pc) {
+                        callee = callee.add(self.called(far));
+                    }
+                    Cycles {
+                        min: not_taken,
+                        max: Some(taken_cycles),
+                    }
+                    .add(Cycles {
+                        /* A conditional call may be skipped. */
+                        min: if not_taken == taken_cycles {
+                            callee.min
+                        } else {
+                            0
+                        },
+                        max: callee.max,
+                    })
+                }
+                /* Waits for an interrupt. */
+                _ if matches!(inst.mnemonic(), Mnemonic::HALT | Mnemonic::STOP) => {
+                    Cycles::unbounded(not_taken)
+                }
+                _ => Cycles {
+                    min: not_taken,
+                    max: Some(taken_cycles),
+                },
+            });
+        }
+
+        cycles
+    }
+
+    fn callee(&mut self, pc: Address, target: u16) -> Cycles {
+        match ControlFlowGraph::target(self.bytes, self.project, pc, target) {
+            Some(callee) => self.called(callee),
+            None => Cycles::unbounded(0),
+        }
+    }
+
+    /* Cycles of a called function, unknown when it does not return. */
+    fn called(&mut self, entry: Address) -> Cycles {
+        self.function(entry).unwrap_or(Cycles::unbounded(0))
+    }
+
+    /* Edges to a block being visited by a depth-first search from the
+     * entry. */
+    fn back_edges(
+        &self,
+        entry: Address,
+        blocks: &BTreeSet<Address>,
+    ) -> BTreeSet<(Address, Address)> {
+        let mut back_edges = BTreeSet::new();
+        let mut visited = BTreeSet::new();
+        let mut on_stack = BTreeSet::new();
+        let mut stack = vec![(entry, 0)];
+        visited.insert(entry);
+        on_stack.insert(entry);
+
+        while let Some((start, next)) = stack.pop() {
+            let successors = self.successors(start);
+            match successors.get(next) {
+                Some(edge) => {
+                    stack.push((start, next + 1));
+                    let target = edge.target();
+                    if !blocks.contains(&target) {
+                        continue;
+                    }
+                    if on_stack.contains(&target) {
+                        back_edges.insert((start, target));
+                    } else if visited.insert(target) {
+                        on_stack.insert(target);
+                        stack.push((target, 0));
+                    }
+                }
+                None => {
+                    on_stack.remove(&start);
+                }
+            }
+        }
+
+        back_edges
+    }
+
+    /* Natural loops of the back edges, one per header. */
+    fn loops(
+        &self,
+        entry: Address,
+        blocks: &BTreeSet<Address>,
+        back_edges: &BTreeSet<(Address, Address)>,
+    ) -> Vec<Loop> {
+        let mut predecessors: BTreeMap<Address, Vec<Address>> = BTreeMap::new();
+        for &start in blocks {
+            for edge in self.successors(start) {
+                predecessors.entry(edge.target()).or_default().push(start);
+            }
+        }
+
+        let mut loops: BTreeMap<Address, Loop> = BTreeMap::new();
+        for &(latch, header) in back_edges {
+            let l = loops.entry(header).or_insert_with(|| Loop {
+                header,
+                blocks: std::iter::once(header).collect(),
+                latches: BTreeSet::new(),
+                count: None,
+                single_exit: false,
+            });
+            l.latches.insert(latch);
+
+            let mut worklist = vec![latch];
+            while let Some(start) = worklist.pop() {
+                if l.blocks.insert(start) {
+                    worklist.extend(predecessors.get(&start).into_iter().flatten());
+                }
+            }
+        }
+
+        for l in loops.values_mut() {
+            if let [latch] = *l.latches.iter().collect::<Vec<_>>() {
+                l.count = self.count(entry, l, *latch, &predecessors);
+            }
+            l.single_exit = l.blocks.iter().all(|start| {
+                let block = &self.cfg.get_blocks()[start];
+                self.exits(block).iter().all(|(_, target)| {
+                    target.is_some_and(|t| l.blocks.contains(&t)) || l.latches.contains(start)
+                })
+            });
+        }
+
+        loops.into_values().collect()
+    }
+
+    /* Times a loop runs when its latch ends with `dec r` and `jr nz`, or with
+     * `dec rr`, `ld a,r`, `or r` and `jr nz`, and the counter is a constant
+     * in every block entering the loop and not written by the loop
+     * otherwise. */
+    fn count(
+        &self,
+        entry: Address,
+        l: &Loop,
+        latch: Address,
+        predecessors: &BTreeMap<Address, Vec<Address>>,
+    ) -> Option<usize> {
+        let block = &self.cfg.get_blocks()[&latch];
+        let instructions = block.get_instructions();
+        let (_, branch) = instructions.last()?;
+        let branches_back = block
+            .get_successors()
+            .iter()
+            .any(|edge| edge.kind() == EdgeKind::Branch && edge.target() == l.header);
+        if !matches!(branch.mnemonic(), Mnemonic::JRNZ | Mnemonic::JPNZ) || !branches_back {
+            return None;
+        }
+
+        let mut before = instructions.iter().rev().skip(1).map(|(_, inst)| inst);
+        let (counter, wrap) = match before.next().map(|inst| (inst.mnemonic(), inst.lhs())) {
+            Some((Mnemonic::DEC, Some(Operand::Reg(reg))))
+                if !matches!(
+                    reg,
+                    Register::AF | Register::BC | Register::DE | Register::HL | Register::SP
+                ) =>
+            {
+                (*reg, 0x100)
+            }
+            Some((Mnemonic::OR, Some(Operand::Reg(low)))) => {
+                let high = match before
+                    .next()
+                    .map(|inst| (inst.mnemonic(), inst.lhs(), inst.rhs()))
+                {
+                    Some((
+                        Mnemonic::LD,
+                        Some(Operand::Reg(Register::A)),
+                        Some(Operand::Reg(high)),
+                    )) => *high,
+                    _ => return None,
+                };
+                let pair = match (high, low) {
+                    (Register::B, Register::C) => Register::BC,
+                    (Register::D, Register::E) => Register::DE,
+                    (Register::H, Register::L) => Register::HL,
+                    _ => return None,
+                };
+                (pair, 0x10000)
+            }
+            _ => return None,
+        };
+
+        /* Decremented once per pass and written by nothing else. */
+        let mut decrements = 0;
+        for start in &l.blocks {
+            for (_, inst) in self.cfg.get_blocks()[start].get_instructions() {
+                if *inst.mnemonic() == Mnemonic::DEC && inst.lhs() == Some(&Operand::Reg(counter)) {
+                    decrements += 1;
+                } else if inst.written().iter().any(|reg| reg.overlaps(counter)) {
+                    return None;
+                }
+            }
+        }
+        if decrements != 1 || l.header == entry {
+            return None;
+        }
+
+        let mut count = None;
+        for start in predecessors.get(&l.header)? {
+            if l.blocks.contains(start) {
+                continue;
+            }
+            let mut values = Values::default();
+            for (_, inst) in self.cfg.get_blocks()[start].get_instructions() {
+                values.step(inst);
+            }
+            let value = values.get(counter)?;
+            if count.is_some_and(|count| count != value) {
+                return None;
+            }
+            count = Some(value);
+        }
+
+        count.map(|count| if count == 0 { wrap } else { count as usize })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timing(code: &[(usize, &[u8])], entries: &[u16]) -> Timing {
+        let mut bytes = vec![0x00; 0x8000];
+        for (start, code) in code {
+            bytes[*start..*start + code.len()].copy_from_slice(code);
+        }
+        let entries: Vec<Address> = entries.iter().map(|&addr| Address::new(0, addr)).collect();
+        let project = Project::new(&bytes);
+        let cfg = ControlFlowGraph::build(&bytes, &entries, &project);
+
+        Timing::build(&cfg, &bytes, &project)
+    }
+
+    fn function(timing: &Timing, entry: u16) -> Option<String> {
+        timing.get_functions()[&Address::new(0, entry)].map(|cycles| cycles.to_string())
+    }
+
+    #[test]
+    fn functions() {
+        let timing = timing(
+            &[
+                (0x0040, &[0xD9]), /* reti */
+                /* call Nop, ret */
+                (0x0100, &[0xCD, 0x50, 0x01, 0xC9]),
+                /* nop, ret */
+                (0x0150, &[0x00, 0xC9]),
+                /* and a, ret z, ld a, 1, ret */
+                (0x0160, &[0xA7, 0xC8, 0x3E, 0x01, 0xC9]),
+                /* ld b, 4, .loop dec b, jr nz, .loop, ret */
+                (0x0170, &[0x06, 0x04, 0x05, 0x20, 0xFD, 0xC9]),
+                /* .loop ldh a, [rLY], jr .loop */
+                (0x0180, &[0xF0, 0x44, 0x18, 0xFC]),
+            ],
+            &[0x0040, 0x0100, 0x0160, 0x0170, 0x0180],
+        );
+
+        assert_eq!(function(&timing, 0x0150).as_deref(), Some("20"));
+        assert_eq!(function(&timing, 0x0100).as_deref(), Some("60"));
+        assert_eq!(function(&timing, 0x0160).as_deref(), Some("24-36"));
+        assert_eq!(function(&timing, 0x0170).as_deref(), Some("84"));
+        assert_eq!(function(&timing, 0x0180), None);
+        assert_eq!(
+            timing.get_blocks()[&Address::new(0, 0x0160)].to_string(),
+            "12-24"
+        );
+
+        let loops: Vec<(Address, Option<usize>)> = timing
+            .get_loops()
+            .values()
+            .map(|l| (l.header(), l.count()))
+            .collect();
+        assert!(loops.contains(&(Address::new(0, 0x0172), Some(4))));
+
+        assert_eq!(
+            timing.vblank().map(|cycles| cycles.to_string()).as_deref(),
+            Some("36")
+        );
+        assert!(!timing.double_speed());
+        assert_eq!(timing.vblank_budget(), VBLANK_CYCLES);
+    }
+
+    #[test]
+    fn double_speed() {
+        let timing = timing(
+            /* ld a, 1, ldh [rKEY1], a, stop, halt, ret */
+            &[(0x0100, &[0x3E, 0x01, 0xE0, 0x4D, 0x10, 0x00, 0x76, 0xC9])],
+            &[0x0100],
+        );
+
+        assert!(timing.double_speed());
+        assert_eq!(timing.vblank_budget(), 2 * VBLANK_CYCLES);
+        assert_eq!(function(&timing, 0x0100).as_deref(), Some("44-?"));
+    }
+}
@@ -0,0 +1,346 @@
+use std::collections::BTreeSet;
+
+use super::address::{Address, BANK_SIZE};
+use super::error::AnalyzerError;
+use super::instruction::{Flow, Instruction};
+use super::project::{DataType, TypedRange};
+use super::warning::Warning;
+
+/* Flags of each ROM byte in a code/data log. */
+const CDL_CODE: u8 = 0x01;
+const CDL_DATA: u8 = 0x02;
+
+/* What an emulator saw while running the ROM: the instructions it executed
+ * and the bytes it read as data. */
+#[derive(Debug, Default)]
+pub struct Trace {
+    executed: BTreeSet<Address>,
+    read: BTreeSet<Address>,
+    warnings: Vec<Warning>,
+}
+
+impl Trace {
+    /* A code/data log has a byte per ROM byte, with bit 0 set for code and
+     * bit 1 for data. Any other file is a text trace with a line per
+     * instruction: Gameboy Doctor logs with `PC:` and `PCMEM:`, or lines
+     * starting with the `bank:address` of the instruction, like `01:4A00` or
+     * `ROM1:4A00`. Other lines are skipped. */
+    pub fn parse(file: &[u8], rom: &[u8]) -> Result<Trace, AnalyzerError> {
+        let trace = if file.len() == rom.len() {
+            Trace::from_cdl(file, rom)
+        } else {
+            Trace::from_text(&String::from_utf8_lossy(file), rom)
+        };
+
+        if trace.executed.is_empty() {
+            return Err(AnalyzerError::InvalidTrace(
+                "no executed ROM address found".to_string(),
+            ));
+        }
+        Ok(trace)
+    }
+
+    fn from_cdl(file: &[u8], rom: &[u8]) -> Trace {
+        let mut trace = Trace::default();
+
+        /* Logs flag every byte of an instruction, the starts are found by
+         * decoding each run of code from its first byte. */
+        let mut offset = 0;
+        while offset < file.len() {
+            let flags = file[offset];
+            if flags & CDL_CODE == 0 {
+                if flags & CDL_DATA != 0 {
+                    trace.read.insert(Address::from_offset(offset));
+                }
+                offset += 1;
+                continue;
+            }
+
+            trace.executed.insert(Address::from_offset(offset));
+            let bank_end = std::cmp::min((offset / BANK_SIZE + 1) * BANK_SIZE, rom.len());
+            offset += match Instruction::from_slice(&rom[offset..bank_end]) {
+                Ok(inst) => inst.size(),
+                Err(_) => 1,
+            };
+        }
+
+        trace
+    }
+
+    fn from_text(text: &str, rom: &[u8]) -> Trace {
+        let mut trace = Trace::default();
+        let mut unknown = BTreeSet::new();
+        let mut bank = 1;
+
+        for line in text.lines() {
+            let address = match Trace::doctor_line(line) {
+                Some((addr, pcmem)) => match Trace::find_bank(rom, addr, &pcmem, bank) {
+                    Some(address) => address,
+                    None => {
+                        unknown.insert(addr);
+                        continue;
+                    }
+                },
+                None => match Trace::address(line) {
+                    Some(address) => address,
+                    None => continue,
+                },
+            };
+            if address.to_offset() >= rom.len() {
+                continue;
+            }
+            if address.bank() != 0 {
+                bank = address.bank();
+            }
+            trace.executed.insert(address);
+        }
+
+        trace.warnings = unknown
+            .into_iter()
+            .map(|target| Warning::UnknownTraceBank { target })
+            .collect();
+        trace
+    }
+
+    /* PC and the bytes at PC of a Gameboy Doctor line. */
+    fn doctor_line(line: &str) -> Option<(u16, Vec<u8>)> {
+        let field = |name: &str| {
+            line.split_whitespace()
+                .find_map(|token| token.strip_prefix(name))
+        };
+        let pc = u16::from_str_radix(field("PC:")?, 16).ok()?;
+        let pcmem = field("PCMEM:")?
+            .split(',')
+            .map(|byte| u8::from_str_radix(byte, 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+
+        Some((pc, pcmem))
+    }
+
+    /* The bank of a switchable ROM address whose bytes are `pcmem`: the bank
+     * of the previous lines if it matches, else the only one matching. */
+    fn find_bank(rom: &[u8], addr: u16, pcmem: &[u8], last: u16) -> Option<Address> {
+        match addr {
+            0x0000..=0x3FFF => return Some(Address::new(0, addr)),
+            0x4000..=0x7FFF => {}
+            _ => return None,
+        }
+
+        let banks: Vec<u16> = (1..(rom.len() / BANK_SIZE) as u16)
+            .filter(|&bank| {
+                let offset = Address::new(bank, addr).to_offset();
+                rom.get(offset..offset + pcmem.len()) == Some(pcmem)
+            })
+            .collect();
+        let bank = match banks.as_slice() {
+            _ if banks.contains(&last) => last,
+            [bank] => *bank,
+            _ => return None,
+        };
+
+        Some(Address::new(bank, addr))
+    }
+
+    /* `bank:address` at the start of a line, with an optional `$` and
+     * letters before the bank. */
+    fn address(line: &str) -> Option<Address> {
+        let token = line.split_whitespace().next()?.trim_start_matches('$');
+        let (bank, addr) = token.split_once(':')?;
+        let bank = bank.trim_start_matches(|c: char| c.is_ascii_alphabetic());
+        let addr = addr.get(..4)?;
+        let bank = u16::from_str_radix(bank, 16).ok()?;
+        let addr = u16::from_str_radix(addr, 16).ok()?;
+
+        match addr {
+            0x0000..=0x3FFF => Some(Address::new(0, addr)),
+            0x4000..=0x7FFF if bank != 0 => Some(Address::new(bank, addr)),
+            _ => None,
+        }
+    }
+
+    /* Starts of the instructions executed. */
+    pub fn get_executed(&self) -> &BTreeSet<Address> {
+        &self.executed
+    }
+
+    /* Bytes read as data and never executed. */
+    pub fn get_read(&self) -> &BTreeSet<Address> {
+        &self.read
+    }
+
+    pub fn get_warnings(&self) -> &Vec<Warning> {
+        &self.warnings
+    }
+
+    /* Code ranges of the executed instructions. A range ends where the
+     * instructions stop being contiguous or after a jump or a return, so
+     * that code reached only through computed jumps gets its own entry. */
+    pub fn code_ranges(&self, rom: &[u8]) -> Vec<TypedRange> {
+        let mut ranges = Vec::new();
+        let mut current: Option<(Address, Address, bool)> = None;
+
+        for &start in &self.executed {
+            let offset = start.to_offset();
+            let bank_end = std::cmp::min((offset / BANK_SIZE + 1) * BANK_SIZE, rom.len());
+            let inst = match Instruction::from_slice(&rom[offset..bank_end]) {
+                Ok(inst) => inst,
+                Err(_) => continue,
+            };
+            let end = Address::new(start.bank(), start.addr() + inst.size() as u16 - 1);
+            let falls_through = !matches!(inst.flow(start.addr()), Flow::Jump(_) | Flow::Return);
+
+            current = match current {
+                /* Overlapping or following an instruction falling through. */
+                Some((first, last, through))
+                    if first.bank() == start.bank()
+                        && (start <= last || (through && start.addr() == last.addr() + 1)) =>
+                {
+                    Some((first, std::cmp::max(last, end), falls_through))
+                }
+                previous => {
+                    if let Some((first, last, _)) = previous {
+                        ranges.push(TypedRange::new(first, last, DataType::Code(None)));
+                    }
+                    Some((start, end, falls_through))
+                }
+            };
+        }
+        if let Some((first, last, _)) = current {
+            ranges.push(TypedRange::new(first, last, DataType::Code(None)));
+        }
+
+        ranges
+    }
+
+    /* Byte ranges of the contiguous bytes read as data. */
+    pub fn data_ranges(&self) -> Vec<TypedRange> {
+        let mut ranges = Vec::new();
+        let mut current: Option<(Address, Address)> = None;
+
+        for &address in &self.read {
+            current = match current {
+                Some((first, last))
+                    if last.bank() == address.bank() && last.addr() + 1 == address.addr() =>
+                {
+                    Some((first, address))
+                }
+                previous => {
+                    if let Some((first, last)) = previous {
+                        ranges.push(TypedRange::new(first, last, DataType::Bytes));
+                    }
+                    Some((address, address))
+                }
+            };
+        }
+        if let Some((first, last)) = current {
+            ranges.push(TypedRange::new(first, last, DataType::Bytes));
+        }
+
+        ranges
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cdl() {
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0101..0x0104].copy_from_slice(&[0xC3, 0x50, 0x01]); /* jp $0150 */
+        rom[0x0150] = 0xC9;
+        let mut cdl = vec![0x00; rom.len()];
+        cdl[0x0100..0x0104].fill(CDL_CODE);
+        cdl[0x0150] = CDL_CODE;
+        cdl[0x0200..0x0204].fill(CDL_DATA);
+        cdl[0x0210] = CDL_DATA;
+
+        let trace = Trace::parse(&cdl, &rom).unwrap();
+        let executed: Vec<u16> = trace.get_executed().iter().map(|a| a.addr()).collect();
+        assert_eq!(executed, [0x0100, 0x0101, 0x0150]);
+        assert_eq!(trace.get_read().len(), 5);
+
+        let ranges = |ranges: Vec<TypedRange>| -> Vec<(u16, u16)> {
+            ranges
+                .iter()
+                .map(|range| (range.start().addr(), range.end().addr()))
+                .collect()
+        };
+        assert_eq!(
+            ranges(trace.code_ranges(&rom)),
+            [(0x0100, 0x0103), (0x0150, 0x0150)]
+        );
+        assert_eq!(
+            ranges(trace.data_ranges()),
+            [(0x0200, 0x0203), (0x0210, 0x0210)]
+        );
+    }
+
+    #[test]
+    fn text() {
+        let rom = vec![0x00; 4 * BANK_SIZE];
+        let trace = Trace::parse(
+            b"01:4000 nop\n\
+              ROM2:4010 ld a, b\n\
+              $00:0150\n\
+              00:C000 in RAM\n\
+              garbage\n",
+            &rom,
+        )
+        .unwrap();
+
+        let executed: Vec<Address> = trace.get_executed().iter().copied().collect();
+        assert_eq!(
+            executed,
+            [
+                Address::new(0, 0x0150),
+                Address::new(1, 0x4000),
+                Address::new(2, 0x4010),
+            ]
+        );
+        assert!(Trace::parse(b"nothing here\n", &rom).is_err());
+    }
+
+    #[test]
+    fn doctor() {
+        let mut rom = vec![0x00; 4 * BANK_SIZE];
+        let mut put = |bank: u16, addr: u16, bytes: &[u8]| {
+            let offset = Address::new(bank, addr).to_offset();
+            rom[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+        put(2, 0x4010, &[0x01, 0x02, 0x03, 0x04]);
+        put(2, 0x4000, &[0xAB, 0xCD, 0xEF, 0x12]);
+        put(3, 0x4000, &[0xAB, 0xCD, 0xEF, 0x12]);
+        put(1, 0x4020, &[0x77; 4]);
+        put(3, 0x4020, &[0x77; 4]);
+
+        let line = |pc: &str, pcmem: &str| {
+            format!(
+                "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:{} PCMEM:{}\n",
+                pc, pcmem
+            )
+        };
+        let log = [
+            line("0150", "00,00,00,00"),
+            line("4010", "01,02,03,04"),
+            line("4000", "AB,CD,EF,12"),
+            line("4020", "77,77,77,77"),
+        ]
+        .concat();
+
+        let trace = Trace::parse(log.as_bytes(), &rom).unwrap();
+        let executed: Vec<Address> = trace.get_executed().iter().copied().collect();
+        assert_eq!(
+            executed,
+            [
+                Address::new(0, 0x0150),
+                Address::new(2, 0x4000),
+                Address::new(2, 0x4010),
+            ]
+        );
+        match trace.get_warnings()[..] {
+            [Warning::UnknownTraceBank { target: 0x4020 }] => {}
+            ref warnings => panic!("unexpected warnings {:?}", warnings),
+        }
+    }
+}
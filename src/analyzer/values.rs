@@ -0,0 +1,290 @@
+use std::collections::BTreeMap;
+
+use super::instruction::{Instruction, Mnemonic, Operand, Register};
+
+/* Constants known in the 8-bit registers and SP while going through code,
+ * and the last ROM bank selected. */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Values {
+    registers: BTreeMap<Register, u8>,
+    sp: Option<u16>,
+    bank: Option<u8>,
+}
+
+impl Values {
+    pub fn bank(&self) -> Option<u8> {
+        self.bank
+    }
+
+    pub fn a(&self) -> Option<u8> {
+        self.registers.get(&Register::A).copied()
+    }
+
+    pub fn get(&self, reg: Register) -> Option<u16> {
+        let byte = |reg: Register| self.registers.get(&reg).copied();
+        let (high, low) = match reg {
+            Register::BC => (Register::B, Register::C),
+            Register::DE => (Register::D, Register::E),
+            Register::HL => (Register::H, Register::L),
+            Register::SP => return self.sp,
+            reg => return byte(reg).map(u16::from),
+        };
+
+        Some(u16::from_be_bytes([byte(high)?, byte(low)?]))
+    }
+
+    pub fn set(&mut self, reg: Register, value: u16) {
+        let [high, low] = value.to_be_bytes();
+        match reg {
+            Register::BC => self
+                .registers
+                .extend([(Register::B, high), (Register::C, low)]),
+            Register::DE => self
+                .registers
+                .extend([(Register::D, high), (Register::E, low)]),
+            Register::HL => self
+                .registers
+                .extend([(Register::H, high), (Register::L, low)]),
+            Register::A
+            | Register::B
+            | Register::C
+            | Register::D
+            | Register::E
+            | Register::H
+            | Register::L => {
+                self.registers.insert(reg, low);
+            }
+            Register::SP => self.sp = Some(value),
+            _ => {}
+        }
+    }
+
+    /* The values both `self` and `other` agree on, where flow from them
+     * meets. */
+    pub fn join(&self, other: &Values) -> Values {
+        Values {
+            registers: self
+                .registers
+                .iter()
+                .filter(|(reg, value)| other.registers.get(reg) == Some(value))
+                .map(|(&reg, &value)| (reg, value))
+                .collect(),
+            sp: self.sp.filter(|&sp| other.sp == Some(sp)),
+            bank: self.bank.filter(|&bank| other.bank == Some(bank)),
+        }
+    }
+
+    /* Value of an 8-bit or 16-bit operand. */
+    fn operand(&self, operand: Option<&Operand>) -> Option<u16> {
+        match operand? {
+            Operand::Imm8(value) => Some(*value as u16),
+            Operand::Imm16(value) => Some(*value),
+            Operand::Reg(reg) => self.get(*reg),
+            _ => None,
+        }
+    }
+
+    /* Width mask of `reg`. */
+    fn mask(reg: Register) -> u16 {
+        match reg {
+            Register::BC | Register::DE | Register::HL | Register::SP => 0xFFFF,
+            _ => 0xFF,
+        }
+    }
+
+    /* Address written by `inst` when it stores A at a constant address. */
+    pub fn store(inst: &Instruction) -> Option<u16> {
+        match (inst.mnemonic(), inst.lhs(), inst.rhs()) {
+            (Mnemonic::LD, Some(Operand::DerefAddr16(addr)), Some(Operand::Reg(Register::A))) => {
+                Some(*addr)
+            }
+            (Mnemonic::LDHL, Some(Operand::DerefAddr8(addr)), _) => Some(0xFF00 | *addr as u16),
+            _ => None,
+        }
+    }
+
+    pub fn step(&mut self, inst: &Instruction) {
+        if let Some(0x2000..=0x3FFF) = Values::store(inst) {
+            self.bank = self.a();
+        }
+
+        let a = self.get(Register::A);
+        let hl = self.get(Register::HL);
+        let sp = self.sp;
+        let value = match (inst.mnemonic(), inst.lhs(), inst.rhs()) {
+            (Mnemonic::LD, Some(Operand::Reg(reg)), source @ Some(Operand::Imm8(_)))
+            | (Mnemonic::LD, Some(Operand::Reg(reg)), source @ Some(Operand::Imm16(_)))
+            | (Mnemonic::LD, Some(Operand::Reg(reg)), source @ Some(Operand::Reg(_))) => {
+                Some((*reg, self.operand(source)))
+            }
+            (Mnemonic::LD, Some(Operand::Reg(Register::HL)), Some(Operand::SPRel8(offset))) => {
+                Some((
+                    Register::HL,
+                    sp.map(|sp| sp.wrapping_add(*offset as i8 as u16)),
+                ))
+            }
+            (Mnemonic::INC, Some(Operand::Reg(reg)), None) => Some((
+                *reg,
+                self.get(*reg)
+                    .map(|value| value.wrapping_add(1) & Values::mask(*reg)),
+            )),
+            (Mnemonic::DEC, Some(Operand::Reg(reg)), None) => Some((
+                *reg,
+                self.get(*reg)
+                    .map(|value| value.wrapping_sub(1) & Values::mask(*reg)),
+            )),
+            (Mnemonic::ADD, Some(Operand::Reg(Register::SP)), Some(Operand::Rel8(offset))) => {
+                Some((
+                    Register::SP,
+                    sp.map(|sp| sp.wrapping_add(*offset as i8 as u16)),
+                ))
+            }
+            (Mnemonic::ADD, Some(Operand::Reg(reg)), source) => {
+                let value = self.get(*reg).zip(self.operand(source));
+                Some((
+                    *reg,
+                    value.map(|(value, n)| value.wrapping_add(n) & Values::mask(*reg)),
+                ))
+            }
+            /* Operations of A with itself do not depend on it. */
+            (Mnemonic::XOR, Some(Operand::Reg(Register::A)), None)
+            | (Mnemonic::SUB, Some(Operand::Reg(Register::A)), None) => {
+                Some((Register::A, Some(0)))
+            }
+            (Mnemonic::SUB, source, None)
+            | (Mnemonic::AND, source, None)
+            | (Mnemonic::OR, source, None)
+            | (Mnemonic::XOR, source, None) => {
+                let value = a
+                    .zip(self.operand(source))
+                    .map(|(a, n)| match inst.mnemonic() {
+                        Mnemonic::SUB => a.wrapping_sub(n) & 0xFF,
+                        Mnemonic::AND => a & n,
+                        Mnemonic::OR => a | n,
+                        _ => a ^ n,
+                    });
+                Some((Register::A, value))
+            }
+            (Mnemonic::LDIL, _, _) | (Mnemonic::LDIR, _, _) => {
+                Some((Register::HL, hl.map(|hl| hl.wrapping_add(1))))
+            }
+            (Mnemonic::LDDL, _, _) | (Mnemonic::LDDR, _, _) => {
+                Some((Register::HL, hl.map(|hl| hl.wrapping_sub(1))))
+            }
+            _ => None,
+        };
+        /* Changes of SP other than loading it. */
+        let sp = match inst.mnemonic() {
+            Mnemonic::PUSH => sp.map(|sp| sp.wrapping_sub(2)),
+            Mnemonic::POP => sp.map(|sp| sp.wrapping_add(2)),
+            _ => sp,
+        };
+
+        for written in inst.written() {
+            self.registers.retain(|&reg, _| !reg.overlaps(written));
+        }
+        self.sp = sp;
+        if inst.writes(Register::SP) {
+            self.sp = None;
+        }
+        if let Some((reg, Some(value))) = value {
+            self.set(reg, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Values after running `code` from nothing known. */
+    fn run(code: &[u8]) -> Values {
+        let mut values = Values::default();
+        let mut offset = 0;
+        while offset < code.len() {
+            let inst = Instruction::from_slice(&code[offset..]).unwrap();
+            values.step(&inst);
+            offset += inst.size();
+        }
+
+        values
+    }
+
+    #[test]
+    fn loads() {
+        let values = run(&[
+            0x21, 0x34, 0x12, /* ld hl, $1234 */
+            0x45, /* ld b, l */
+            0x2C, /* inc l */
+            0x0D, /* dec c */
+        ]);
+        assert_eq!(values.get(Register::HL), Some(0x1235));
+        assert_eq!(values.get(Register::B), Some(0x34));
+        assert_eq!(values.get(Register::C), None);
+        assert_eq!(values.get(Register::BC), None);
+        assert_eq!(values.a(), None);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let values = run(&[
+            0xAF, /* xor a */
+            0xC6, 0xF0, /* add $F0 */
+            0xC6, 0x20, /* add $20 */
+            0xE6, 0x1C, /* and $1C */
+            0xF6, 0x01, /* or 1 */
+            0x21, 0xFF, 0xFF, /* ld hl, $FFFF */
+            0x23, /* inc hl */
+            0x11, 0x01, 0x00, /* ld de, 1 */
+            0x19, /* add hl, de */
+        ]);
+        assert_eq!(values.a(), Some(0x11));
+        assert_eq!(values.get(Register::HL), Some(0x0001));
+
+        /* ADC depends on the carry, which is not followed. */
+        let values = run(&[0x3E, 0x01, 0xCE, 0x01]);
+        assert_eq!(values.a(), None);
+    }
+
+    #[test]
+    fn stack_pointer() {
+        let values = run(&[
+            0x31, 0xFE, 0xDF, /* ld sp, $DFFE */
+            0xC5, /* push bc */
+            0xC5, /* push bc */
+            0xE1, /* pop hl */
+            0xE8, 0xFE, /* add sp, -2 */
+            0xF8, 0x04, /* ld hl, sp+4 */
+        ]);
+        assert_eq!(values.get(Register::SP), Some(0xDFFA));
+        assert_eq!(values.get(Register::HL), Some(0xDFFE));
+
+        let values = run(&[0x31, 0xFE, 0xDF, 0xF9 /* ld sp, hl */]);
+        assert_eq!(values.get(Register::SP), None);
+    }
+
+    #[test]
+    fn bank() {
+        let values = run(&[
+            0x3E, 0x05, /* ld a, 5 */
+            0xEA, 0x00, 0x20, /* ld [$2000], a */
+            0x3E, 0x07, /* ld a, 7 */
+            0xEA, 0x00, 0xC0, /* ld [$C000], a */
+        ]);
+        assert_eq!(values.bank(), Some(5));
+
+        let values = run(&[0xF0, 0x80, 0xEA, 0x00, 0x20]);
+        assert_eq!(values.bank(), None);
+    }
+
+    #[test]
+    fn join() {
+        let left = run(&[0x3E, 0x05, 0x06, 0x01, 0x31, 0xFE, 0xDF]);
+        let right = run(&[0x3E, 0x05, 0x06, 0x02, 0x31, 0xFE, 0xDF]);
+        let joined = left.join(&right);
+        assert_eq!(joined.a(), Some(5));
+        assert_eq!(joined.get(Register::B), None);
+        assert_eq!(joined.get(Register::SP), Some(0xDFFE));
+        assert_eq!(joined.join(&Values::default()), Values::default());
+    }
+}
@@ -0,0 +1,139 @@
+use super::address::Address;
+
+#[derive(Clone)]
+pub enum Warning {
+    /* An instruction needs more bytes than are left before the end of the
+     * bank (bank_boundary) or the end of the file. */
+    TruncatedInstruction {
+        address: Address,
+        available: usize,
+        expected: usize,
+        bank_boundary: bool,
+    },
+    InvalidOpcode {
+        address: Address,
+        opcode: u8,
+    },
+    /* Write to ROM that does not reach a memory bank controller register. */
+    RomWrite {
+        address: Address,
+        target: u16,
+    },
+    UnusableRead {
+        address: Address,
+        target: u16,
+    },
+    EchoRamAccess {
+        address: Address,
+        target: u16,
+    },
+    /* Code flows into a range the project says is data. */
+    FlowIntoData {
+        address: Address,
+    },
+    /* A trace runs code at a switchable ROM address without naming its bank,
+     * and its bytes are in several banks. */
+    UnknownTraceBank {
+        target: u16,
+    },
+    /* Two projects being merged disagree, ours is kept. */
+    AnnotationConflict {
+        address: Address,
+        what: &'static str,
+        ours: String,
+        theirs: String,
+    },
+    /* Paths reach the block with different stack depths. */
+    StackMismatch {
+        address: Address,
+    },
+    /* A return with bytes left on the stack, or popped more than pushed. */
+    UnbalancedStack {
+        address: Address,
+        pushed: i32,
+    },
+    /* A pop taking the return address of its function off the stack. */
+    ReturnAddressPopped {
+        address: Address,
+    },
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::TruncatedInstruction {
+                address,
+                available,
+                expected,
+                bank_boundary,
+            } => write!(
+                f,
+                "{}: instruction needs {} bytes, only {} left before the end of the {}",
+                address,
+                expected,
+                available,
+                if *bank_boundary { "bank" } else { "file" }
+            ),
+            Self::InvalidOpcode { address, opcode } => {
+                write!(f, "{}: invalid opcode ${:02X}", address, opcode)
+            }
+            Self::RomWrite { address, target } => write!(
+                f,
+                "{}: write to ROM at ${:04X} which is not a bank controller register",
+                address, target
+            ),
+            Self::UnusableRead { address, target } => {
+                write!(
+                    f,
+                    "{}: read from unusable memory at ${:04X}",
+                    address, target
+                )
+            }
+            Self::EchoRamAccess { address, target } => {
+                write!(f, "{}: access to echo RAM at ${:04X}", address, target)
+            }
+            Self::FlowIntoData { address } => {
+                write!(f, "{}: code flows into a data range", address)
+            }
+            Self::UnknownTraceBank { target } => write!(
+                f,
+                "${:04X}: executed by the trace in a bank that cannot be told",
+                target
+            ),
+            Self::AnnotationConflict {
+                address,
+                what,
+                ours,
+                theirs,
+            } => write!(
+                f,
+                "{}: conflicting {}, keeping `{}` over `{}`",
+                address, what, ours, theirs
+            ),
+            Self::StackMismatch { address } => {
+                write!(f, "{}: reached with different stack depths", address)
+            }
+            Self::UnbalancedStack { address, pushed } if *pushed > 0 => {
+                write!(
+                    f,
+                    "{}: returns with {} bytes left on the stack",
+                    address, pushed
+                )
+            }
+            Self::UnbalancedStack { address, pushed } => write!(
+                f,
+                "{}: returns with {} more bytes popped than pushed",
+                address, -pushed
+            ),
+            Self::ReturnAddressPopped { address } => {
+                write!(f, "{}: pops the return address of its function", address)
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
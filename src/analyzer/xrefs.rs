@@ -0,0 +1,287 @@
+use std::collections::BTreeMap;
+
+use super::address::Address;
+use super::control_flow::{ControlFlowGraph, JumpTable, PointerTable};
+use super::instruction::{Flow, Instruction, Mnemonic, Operand, Register};
+use super::memory::{AccessKind, MemoryAccess};
+use super::project::{DataType, Project};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum XrefKind {
+    Call,
+    Jump,
+    Read,
+    Write,
+    Pointer, /* Address loaded in HL or DE, or stored in a pointer table */
+}
+
+impl XrefKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            XrefKind::Call => "call",
+            XrefKind::Jump => "jump",
+            XrefKind::Read => "read",
+            XrefKind::Write => "write",
+            XrefKind::Pointer => "pointer",
+        }
+    }
+}
+
+/* A reference from code or data at `from` to `to`. RAM and I/O addresses
+ * are in bank 0. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Xref {
+    from: Address,
+    to: Address,
+    kind: XrefKind,
+}
+
+impl Xref {
+    pub fn from(&self) -> Address {
+        self.from
+    }
+
+    pub fn to(&self) -> Address {
+        self.to
+    }
+
+    pub fn kind(&self) -> XrefKind {
+        self.kind
+    }
+}
+
+/* Cross references of every address used by the code found by flow analysis
+ * and by the pointer tables, keyed by referenced address. */
+#[derive(Debug, Default)]
+pub struct Xrefs {
+    xrefs: BTreeMap<Address, Vec<Xref>>,
+}
+
+impl Xrefs {
+    pub fn build(cfg: &ControlFlowGraph, bytes: &[u8], project: &Project) -> Xrefs {
+        let mut xrefs = Xrefs::default();
+
+        for block in cfg.get_blocks().values() {
+            for (pc, inst) in block.get_instructions() {
+                xrefs.add_instruction(cfg, bytes, project, *pc, inst);
+            }
+        }
+        for (&pc, &target) in cfg.get_far_calls() {
+            xrefs.add(pc, target, XrefKind::Call);
+        }
+
+        /* Every word of the tables found and of the pointer tables declared
+         * by the project. */
+        let tables = cfg
+            .get_jump_tables()
+            .values()
+            .flat_map(JumpTable::get_targets)
+            .chain(
+                cfg.get_pointer_tables()
+                    .values()
+                    .flat_map(PointerTable::get_targets),
+            );
+        for &(from, target) in tables {
+            xrefs.add(from, target, XrefKind::Pointer);
+        }
+        for range in project.get_types().values() {
+            if *range.data_type() != DataType::Pointers {
+                continue;
+            }
+            for addr in (range.start().addr()..range.end().addr()).step_by(2) {
+                let from = Address::new(range.start().bank(), addr);
+                let offset = from.to_offset();
+                if offset + 1 >= bytes.len() {
+                    break;
+                }
+                let word = u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+                if let Some(target) = Xrefs::location(bytes, project, from, word) {
+                    xrefs.add(from, target, XrefKind::Pointer);
+                }
+            }
+        }
+
+        for refs in xrefs.xrefs.values_mut() {
+            refs.sort_by_key(|xref| (xref.from, xref.kind));
+            refs.dedup();
+        }
+
+        xrefs
+    }
+
+    fn add_instruction(
+        &mut self,
+        cfg: &ControlFlowGraph,
+        bytes: &[u8],
+        project: &Project,
+        pc: Address,
+        inst: &Instruction,
+    ) {
+        let flow = match inst.flow(pc.addr()) {
+            Flow::Call(target) => Some((Some(target), XrefKind::Call)),
+            Flow::Jump(target) => Some((target, XrefKind::Jump)),
+            Flow::Branch(target) => Some((Some(target), XrefKind::Jump)),
+            _ => None,
+        };
+        if let Some((target, kind)) = flow {
+            /* Targets resolved from the constants in registers first. */
+            let target = match cfg.get_resolved().get(&pc) {
+                Some(&target) => Some(target),
+                None => {
+                    target.and_then(|target| ControlFlowGraph::target(bytes, project, pc, target))
+                }
+            };
+            if let Some(target) = target {
+                self.add(pc, target, kind);
+            }
+        }
+
+        let mut accesses = MemoryAccess::of(inst, pc);
+        if let Some(values) = cfg.get_constants().values(pc) {
+            accesses.extend(MemoryAccess::through_registers(inst, pc, values));
+        }
+        for access in accesses {
+            let target = match Xrefs::location(bytes, project, pc, access.target()) {
+                Some(target) => target,
+                None => continue,
+            };
+            if access.kind() != AccessKind::Write {
+                self.add(pc, target, XrefKind::Read);
+            }
+            if access.kind() != AccessKind::Read {
+                self.add(pc, target, XrefKind::Write);
+            }
+        }
+
+        /* Small values are sizes and offsets more often than addresses. */
+        if let (Mnemonic::LD, Some(Operand::Reg(Register::HL)), Some(Operand::Imm16(value)))
+        | (Mnemonic::LD, Some(Operand::Reg(Register::DE)), Some(Operand::Imm16(value))) =
+            (inst.mnemonic(), inst.lhs(), inst.rhs())
+        {
+            if *value >= 0x0100 {
+                if let Some(target) = Xrefs::location(bytes, project, pc, *value) {
+                    self.add(pc, target, XrefKind::Pointer);
+                }
+            }
+        }
+    }
+
+    /* ROM addresses in the bank they are seen from, the others in bank 0. */
+    fn location(bytes: &[u8], project: &Project, from: Address, addr: u16) -> Option<Address> {
+        match addr {
+            0x0000..=0x7FFF => ControlFlowGraph::target(bytes, project, from, addr),
+            _ => Some(Address::new(0, addr)),
+        }
+    }
+
+    fn add(&mut self, from: Address, to: Address, kind: XrefKind) {
+        self.xrefs
+            .entry(to)
+            .or_default()
+            .push(Xref { from, to, kind });
+    }
+
+    pub fn get_xrefs(&self) -> &BTreeMap<Address, Vec<Xref>> {
+        &self.xrefs
+    }
+
+    /* References to `address`, sorted by origin. */
+    pub fn to(&self, address: Address) -> &[Xref] {
+        match self.xrefs.get(&address) {
+            Some(xrefs) => xrefs,
+            None => &[],
+        }
+    }
+
+    /* References made by the instruction or data word at `address`. */
+    pub fn from(&self, address: Address) -> Vec<&Xref> {
+        self.xrefs
+            .values()
+            .flatten()
+            .filter(|xref| xref.from == address)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::project::TypedRange;
+
+    #[test]
+    fn table_entries() {
+        let mut bytes = vec![0x00; 0x8000];
+        let code = [
+            0xEF, /* rst $28 */
+            0x50, 0x01, /* dw $0150 */
+            0x00, 0xC0, /* dw $C000 */
+            0x60, 0x01, /* dw $0160 */
+        ];
+        bytes[0x0100..0x0100 + code.len()].copy_from_slice(&code);
+        bytes[0x0150] = 0xC9; /* ret */
+        bytes[0x0160] = 0xC9;
+
+        let mut project = Project::new(&bytes);
+        project.add_table_rst(0x28);
+        let range = TypedRange::new(
+            Address::new(0, 0x0101),
+            Address::new(0, 0x0106),
+            DataType::Pointers,
+        );
+        project.add_type(range).unwrap();
+
+        let cfg = ControlFlowGraph::build(&bytes, &[Address::new(0, 0x0100)], &project);
+        let xrefs = Xrefs::build(&cfg, &bytes, &project);
+        let from = |to: u16| -> Vec<(Address, XrefKind)> {
+            xrefs
+                .to(Address::new(0, to))
+                .iter()
+                .map(|xref| (xref.from(), xref.kind()))
+                .collect()
+        };
+
+        assert_eq!(from(0x0150), [(Address::new(0, 0x0101), XrefKind::Pointer)]);
+        assert_eq!(from(0x0160), [(Address::new(0, 0x0105), XrefKind::Pointer)]);
+        assert_eq!(from(0x0028), [(Address::new(0, 0x0100), XrefKind::Call)]);
+    }
+
+    #[test]
+    fn instructions() {
+        let mut bytes = vec![0x00; 0x8000];
+        let code = [
+            0xCD, 0x50, 0x01, /* call $0150 */
+            0xFA, 0x00, 0xC0, /* ld a, [$C000] */
+            0xEA, 0x01, 0xC0, /* ld [$C001], a */
+            0x21, 0x00, 0x02, /* ld hl, $0200 */
+            0x34, /* inc [hl] */
+            0x21, 0x10, 0x00, /* ld hl, $0010 */
+            0xC3, 0x10, 0x01, /* jp @ */
+        ];
+        bytes[0x0100..0x0100 + code.len()].copy_from_slice(&code);
+        bytes[0x0150] = 0xC9;
+
+        let project = Project::new(&bytes);
+        let cfg = ControlFlowGraph::build(&bytes, &[Address::new(0, 0x0100)], &project);
+        let xrefs = Xrefs::build(&cfg, &bytes, &project);
+        let all: Vec<(u16, u16, XrefKind)> = xrefs
+            .get_xrefs()
+            .values()
+            .flatten()
+            .map(|xref| (xref.from().addr(), xref.to().addr(), xref.kind()))
+            .collect();
+
+        assert_eq!(
+            all,
+            [
+                (0x0110, 0x0110, XrefKind::Jump),
+                (0x0100, 0x0150, XrefKind::Call),
+                (0x0109, 0x0200, XrefKind::Pointer),
+                (0x010C, 0x0200, XrefKind::Read),
+                (0x010C, 0x0200, XrefKind::Write),
+                (0x0103, 0xC000, XrefKind::Read),
+                (0x0106, 0xC001, XrefKind::Write),
+            ]
+        );
+        assert_eq!(xrefs.from(Address::new(0, 0x010C)).len(), 2);
+    }
+}
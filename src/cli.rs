@@ -0,0 +1,622 @@
+use std::path::PathBuf;
+
+use analboy::analyzer::{
+    Address, AnalyzerError, Compression, Project, Register, Syntax, DEFAULT_PALETTE,
+};
+
+pub const USAGE: &str = "\
+usage: analboy <command> [options] <rom>
+       analboy doctor --expected <log> [options] <log>
+
+commands:
+    header          print the cartridge header
+    disasm          disassemble the cartridge
+    cfg             print the control flow graph in Graphviz DOT format
+    memory          list the WRAM and HRAM variables used by each function
+    symbols         write a .sym file with the known and generated labels
+    fix-checksum    rewrite the header and global checksums
+    project         write a project file with the loaded annotations and
+                    names, merging every --project given
+    xrefs           list the calls, jumps, reads, writes and pointers to
+                    each address
+    strings         list the strings found in data with their references
+    gfx             list the tiles copied to VRAM, with --output: write
+                    them as PNG files in that directory, with --range:
+                    write the tiles of the range as a PNG to --output
+    timing          list the fewest and most cycles of each function, and
+                    whether the VBlank handler fits in VBlank
+    stack           list the stack used by each function with its callees,
+                    and the variables the deepest stack runs into
+    decompile       print each function as C-like pseudocode
+    compressed      list the data given to the decompressors found, with
+                    --output: write it decompressed in that directory,
+                    tiles for VRAM also as PNG files
+    decompress      decompress the data at --address in --format to
+                    --output
+    run             emulate a call to the routine at --address from the
+                    state the boot ROM leaves and print the registers it
+                    returns, with --output: write $8000-$FFFF there
+    doctor          compare a Gameboy Doctor log with the --expected one
+                    and print the first state where they differ, after
+                    the instructions leading to it
+
+options:
+    -o, --output <file>     write to <file> instead of stdout (fix-checksum:
+                            instead of modifying <rom> in place)
+    -s, --syntax <syntax>   assembler syntax: rgbds (default) or wla
+    -b, --bank <bank>       only disassemble ROM bank <bank>, also the default
+                            bank of addresses given without one
+    -r, --range <start-end> only disassemble from <start> to <end> included
+    -f, --follow <addr>     only disassemble code reachable from <addr>, for
+                            cfg: start the flow analysis there
+    -a, --address <addr>    xrefs: only list the references to <addr>, which
+                            can be a RAM or I/O address, timing: only the
+                            function at <addr>, with the cycles of its
+                            blocks, stack: only the function at <addr>,
+                            with its deepest chain of calls,
+                            decompile: only the function at <addr>,
+                            decompress: the data to decompress,
+                            run: the routine to call
+    --format <format>       compression of the data to decompress: rle,
+                            rle-literal, lzss, pokemon-lz or gbdk
+    --symbols <file>        load names from a .sym file, can be repeated
+    --project <file>        load labels, comments, data types and bank hints
+                            from a project file made for <rom>, can be
+                            repeated to merge several
+    --trace <file>          mark the code executed in an emulator trace
+                            (Gameboy Doctor log or `bank:address` lines) or
+                            code/data log as code and the bytes read as
+                            data, can be repeated
+    --charmap <file>        text encoding of the strings, an RGBDS charmap or
+                            a table of `80=A` lines (default: ASCII), for
+                            disasm: print the strings found as text
+    --terminator <byte>     byte ending strings (default: the one the charmap
+                            maps to <END> or @, else $00)
+    --registers <values>    registers set before run, as `hl=4B00,de=C000`
+    --cycles <count>        cycles run runs at most (default: 1000000)
+    --expected <log>        Gameboy Doctor log doctor compares against
+    --palette <colours>     the 4 colours of gfx PNGs, lightest first, as
+                            RRGGBB hexadecimal separated by commas (default:
+                            FFFFFF,AAAAAA,555555,000000)
+    --width <tiles>         tiles per row of gfx PNGs (default: 16)
+    --table-rst <vector>    the routine at RST <vector> reads a jump table
+                            inline after the `rst`, can be repeated (default:
+                            RST routines that pop HL and `jp hl`)
+    --no-hardware-names     print I/O register addresses instead of names
+    -q, --quiet             do not print analysis warnings
+    -h, --help              print this help
+
+addresses are hexadecimal, optionally prefixed by a bank: 03:4A00 or $4A00";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Header,
+    Disasm,
+    Cfg,
+    Memory,
+    Symbols,
+    FixChecksum,
+    Project,
+    Xrefs,
+    Strings,
+    Gfx,
+    Timing,
+    Stack,
+    Decompile,
+    Compressed,
+    Decompress,
+    Run,
+    Doctor,
+}
+
+impl Command {
+    /* Whether the command uses the option named `option` in its long form,
+     * `None` for unknown options. */
+    fn accepts(self, option: &str) -> Option<bool> {
+        use Command::*;
+
+        /* Commands analysing the rom with the loaded names and annotations. */
+        let analysis = !matches!(self, Header | FixChecksum | Decompress | Run | Doctor);
+        let accepts = match option {
+            "--output" | "--help" => true,
+            "--quiet" | "--symbols" | "--project" | "--trace" | "--table-rst" => analysis,
+            "--follow" => analysis && self != Project,
+            "--syntax" => matches!(self, Disasm | Cfg | Doctor),
+            "--no-hardware-names" => {
+                matches!(self, Disasm | Cfg | Xrefs | Strings | Decompile | Doctor)
+            }
+            "--charmap" => matches!(self, Disasm | Project | Strings),
+            "--terminator" => matches!(self, Disasm | Strings),
+            "--range" => matches!(self, Disasm | Gfx),
+            "--address" => matches!(self, Xrefs | Timing | Stack | Decompile | Decompress | Run),
+            "--palette" | "--width" => matches!(self, Gfx | Compressed),
+            "--format" => self == Decompress,
+            "--registers" | "--cycles" => self == Run,
+            "--expected" => self == Doctor,
+            /* The default bank of the addresses given. */
+            "--bank" => ["--follow", "--range", "--address"]
+                .iter()
+                .any(|option| self.accepts(option) == Some(true)),
+            _ => return None,
+        };
+
+        Some(accepts)
+    }
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub command: Command,
+    pub rom: PathBuf,
+    pub output: Option<PathBuf>,
+    pub syntax: Syntax,
+    pub quiet: bool,
+    pub hardware_names: bool,
+    pub symbols: Vec<PathBuf>,
+    pub projects: Vec<PathBuf>,
+    pub traces: Vec<PathBuf>,
+    pub table_rsts: Vec<u8>,
+    pub charmap: Option<PathBuf>,
+    pub terminator: Option<u8>,
+    pub palette: [[u8; 3]; 4],
+    pub width: usize,
+    pub bank: Option<u16>,
+    pub range: Option<(Address, Address)>,
+    pub follow: Option<Address>,
+    pub address: Option<Address>,
+    pub format: Option<Compression>,
+    pub registers: Vec<(Register, u16)>,
+    pub cycles: u64,
+    pub expected: Option<PathBuf>,
+}
+
+pub enum CliError {
+    Help,
+    Usage(String),
+    Analyzer(AnalyzerError),
+    Io(PathBuf, std::io::Error),
+    /* Error in an input file other than the rom. */
+    Input(PathBuf, AnalyzerError),
+    /* Logs compared by `doctor` differ, the difference is in the output. */
+    Mismatch,
+}
+
+impl CliError {
+    /* 2 for command line mistakes, 1 for everything else, as cmp exits
+     * with 1 when files differ. */
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Help => 0,
+            Self::Usage(_) => 2,
+            Self::Analyzer(_) | Self::Io(_, _) | Self::Input(_, _) | Self::Mismatch => 1,
+        }
+    }
+}
+
+impl std::fmt::Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Help => write!(f, "{}", USAGE),
+            Self::Usage(msg) => write!(f, "{}\n\n{}", msg, USAGE),
+            Self::Analyzer(e) => write!(f, "{}", e),
+            Self::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            Self::Input(path, e) => write!(f, "{}: {}", path.display(), e),
+            Self::Mismatch => write!(f, "logs differ"),
+        }
+    }
+}
+
+impl From<AnalyzerError> for CliError {
+    fn from(err: AnalyzerError) -> CliError {
+        CliError::Analyzer(err)
+    }
+}
+
+impl Options {
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Options, CliError> {
+        let name = args.next();
+        let command = match name.as_deref() {
+            Some("header") => Command::Header,
+            Some("disasm") => Command::Disasm,
+            Some("cfg") => Command::Cfg,
+            Some("memory") => Command::Memory,
+            Some("symbols") => Command::Symbols,
+            Some("fix-checksum") => Command::FixChecksum,
+            Some("project") => Command::Project,
+            Some("xrefs") => Command::Xrefs,
+            Some("strings") => Command::Strings,
+            Some("gfx") => Command::Gfx,
+            Some("timing") => Command::Timing,
+            Some("stack") => Command::Stack,
+            Some("decompile") => Command::Decompile,
+            Some("compressed") => Command::Compressed,
+            Some("decompress") => Command::Decompress,
+            Some("run") => Command::Run,
+            Some("doctor") => Command::Doctor,
+            Some("-h") | Some("--help") | Some("help") => return Err(CliError::Help),
+            Some(other) => return Err(CliError::Usage(format!("unknown command `{}`", other))),
+            None => return Err(CliError::Usage("missing command".to_string())),
+        };
+
+        let mut rom = None;
+        let mut output = None;
+        let mut syntax = Syntax::Rgbds;
+        let mut quiet = false;
+        let mut hardware_names = true;
+        let mut symbols = Vec::new();
+        let mut projects = Vec::new();
+        let mut traces = Vec::new();
+        let mut table_rsts = Vec::new();
+        let mut charmap = None;
+        let mut terminator = None;
+        let mut palette = DEFAULT_PALETTE;
+        let mut width = 16;
+        let mut bank = None;
+        let mut range = None;
+        let mut follow = None;
+        let mut query = None;
+        let mut format = None;
+        let mut registers = Vec::new();
+        let mut cycles = 1_000_000;
+        let mut expected = None;
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| CliError::Usage(format!("missing value for `{}`", name)))
+            };
+
+            let long = match arg.as_str() {
+                "-o" => "--output",
+                "-s" => "--syntax",
+                "-q" => "--quiet",
+                "-b" => "--bank",
+                "-r" => "--range",
+                "-f" => "--follow",
+                "-a" => "--address",
+                "-h" => "--help",
+                long => long,
+            };
+            if command.accepts(long) == Some(false) {
+                return Err(CliError::Usage(format!(
+                    "`{}` does not take `{}`",
+                    name.as_deref().unwrap_or_default(),
+                    arg
+                )));
+            }
+
+            match arg.as_str() {
+                "-o" | "--output" => output = Some(PathBuf::from(value(&arg)?)),
+                "-s" | "--syntax" => syntax = value(&arg)?.parse().map_err(CliError::Usage)?,
+                "-q" | "--quiet" => quiet = true,
+                "--no-hardware-names" => hardware_names = false,
+                "--symbols" => symbols.push(PathBuf::from(value(&arg)?)),
+                "--project" => projects.push(PathBuf::from(value(&arg)?)),
+                "--trace" => traces.push(PathBuf::from(value(&arg)?)),
+                "--table-rst" => {
+                    table_rsts.push(Project::vector(&value(&arg)?).map_err(CliError::Usage)?)
+                }
+                "--charmap" => charmap = Some(PathBuf::from(value(&arg)?)),
+                "--terminator" => {
+                    let value = value(&arg)?;
+                    let digits = value.trim_start_matches('$').trim_start_matches("0x");
+                    terminator = Some(
+                        u8::from_str_radix(digits, 16)
+                            .map_err(|_| CliError::Usage(format!("invalid byte `{}`", value)))?,
+                    );
+                }
+                "--palette" => palette = Options::palette(&value(&arg)?)?,
+                "--width" => {
+                    let value = value(&arg)?;
+                    width = value
+                        .parse()
+                        .ok()
+                        .filter(|&width| width > 0)
+                        .ok_or_else(|| CliError::Usage(format!("invalid width `{}`", value)))?;
+                }
+                "-b" | "--bank" => {
+                    let value = value(&arg)?;
+                    let digits = value.trim_start_matches('$').trim_start_matches("0x");
+                    bank = Some(
+                        u16::from_str_radix(digits, 16)
+                            .map_err(|_| CliError::Usage(format!("invalid bank `{}`", value)))?,
+                    );
+                }
+                "-r" | "--range" => range = Some(value(&arg)?),
+                "-f" | "--follow" => follow = Some(value(&arg)?),
+                "-a" | "--address" => query = Some(value(&arg)?),
+                "--registers" => registers = Options::registers(&value(&arg)?)?,
+                "--cycles" => {
+                    let value = value(&arg)?;
+                    cycles = value
+                        .parse()
+                        .map_err(|_| CliError::Usage(format!("invalid cycle count `{}`", value)))?;
+                }
+                "--expected" => expected = Some(PathBuf::from(value(&arg)?)),
+                "--format" => format = Some(value(&arg)?.parse().map_err(CliError::Usage)?),
+                "-h" | "--help" => return Err(CliError::Help),
+                _ if arg.starts_with('-') && arg.len() > 1 => {
+                    return Err(CliError::Usage(format!("unknown option `{}`", arg)))
+                }
+                _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+                _ => return Err(CliError::Usage(format!("unexpected argument `{}`", arg))),
+            }
+        }
+
+        /* Addresses are resolved once the default bank is known. */
+        let address = |s: &str| {
+            let s = match bank {
+                Some(bank) if !s.contains(':') => format!("{:X}:{}", bank, s),
+                _ => s.to_string(),
+            };
+            s.parse::<Address>().map_err(CliError::Usage)
+        };
+        let range = match range {
+            Some(range) => {
+                let (start, end) = range.split_once('-').ok_or_else(|| {
+                    CliError::Usage(format!("invalid range `{}`, expected start-end", range))
+                })?;
+                let start = address(start)?;
+                /* The end is in the same bank as the start unless told
+                 * otherwise, or unless it is on the other side of $4000. */
+                let end = if end.contains(':') {
+                    address(end)?
+                } else {
+                    match address(&format!("{:X}:{}", start.bank(), end)) {
+                        Ok(end) => end,
+                        Err(_) => end.parse::<Address>().map_err(CliError::Usage)?,
+                    }
+                };
+                if end.bank() != start.bank() {
+                    return Err(CliError::Usage(format!(
+                        "range `{}` crosses from bank {:02X} into bank {:02X}, give one range per bank",
+                        range,
+                        start.bank(),
+                        end.bank()
+                    )));
+                }
+                Some((start, end))
+            }
+            None => None,
+        };
+        let follow = match follow {
+            Some(follow) => Some(address(&follow)?),
+            None => None,
+        };
+        /* RAM and I/O addresses are in bank 0, like in .sym files. */
+        let query = match query {
+            Some(query) => {
+                let digits = query.trim_start_matches('$').trim_start_matches("0x");
+                match u16::from_str_radix(digits, 16) {
+                    Ok(addr) if addr >= 0x8000 => Some(Address::new(0, addr)),
+                    _ => Some(address(&query)?),
+                }
+            }
+            None => None,
+        };
+
+        Ok(Options {
+            command,
+            rom: rom.ok_or_else(|| match command {
+                Command::Doctor => CliError::Usage("missing log path".to_string()),
+                _ => CliError::Usage("missing rom path".to_string()),
+            })?,
+            output,
+            syntax,
+            quiet,
+            hardware_names,
+            symbols,
+            projects,
+            traces,
+            table_rsts,
+            charmap,
+            terminator,
+            palette,
+            width,
+            bank,
+            range,
+            follow,
+            address: query,
+            format,
+            registers,
+            cycles,
+            expected,
+        })
+    }
+
+    /* `reg=value` separated by commas, values in hexadecimal. */
+    fn registers(s: &str) -> Result<Vec<(Register, u16)>, CliError> {
+        let invalid = || CliError::Usage(format!("invalid registers `{}`", s));
+
+        let mut registers = Vec::new();
+        for assignment in s.split(',') {
+            let (name, value) = assignment.split_once('=').ok_or_else(invalid)?;
+            let reg = match name.trim().to_ascii_lowercase().as_str() {
+                "a" => Register::A,
+                "b" => Register::B,
+                "c" => Register::C,
+                "d" => Register::D,
+                "e" => Register::E,
+                "h" => Register::H,
+                "l" => Register::L,
+                "af" => Register::AF,
+                "bc" => Register::BC,
+                "de" => Register::DE,
+                "hl" => Register::HL,
+                "sp" => Register::SP,
+                _ => return Err(invalid()),
+            };
+            let digits = value
+                .trim()
+                .trim_start_matches('$')
+                .trim_start_matches("0x");
+            let value = u16::from_str_radix(digits, 16).map_err(|_| invalid())?;
+            registers.push((reg, value));
+        }
+
+        Ok(registers)
+    }
+
+    /* `RRGGBB,RRGGBB,RRGGBB,RRGGBB`, with an optional `#` before each. */
+    fn palette(s: &str) -> Result<[[u8; 3]; 4], CliError> {
+        let invalid = || CliError::Usage(format!("invalid palette `{}`", s));
+        let colours: Vec<&str> = s.split(',').collect();
+        if colours.len() != 4 {
+            return Err(invalid());
+        }
+
+        let mut palette = [[0; 3]; 4];
+        for (colour, hex) in palette.iter_mut().zip(colours) {
+            let hex = hex.trim().trim_start_matches('#');
+            let rgb = u32::from_str_radix(hex, 16).map_err(|_| invalid())?;
+            if hex.len() != 6 {
+                return Err(invalid());
+            }
+            colour.copy_from_slice(&rgb.to_be_bytes()[1..]);
+        }
+
+        Ok(palette)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Options, CliError> {
+        Options::parse(args.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn commands() {
+        let options = parse("disasm -s wla -q game.gb").ok().unwrap();
+        assert_eq!(options.command, Command::Disasm);
+        assert_eq!(options.rom, PathBuf::from("game.gb"));
+        assert_eq!(options.syntax, Syntax::Wla);
+        assert!(options.quiet);
+
+        let options = parse("disasm -b 2 -r 4000-4FFF game.gb").ok().unwrap();
+        assert_eq!(
+            options.range,
+            Some((Address::new(2, 0x4000), Address::new(2, 0x4FFF)))
+        );
+        let options = parse("cfg -f 03:4000 game.gb").ok().unwrap();
+        assert_eq!(options.follow, Some(Address::new(3, 0x4000)));
+        let options = parse("cfg --table-rst 28 --table-rst $30 game.gb")
+            .ok()
+            .unwrap();
+        assert_eq!(options.table_rsts, [0x28, 0x30]);
+        let options = parse("disasm --trace a.log --trace game.cdl game.gb")
+            .ok()
+            .unwrap();
+        assert_eq!(
+            options.traces,
+            [PathBuf::from("a.log"), PathBuf::from("game.cdl")]
+        );
+
+        let options = parse("xrefs -a C000 game.gb").ok().unwrap();
+        assert_eq!(options.address, Some(Address::new(0, 0xC000)));
+        let options = parse("xrefs -b 3 -a 4000 game.gb").ok().unwrap();
+        assert_eq!(options.address, Some(Address::new(3, 0x4000)));
+
+        let options = parse("strings --charmap main.tbl --terminator 50 game.gb")
+            .ok()
+            .unwrap();
+        assert_eq!(options.charmap, Some(PathBuf::from("main.tbl")));
+        assert_eq!(options.terminator, Some(0x50));
+
+        let options = parse("gfx -r 4000-47FF --palette #E0F8D0,88C070,346856,081820 game.gb")
+            .ok()
+            .unwrap();
+        assert_eq!(options.palette[0], [0xE0, 0xF8, 0xD0]);
+        assert_eq!(options.palette[3], [0x08, 0x18, 0x20]);
+        assert_eq!(options.width, 16);
+
+        let options = parse("decompress -a 02:5000 --format pokemon-lz -o out.bin game.gb")
+            .ok()
+            .unwrap();
+        assert_eq!(options.address, Some(Address::new(2, 0x5000)));
+        assert_eq!(options.format, Some(Compression::PokemonLz));
+        let options = parse("timing -a 0150 game.gb").ok().unwrap();
+        assert_eq!(options.address, Some(Address::new(0, 0x0150)));
+        let options = parse("run -a 03:4000 --registers hl=C000,a=1 game.gb")
+            .ok()
+            .unwrap();
+        assert_eq!(options.address, Some(Address::new(3, 0x4000)));
+        assert_eq!(
+            options.registers,
+            [(Register::HL, 0xC000), (Register::A, 1)]
+        );
+        assert_eq!(options.cycles, 1_000_000);
+
+        let options = parse("doctor --expected a.log b.log").ok().unwrap();
+        assert_eq!(options.expected, Some(PathBuf::from("a.log")));
+        assert_eq!(options.rom, PathBuf::from("b.log"));
+
+        let options = parse("fix-checksum -o fixed.gb game.gb").ok().unwrap();
+        assert_eq!(options.command, Command::FixChecksum);
+        assert_eq!(options.output, Some(PathBuf::from("fixed.gb")));
+    }
+
+    #[test]
+    fn usage_errors() {
+        let usage = |args: &str| matches!(parse(args), Err(CliError::Usage(_)));
+
+        assert!(usage(""));
+        assert!(usage("dissasm game.gb"));
+        assert!(usage("disasm"));
+        assert!(usage("disasm --bogus game.gb"));
+        assert!(usage("disasm -o"));
+        assert!(usage("disasm -s masm game.gb"));
+        assert!(usage("disasm game.gb other.gb"));
+        assert!(usage("disasm -r 4000 game.gb"));
+        assert!(matches!(
+            parse("disasm -r 3FFE-4001 game.gb"),
+            Err(CliError::Usage(message)) if message.contains("crosses from bank 00 into bank 01")
+        ));
+        assert!(usage("disasm -r 02:7FF0-03:4010 game.gb"));
+        assert!(usage("disasm -b zz game.gb"));
+        assert!(usage("disasm --table-rst 09 game.gb"));
+        assert!(usage("strings --terminator 100 game.gb"));
+        assert!(usage("gfx --palette FFFFFF,000000 game.gb"));
+        assert!(usage("gfx --width 0 game.gb"));
+        assert!(usage("decompress --format zip game.gb"));
+        assert!(usage("run --registers ix=0 game.gb"));
+        assert!(usage("run --cycles lots game.gb"));
+        assert!(usage("doctor --expected a.log"));
+        assert!(matches!(parse("header -h"), Err(CliError::Help)));
+    }
+
+    #[test]
+    fn unused_options() {
+        let usage = |args: &str| matches!(parse(args), Err(CliError::Usage(_)));
+
+        assert!(usage("header -q game.gb"));
+        assert!(usage("fix-checksum -s wla game.gb"));
+        assert!(usage("cfg -r 4000-4FFF game.gb"));
+        assert!(usage("header -b 1 game.gb"));
+        assert!(usage("fix-checksum --no-hardware-names game.gb"));
+        assert!(usage("header --symbols game.sym game.gb"));
+        assert!(usage("project -f 0150 game.gb"));
+        assert!(usage("header --table-rst 28 game.gb"));
+        assert!(usage("disasm -a C000 game.gb"));
+        assert!(usage("cfg --charmap main.tbl game.gb"));
+        assert!(usage("project --terminator 50 game.gb"));
+        assert!(usage("disasm --width 8 game.gb"));
+        assert!(usage("compressed --format rle game.gb"));
+        assert!(usage("timing --format rle game.gb"));
+        assert!(usage("run --project game.txt -a 0150 game.gb"));
+        assert!(usage("header --trace a.log game.gb"));
+        assert!(usage("doctor -b 1 --expected a.log b.log"));
+        assert!(usage("disasm --expected a.log game.gb"));
+        assert!(parse("doctor -s wla --expected a.log b.log").is_ok());
+        assert!(usage("timing --cycles 10 game.gb"));
+        assert!(usage("decompile -s wla game.gb"));
+        assert!(parse("decompile -a 0150 --no-hardware-names game.gb").is_ok());
+        assert!(parse("stack -f 0150 -a 0150 -b 0 -q game.gb").is_ok());
+        assert!(usage("stack --no-hardware-names game.gb"));
+        assert!(usage("decompress -q -a 5000 --format rle game.gb"));
+        assert!(parse("project --project a.txt --project b.txt game.gb").is_ok());
+        assert!(parse("cfg -s wla -q -o cfg.dot game.gb").is_ok());
+    }
+}
@@ -0,0 +1,967 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+
+use analboy::analyzer::{
+    escape, io_register_name, AccessKind, Address, Analyzer, AnalyzerError, Charmap,
+    ControlFlowGraph, DataType, DoctorLog, Formatter, Header, Region, Register, Registers,
+    StackUsage, SymbolTable, TypedRange, Warning, Xref, DOCTOR_CONTEXT, TILE_SIZE,
+};
+
+use crate::cli::{CliError, Command, Options};
+
+pub fn run(options: &Options) -> Result<(), CliError> {
+    /* Logs are compared without a rom. */
+    if options.command == Command::Doctor {
+        return doctor(options);
+    }
+
+    let mut analyzer = Analyzer::from_path(&options.rom).map_err(|e| match e {
+        AnalyzerError::InvalidCartridge(e) => CliError::Io(options.rom.clone(), e),
+        e => CliError::Analyzer(e),
+    })?;
+    for path in &options.symbols {
+        analyzer.load_symbols(path).map_err(|e| match e {
+            AnalyzerError::InvalidSymbolFile(e) => CliError::Io(path.clone(), e),
+            e => CliError::Input(path.clone(), e),
+        })?;
+    }
+    for path in &options.projects {
+        let conflicts = analyzer.load_project(path).map_err(|e| match e {
+            AnalyzerError::InvalidProjectFile(e) => CliError::Io(path.clone(), e),
+            e => CliError::Input(path.clone(), e),
+        })?;
+        warnings(options, &conflicts);
+    }
+    for path in &options.traces {
+        let conflicts = analyzer.load_trace(path).map_err(|e| match e {
+            AnalyzerError::InvalidTraceFile(e) => CliError::Io(path.clone(), e),
+            e => CliError::Input(path.clone(), e),
+        })?;
+        warnings(options, &conflicts);
+    }
+    if let Some(path) = &options.charmap {
+        analyzer
+            .load_charmap(&path.to_string_lossy(), path)
+            .map_err(|e| match e {
+                /* The error already names the file. */
+                e @ AnalyzerError::InvalidCharmapFile(_) => CliError::Analyzer(e),
+                e => CliError::Input(path.clone(), e),
+            })?;
+    }
+    for &vector in &options.table_rsts {
+        analyzer.get_project_mut().add_table_rst(vector);
+    }
+
+    match options.command {
+        Command::Header => header(&analyzer, options),
+        Command::Disasm => disasm(&mut analyzer, options),
+        Command::Cfg => cfg(&analyzer, options),
+        Command::Memory => memory(&analyzer, options),
+        Command::Symbols => symbols(&analyzer, options),
+        Command::FixChecksum => fix_checksum(&mut analyzer, options),
+        Command::Project => project(&mut analyzer, options),
+        Command::Xrefs => xrefs(&analyzer, options),
+        Command::Strings => strings(&analyzer, options),
+        Command::Gfx => gfx(&analyzer, options),
+        Command::Timing => timing(&analyzer, options),
+        Command::Stack => stack(&analyzer, options),
+        Command::Decompile => decompile(&analyzer, options),
+        Command::Compressed => compressed(&analyzer, options),
+        Command::Decompress => decompress(&analyzer, options),
+        Command::Run => run_routine(&analyzer, options),
+        Command::Doctor => unreachable!(),
+    }
+}
+
+fn header(analyzer: &Analyzer, options: &Options) -> Result<(), CliError> {
+    let header = analyzer.header()?;
+    let bytes = analyzer.get_bytes();
+    let header_checksum = Header::compute_header_checksum(bytes);
+    let global_checksum = Header::compute_global_checksum(bytes);
+
+    let cgb = if header.cgb_only() {
+        "required"
+    } else if header.supports_cgb() {
+        "supported"
+    } else {
+        "no"
+    };
+    let check = |ok: bool, expected: String| {
+        if ok {
+            "ok".to_string()
+        } else {
+            format!("expected {}", expected)
+        }
+    };
+
+    output(options, |out| {
+        writeln!(out, "Title:            {}", header.title())?;
+        writeln!(out, "Licensee:         {}", header.licensee())?;
+        writeln!(out, "CGB:              {}", cgb)?;
+        writeln!(
+            out,
+            "SGB:              {}",
+            if header.supports_sgb() { "yes" } else { "no" }
+        )?;
+        writeln!(
+            out,
+            "Cartridge type:   ${:02X} ({})",
+            header.cartridge_type(),
+            header.cartridge_type_name()
+        )?;
+        writeln!(
+            out,
+            "ROM size:         {} banks ({} KiB, file is {} KiB)",
+            header.rom_banks(),
+            header.rom_banks() * 16,
+            bytes.len() / 1024
+        )?;
+        writeln!(out, "RAM size:         {} KiB", header.ram_size() / 1024)?;
+        writeln!(
+            out,
+            "Destination:      {}",
+            if header.japanese() {
+                "Japan"
+            } else {
+                "Overseas"
+            }
+        )?;
+        writeln!(out, "Version:          {}", header.version())?;
+        writeln!(
+            out,
+            "Nintendo logo:    {}",
+            if header.logo_valid() { "ok" } else { "invalid" }
+        )?;
+        writeln!(
+            out,
+            "Header checksum:  ${:02X} ({})",
+            header.header_checksum(),
+            check(
+                header.header_checksum() == header_checksum,
+                format!("${:02X}", header_checksum)
+            )
+        )?;
+        writeln!(
+            out,
+            "Global checksum:  ${:04X} ({})",
+            header.global_checksum(),
+            check(
+                header.global_checksum() == global_checksum,
+                format!("${:04X}", global_checksum)
+            )
+        )
+    })
+}
+
+fn disasm(analyzer: &mut Analyzer, options: &Options) -> Result<(), CliError> {
+    /* Strings found with the charmap given are printed as text, unless the
+     * project types them otherwise. */
+    let cfg = analyzer.control_flow();
+    if let Some(path) = &options.charmap {
+        let (charmap, terminator) = text_encoding(analyzer, options);
+        let data_type = DataType::Text(Some(path.to_string_lossy().to_string()));
+        for string in analyzer.strings(&cfg, &charmap, terminator) {
+            let range = TypedRange::new(string.start(), string.end(), data_type.clone());
+            let _ = analyzer.get_project_mut().add_type(range);
+        }
+    }
+
+    let disassembly = match (options.range, options.follow, options.bank) {
+        (Some(_), Some(_), _) => {
+            return Err(CliError::Usage(
+                "--range and --follow cannot be used together".to_string(),
+            ))
+        }
+        (Some((start, end)), None, _) => analyzer.disassemble_range(start, end, &cfg)?,
+        (None, Some(entry), _) => analyzer.follow(entry)?,
+        (None, None, Some(bank)) => analyzer.disassemble_bank(bank, &cfg)?,
+        (None, None, None) => analyzer.disassemble(&cfg)?,
+    };
+    warnings(options, disassembly.get_warnings());
+
+    let mut formatter = formatter(options);
+    formatter.set_symbols(analyzer.disassembly_symbols(&disassembly));
+    formatter.set_comments(analyzer.get_project().get_comments().clone());
+    formatter.set_annotations(cfg.get_constants().get_annotations().clone());
+    formatter.set_charmaps(analyzer.get_project().get_charmaps().clone());
+    output(options, |out| {
+        formatter.listing(&disassembly, analyzer.get_bytes(), out)
+    })
+}
+
+fn cfg(analyzer: &Analyzer, options: &Options) -> Result<(), CliError> {
+    let cfg = control_flow(analyzer, options)?;
+
+    let mut formatter = formatter(options);
+    formatter.set_symbols(analyzer.control_flow_symbols(&cfg));
+    output(options, |out| formatter.graph(&cfg, out))
+}
+
+fn memory(analyzer: &Analyzer, options: &Options) -> Result<(), CliError> {
+    let cfg = control_flow(analyzer, options)?;
+    let usage = analyzer.memory_usage(&cfg);
+    let symbols = analyzer.control_flow_symbols(&cfg);
+    warnings(options, usage.get_warnings());
+
+    output(options, |out| {
+        for &entry in cfg.get_functions().keys() {
+            let variables = usage.variables(entry);
+            if variables.is_empty() {
+                continue;
+            }
+
+            match symbols.get(entry) {
+                Some(symbol) => writeln!(out, "{} {}:", entry, symbol.name())?,
+                None => writeln!(out, "{}:", entry)?,
+            }
+            for (addr, kind) in variables {
+                let kind = match kind {
+                    AccessKind::Read => "read",
+                    AccessKind::Write => "write",
+                    AccessKind::ReadWrite => "read/write",
+                };
+                let name = match symbols.lookup(entry.bank(), addr) {
+                    Some(symbol) => symbol.name(),
+                    None => "",
+                };
+                writeln!(
+                    out,
+                    "    {:<6} ${:04X}  {:<10}  {}",
+                    Region::of(addr).name(),
+                    addr,
+                    kind,
+                    name
+                )?;
+            }
+        }
+
+        Ok(())
+    })
+}
+
+fn symbols(analyzer: &Analyzer, options: &Options) -> Result<(), CliError> {
+    let cfg = control_flow(analyzer, options)?;
+    let symbols = analyzer.control_flow_symbols(&cfg);
+
+    output(options, |out| symbols.write_sym(out))
+}
+
+fn fix_checksum(analyzer: &mut Analyzer, options: &Options) -> Result<(), CliError> {
+    let (header_checksum, global_checksum) = analyzer.fix_checksums()?;
+
+    let path = options.output.as_deref().unwrap_or(&options.rom);
+    analyzer
+        .save(path)
+        .map_err(|e| CliError::Io(path.to_path_buf(), e))?;
+    println!(
+        "{}: header checksum ${:02X}, global checksum ${:04X}",
+        path.display(),
+        header_checksum,
+        global_checksum
+    );
+
+    Ok(())
+}
+
+/* Names loaded from .sym files become project labels, so that a project can
+ * be started from an existing symbol file. */
+fn project(analyzer: &mut Analyzer, options: &Options) -> Result<(), CliError> {
+    let symbols = analyzer.get_symbols().clone();
+    let project = analyzer.get_project_mut();
+    for (&address, symbol) in symbols.get_symbols() {
+        if !project.get_labels().contains_key(&address) {
+            project.set_label(address, symbol.name());
+        }
+    }
+
+    let project = analyzer.get_project();
+    output(options, |out| project.write(out))
+}
+
+/* References grouped by target. */
+fn xrefs(analyzer: &Analyzer, options: &Options) -> Result<(), CliError> {
+    let cfg = control_flow(analyzer, options)?;
+    let xrefs = analyzer.xrefs(&cfg);
+    let symbols = analyzer.control_flow_symbols(&cfg);
+    let functions = functions(&cfg);
+
+    let targets: Vec<Address> = match options.address {
+        Some(address) => vec![address],
+        None => xrefs.get_xrefs().keys().copied().collect(),
+    };
+    output(options, |out| {
+        for target in targets {
+            writeln!(out, "{}:", name(&symbols, options, target))?;
+            references(out, &cfg, &functions, &symbols, options, xrefs.to(target))?;
+        }
+
+        Ok(())
+    })
+}
+
+/* Strings found in data, each with its references. */
+fn strings(analyzer: &Analyzer, options: &Options) -> Result<(), CliError> {
+    let cfg = control_flow(analyzer, options)?;
+    let (charmap, terminator) = text_encoding(analyzer, options);
+    let strings = analyzer.strings(&cfg, &charmap, terminator);
+    let xrefs = analyzer.xrefs(&cfg);
+    let symbols = analyzer.control_flow_symbols(&cfg);
+    let functions = functions(&cfg);
+
+    output(options, |out| {
+        for string in &strings {
+            writeln!(
+                out,
+                "{} \"{}\"",
+                name(&symbols, options, string.start()),
+                escape(string.text())
+            )?;
+            references(
+                out,
+                &cfg,
+                &functions,
+                &symbols,
+                options,
+                xrefs.to(string.start()),
+            )?;
+        }
+
+        Ok(())
+    })
+}
+
+/* Tiles copied to VRAM: listed, or written as one PNG per copied range in
+ * the output directory. With a range, its tiles are written as one PNG. */
+fn gfx(analyzer: &Analyzer, options: &Options) -> Result<(), CliError> {
+    if let Some((start, end)) = options.range {
+        let path = options
+            .output
+            .as_ref()
+            .ok_or_else(|| CliError::Usage("gfx --range needs --output".to_string()))?;
+        let bytes = analyzer.get_bytes();
+        if start.to_offset() > end.to_offset() || end.to_offset() >= bytes.len() {
+            return Err(AnalyzerError::InvalidRange(start, end).into());
+        }
+
+        let png = Analyzer::export_tiles(
+            &bytes[start.to_offset()..=end.to_offset()],
+            options.width,
+            &options.palette,
+        );
+        return std::fs::write(path, png).map_err(|e| CliError::Io(path.clone(), e));
+    }
+
+    let cfg = control_flow(analyzer, options)?;
+    let copies = analyzer.tile_copies(&cfg);
+    let dir = match &options.output {
+        Some(dir) => dir,
+        None => {
+            return output(options, |out| {
+                for copy in &copies {
+                    writeln!(
+                        out,
+                        "{}-{:04X}  {:>3} tiles to ${:04X}, copied at {}",
+                        copy.source(),
+                        copy.end().addr(),
+                        copy.size() / TILE_SIZE,
+                        copy.destination(),
+                        copy.pc()
+                    )?;
+                }
+
+                Ok(())
+            })
+        }
+    };
+
+    std::fs::create_dir_all(dir).map_err(|e| CliError::Io(dir.clone(), e))?;
+    let mut written = std::collections::BTreeSet::new();
+    for copy in &copies {
+        if !written.insert((copy.source(), copy.size())) {
+            continue;
+        }
+        let start = copy.source().to_offset();
+        let png = Analyzer::export_tiles(
+            &analyzer.get_bytes()[start..start + copy.size()],
+            options.width,
+            &options.palette,
+        );
+        let path = dir.join(format!(
+            "gfx_{:02X}_{:04X}.png",
+            copy.source().bank(),
+            copy.source().addr()
+        ));
+        std::fs::write(&path, png).map_err(|e| CliError::Io(path.clone(), e))?;
+    }
+
+    Ok(())
+}
+
+/* Cycles of every function, or of the blocks of the function at --address,
+ * and whether the VBlank handler fits in VBlank. */
+fn timing(analyzer: &Analyzer, options: &Options) -> Result<(), CliError> {
+    let cfg = control_flow(analyzer, options)?;
+    let timing = analyzer.timing(&cfg);
+    let symbols = analyzer.control_flow_symbols(&cfg);
+
+    output(options, |out| {
+        for (&entry, function) in cfg.get_functions() {
+            if options.address.is_some_and(|address| address != entry) {
+                continue;
+            }
+            let cycles = match timing.get_functions().get(&entry) {
+                Some(Some(cycles)) => format!("{} cycles", cycles),
+                _ => "never returns".to_string(),
+            };
+            match symbols.get(entry) {
+                Some(symbol) => writeln!(out, "{} {}: {}", entry, symbol.name(), cycles)?,
+                None => writeln!(out, "{}: {}", entry, cycles)?,
+            }
+            if options.address.is_none() {
+                continue;
+            }
+
+            for start in function.get_blocks() {
+                let count = match timing.get_loops().get(start).map(|l| l.count()) {
+                    Some(Some(count)) => format!("  loop, {} times", count),
+                    Some(None) => "  loop".to_string(),
+                    None => String::new(),
+                };
+                writeln!(
+                    out,
+                    "    {}  {:>9}{}",
+                    start,
+                    timing.get_blocks()[start].to_string(),
+                    count
+                )?;
+            }
+        }
+        if options.address.is_some() {
+            return Ok(());
+        }
+
+        let speed = if timing.double_speed() {
+            " at CGB double speed"
+        } else {
+            ""
+        };
+        let budget = timing.vblank_budget();
+        match timing.vblank() {
+            Some(cycles) => {
+                let fits = match cycles.max() {
+                    Some(max) if max <= budget => "fits",
+                    _ if cycles.min() > budget => "does not fit",
+                    _ => "may not fit",
+                };
+                writeln!(
+                    out,
+                    "VBlank handler: {} cycles of {}{}, {}",
+                    cycles, budget, speed, fits
+                )?;
+            }
+            None => writeln!(out, "VBlank handler: never returns")?,
+        }
+
+        Ok(())
+    })
+}
+
+/* Stack used by every function, or the deepest chain of calls from the one
+ * at --address, and the variables in the range the stack can reach. */
+fn stack(analyzer: &Analyzer, options: &Options) -> Result<(), CliError> {
+    let cfg = control_flow(analyzer, options)?;
+    let stack = analyzer.stack(&cfg);
+    let symbols = analyzer.control_flow_symbols(&cfg);
+    warnings(options, stack.get_warnings());
+
+    let bytes = |usage: &StackUsage| {
+        if usage.bounded() {
+            format!("{} bytes", usage.total())
+        } else {
+            format!("at least {} bytes", usage.total())
+        }
+    };
+    let name = |entry: Address| symbols.get(entry).map_or("", |symbol| symbol.name());
+
+    output(options, |out| {
+        for (&entry, usage) in stack.get_functions() {
+            if options.address.is_some_and(|address| address != entry) {
+                continue;
+            }
+            let lowest = match usage.lowest() {
+                Some(lowest) => format!(", loads SP, down to ${:04X}", lowest),
+                None => String::new(),
+            };
+            match symbols.get(entry) {
+                Some(symbol) => write!(out, "{} {}: ", entry, symbol.name())?,
+                None => write!(out, "{}: ", entry)?,
+            }
+            writeln!(out, "{}, {} pushed{}", bytes(usage), usage.own(), lowest)?;
+            if options.address.is_none() {
+                continue;
+            }
+
+            for callee in stack.path(entry).into_iter().skip(1) {
+                let usage = &stack.get_functions()[&callee];
+                writeln!(out, "    {} {:<24} {}", callee, name(callee), bytes(usage))?;
+            }
+        }
+        if options.address.is_some() {
+            return Ok(());
+        }
+
+        let lowest = match stack.lowest() {
+            Some(lowest) => lowest,
+            None => return Ok(()),
+        };
+        let top = stack.top();
+        let interrupt = match stack.interrupt() {
+            Some(vector) => match symbols.get(vector) {
+                Some(symbol) => format!(", with {} on top", symbol.name()),
+                None => format!(", with the interrupt handler at {} on top", vector),
+            },
+            None => String::new(),
+        };
+        let bound = if stack.bounded() { "" } else { " at least" };
+        writeln!(
+            out,
+            "Stack: ${:04X} down to{} ${:04X}, {} bytes{}",
+            top,
+            bound,
+            lowest,
+            top.saturating_sub(lowest),
+            interrupt
+        )?;
+
+        let usage = analyzer.memory_usage(&cfg);
+        let overlapping: BTreeMap<u16, AccessKind> = cfg
+            .get_functions()
+            .keys()
+            .flat_map(|&entry| usage.variables(entry))
+            .filter(|&(addr, _)| addr >= lowest && addr < top)
+            .collect();
+        for addr in overlapping.keys() {
+            let name = match symbols.lookup(0, *addr) {
+                Some(symbol) => symbol.name(),
+                None => "",
+            };
+            writeln!(
+                out,
+                "    {:<6} ${:04X}  overwritten by the stack  {}",
+                Region::of(*addr).name(),
+                addr,
+                name
+            )?;
+        }
+
+        Ok(())
+    })
+}
+
+/* Pseudocode of every function, or of the one at --address. */
+fn decompile(analyzer: &Analyzer, options: &Options) -> Result<(), CliError> {
+    let cfg = control_flow(analyzer, options)?;
+    let symbols = analyzer.control_flow_symbols(&cfg);
+    let mut decompiler = analyzer.decompiler(&cfg, &symbols);
+    decompiler.set_hardware_names(options.hardware_names);
+
+    output(options, |out| {
+        let mut first = true;
+        for &entry in cfg.get_functions().keys() {
+            if options.address.is_some_and(|address| address != entry) {
+                continue;
+            }
+            if let Some(pseudocode) = decompiler.decompile(entry) {
+                if !first {
+                    writeln!(out)?;
+                }
+                first = false;
+                writeln!(out, "/* {} */", entry)?;
+                write!(out, "{}", pseudocode)?;
+            }
+        }
+
+        Ok(())
+    })
+}
+
+/* Data given to the decompressors, listed or written decompressed. */
+fn compressed(analyzer: &Analyzer, options: &Options) -> Result<(), CliError> {
+    let cfg = control_flow(analyzer, options)?;
+    let found = analyzer.compressed_data(&cfg);
+    let dir = match &options.output {
+        Some(dir) => dir,
+        None => {
+            return output(options, |out| {
+                for data in &found {
+                    let destination = match data.destination() {
+                        Some(destination) => format!(" to ${:04X}", destination),
+                        None => String::new(),
+                    };
+                    writeln!(
+                        out,
+                        "{}-{:04X}  {:<11} {:>5} bytes{}, decompressed at {} by {}",
+                        data.source(),
+                        data.end().addr(),
+                        data.compression(),
+                        data.decompressed_size(),
+                        destination,
+                        data.call(),
+                        data.decompressor()
+                    )?;
+                }
+
+                Ok(())
+            })
+        }
+    };
+
+    std::fs::create_dir_all(dir).map_err(|e| CliError::Io(dir.clone(), e))?;
+    let mut written = std::collections::BTreeSet::new();
+    for data in &found {
+        if !written.insert(data.source()) {
+            continue;
+        }
+        let name = format!("{:02X}_{:04X}", data.source().bank(), data.source().addr());
+        let bytes = data.decompress(analyzer.get_bytes());
+        let path = dir.join(format!("{}.bin", name));
+        std::fs::write(&path, &bytes).map_err(|e| CliError::Io(path.clone(), e))?;
+
+        if data
+            .destination()
+            .is_some_and(|addr| (0x8000..0x9800).contains(&addr))
+        {
+            let png = Analyzer::export_tiles(&bytes, options.width, &options.palette);
+            let path = dir.join(format!("{}.png", name));
+            std::fs::write(&path, png).map_err(|e| CliError::Io(path.clone(), e))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn decompress(analyzer: &Analyzer, options: &Options) -> Result<(), CliError> {
+    let (address, format) = match (options.address, options.format) {
+        (Some(address), Some(format)) => (address, format),
+        _ => {
+            return Err(CliError::Usage(
+                "decompress needs --address and --format".to_string(),
+            ))
+        }
+    };
+    let path = options
+        .output
+        .as_ref()
+        .ok_or_else(|| CliError::Usage("decompress needs --output".to_string()))?;
+
+    let (bytes, _) = analyzer.decompress(address, format)?;
+    std::fs::write(path, bytes).map_err(|e| CliError::Io(path.clone(), e))
+}
+
+/* Emulates a call to the routine at --address. */
+fn run_routine(analyzer: &Analyzer, options: &Options) -> Result<(), CliError> {
+    let entry = options
+        .address
+        .ok_or_else(|| CliError::Usage("run needs --address".to_string()))?;
+    let mut registers = Registers::default();
+    for &(reg, value) in &options.registers {
+        registers.set(reg, value);
+    }
+
+    let (cpu, ram) = analyzer.run(entry, registers, options.cycles)?;
+    if let Some(path) = &options.output {
+        std::fs::write(path, ram).map_err(|e| CliError::Io(path.clone(), e))?;
+    }
+
+    let r = cpu.registers();
+    println!("returned after {} cycles", cpu.cycles());
+    println!(
+        "af={:04X} bc={:04X} de={:04X} hl={:04X} sp={:04X}",
+        r.get(Register::AF),
+        r.get(Register::BC),
+        r.get(Register::DE),
+        r.get(Register::HL),
+        r.sp
+    );
+
+    Ok(())
+}
+
+/* First state where the log differs from --expected, with the instructions
+ * of the expected log leading to it. */
+fn doctor(options: &Options) -> Result<(), CliError> {
+    let expected = options
+        .expected
+        .as_ref()
+        .ok_or_else(|| CliError::Usage("doctor needs --expected".to_string()))?;
+    let read = |path: &PathBuf| {
+        let text = std::fs::read_to_string(path).map_err(|e| CliError::Io(path.clone(), e))?;
+        DoctorLog::parse(&text).map_err(|e| CliError::Input(path.clone(), e))
+    };
+    let log = read(&options.rom)?;
+    let expected = read(expected)?;
+    let formatter = formatter(options);
+
+    /* Like cmp, a difference is a failure. */
+    let divergence = match expected.compare(&log) {
+        Some(divergence) => divergence,
+        None => {
+            return output(options, |out| {
+                writeln!(
+                    out,
+                    "logs match, {} instructions",
+                    expected.get_states().len()
+                )
+            })
+        }
+    };
+    output(options, |out| {
+        let index = divergence.index();
+        let states = expected.get_states();
+        for (i, state) in states
+            .iter()
+            .enumerate()
+            .take(index)
+            .skip(index.saturating_sub(DOCTOR_CONTEXT + 1))
+        {
+            let size = state.instruction().map_or(1, |inst| inst.size());
+            let bytes: Vec<String> = state.pcmem()[..size]
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect();
+            writeln!(
+                out,
+                "{} ${:04X}  {:<9} {}",
+                if i + 1 == index { ">" } else { " " },
+                state.registers().pc,
+                bytes.join(" "),
+                state.disassemble(&formatter)
+            )?;
+        }
+
+        match (divergence.expected(), divergence.actual()) {
+            (Some(expected), Some(actual)) => {
+                let what = if index == 0 {
+                    "initial states differ".to_string()
+                } else {
+                    format!("after instruction {}", index)
+                };
+                writeln!(
+                    out,
+                    "{}: {} differ",
+                    what,
+                    divergence.differences().join(", ")
+                )?;
+                writeln!(out, "expected (line {}): {}", expected.line(), expected)?;
+                writeln!(out, "actual   (line {}): {}", actual.line(), actual)
+            }
+            (Some(expected), None) => {
+                writeln!(out, "log ends after {} instructions", index)?;
+                writeln!(out, "expected (line {}): {}", expected.line(), expected)
+            }
+            (None, Some(actual)) => {
+                writeln!(out, "expected log ends after {} instructions", index)?;
+                writeln!(out, "actual   (line {}): {}", actual.line(), actual)
+            }
+            (None, None) => Ok(()),
+        }
+    })?;
+
+    Err(CliError::Mismatch)
+}
+
+/* The charmap given on the command line or ASCII, and the terminator given
+ * or else the one of the charmap, or $00. */
+fn text_encoding(analyzer: &Analyzer, options: &Options) -> (Charmap, u8) {
+    let charmap = options.charmap.as_ref().and_then(|path| {
+        analyzer
+            .get_project()
+            .get_charmaps()
+            .get(path.to_string_lossy().as_ref())
+    });
+    let terminator = options
+        .terminator
+        .or_else(|| charmap.and_then(Charmap::terminator))
+        .unwrap_or(0);
+
+    (charmap.cloned().unwrap_or_else(Charmap::ascii), terminator)
+}
+
+/* Entry of the first function each block of `cfg` belongs to. */
+fn functions(cfg: &ControlFlowGraph) -> BTreeMap<Address, Address> {
+    let mut functions = BTreeMap::new();
+    for (&entry, function) in cfg.get_functions() {
+        for &block in function.get_blocks() {
+            functions.entry(block).or_insert(entry);
+        }
+    }
+
+    functions
+}
+
+/* Address and name of `address`: its symbol or I/O register. Only ROM
+ * addresses have a bank, which bank of WRAM or VRAM is mapped is not known
+ * statically. */
+fn name(symbols: &SymbolTable, options: &Options, address: Address) -> String {
+    let location = match address.addr() {
+        0x0000..=0x7FFF => address.to_string(),
+        addr => format!("${:04X}", addr),
+    };
+    let name = match symbols.get(address) {
+        Some(symbol) => Some(symbol.name().to_string()),
+        None if options.hardware_names && address.addr() >= 0xFF00 => {
+            io_register_name(address.addr())
+        }
+        None => None,
+    };
+
+    match name {
+        Some(name) => format!("{} {}", location, name),
+        None => location,
+    }
+}
+
+/* One line per reference, with the function it is made from. */
+fn references(
+    out: &mut dyn Write,
+    cfg: &ControlFlowGraph,
+    functions: &BTreeMap<Address, Address>,
+    symbols: &SymbolTable,
+    options: &Options,
+    xrefs: &[Xref],
+) -> std::io::Result<()> {
+    for xref in xrefs {
+        let block = cfg
+            .get_blocks()
+            .range(..=xref.from())
+            .next_back()
+            .filter(|(_, block)| xref.from() < block.end());
+        let from = match block.and_then(|(start, _)| functions.get(start)) {
+            Some(&entry) => format!("in {}", name(symbols, options, entry)),
+            None => "data".to_string(),
+        };
+        writeln!(
+            out,
+            "    {:<8} {}  {}",
+            xref.kind().name(),
+            xref.from(),
+            from
+        )?;
+    }
+
+    Ok(())
+}
+
+fn control_flow(analyzer: &Analyzer, options: &Options) -> Result<ControlFlowGraph, CliError> {
+    let cfg = match options.follow {
+        Some(entry) => analyzer.control_flow_from(&[entry])?,
+        None => analyzer.control_flow(),
+    };
+    warnings(options, cfg.get_warnings());
+
+    Ok(cfg)
+}
+
+fn formatter(options: &Options) -> Formatter {
+    let mut formatter = Formatter::new(options.syntax);
+    formatter.set_hardware_names(options.hardware_names);
+
+    formatter
+}
+
+fn warnings(options: &Options, warnings: &[Warning]) {
+    if options.quiet {
+        return;
+    }
+
+    for warning in warnings {
+        eprintln!("warning: {}", warning);
+    }
+}
+
+/* Runs `write` on the output file, or stdout when there is none. */
+fn output<F>(options: &Options, write: F) -> Result<(), CliError>
+where
+    F: FnOnce(&mut dyn Write) -> std::io::Result<()>,
+{
+    let result = match &options.output {
+        Some(path) => std::fs::File::create(path)
+            .and_then(|file| {
+                let mut out = std::io::BufWriter::new(file);
+                write(&mut out)?;
+                out.flush()
+            })
+            .map_err(|e| CliError::Io(path.clone(), e)),
+        None => {
+            let stdout = std::io::stdout();
+            let mut out = std::io::BufWriter::new(stdout.lock());
+            write(&mut out)
+                .and_then(|_| out.flush())
+                .map_err(|e| CliError::Io("<stdout>".into(), e))
+        }
+    };
+
+    match result {
+        /* Piping into `head` closes stdout early, that is not an error. */
+        Err(CliError::Io(_, ref e)) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        let options = Options::parse(["xrefs", "game.gb"].iter().map(|s| s.to_string()))
+            .ok()
+            .unwrap();
+        let mut symbols = SymbolTable::new();
+        symbols.insert(Address::new(0, 0xC000), "wCounter", false);
+        let name = |bank, addr| name(&symbols, &options, Address::new(bank, addr));
+
+        assert_eq!(name(1, 0x4000), "01:4000");
+        assert_eq!(name(0, 0xC000), "$C000 wCounter");
+        assert_eq!(name(0, 0xD000), "$D000");
+        assert_eq!(name(0, 0xFF40), "$FF40 rLCDC");
+    }
+
+    #[test]
+    fn doctor_exit_status() {
+        let dir = std::env::temp_dir().join(format!("analboy-doctor-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let state = |pc: u16| {
+            format!(
+                "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:{:04X} PCMEM:00,00,00,00\n",
+                pc
+            )
+        };
+        let expected = dir.join("expected.log");
+        let out = dir.join("out.txt");
+        std::fs::write(&expected, state(0x0100) + &state(0x0101)).unwrap();
+
+        let exit_code = |log: String| {
+            let path = dir.join("actual.log");
+            std::fs::write(&path, log).unwrap();
+            let args = [
+                "doctor",
+                path.to_str().unwrap(),
+                "--expected",
+                expected.to_str().unwrap(),
+                "-o",
+                out.to_str().unwrap(),
+            ];
+            let options = Options::parse(args.iter().map(|s| s.to_string()))
+                .ok()
+                .unwrap();
+            run(&options).map_or_else(|e| e.exit_code(), |_| 0)
+        };
+
+        assert_eq!(exit_code(state(0x0100) + &state(0x0101)), 0);
+        assert_eq!(exit_code(state(0x0100) + &state(0x0102)), 1);
+        assert_eq!(exit_code(state(0x0100)), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
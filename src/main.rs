@@ -1,13 +1,19 @@
 use std::env;
-use std::path;
+use std::process;
 
-mod analyzer;
+mod cli;
+mod commands;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let result =
+        cli::Options::parse(env::args().skip(1)).and_then(|options| commands::run(&options));
 
-    let cartridge_path = path::Path::new(&args[1]);
-    let analyzer = analyzer::Analyzer::from_path(cartridge_path).unwrap();
-    let disass = analyzer.disassemble();
-    println!("{:#?}", disass);
+    if let Err(e) = result {
+        match e {
+            cli::CliError::Help => println!("{}", e),
+            cli::CliError::Mismatch => {}
+            _ => eprintln!("analboy: error: {}", e),
+        }
+        process::exit(e.exit_code());
+    }
 }